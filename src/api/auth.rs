@@ -0,0 +1,209 @@
+//! API token handling.
+//!
+//! Fly accepts personal access tokens and OAuth tokens as `Bearer`
+//! credentials, while macaroon tokens (including deploy tokens) use the
+//! `FlyV1` scheme. Tokens obtained from a `token_command` can be refreshed
+//! when they are about to expire or get rejected.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// Refresh tokens this long before their reported expiry.
+const REFRESH_MARGIN_SECS: i64 = 60;
+
+/// Kind of API token, which decides the Authorization scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    /// `FlyV1 fm2_...` macaroon, e.g. from `fly tokens create`.
+    Macaroon,
+    /// `fo1_...` personal access token.
+    Personal,
+    /// Session token from `fly auth login`.
+    OAuth,
+}
+
+impl TokenKind {
+    /// Detect the token kind from its format.
+    pub fn detect(token: &str) -> Self {
+        let token = token.trim();
+        if token.starts_with("FlyV1 ") || token.starts_with("fm1") || token.starts_with("fm2_") {
+            TokenKind::Macaroon
+        } else if token.starts_with("fo1_") {
+            TokenKind::Personal
+        } else {
+            TokenKind::OAuth
+        }
+    }
+}
+
+/// What a token may do, which decides whether mutating calls are attempted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Acts as a user, with whatever their org roles allow.
+    Full,
+    /// Macaroon limited to some apps or orgs; may change them.
+    Deploy,
+    /// May only read.
+    ReadOnly,
+}
+
+impl TokenScope {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim() {
+            "full" => Ok(Self::Full),
+            "deploy" => Ok(Self::Deploy),
+            "read_only" => Ok(Self::ReadOnly),
+            other => anyhow::bail!(
+                "Invalid token scope: {}. Use 'full', 'deploy' or 'read_only'",
+                other
+            ),
+        }
+    }
+
+    /// Scope of a token whose scope wasn't configured. Macaroon caveats
+    /// aren't decoded, so every macaroon is taken as a deploy token.
+    pub fn infer(kind: TokenKind) -> Self {
+        match kind {
+            TokenKind::Macaroon => Self::Deploy,
+            TokenKind::Personal | TokenKind::OAuth => Self::Full,
+        }
+    }
+
+    pub fn can_mutate(self) -> bool {
+        self != Self::ReadOnly
+    }
+}
+
+/// A token as produced by a `token_command`: either the bare token or JSON
+/// with an optional expiry.
+#[derive(Deserialize)]
+struct IssuedToken {
+    token: String,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+struct TokenState {
+    value: String,
+    kind: TokenKind,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Credentials shared by the clients of one profile.
+pub struct Credentials {
+    state: RwLock<TokenState>,
+    refresh_command: Option<String>,
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl Credentials {
+    /// Wrap a token, optionally with a command that prints a fresh one.
+    pub fn new(token: String, refresh_command: Option<String>) -> Self {
+        let issued = parse_issued(&token);
+        Self {
+            state: RwLock::new(TokenState {
+                kind: TokenKind::detect(&issued.token),
+                value: issued.token,
+                expires_at: issued.expires_at,
+            }),
+            refresh_command,
+            refreshing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Kind of the current token.
+    pub fn kind(&self) -> TokenKind {
+        self.read().kind
+    }
+
+    /// Expiry of the current token, when known.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.read().expires_at
+    }
+
+    /// Authorization header value, refreshing first if the token is about to expire.
+    pub async fn authorization(&self) -> Result<String> {
+        let expiring = self
+            .expires_at()
+            .map(|at| at - Duration::seconds(REFRESH_MARGIN_SECS) <= Utc::now())
+            .unwrap_or(false);
+        if expiring {
+            self.refresh().await?;
+        }
+
+        let state = self.read();
+        Ok(match state.kind {
+            TokenKind::Macaroon if state.value.starts_with("FlyV1 ") => state.value.clone(),
+            TokenKind::Macaroon => format!("FlyV1 {}", state.value),
+            TokenKind::Personal | TokenKind::OAuth => format!("Bearer {}", state.value),
+        })
+    }
+
+    /// Fetch a new token from the refresh command.
+    ///
+    /// Returns `false` when no refresh command is configured.
+    pub async fn refresh(&self) -> Result<bool> {
+        let Some(command) = self.refresh_command.clone() else {
+            return Ok(false);
+        };
+
+        // Concurrent requests that hit an expired token share one refresh
+        let _guard = self.refreshing.lock().await;
+
+        let output = tokio::task::spawn_blocking(move || run_token_command(&command))
+            .await
+            .context("Token refresh task failed")??;
+        let issued = parse_issued(&output);
+
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.kind = TokenKind::detect(&issued.token);
+        state.value = issued.token;
+        state.expires_at = issued.expires_at;
+        tracing::info!(kind = ?state.kind, "Refreshed Fly.io API token");
+
+        Ok(true)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, TokenState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Run a shell command that prints a token on stdout.
+pub fn run_token_command(command: &str) -> Result<String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .context("Failed to run token command")?;
+
+    if !output.status.success() {
+        anyhow::bail!("Token command exited with {}", output.status);
+    }
+
+    let token = String::from_utf8(output.stdout)
+        .context("Token command printed invalid UTF-8")?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        anyhow::bail!("Token command printed no token");
+    }
+    Ok(token)
+}
+
+fn parse_issued(raw: &str) -> IssuedToken {
+    let raw = raw.trim();
+    if raw.starts_with('{') {
+        if let Ok(issued) = serde_json::from_str(raw) {
+            return issued;
+        }
+    }
+    IssuedToken {
+        token: raw.to_string(),
+        expires_at: None,
+    }
+}
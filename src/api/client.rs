@@ -1,93 +1,267 @@
 //! Fly.io GraphQL API client with connection pooling.
 
 use anyhow::{Context, Result};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
 
-use crate::models::{App, GraphQLResponse, Machine};
+use super::auth::Credentials;
+use super::client_config::FlyClientConfig;
+use super::middleware::Middlewares;
+use super::operations::{
+    AppNameVariables, AppPageVariables, GetAppStatus, GetUser, ListApps, ListAppsVariables,
+    ListMachinesPage, Operation, PageInfo,
+};
+use super::queries;
+use super::rate_limit::RateLimiter;
+use super::response;
+use super::retry::{self, RetryPolicy};
+use super::stats;
+use super::timeout;
+use super::warnings::report_warnings;
 
-const GRAPHQL_ENDPOINT: &str = "https://api.fly.io/graphql";
+use crate::error::FlyError;
+use crate::models::{
+    Allocation, App, AppStatus, BillingSummary, Build, Certificate, DeployToken, GraphQLError,
+    GraphQLResponse, ImageRef, IpAddress, LogEntry, LogPage, Machine, MachineConfig, MachineEvent,
+    OrganizationDetail, OrganizationMember, Page, Region, Release, RemoteBuilder, Secret,
+    StopReason, UserInfo, VmSize, Warning, WireGuardPeer, WireGuardPeerCreated,
+};
+
+/// Page size used when fetching every page of a connection.
+pub const PAGE_SIZE: i32 = 100;
+
+/// Most pages `collect_all` follows before stopping with a warning.
+const MAX_PAGES: usize = 1000;
+
+/// Longest `Retry-After` delay we are willing to honor.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
 
 /// Fly.io GraphQL client with persistent connection.
 pub struct FlyClient {
     client: Client,
-    token: String,
+    credentials: Arc<Credentials>,
+    graphql_url: String,
+    api_url: String,
+    retry: RetryPolicy,
+    limiter: RateLimiter,
+    middleware: Middlewares,
+    /// Send persisted query hashes; cleared if the server doesn't support them.
+    persisted_queries: AtomicBool,
 }
 
 impl FlyClient {
     /// Create a new Fly.io client.
-    pub fn new(token: String) -> Result<Self> {
-        let client = Client::builder()
-            .pool_max_idle_per_host(5)
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .context("Failed to build HTTP client")?;
+    pub fn new(credentials: Arc<Credentials>, config: &FlyClientConfig) -> Result<Self> {
+        let client = config.http_client()?;
+
+        Ok(Self {
+            client,
+            credentials,
+            graphql_url: config.graphql_url.clone(),
+            api_url: config.api_url.clone(),
+            retry: RetryPolicy::from_env(),
+            limiter: RateLimiter::from_env(),
+            middleware: config.middleware.clone(),
+            persisted_queries: AtomicBool::new(config.persisted_queries),
+        })
+    }
 
-        Ok(Self { client, token })
+    /// Credentials this client authenticates with.
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
     }
 
     /// Execute a GraphQL query.
     async fn query<T: for<'de> Deserialize<'de>>(
         &self,
-        query: &str,
+        query: &'static str,
         variables: Option<Value>,
     ) -> Result<T> {
-        let body = GraphQLRequest {
-            query: query.to_string(),
-            variables,
-        };
+        let (data, warnings) = self.query_partial(query, variables).await?;
+        report_warnings(warnings);
+        Ok(data)
+    }
 
-        let response = self
-            .client
-            .post(GRAPHQL_ENDPOINT)
-            .header("Authorization", format!("Bearer {}", self.token))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await
-            .context("Failed to send GraphQL request")?;
+    /// Run a typed operation.
+    async fn execute<O: Operation>(&self, variables: O::Variables) -> Result<O::Data> {
+        let (data, warnings) = self.execute_partial::<O>(variables).await?;
+        report_warnings(warnings);
+        Ok(data)
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            anyhow::bail!("GraphQL request failed: {} - {}", status, text);
-        }
+    /// Run a typed operation, returning field-level errors that came back
+    /// alongside the data as warnings.
+    async fn execute_partial<O: Operation>(
+        &self,
+        variables: O::Variables,
+    ) -> Result<(O::Data, Vec<Warning>)> {
+        let variables =
+            serde_json::to_value(variables).context("Failed to encode GraphQL variables")?;
+        let variables = Some(variables).filter(|v| !v.is_null());
+        self.query_partial(O::document(), variables).await
+    }
 
-        // Get raw text for debugging
-        let text = response.text().await.context("Failed to read response")?;
+    /// Execute a GraphQL query, returning field-level errors that came
+    /// back alongside the data as warnings.
+    ///
+    /// Unlike `query`, the warnings are left to the caller to report.
+    async fn query_partial<T: for<'de> Deserialize<'de>>(
+        &self,
+        query: &'static str,
+        variables: Option<Value>,
+    ) -> Result<(T, Vec<Warning>)> {
+        let prepared = queries::prepare(query);
+        let text = self.send_document(&prepared, variables).await?;
 
-        let result: GraphQLResponse<T> = serde_json::from_str(&text).map_err(|e| {
-            anyhow::anyhow!(
-                "JSON parse error: {} | Raw: {}",
-                e,
-                &text[..text.len().min(300)]
-            )
-        })?;
+        let result: GraphQLResponse<T> = response::parse("graphql", &text)?;
 
         // Only fail on GraphQL errors if there's no data at all
         // GraphQL allows partial results with field-level errors
-        if result.data.is_none() {
-            if let Some(errors) = result.errors {
-                if !errors.is_empty() {
-                    let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
-                    anyhow::bail!("GraphQL errors: {}", messages.join(", "));
+        let errors = result.errors.unwrap_or_default();
+        let Some(data) = result.data else {
+            if !errors.is_empty() {
+                return Err(FlyError::from_graphql(&errors).into());
+            }
+            anyhow::bail!("GraphQL response missing data field");
+        };
+
+        Ok((data, errors.iter().map(Warning::from).collect()))
+    }
+
+    /// Send a document, by hash first if persisted queries are on.
+    ///
+    /// A server that doesn't know the hash yet gets the full document along
+    /// with it, which registers it; one that doesn't support persisted
+    /// queries gets full documents from then on.
+    async fn send_document(
+        &self,
+        prepared: &queries::Prepared,
+        variables: Option<Value>,
+    ) -> Result<String> {
+        let span = tracing::debug_span!("graphql_request", attempts = tracing::field::Empty);
+        let persisted = serde_json::json!({
+            "persistedQuery": { "version": 1, "sha256Hash": prepared.hash }
+        });
+
+        let mut extensions = None;
+        if self.persisted_queries.load(Ordering::Relaxed) {
+            let body = GraphQLRequest {
+                query: None,
+                variables: variables.clone(),
+                extensions: Some(persisted.clone()),
+            };
+            let text = self.send_with_retry(&body).instrument(span.clone()).await?;
+            match persisted_query_miss(&text) {
+                None => return Ok(text),
+                Some(PersistedQueryMiss::NotFound) => extensions = Some(persisted),
+                Some(PersistedQueryMiss::NotSupported) => {
+                    tracing::info!("GraphQL server doesn't support persisted queries");
+                    self.persisted_queries.store(false, Ordering::Relaxed);
                 }
             }
         }
 
-        result.data.context("GraphQL response missing data field")
+        let body = GraphQLRequest {
+            query: Some(prepared.text.clone()),
+            variables,
+            extensions,
+        };
+        self.send_with_retry(&body).instrument(span).await
+    }
+
+    /// POST a GraphQL request, retrying 429s, 5xxs and connection failures.
+    ///
+    /// Returns the raw response body. GraphQL-level errors arrive with a 200
+    /// and are never retried.
+    async fn send_with_retry(&self, body: &GraphQLRequest) -> Result<String> {
+        let mut attempt = 1;
+        let mut refreshed = false;
+
+        loop {
+            let authorization = self.credentials.authorization().await?;
+            self.limiter.acquire().await;
+            let request = self
+                .client
+                .post(&self.graphql_url)
+                .header("Authorization", authorization)
+                .header("Content-Type", "application/json")
+                .json(body);
+            let sent = self
+                .middleware
+                .send("graphql", timeout::apply(request))
+                .await;
+
+            let (error, retryable, retry_after) = match sent {
+                Ok(response) if response.status().is_success() => {
+                    tracing::Span::current().record("attempts", attempt);
+                    return response.text().await.context("Failed to read response");
+                }
+                Ok(response)
+                    if response.status() == StatusCode::UNAUTHORIZED
+                        && !refreshed
+                        && self.credentials.refresh().await? =>
+                {
+                    // Retry once with the fresh token; doesn't count as an attempt
+                    refreshed = true;
+                    continue;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = parse_retry_after(&response);
+                    let text = response.text().await.unwrap_or_default();
+                    (
+                        anyhow::Error::new(FlyError::from_status(status, text))
+                            .context("GraphQL request failed"),
+                        retry::is_retryable_status(status),
+                        retry_after,
+                    )
+                }
+                Err(e) => {
+                    let retryable = retry::is_retryable_error(&e);
+                    (
+                        anyhow::Error::new(e).context("Failed to send GraphQL request"),
+                        retryable,
+                        None,
+                    )
+                }
+            };
+
+            if !retryable || attempt >= self.retry.max_attempts {
+                tracing::Span::current().record("attempts", attempt);
+                return Err(error);
+            }
+
+            let delay = retry_after.unwrap_or_else(|| self.retry.delay_for(attempt));
+            tracing::warn!(
+                attempt,
+                max_attempts = self.retry.max_attempts,
+                delay_ms = delay.as_millis() as u64,
+                "Retrying Fly API request: {:#}",
+                error
+            );
+
+            stats::record_retry("graphql");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
 
     /// Check if the client can connect to Fly.io API.
     pub async fn ping(&self) -> Result<bool> {
-        let query = r#"
-            query {
-                viewer {
-                    id
-                }
-            }
-        "#;
+        let id = self.get_viewer_id().await?;
+        Ok(!id.is_empty())
+    }
+
+    /// Get the authenticated viewer's ID.
+    pub async fn get_viewer_id(&self) -> Result<String> {
+        let query = queries::GET_VIEWER_ID;
 
         #[derive(Deserialize)]
         struct ViewerResponse {
@@ -100,72 +274,97 @@ impl FlyClient {
         }
 
         let result: ViewerResponse = self.query(query, None).await?;
-        Ok(!result.viewer.id.is_empty())
+        Ok(result.viewer.id)
     }
 
-    /// List all apps for the authenticated user.
-    pub async fn list_apps(&self, limit: Option<i32>) -> Result<Vec<App>> {
+    /// List a page of apps for the authenticated user.
+    pub async fn list_apps(
+        &self,
+        limit: Option<i32>,
+        after: Option<&str>,
+        organization_id: Option<&str>,
+    ) -> Result<Page<App>> {
         let limit = limit.unwrap_or(25);
 
-        let query = r#"
-            query($first: Int) {
-                apps(first: $first) {
-                    nodes {
-                        id
-                        name
-                        status
-                        deployed
-                        hostname
-                        organization {
-                            id
-                            name
-                            slug
-                        }
-                        currentRelease {
-                            id
-                            version
-                            status
-                            description
-                            createdAt
-                        }
-                    }
-                }
-            }
-        "#;
+        let variables = ListAppsVariables {
+            first: limit,
+            after: after.map(String::from),
+            organization_id: organization_id.map(String::from),
+        };
+        let (result, mut warnings) = self.execute_partial::<ListApps>(variables).await?;
+        let next_cursor = result.apps.page_info.next_cursor();
 
-        #[derive(Deserialize)]
-        struct AppsResponse {
-            apps: AppsNodes,
+        // Apps the token can't read come back as null with an error per node
+        let omitted = result.apps.nodes.iter().filter(|n| n.is_none()).count();
+        if omitted > warnings.len() {
+            warnings.push(Warning {
+                message: format!(
+                    "{} apps were omitted without an error",
+                    omitted - warnings.len()
+                ),
+                path: Some("apps.nodes".into()),
+                code: None,
+            });
         }
 
+        let apps = result
+            .apps
+            .nodes
+            .into_iter()
+            .flatten() // Skip None values
+            .map(App::from)
+            .collect();
+
+        Ok(Page {
+            items: apps,
+            next_cursor,
+            warnings,
+        })
+    }
+
+    /// Get status for a specific app.
+    pub async fn get_app_status(&self, app_name: &str) -> Result<AppStatus> {
+        let variables = AppNameVariables {
+            name: app_name.to_string(),
+        };
+        let result = self.execute::<GetAppStatus>(variables).await?;
+        let node = result
+            .app
+            .ok_or_else(|| anyhow::anyhow!("App not found: {}", app_name))?;
+
+        Ok(AppStatus {
+            app: node.app.into(),
+            machines: node.machines.nodes.into_iter().map(Machine::from).collect(),
+            allocations: node.allocations.into_iter().map(Allocation::from).collect(),
+        })
+    }
+
+    /// List a page of release history for an app, newest first.
+    pub async fn list_releases(
+        &self,
+        app_name: &str,
+        limit: Option<i32>,
+        after: Option<&str>,
+    ) -> Result<Page<Release>> {
+        let limit = limit.unwrap_or(25);
+
+        let query = queries::LIST_RELEASES;
+
         #[derive(Deserialize)]
-        struct AppsNodes {
-            // Some apps may return null due to authorization errors
-            nodes: Vec<Option<AppNode>>,
+        struct AppResponse {
+            app: AppReleases,
         }
 
         #[derive(Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct AppNode {
-            id: String,
-            name: String,
-            #[serde(default)]
-            status: String,
-            #[serde(default)]
-            deployed: bool,
-            #[serde(default)]
-            hostname: Option<String>,
-            #[serde(default)]
-            organization: Option<OrgNode>,
-            #[serde(default)]
-            current_release: Option<ReleaseNode>,
+        struct AppReleases {
+            releases: ReleasesNodes,
         }
 
         #[derive(Deserialize)]
-        struct OrgNode {
-            id: String,
-            name: String,
-            slug: String,
+        #[serde(rename_all = "camelCase")]
+        struct ReleasesNodes {
+            nodes: Vec<ReleaseNode>,
+            page_info: PageInfo,
         }
 
         #[derive(Deserialize)]
@@ -177,104 +376,79 @@ impl FlyClient {
             #[serde(default)]
             description: Option<String>,
             #[serde(default)]
+            user: Option<UserNode>,
+            #[serde(default)]
             created_at: Option<String>,
         }
 
-        let variables = serde_json::json!({ "first": limit });
-        let result: AppsResponse = self.query(query, Some(variables)).await?;
+        #[derive(Deserialize)]
+        struct UserNode {
+            #[serde(default)]
+            email: Option<String>,
+        }
 
-        // Filter out unauthorized apps (null values)
-        let apps = result
-            .apps
+        let variables = serde_json::json!({ "name": app_name, "first": limit, "after": after });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+        let next_cursor = result.app.releases.page_info.next_cursor();
+
+        let releases = result
+            .app
+            .releases
             .nodes
             .into_iter()
-            .flatten() // Skip None values
-            .map(|n| App {
+            .map(|n| Release {
                 id: n.id,
-                name: n.name,
+                version: n.version,
                 status: n.status,
-                deployed: n.deployed,
-                hostname: n.hostname,
-                organization: n.organization.map(|o| crate::models::Organization {
-                    id: o.id,
-                    name: o.name,
-                    slug: o.slug,
-                }),
-                current_release: n.current_release.map(|r| crate::models::Release {
-                    id: r.id,
-                    version: r.version,
-                    status: r.status,
-                    description: r.description,
-                    created_at: r.created_at,
-                }),
+                description: n.description,
+                user: n.user.and_then(|u| u.email),
+                created_at: n.created_at,
             })
             .collect();
 
-        Ok(apps)
+        Ok(Page {
+            items: releases,
+            next_cursor,
+            warnings: Vec::new(),
+        })
     }
 
-    /// Get status for a specific app.
-    pub async fn get_app_status(&self, app_name: &str) -> Result<Value> {
-        let query = r#"
-            query($name: String!) {
-                app(name: $name) {
-                    id
-                    name
-                    status
-                    deployed
-                    hostname
-                    organization {
-                        id
-                        name
-                        slug
-                    }
-                    currentRelease {
-                        id
-                        version
-                        status
-                        description
-                        createdAt
-                    }
-                    machines {
-                        nodes {
-                            id
-                            name
-                            state
-                            region
-                        }
-                    }
-                    allocations {
-                        id
-                        status
-                        region
-                        version
-                    }
-                }
-            }
-        "#;
+    /// List all machines for an app.
+    pub async fn list_machines(&self, app_name: &str) -> Result<Vec<Machine>> {
+        collect_pages(|cursor| async move {
+            self.list_machines_page(app_name, Some(PAGE_SIZE), cursor.as_deref())
+                .await
+        })
+        .await
+    }
 
-        let variables = serde_json::json!({ "name": app_name });
-        let result: Value = self.query(query, Some(variables)).await?;
+    /// List a page of machines for an app.
+    pub async fn list_machines_page(
+        &self,
+        app_name: &str,
+        limit: Option<i32>,
+        after: Option<&str>,
+    ) -> Result<Page<Machine>> {
+        let limit = limit.unwrap_or(25);
 
-        Ok(result)
+        let variables = AppPageVariables {
+            name: app_name.to_string(),
+            first: limit,
+            after: after.map(String::from),
+        };
+        let result = self.execute::<ListMachinesPage>(variables).await?;
+        let machines = result.app.machines;
+
+        Ok(Page {
+            next_cursor: machines.page_info.next_cursor(),
+            items: machines.nodes.into_iter().map(Machine::from).collect(),
+            warnings: Vec::new(),
+        })
     }
 
-    /// List machines for an app.
-    pub async fn list_machines(&self, app_name: &str) -> Result<Vec<Machine>> {
-        let query = r#"
-            query($name: String!) {
-                app(name: $name) {
-                    machines {
-                        nodes {
-                            id
-                            name
-                            state
-                            region
-                        }
-                    }
-                }
-            }
-        "#;
+    /// List machines for an app including their config (image, env, size).
+    pub async fn list_machines_with_config(&self, app_name: &str) -> Result<Vec<Machine>> {
+        let query = queries::LIST_MACHINES_WITH_CONFIG;
 
         #[derive(Deserialize)]
         struct AppResponse {
@@ -292,12 +466,13 @@ impl FlyClient {
         }
 
         #[derive(Deserialize)]
-        #[serde(rename_all = "camelCase")]
         struct MachineNode {
             id: String,
             name: String,
             state: String,
             region: String,
+            #[serde(default)]
+            config: Option<MachineConfig>,
         }
 
         let variables = serde_json::json!({ "name": app_name });
@@ -315,156 +490,1413 @@ impl FlyClient {
                 region: n.region,
                 instance_id: None,
                 private_ip: None,
-                config: None,
+                config: n.config,
+                image_ref: None,
+                created_at: None,
+                updated_at: None,
+                checks: Vec::new(),
+                cordoned: false,
             })
             .collect();
 
         Ok(machines)
     }
 
-    /// Get current user info.
-    pub async fn get_user(&self) -> Result<Value> {
-        let query = r#"
-            query {
-                viewer {
-                    id
-                    email
-                    name
-                    organizations {
-                        nodes {
-                            id
-                            name
-                            slug
-                        }
-                    }
-                }
-            }
-        "#;
+    /// List recent events for a machine, newest first.
+    pub async fn list_machine_events(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+    ) -> Result<Vec<MachineEvent>> {
+        let query = queries::LIST_MACHINE_EVENTS;
 
-        let result: Value = self.query(query, None).await?;
-        Ok(result)
-    }
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: AppMachine,
+        }
 
-    /// List all Fly.io regions.
-    pub async fn list_regions(&self) -> Result<Value> {
-        let query = r#"
-            query {
-                platform {
-                    regions {
-                        code
-                        name
-                        gatewayAvailable
-                    }
-                }
-            }
-        "#;
+        #[derive(Deserialize)]
+        struct AppMachine {
+            machine: MachineEvents,
+        }
 
-        let result: Value = self.query(query, None).await?;
-        Ok(result)
-    }
+        #[derive(Deserialize)]
+        struct MachineEvents {
+            events: EventsNodes,
+        }
 
-    /// List secrets for an app (names only, values are not exposed).
-    pub async fn list_secrets(&self, app_name: &str) -> Result<Value> {
-        let query = r#"
-            query($name: String!) {
-                app(name: $name) {
-                    secrets {
-                        name
-                        digest
-                        createdAt
-                    }
-                }
-            }
-        "#;
+        #[derive(Deserialize)]
+        struct EventsNodes {
+            nodes: Vec<EventNode>,
+        }
 
-        let variables = serde_json::json!({ "name": app_name });
-        let result: Value = self.query(query, Some(variables)).await?;
-        Ok(result)
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct EventNode {
+            #[serde(default)]
+            id: Option<String>,
+            kind: String,
+            #[serde(default)]
+            status: Option<String>,
+            #[serde(default)]
+            source: Option<String>,
+            timestamp: String,
+            #[serde(default)]
+            exit_code: Option<i32>,
+            #[serde(default)]
+            oom_killed: Option<bool>,
+            #[serde(default)]
+            requested_stop: Option<bool>,
+        }
+
+        let variables = serde_json::json!({ "name": app_name, "machineId": machine_id });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        let mut events: Vec<MachineEvent> = result
+            .app
+            .machine
+            .events
+            .nodes
+            .into_iter()
+            .map(|n| MachineEvent {
+                id: n.id,
+                kind: n.kind,
+                status: n.status,
+                source: n.source,
+                timestamp: n.timestamp,
+                exit_code: n.exit_code,
+                oom_killed: n.oom_killed,
+                requested_stop: n.requested_stop,
+            })
+            .collect();
+
+        // RFC 3339 timestamps sort lexicographically
+        events.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+
+        Ok(events)
     }
 
-    /// Set a secret for an app.
-    pub async fn set_secret(&self, app_name: &str, key: &str, value: &str) -> Result<Value> {
-        let query = r#"
-            mutation($input: SetSecretsInput!) {
-                setSecrets(input: $input) {
-                    release {
-                        id
-                        version
-                        status
-                    }
-                }
-            }
-        "#;
+    /// Determine why a machine last stopped from its latest exit event.
+    pub async fn get_machine_stopped_reason(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+    ) -> Result<StopReason> {
+        let events = self.list_machine_events(app_name, machine_id).await?;
 
-        let variables = serde_json::json!({
-            "input": {
-                "appId": app_name,
-                "secrets": [
-                    {
-                        "key": key,
-                        "value": value
-                    }
-                ]
-            }
-        });
+        let reason = events
+            .iter()
+            .find(|e| e.kind == "exit")
+            .map(StopReason::from_exit_event)
+            .unwrap_or(StopReason::Unknown);
 
-        let result: Value = self.query(query, Some(variables)).await?;
-        Ok(result)
+        Ok(reason)
     }
 
-    /// Delete a secret from an app.
-    pub async fn delete_secret(&self, app_name: &str, key: &str) -> Result<Value> {
-        let query = r#"
-            mutation($input: UnsetSecretsInput!) {
-                unsetSecrets(input: $input) {
-                    release {
-                        id
-                        version
-                        status
-                    }
-                }
-            }
-        "#;
+    /// Fetch a page of app logs, starting after `next_token` when given.
+    pub async fn get_logs(
+        &self,
+        app_name: &str,
+        region: Option<&str>,
+        instance: Option<&str>,
+        next_token: Option<&str>,
+    ) -> Result<LogPage> {
+        let mut query = Vec::new();
+        if let Some(token) = next_token {
+            query.push(("next_token", token));
+        }
+        if let Some(region) = region {
+            query.push(("region", region));
+        }
+        if let Some(instance) = instance {
+            query.push(("instance", instance));
+        }
 
-        let variables = serde_json::json!({
-            "input": {
-                "appId": app_name,
-                "keys": [key]
-            }
-        });
+        let authorization = self.credentials.authorization().await?;
+        self.limiter.acquire().await;
+        let request = self
+            .client
+            .get(format!("{}/api/v1/apps/{}/logs", self.api_url, app_name))
+            .header("Authorization", authorization)
+            .query(&query);
+        let response = self
+            .middleware
+            .send("logs", timeout::apply(request))
+            .await
+            .context("Failed to send logs request")?;
 
-        let result: Value = self.query(query, Some(variables)).await?;
-        Ok(result)
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow::Error::new(FlyError::from_status(status, text))
+                .context("Logs request failed"));
+        }
+
+        #[derive(Deserialize)]
+        struct LogsResponse {
+            data: Vec<LogData>,
+            #[serde(default)]
+            meta: Option<LogMeta>,
+        }
+
+        #[derive(Deserialize)]
+        struct LogData {
+            attributes: LogEntry,
+        }
+
+        #[derive(Deserialize)]
+        struct LogMeta {
+            #[serde(default)]
+            next_token: Option<String>,
+        }
+
+        let result: LogsResponse = response
+            .json()
+            .await
+            .context("Failed to parse logs response")?;
+
+        Ok(LogPage {
+            entries: result.data.into_iter().map(|d| d.attributes).collect(),
+            // Keep the caller's cursor if the API didn't advance it
+            next_token: result
+                .meta
+                .and_then(|m| m.next_token)
+                .filter(|t| !t.is_empty())
+                .or_else(|| next_token.map(String::from)),
+        })
     }
 
-    /// Restart an app (restarts all machines).
-    pub async fn restart_app(&self, app_name: &str) -> Result<Value> {
-        let query = r#"
-            mutation($input: RestartAppInput!) {
-                restartApp(input: $input) {
-                    app {
-                        id
-                        name
-                        status
-                    }
-                }
-            }
-        "#;
+    /// Get current user info.
+    pub async fn get_user(&self) -> Result<UserInfo> {
+        let result = self.execute::<GetUser>(()).await?;
+        Ok(result.viewer.into())
+    }
 
-        let variables = serde_json::json!({
-            "input": {
-                "appId": app_name
-            }
-        });
+    /// List all Fly.io regions.
+    pub async fn list_regions(&self) -> Result<Vec<Region>> {
+        let query = queries::LIST_REGIONS;
 
-        let result: Value = self.query(query, Some(variables)).await?;
-        Ok(result)
+        #[derive(Deserialize)]
+        struct PlatformResponse {
+            platform: Platform,
+        }
+
+        #[derive(Deserialize)]
+        struct Platform {
+            regions: Vec<Region>,
+        }
+
+        let result: PlatformResponse = self.query(query, None).await?;
+        Ok(result.platform.regions)
     }
-}
+
+    /// List VM size presets with their prices.
+    pub async fn list_vm_sizes(&self) -> Result<Vec<VmSize>> {
+        let query = queries::LIST_VM_SIZES;
+
+        #[derive(Deserialize)]
+        struct PlatformResponse {
+            platform: Platform,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Platform {
+            vm_sizes: Vec<VmSize>,
+        }
+
+        let result: PlatformResponse = self.query(query, None).await?;
+        Ok(result.platform.vm_sizes)
+    }
+
+    /// Codes of an app's backup regions.
+    pub async fn get_backup_regions(&self, app_name: &str) -> Result<Vec<String>> {
+        let query = queries::GET_BACKUP_REGIONS;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: Option<AppRegions>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AppRegions {
+            #[serde(default)]
+            backup_regions: Vec<RegionCode>,
+        }
+
+        #[derive(Deserialize)]
+        struct RegionCode {
+            code: String,
+        }
+
+        let variables = serde_json::json!({ "name": app_name });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        let app = result
+            .app
+            .ok_or_else(|| anyhow::anyhow!("App not found: {}", app_name))?;
+        Ok(app.backup_regions.into_iter().map(|r| r.code).collect())
+    }
+
+    /// List secrets for an app (names only, values are not exposed).
+    pub async fn list_secrets(&self, app_name: &str) -> Result<Vec<Secret>> {
+        let query = queries::LIST_SECRETS;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: Option<AppSecrets>,
+        }
+
+        #[derive(Deserialize)]
+        struct AppSecrets {
+            secrets: Vec<Secret>,
+        }
+
+        let variables = serde_json::json!({ "name": app_name });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+        result
+            .app
+            .map(|a| a.secrets)
+            .ok_or_else(|| anyhow::anyhow!("App not found: {}", app_name))
+    }
+
+    /// Set a secret for an app.
+    pub async fn set_secret(&self, app_name: &str, key: &str, value: &str) -> Result<Value> {
+        self.set_secrets(app_name, &[(key.to_string(), value.to_string())])
+            .await
+    }
+
+    /// Set several secrets at once, creating a single release.
+    pub async fn set_secrets(&self, app_name: &str, secrets: &[(String, String)]) -> Result<Value> {
+        let query = queries::SET_SECRETS;
+
+        let secrets: Vec<Value> = secrets
+            .iter()
+            .map(|(key, value)| serde_json::json!({ "key": key, "value": value }))
+            .collect();
+
+        let variables = serde_json::json!({
+            "input": {
+                "appId": app_name,
+                "secrets": secrets
+            }
+        });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// Delete a secret from an app.
+    pub async fn delete_secret(&self, app_name: &str, key: &str) -> Result<Value> {
+        self.unset_secrets(app_name, &[key.to_string()]).await
+    }
+
+    /// Remove several secrets at once, creating a single release.
+    pub async fn unset_secrets(&self, app_name: &str, keys: &[String]) -> Result<Value> {
+        let query = queries::UNSET_SECRETS;
+
+        let variables = serde_json::json!({
+            "input": {
+                "appId": app_name,
+                "keys": keys
+            }
+        });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// List IP addresses allocated to an app.
+    pub async fn list_ips(&self, app_name: &str) -> Result<Vec<IpAddress>> {
+        let query = queries::LIST_IPS;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AppResponse {
+            app: AppIps,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AppIps {
+            ip_addresses: IpNodes,
+        }
+
+        #[derive(Deserialize)]
+        struct IpNodes {
+            nodes: Vec<IpNode>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct IpNode {
+            id: String,
+            address: String,
+            #[serde(rename = "type")]
+            ip_type: String,
+            #[serde(default)]
+            region: Option<String>,
+            #[serde(default)]
+            created_at: Option<String>,
+        }
+
+        let variables = serde_json::json!({ "name": app_name });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        let ips = result
+            .app
+            .ip_addresses
+            .nodes
+            .into_iter()
+            .map(|n| IpAddress {
+                id: n.id,
+                address: n.address,
+                ip_type: n.ip_type,
+                region: n.region,
+                created_at: n.created_at,
+            })
+            .collect();
+
+        Ok(ips)
+    }
+
+    /// Allocate an IP address (v4, v6, shared_v4 or private_v6).
+    pub async fn allocate_ip(
+        &self,
+        app_name: &str,
+        ip_type: &str,
+        region: Option<&str>,
+    ) -> Result<Value> {
+        let query = queries::ALLOCATE_IP;
+
+        let variables = serde_json::json!({
+            "input": {
+                "appId": app_name,
+                "type": ip_type,
+                "region": region
+            }
+        });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// Release an IP address from an app.
+    pub async fn release_ip(&self, app_name: &str, address: &str) -> Result<Value> {
+        let query = queries::RELEASE_IP;
+
+        let variables = serde_json::json!({
+            "input": {
+                "appId": app_name,
+                "ip": address
+            }
+        });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// List all TLS certificates for an app.
+    pub async fn list_certificates(&self, app_name: &str) -> Result<Vec<Certificate>> {
+        collect_pages(|cursor| async move {
+            self.list_certificates_page(app_name, Some(PAGE_SIZE), cursor.as_deref())
+                .await
+        })
+        .await
+    }
+
+    /// List a page of TLS certificates for an app.
+    pub async fn list_certificates_page(
+        &self,
+        app_name: &str,
+        limit: Option<i32>,
+        after: Option<&str>,
+    ) -> Result<Page<Certificate>> {
+        let limit = limit.unwrap_or(25);
+
+        let query = queries::LIST_CERTIFICATES_PAGE;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: AppCertificates,
+        }
+
+        #[derive(Deserialize)]
+        struct AppCertificates {
+            certificates: CertificateNodes,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CertificateNodes {
+            nodes: Vec<Certificate>,
+            page_info: PageInfo,
+        }
+
+        let variables = serde_json::json!({ "name": app_name, "first": limit, "after": after });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        let next_cursor = result.app.certificates.page_info.next_cursor();
+
+        Ok(Page {
+            items: result.app.certificates.nodes,
+            next_cursor,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Get a certificate's current validation and issuance status.
+    pub async fn check_certificate(&self, app_name: &str, hostname: &str) -> Result<Certificate> {
+        let query = queries::CHECK_CERTIFICATE;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: AppCertificate,
+        }
+
+        #[derive(Deserialize)]
+        struct AppCertificate {
+            certificate: Certificate,
+        }
+
+        let variables = serde_json::json!({ "name": app_name, "hostname": hostname });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        Ok(result.app.certificate)
+    }
+
+    /// Add a certificate for a custom hostname.
+    pub async fn add_certificate(&self, app_name: &str, hostname: &str) -> Result<Certificate> {
+        let query = queries::ADD_CERTIFICATE;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AddResponse {
+            add_certificate: AddPayload,
+        }
+
+        #[derive(Deserialize)]
+        struct AddPayload {
+            certificate: Certificate,
+        }
+
+        let variables = serde_json::json!({ "appId": app_name, "hostname": hostname });
+        let result: AddResponse = self.query(query, Some(variables)).await?;
+
+        Ok(result.add_certificate.certificate)
+    }
+
+    /// Remove a certificate from an app.
+    pub async fn delete_certificate(&self, app_name: &str, hostname: &str) -> Result<Value> {
+        let query = queries::DELETE_CERTIFICATE;
+
+        let variables = serde_json::json!({ "appId": app_name, "hostname": hostname });
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// Restart an app (restarts all machines).
+    pub async fn restart_app(&self, app_name: &str) -> Result<Value> {
+        let query = queries::RESTART_APP;
+
+        let variables = serde_json::json!({
+            "input": {
+                "appId": app_name
+            }
+        });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// Look up an organization's ID by slug.
+    pub async fn get_organization_id(&self, slug: &str) -> Result<String> {
+        let query = queries::GET_ORGANIZATION_ID;
+
+        #[derive(Deserialize)]
+        struct OrgResponse {
+            organization: Option<OrgNode>,
+        }
+
+        #[derive(Deserialize)]
+        struct OrgNode {
+            id: String,
+        }
+
+        let variables = serde_json::json!({ "slug": slug });
+        let result: OrgResponse = self.query(query, Some(variables)).await?;
+
+        result
+            .organization
+            .map(|o| o.id)
+            .ok_or_else(|| anyhow::anyhow!("Organization not found: {}", slug))
+    }
+
+    /// Create an app in an organization.
+    pub async fn create_app(
+        &self,
+        name: &str,
+        org_slug: &str,
+        region: Option<&str>,
+        network: Option<&str>,
+    ) -> Result<App> {
+        let org_id = self.get_organization_id(org_slug).await?;
+
+        let query = queries::CREATE_APP;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CreateResponse {
+            create_app: CreatePayload,
+        }
+
+        #[derive(Deserialize)]
+        struct CreatePayload {
+            app: App,
+        }
+
+        let mut input = serde_json::json!({
+            "organizationId": org_id,
+            "name": name,
+            "machines": true,
+        });
+        if let Some(region) = region {
+            input["preferredRegion"] = serde_json::json!(region);
+        }
+        if let Some(network) = network {
+            input["network"] = serde_json::json!(network);
+        }
+
+        let variables = serde_json::json!({ "input": input });
+        let result: CreateResponse = self.query(query, Some(variables)).await?;
+        Ok(result.create_app.app)
+    }
+
+    /// Delete an app and all of its resources.
+    pub async fn delete_app(&self, app_name: &str) -> Result<Value> {
+        let query = queries::DELETE_APP;
+
+        let variables = serde_json::json!({ "appId": app_name });
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// Move an app to another organization.
+    pub async fn move_app(&self, app_name: &str, org_slug: &str) -> Result<App> {
+        let org_id = self.get_organization_id(org_slug).await?;
+
+        let query = queries::MOVE_APP;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct MoveResponse {
+            move_app: MovePayload,
+        }
+
+        #[derive(Deserialize)]
+        struct MovePayload {
+            app: App,
+        }
+
+        let variables = serde_json::json!({
+            "input": {
+                "appId": app_name,
+                "organizationId": org_id
+            }
+        });
+        let result: MoveResponse = self.query(query, Some(variables)).await?;
+        Ok(result.move_app.app)
+    }
+
+    /// List every organization the viewer belongs to.
+    pub async fn list_organizations(&self) -> Result<Vec<OrganizationDetail>> {
+        collect_pages(|cursor| async move {
+            self.list_organizations_page(Some(PAGE_SIZE), cursor.as_deref())
+                .await
+        })
+        .await
+    }
+
+    /// List a page of organizations the viewer belongs to.
+    pub async fn list_organizations_page(
+        &self,
+        limit: Option<i32>,
+        after: Option<&str>,
+    ) -> Result<Page<OrganizationDetail>> {
+        let limit = limit.unwrap_or(25);
+
+        let query = queries::LIST_ORGANIZATIONS_PAGE;
+
+        #[derive(Deserialize)]
+        struct OrgsResponse {
+            organizations: OrgNodes,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct OrgNodes {
+            nodes: Vec<OrgNode>,
+            page_info: PageInfo,
+        }
+
+        let variables = serde_json::json!({ "first": limit, "after": after });
+        let result: OrgsResponse = self.query(query, Some(variables)).await?;
+        let next_cursor = result.organizations.page_info.next_cursor();
+
+        Ok(Page {
+            items: result
+                .organizations
+                .nodes
+                .into_iter()
+                .map(OrgNode::into_detail)
+                .collect(),
+            next_cursor,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Get an organization with its apps and members.
+    pub async fn get_organization(&self, slug: &str) -> Result<OrganizationDetail> {
+        let query = queries::GET_ORGANIZATION;
+
+        #[derive(Deserialize)]
+        struct OrgResponse {
+            organization: Option<OrgNode>,
+        }
+
+        let variables = serde_json::json!({ "slug": slug });
+        let result: OrgResponse = self.query(query, Some(variables)).await?;
+
+        result
+            .organization
+            .map(OrgNode::into_detail)
+            .ok_or_else(|| anyhow::anyhow!("Organization not found: {}", slug))
+    }
+
+    /// Get an organization's credit balance and current invoice.
+    ///
+    /// Fly only shows billing to org admins; for anyone else the billing
+    /// fields come back null with an error each, which is reported as an
+    /// auth error naming the caller's role.
+    pub async fn get_billing(&self, slug: &str) -> Result<BillingSummary> {
+        let query = queries::GET_BILLING;
+
+        #[derive(Deserialize)]
+        struct OrgResponse {
+            organization: Option<OrgNode>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct OrgNode {
+            slug: String,
+            #[serde(default)]
+            viewer_role: Option<String>,
+            #[serde(flatten)]
+            billing: Value,
+        }
+
+        let variables = serde_json::json!({ "slug": slug });
+        let (result, warnings): (OrgResponse, _) =
+            self.query_partial(query, Some(variables)).await?;
+        let org = result
+            .organization
+            .ok_or_else(|| FlyError::NotFound(format!("Organization not found: {}", slug)))?;
+
+        let denied = warnings.iter().any(|w| {
+            let message = w.message.to_lowercase();
+            matches!(w.code.as_deref(), Some("UNAUTHORIZED" | "FORBIDDEN"))
+                || message.contains("not authorized")
+                || message.contains("unauthorized")
+        });
+        if denied {
+            return Err(FlyError::Auth(format!(
+                "Billing for {} is only visible to organization admins (your role: {})",
+                org.slug,
+                org.viewer_role.as_deref().unwrap_or("unknown")
+            ))
+            .into());
+        }
+        report_warnings(warnings);
+
+        let mut billing = org.billing;
+        billing["org"] = Value::String(org.slug);
+        serde_json::from_value(billing).context("Failed to parse billing summary")
+    }
+
+    /// Get the slug of the organization that owns an app.
+    pub async fn get_app_org_slug(&self, app_name: &str) -> Result<String> {
+        let query = queries::GET_APP_ORG_SLUG;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: AppOrg,
+        }
+
+        #[derive(Deserialize)]
+        struct AppOrg {
+            organization: OrgSlug,
+        }
+
+        #[derive(Deserialize)]
+        struct OrgSlug {
+            slug: String,
+        }
+
+        let variables = serde_json::json!({ "name": app_name });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+        Ok(result.app.organization.slug)
+    }
+
+    /// List an app's most recent builds, newest first.
+    pub async fn list_builds(&self, app_name: &str, limit: Option<i32>) -> Result<Vec<Build>> {
+        let query = queries::LIST_BUILDS;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: AppBuilds,
+        }
+
+        #[derive(Deserialize)]
+        struct AppBuilds {
+            builds: BuildNodes,
+        }
+
+        #[derive(Deserialize)]
+        struct BuildNodes {
+            nodes: Vec<BuildNode>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct BuildNode {
+            id: String,
+            status: String,
+            #[serde(default)]
+            image: Option<String>,
+            #[serde(default)]
+            logs_url: Option<String>,
+            #[serde(default)]
+            in_progress: bool,
+            #[serde(default)]
+            created_by: Option<UserNode>,
+            #[serde(default)]
+            created_at: Option<String>,
+            #[serde(default)]
+            updated_at: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        struct UserNode {
+            #[serde(default)]
+            email: Option<String>,
+        }
+
+        let variables = serde_json::json!({ "name": app_name, "first": limit.unwrap_or(10) });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        Ok(result
+            .app
+            .builds
+            .nodes
+            .into_iter()
+            .map(|n| {
+                let duration_secs = match (&n.created_at, &n.updated_at) {
+                    (Some(created), Some(updated)) if !n.in_progress => {
+                        let created = chrono::DateTime::parse_from_rfc3339(created).ok();
+                        let updated = chrono::DateTime::parse_from_rfc3339(updated).ok();
+                        created.zip(updated).map(|(c, u)| (u - c).num_seconds())
+                    }
+                    _ => None,
+                };
+                Build {
+                    id: n.id,
+                    status: n.status,
+                    image: n.image,
+                    logs_url: n.logs_url,
+                    in_progress: n.in_progress,
+                    user: n.created_by.and_then(|u| u.email),
+                    created_at: n.created_at,
+                    updated_at: n.updated_at,
+                    duration_secs,
+                }
+            })
+            .collect())
+    }
+
+    /// An organization's remote builder app and image.
+    pub async fn get_remote_builder(&self, org_slug: &str) -> Result<RemoteBuilder> {
+        let query = queries::GET_REMOTE_BUILDER;
+
+        #[derive(Deserialize)]
+        struct OrgResponse {
+            organization: Option<OrgBuilder>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct OrgBuilder {
+            #[serde(default)]
+            remote_builder_image: Option<String>,
+            #[serde(default)]
+            remote_builder_app: Option<AppName>,
+        }
+
+        #[derive(Deserialize)]
+        struct AppName {
+            name: String,
+        }
+
+        let variables = serde_json::json!({ "slug": org_slug });
+        let result: OrgResponse = self.query(query, Some(variables)).await?;
+        let org = result
+            .organization
+            .ok_or_else(|| anyhow::anyhow!("Organization not found: {}", org_slug))?;
+
+        Ok(RemoteBuilder {
+            app: org.remote_builder_app.map(|a| a.name),
+            image: org.remote_builder_image,
+        })
+    }
+
+    /// Latest image pushed for an app, if any.
+    pub async fn get_latest_image(&self, app_name: &str) -> Result<Option<ImageRef>> {
+        let query = queries::GET_LATEST_IMAGE;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: AppImage,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AppImage {
+            latest_image_details: Option<ImageRef>,
+        }
+
+        let variables = serde_json::json!({ "name": app_name });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+        Ok(result.app.latest_image_details)
+    }
+
+    /// List the deploy tokens issued for an app, or for an org when `app_name` is `None`.
+    pub async fn list_deploy_tokens(
+        &self,
+        app_name: Option<&str>,
+        org_slug: Option<&str>,
+    ) -> Result<Vec<DeployToken>> {
+        #[derive(Deserialize)]
+        struct TokensResponse {
+            #[serde(rename = "app", alias = "organization")]
+            owner: Option<TokenOwner>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TokenOwner {
+            limited_access_tokens: TokenNodes,
+        }
+
+        #[derive(Deserialize)]
+        struct TokenNodes {
+            nodes: Vec<DeployToken>,
+        }
+
+        let (query, variables, owner) = match (app_name, org_slug) {
+            (Some(app), _) => (
+                r#"
+                    query($name: String!) {
+                        app(name: $name) {
+                            limitedAccessTokens {
+                                nodes { id name expiresAt }
+                            }
+                        }
+                    }
+                "#,
+                serde_json::json!({ "name": app }),
+                app,
+            ),
+            (None, Some(org)) => (
+                r#"
+                    query($slug: String!) {
+                        organization(slug: $slug) {
+                            limitedAccessTokens {
+                                nodes { id name expiresAt }
+                            }
+                        }
+                    }
+                "#,
+                serde_json::json!({ "slug": org }),
+                org,
+            ),
+            (None, None) => anyhow::bail!("Listing tokens requires an app or org"),
+        };
+
+        let result: TokensResponse = self.query(query, Some(variables)).await?;
+        result
+            .owner
+            .map(|o| o.limited_access_tokens.nodes)
+            .ok_or_else(|| anyhow::anyhow!("Token owner not found: {}", owner))
+    }
+
+    /// Create a deploy token scoped to an app, or to a whole org when `app_name` is `None`.
+    ///
+    /// Returns the token metadata and the `FlyV1 ...` secret.
+    pub async fn create_deploy_token(
+        &self,
+        app_name: Option<&str>,
+        org_slug: Option<&str>,
+        name: &str,
+        expiry: Option<&str>,
+    ) -> Result<(DeployToken, String)> {
+        let (organization_id, profile, profile_params) = match (app_name, org_slug) {
+            (Some(app), _) => {
+                let (app_id, org_id) = self.get_app_ids(app).await?;
+                (org_id, "deploy", serde_json::json!({ "appId": app_id }))
+            }
+            (None, Some(org)) => (self.get_organization_id(org).await?, "org", Value::Null),
+            (None, None) => anyhow::bail!("Creating a token requires an app or org"),
+        };
+
+        let query = queries::CREATE_DEPLOY_TOKEN;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CreateResponse {
+            create_limited_access_token: CreatePayload,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CreatePayload {
+            limited_access_token: CreatedToken,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CreatedToken {
+            #[serde(flatten)]
+            token: DeployToken,
+            token_header: String,
+        }
+
+        let variables = serde_json::json!({
+            "input": {
+                "name": name,
+                "organizationId": organization_id,
+                "profile": profile,
+                "profileParams": profile_params,
+                "expiry": expiry
+            }
+        });
+
+        let result: CreateResponse = self.query(query, Some(variables)).await?;
+        let created = result.create_limited_access_token.limited_access_token;
+        Ok((created.token, created.token_header))
+    }
+
+    /// Revoke a deploy token by ID.
+    pub async fn revoke_deploy_token(&self, id: &str) -> Result<Value> {
+        let query = queries::REVOKE_DEPLOY_TOKEN;
+
+        let variables = serde_json::json!({ "input": { "id": id } });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// List WireGuard peers in an organization.
+    pub async fn list_wireguard_peers(&self, org_slug: &str) -> Result<Vec<WireGuardPeer>> {
+        let query = queries::LIST_WIREGUARD_PEERS;
+
+        #[derive(Deserialize)]
+        struct OrgResponse {
+            organization: Option<OrgPeers>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct OrgPeers {
+            wire_guard_peers: PeerNodes,
+        }
+
+        #[derive(Deserialize)]
+        struct PeerNodes {
+            nodes: Vec<WireGuardPeer>,
+        }
+
+        let variables = serde_json::json!({ "slug": org_slug });
+        let result: OrgResponse = self.query(query, Some(variables)).await?;
+
+        result
+            .organization
+            .map(|o| o.wire_guard_peers.nodes)
+            .ok_or_else(|| anyhow::anyhow!("Organization not found: {}", org_slug))
+    }
+
+    /// Register a WireGuard peer with `pubkey` in an organization.
+    pub async fn add_wireguard_peer(
+        &self,
+        org_slug: &str,
+        name: &str,
+        region: &str,
+        pubkey: &str,
+        network: Option<&str>,
+    ) -> Result<WireGuardPeerCreated> {
+        let organization_id = self.get_organization_id(org_slug).await?;
+        let query = queries::ADD_WIREGUARD_PEER;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AddResponse {
+            add_wire_guard_peer: WireGuardPeerCreated,
+        }
+
+        let variables = serde_json::json!({
+            "input": {
+                "organizationId": organization_id,
+                "name": name,
+                "region": region,
+                "pubkey": pubkey,
+                "network": network
+            }
+        });
+
+        let result: AddResponse = self.query(query, Some(variables)).await?;
+        Ok(result.add_wire_guard_peer)
+    }
+
+    /// Remove a WireGuard peer by name.
+    pub async fn remove_wireguard_peer(&self, org_slug: &str, name: &str) -> Result<Value> {
+        let organization_id = self.get_organization_id(org_slug).await?;
+        let query = queries::REMOVE_WIREGUARD_PEER;
+
+        let variables = serde_json::json!({
+            "input": { "organizationId": organization_id, "name": name }
+        });
+
+        let result: Value = self.query(query, Some(variables)).await?;
+        Ok(result)
+    }
+
+    /// Internal IDs of an app and its organization.
+    async fn get_app_ids(&self, app_name: &str) -> Result<(String, String)> {
+        let query = queries::GET_APP_IDS;
+
+        #[derive(Deserialize)]
+        struct AppResponse {
+            app: Option<AppNode>,
+        }
+
+        #[derive(Deserialize)]
+        struct AppNode {
+            id: String,
+            organization: OrgId,
+        }
+
+        #[derive(Deserialize)]
+        struct OrgId {
+            id: String,
+        }
+
+        let variables = serde_json::json!({ "name": app_name });
+        let result: AppResponse = self.query(query, Some(variables)).await?;
+
+        result
+            .app
+            .map(|a| (a.id, a.organization.id))
+            .ok_or_else(|| anyhow::anyhow!("App not found: {}", app_name))
+    }
+
+    /// List Fly Postgres cluster apps visible to the token.
+    pub async fn list_postgres_clusters(&self) -> Result<Vec<App>> {
+        let query = queries::LIST_POSTGRES_CLUSTERS;
+
+        #[derive(Deserialize)]
+        struct AppsResponse {
+            apps: AppsNodes,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AppsNodes {
+            nodes: Vec<Option<App>>,
+            page_info: PageInfo,
+        }
+
+        collect_pages(|cursor| async move {
+            let variables = serde_json::json!({ "after": cursor });
+            let result: AppsResponse = self.query(query, Some(variables)).await?;
+            Ok(Page {
+                items: result.apps.nodes.into_iter().flatten().collect(),
+                next_cursor: result.apps.page_info.next_cursor(),
+                warnings: Vec::new(),
+            })
+        })
+        .await
+    }
+}
 
 #[derive(Serialize)]
 struct GraphQLRequest {
-    query: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     variables: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions: Option<Value>,
+}
+
+/// Why a persisted query hash was rejected.
+enum PersistedQueryMiss {
+    /// The server hasn't seen the document yet.
+    NotFound,
+    NotSupported,
+}
+
+/// Whether a response rejected a persisted query hash.
+fn persisted_query_miss(text: &str) -> Option<PersistedQueryMiss> {
+    #[derive(Deserialize)]
+    struct Errors {
+        #[serde(default)]
+        errors: Vec<GraphQLError>,
+    }
+
+    let errors = serde_json::from_str::<Errors>(text).ok()?.errors;
+    errors.iter().find_map(|e| {
+        let code = e
+            .extensions
+            .as_ref()
+            .and_then(|x| x.get("code"))
+            .and_then(|c| c.as_str());
+        match (code, e.message.as_str()) {
+            (Some("PERSISTED_QUERY_NOT_FOUND"), _) | (_, "PersistedQueryNotFound") => {
+                Some(PersistedQueryMiss::NotFound)
+            }
+            (Some("PERSISTED_QUERY_NOT_SUPPORTED"), _) | (_, "PersistedQueryNotSupported") => {
+                Some(PersistedQueryMiss::NotSupported)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Organization node shared by the list and detail queries.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgNode {
+    id: String,
+    name: String,
+    slug: String,
+    #[serde(rename = "type", default)]
+    org_type: String,
+    #[serde(default)]
+    billing_status: Option<String>,
+    #[serde(default)]
+    viewer_role: Option<String>,
+    members: OrgMembers,
+    apps: OrgApps,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgMembers {
+    total_count: usize,
+    #[serde(default)]
+    edges: Vec<OrgMemberEdge>,
+}
+
+#[derive(Deserialize)]
+struct OrgMemberEdge {
+    role: String,
+    node: OrgMemberNode,
+}
+
+#[derive(Deserialize)]
+struct OrgMemberNode {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrgApps {
+    total_count: usize,
+    #[serde(default)]
+    nodes: Vec<App>,
+}
+
+impl OrgNode {
+    fn into_detail(self) -> OrganizationDetail {
+        OrganizationDetail {
+            id: self.id,
+            name: self.name,
+            slug: self.slug,
+            org_type: self.org_type,
+            billing_status: self.billing_status,
+            viewer_role: self.viewer_role,
+            member_count: self.members.total_count,
+            app_count: self.apps.total_count,
+            apps: self.apps.nodes,
+            members: self
+                .members
+                .edges
+                .into_iter()
+                .map(|e| OrganizationMember {
+                    id: e.node.id,
+                    name: e.node.name,
+                    email: e.node.email,
+                    role: e.role,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Parse a `Retry-After` header given in seconds.
+fn parse_retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|secs| Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+/// Fetch every page of a connection by following `next_cursor`.
+///
+/// Page warnings, including a `truncated` stop, go to the call's `warnings`.
+pub async fn collect_pages<T, F, Fut>(fetch: F) -> Result<Vec<T>>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Page<T>>>,
+{
+    let all = collect_all(fetch).await?;
+    report_warnings(all.warnings);
+    Ok(all.items)
+}
+
+/// Like `collect_pages`, but keep every page's warnings in one final page.
+///
+/// Stops with a warning, rather than looping, if the API hands back a
+/// cursor it already gave or there are more than `MAX_PAGES` pages.
+pub async fn collect_all<T, F, Fut>(mut fetch: F) -> Result<Page<T>>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Page<T>>>,
+{
+    let mut all = Page {
+        items: Vec::new(),
+        next_cursor: None,
+        warnings: Vec::new(),
+    };
+    let mut cursor = None;
+    let mut seen = HashSet::new();
+
+    for _ in 0..MAX_PAGES {
+        let page = fetch(cursor.take()).await?;
+        all.items.extend(page.items);
+        all.warnings.extend(page.warnings);
+
+        let Some(next) = page.next_cursor else {
+            return Ok(all);
+        };
+        if !seen.insert(next.clone()) {
+            all.warnings.push(Warning {
+                message: format!("Stopped paging: cursor {:?} was returned twice", next),
+                path: None,
+                code: Some("truncated".into()),
+            });
+            return Ok(all);
+        }
+        cursor = Some(next);
+    }
+
+    all.warnings.push(Warning {
+        message: format!("Stopped paging after {} pages", MAX_PAGES),
+        path: None,
+        code: Some("truncated".into()),
+    });
+    all.next_cursor = cursor;
+    Ok(all)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(item: u32, next: Option<&str>) -> Result<Page<u32>> {
+        Ok(Page {
+            items: vec![item],
+            next_cursor: next.map(String::from),
+            warnings: Vec::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn paging_follows_cursors_to_the_end() {
+        let all = collect_all(|cursor| async move {
+            match cursor.as_deref() {
+                None => page(1, Some("a")),
+                Some("a") => page(2, Some("b")),
+                _ => page(3, None),
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(all.items, [1, 2, 3]);
+        assert!(all.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn paging_stops_when_a_cursor_repeats() {
+        let all = collect_all(|cursor| async move {
+            match cursor.as_deref() {
+                None => page(1, Some("a")),
+                _ => page(2, Some("a")),
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(all.items, [1, 2]);
+        assert_eq!(all.warnings[0].code.as_deref(), Some("truncated"));
+    }
+
+    #[tokio::test]
+    async fn paging_stops_after_the_page_limit() {
+        let mut n = 0;
+        let all = collect_all(|_| {
+            n += 1;
+            let next = n.to_string();
+            async move { page(1, Some(&next)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(all.items.len(), MAX_PAGES);
+        assert_eq!(
+            all.next_cursor.as_deref(),
+            Some(MAX_PAGES.to_string().as_str())
+        );
+        assert_eq!(all.warnings[0].code.as_deref(), Some("truncated"));
+    }
+
+    #[tokio::test]
+    async fn collect_pages_reports_a_truncated_listing() {
+        let (items, warnings) = crate::api::with_warnings(collect_pages(|cursor| async move {
+            match cursor.as_deref() {
+                None => page(1, Some("a")),
+                _ => page(2, Some("a")),
+            }
+        }))
+        .await;
+        assert_eq!(items.unwrap(), [1, 2]);
+        assert_eq!(warnings[0].code.as_deref(), Some("truncated"));
+    }
 }
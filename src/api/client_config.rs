@@ -0,0 +1,228 @@
+//! Endpoints and HTTP settings shared by the API clients.
+//!
+//! Defaults point at Fly.io's public APIs. Each setting can be overridden
+//! with an env var to target a mock server or go through a corporate proxy:
+//!
+//! - `FGP_FLY_GRAPHQL_URL`: GraphQL endpoint
+//! - `FGP_FLY_API_URL`: REST base for logs and Prometheus
+//! - `FGP_FLY_MACHINES_URL`: Machines API base
+//! - `FGP_FLY_FLAPS_URL` (or flyctl's `FLY_FLAPS_BASE_URL`): Machines API
+//!   host to use instead, e.g. `http://_api.internal:4280` inside a Fly
+//!   private network
+//! - `FGP_FLY_PROXY`: proxy for every request (`HTTPS_PROXY` and friends are
+//!   honored without it)
+//! - `FGP_FLY_CA_CERT`: PEM file with extra root certificates to trust
+//! - `FGP_FLY_TIMEOUT_MS`: default request timeout
+//! - `FGP_FLY_PERSISTED_QUERIES`: send GraphQL persisted query hashes
+//!   instead of full documents where the server supports them
+//!
+//! Connection tuning, for workloads that poll often:
+//!
+//! - `FGP_FLY_HTTP2_PRIOR_KNOWLEDGE`: speak HTTP/2 from the first byte,
+//!   skipping ALPN; only for endpoints known to support it
+//! - `FGP_FLY_TCP_KEEPALIVE_MS`: TCP keepalive interval
+//! - `FGP_FLY_POOL_IDLE_TIMEOUT_MS`: how long an idle connection is kept
+//! - `FGP_FLY_POOL_MAX_IDLE_PER_HOST`: idle connections kept per host
+//!
+//! `FGP_FLY_EXTRA_HEADERS` adds headers to every request, as `Name: value`
+//! pairs separated by `;`. `FGP_FLY_FIXTURE_MODE` records responses to, or
+//! replays them from, `FGP_FLY_FIXTURE_DIR` (see `recording`). Other request
+//! hooks (`middleware`) can only be added in code.
+
+use anyhow::{Context, Result};
+use reqwest::{Certificate, Client, Proxy};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::middleware::{ExtraHeaders, Middleware, Middlewares};
+use super::recording::{self, FixtureMode, Recorder, Replayer};
+use super::stats;
+use super::timeout::DEFAULT_REQUEST_TIMEOUT;
+
+const DEFAULT_GRAPHQL_URL: &str = "https://api.fly.io/graphql";
+const DEFAULT_API_URL: &str = "https://api.fly.io";
+const DEFAULT_MACHINES_URL: &str = "https://api.machines.dev/v1";
+
+/// Where the API clients connect and how.
+#[derive(Debug, Clone)]
+pub struct FlyClientConfig {
+    pub graphql_url: String,
+    /// Base of the REST endpoints (`/api/v1/apps/.../logs`, `/prometheus`).
+    pub api_url: String,
+    /// Machines API base, including the `/v1` version prefix.
+    pub machines_url: String,
+    /// Machines API (flaps) host that replaces `machines_url` when set.
+    pub flaps_url: Option<String>,
+    pub proxy: Option<String>,
+    /// PEM-encoded certificates added to the trusted roots.
+    pub ca_cert_pem: Option<Vec<u8>>,
+    /// Default timeout for every request.
+    pub timeout: Duration,
+    /// Try persisted query hashes before full GraphQL documents.
+    pub persisted_queries: bool,
+    /// Use HTTP/2 without negotiating it first.
+    pub http2_prior_knowledge: bool,
+    /// TCP keepalive interval; reqwest's default when unset.
+    pub tcp_keepalive: Option<Duration>,
+    /// Idle time before a pooled connection is closed; reqwest's default
+    /// (90s) when unset.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Idle connections kept open per host; 0 disables pooling.
+    pub pool_max_idle_per_host: usize,
+    /// Hooks run around every request, in order.
+    pub middleware: Middlewares,
+}
+
+impl Default for FlyClientConfig {
+    fn default() -> Self {
+        Self {
+            graphql_url: DEFAULT_GRAPHQL_URL.to_string(),
+            api_url: DEFAULT_API_URL.to_string(),
+            machines_url: DEFAULT_MACHINES_URL.to_string(),
+            flaps_url: None,
+            proxy: None,
+            ca_cert_pem: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            persisted_queries: false,
+            http2_prior_knowledge: false,
+            tcp_keepalive: None,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: stats::POOL_MAX_IDLE_PER_HOST,
+            middleware: Middlewares::default(),
+        }
+    }
+}
+
+impl FlyClientConfig {
+    /// Build a config from `FGP_FLY_*` env vars, falling back to defaults.
+    ///
+    /// The CA file is read here so a daemon that later changes directory
+    /// still sees it.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+
+        if let Some(url) = env_url("FGP_FLY_GRAPHQL_URL") {
+            config.graphql_url = url;
+        }
+        if let Some(url) = env_url("FGP_FLY_API_URL") {
+            config.api_url = url;
+        }
+        if let Some(url) = env_url("FGP_FLY_MACHINES_URL") {
+            config.machines_url = url;
+        }
+        config.flaps_url = env_url("FGP_FLY_FLAPS_URL").or_else(|| env_url("FLY_FLAPS_BASE_URL"));
+        config.proxy = env_url("FGP_FLY_PROXY");
+
+        if let Some(path) = env_url("FGP_FLY_CA_CERT") {
+            let path = shellexpand::tilde(&path).to_string();
+            let pem = std::fs::read(&path)
+                .with_context(|| format!("Failed to read FGP_FLY_CA_CERT {}", path))?;
+            Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Invalid PEM certificates in {}", path))?;
+            config.ca_cert_pem = Some(pem);
+        }
+
+        if let Some(timeout) = env_duration("FGP_FLY_TIMEOUT_MS")? {
+            config.timeout = timeout;
+        }
+
+        config.persisted_queries = env_flag("FGP_FLY_PERSISTED_QUERIES");
+        config.http2_prior_knowledge = env_flag("FGP_FLY_HTTP2_PRIOR_KNOWLEDGE");
+        config.tcp_keepalive = env_duration("FGP_FLY_TCP_KEEPALIVE_MS")?;
+        config.pool_idle_timeout = env_duration("FGP_FLY_POOL_IDLE_TIMEOUT_MS")?;
+        if let Ok(max) = std::env::var("FGP_FLY_POOL_MAX_IDLE_PER_HOST") {
+            config.pool_max_idle_per_host = max
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid FGP_FLY_POOL_MAX_IDLE_PER_HOST: {}", max))?;
+        }
+
+        if let Ok(spec) = std::env::var("FGP_FLY_EXTRA_HEADERS") {
+            let headers = ExtraHeaders::parse(&spec).context("Invalid FGP_FLY_EXTRA_HEADERS")?;
+            config = config.with_middleware(Arc::new(headers));
+        }
+
+        let fixture_dir = recording::fixture_dir_from_env();
+        match FixtureMode::from_env()? {
+            Some(FixtureMode::Record) => {
+                config = config.with_middleware(Arc::new(Recorder::new(fixture_dir)));
+            }
+            Some(FixtureMode::Replay) => {
+                config = config.with_middleware(Arc::new(Replayer::new(fixture_dir)));
+            }
+            None => {}
+        }
+
+        Ok(config)
+    }
+
+    /// Add a hook run around every request, after those already added.
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Machines API base URL, preferring `flaps_url`.
+    pub fn machines_base(&self) -> String {
+        match &self.flaps_url {
+            Some(flaps) => format!("{}/v1", flaps),
+            None => self.machines_url.clone(),
+        }
+    }
+
+    /// Build an HTTP client with the proxy, roots and timeout applied.
+    pub(super) fn http_client(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .timeout(self.timeout);
+        stats::set_pool_max_idle_per_host(self.pool_max_idle_per_host);
+
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(interval) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(interval);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder
+                .proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy URL: {}", proxy))?);
+        }
+        if let Some(pem) = &self.ca_cert_pem {
+            for cert in Certificate::from_pem_bundle(pem).context("Invalid CA certificates")? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        builder.build().context("Failed to build HTTP client")
+    }
+}
+
+/// Whether a boolean env var is set to `1`, `true` or `yes`.
+fn env_flag(key: &str) -> bool {
+    matches!(
+        std::env::var(key).as_deref(),
+        Ok("1") | Ok("true") | Ok("yes")
+    )
+}
+
+/// Positive millisecond duration from an env var.
+fn env_duration(key: &str) -> Result<Option<Duration>> {
+    let Ok(ms) = std::env::var(key) else {
+        return Ok(None);
+    };
+    let parsed = ms.trim().parse::<u64>().ok().filter(|ms| *ms > 0);
+    let ms = parsed.with_context(|| format!("Invalid {}: {}", key, ms))?;
+    Ok(Some(Duration::from_millis(ms)))
+}
+
+/// Non-empty env var with any trailing slash removed.
+fn env_url(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|v| v.trim().trim_end_matches('/').to_string())
+        .filter(|v| !v.is_empty())
+}
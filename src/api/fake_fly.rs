@@ -0,0 +1,545 @@
+//! Fly API stand-in on a local wiremock server (feature `test-support`).
+//!
+//! One server plays every API the clients talk to: GraphQL at `/graphql`,
+//! the Machines API under `/v1` and the REST endpoints (logs, Prometheus)
+//! at its root. `start` answers them for the `fixtures` app `web` in the
+//! `acme` org: GraphQL documents get the fixture graph for their root field
+//! and keep the fields they select, Machines API reads get its machines and
+//! volume, and every mutation and Machines API write succeeds. Responses a
+//! test mounts itself win over the fixtures:
+//!
+//! ```ignore
+//! let fly = FakeFly::start();
+//! fly.graphql_error("setSecrets", "Validation failed: value is too long");
+//! let service = FlyService::in_memory(config_for(&fly))?;
+//! ```
+//!
+//! The server runs on its own thread and mounts go through a private
+//! runtime, so synchronous tests can drive a `FlyService`, which blocks on
+//! every call.
+
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+use wiremock::matchers::{body_string_contains, method, path, path_regex};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use super::client_config::FlyClientConfig;
+use super::fixtures::{self, APP, ORG};
+
+/// Priority of the fixture mocks; mocks mounted by tests (priority 5 by
+/// default) take precedence.
+const FIXTURE_PRIORITY: u8 = 10;
+
+/// The started machine in the fixtures.
+pub const MACHINE_ID: &str = "148e21ea7d5e89";
+/// The stopped machine in the fixtures.
+pub const STOPPED_MACHINE_ID: &str = "3d8d9e1b2c4f67";
+/// The machine the Machines API creates.
+pub const NEW_MACHINE_ID: &str = "e784079b449483";
+/// The fixture app's volume.
+pub const VOLUME_ID: &str = "vol_4d8e2a7c1b3f";
+
+/// A wiremock server answering as the Fly APIs.
+pub struct FakeFly {
+    server: MockServer,
+    runtime: Runtime,
+}
+
+impl FakeFly {
+    /// Start a server with the fixtures mounted.
+    pub fn start() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime");
+        let server = runtime.block_on(MockServer::start());
+        let fly = Self { server, runtime };
+        fly.mount_fixtures();
+        fly
+    }
+
+    /// Client settings pointing every API at this server.
+    pub fn client_config(&self) -> FlyClientConfig {
+        FlyClientConfig {
+            graphql_url: format!("{}/graphql", self.server.uri()),
+            api_url: self.server.uri(),
+            machines_url: format!("{}/v1", self.server.uri()),
+            ..FlyClientConfig::default()
+        }
+    }
+
+    /// Mount `mock` ahead of the fixtures.
+    pub fn mount(&self, mock: Mock) {
+        self.runtime.block_on(mock.mount(&self.server));
+    }
+
+    /// Answer GraphQL documents that contain `field` with `data`.
+    pub fn graphql(&self, field: &str, data: Value) {
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/graphql"))
+                .and(body_string_contains(field))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": data }))),
+        );
+    }
+
+    /// Fail GraphQL documents that contain `field` with `message`, as a
+    /// GraphQL error with no data.
+    pub fn graphql_error(&self, field: &str, message: &str) {
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/graphql"))
+                .and(body_string_contains(field))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "data": null,
+                    "errors": [{ "message": message }]
+                }))),
+        );
+    }
+
+    /// Answer `verb` requests to `path` with `status` and a JSON `body`.
+    pub fn respond(&self, verb: &str, request_path: &str, status: u16, body: Value) {
+        self.mount(
+            Mock::given(method(verb))
+                .and(path(request_path))
+                .respond_with(ResponseTemplate::new(status).set_body_json(body)),
+        );
+    }
+
+    /// Requests received so far with `verb` and `path`.
+    pub fn requests(&self, verb: &str, request_path: &str) -> Vec<Request> {
+        self.received()
+            .into_iter()
+            .filter(|r| r.method.as_str() == verb && r.url.path() == request_path)
+            .collect()
+    }
+
+    /// GraphQL requests received so far whose document contains `field`.
+    pub fn graphql_requests(&self, field: &str) -> Vec<Value> {
+        self.requests("POST", "/graphql")
+            .into_iter()
+            .filter_map(|r| serde_json::from_slice::<Value>(&r.body).ok())
+            .filter(|body| body["query"].as_str().is_some_and(|q| q.contains(field)))
+            .collect()
+    }
+
+    fn received(&self) -> Vec<Request> {
+        self.runtime
+            .block_on(self.server.received_requests())
+            .unwrap_or_default()
+    }
+
+    fn fixture(&self, mock: Mock) {
+        self.mount(mock.with_priority(FIXTURE_PRIORITY));
+    }
+
+    fn mount_fixtures(&self) {
+        let graph = [
+            ("viewer{", json!({ "viewer": viewer() })),
+            ("apps(first:", json!({ "apps": connection(vec![app()]) })),
+            ("apps(role:", json!({ "apps": connection(Vec::new()) })),
+            ("app(name:", json!({ "app": app() })),
+            (
+                "organizations(first:",
+                json!({ "organizations": connection(vec![organization()]) }),
+            ),
+            (
+                "organization(slug:",
+                json!({ "organization": organization() }),
+            ),
+            ("platform{", json!({ "platform": platform() })),
+        ];
+        let release = json!({ "release": fixtures::release() });
+        let certificate = json!({ "certificate": certificate() });
+        let mutations = [
+            ("{setSecrets(", release.clone()),
+            ("{unsetSecrets(", release),
+            (
+                "{allocateIpAddress(",
+                json!({ "ipAddress": ip_address(), "app": { "sharedIpAddress": null } }),
+            ),
+            ("{releaseIpAddress(", json!({ "app": { "name": APP } })),
+            ("{addCertificate(", certificate),
+            (
+                "{deleteCertificate(",
+                json!({ "app": { "name": APP }, "certificate": { "id": "cert_www", "hostname": "www.acme.test" } }),
+            ),
+            ("{restartApp(", json!({ "app": app() })),
+            ("{createApp(", json!({ "app": app() })),
+            (
+                "{deleteApp(",
+                json!({ "organization": fixtures::organization() }),
+            ),
+            ("{moveApp(", json!({ "app": app() })),
+            (
+                "{createLimitedAccessToken(",
+                json!({ "limitedAccessToken": {
+                    "id": "tok_deploy",
+                    "name": "ci",
+                    "expiresAt": "2027-10-01T00:00:00Z",
+                    "tokenHeader": "FlyV1 fm2_secret"
+                } }),
+            ),
+            (
+                "{deleteLimitedAccessToken(",
+                json!({ "token": "tok_deploy" }),
+            ),
+            (
+                "{addWireGuardPeer(",
+                json!({
+                    "network": "default",
+                    "endpointip": "203.0.113.10",
+                    "peerip": "fdaa:0:1:a7b:8c::2",
+                    "pubkey": "c2VydmVyLXB1YmxpYy1rZXk="
+                }),
+            ),
+            (
+                "{removeWireGuardPeer(",
+                json!({ "organization": { "id": "org_acme" } }),
+            ),
+        ];
+        for (field, data) in graph {
+            self.fixture(
+                Mock::given(method("POST"))
+                    .and(path("/graphql"))
+                    .and(body_string_contains(field))
+                    .respond_with(
+                        ResponseTemplate::new(200).set_body_json(json!({ "data": data })),
+                    ),
+            );
+        }
+        for (field, payload) in mutations {
+            let name = field.trim_matches(|c| c == '{' || c == '(');
+            self.fixture(
+                Mock::given(method("POST"))
+                    .and(path("/graphql"))
+                    .and(body_string_contains(field))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({ "data": { name: payload } })),
+                    ),
+            );
+        }
+
+        let machines = fixtures::machines();
+        let reads = [
+            (format!("/v1/apps/{}/machines", APP), machines.clone()),
+            (format!("/v1/apps/{}/volumes", APP), json!([volume()])),
+            (
+                format!("/api/v1/apps/{}/logs", APP),
+                json!({ "data": logs(), "meta": { "next_token": "tok_2" } }),
+            ),
+            (
+                format!("/prometheus/{}/api/v1/query_range", ORG),
+                prometheus(),
+            ),
+        ];
+        for (request_path, body) in reads {
+            self.fixture(
+                Mock::given(method("GET"))
+                    .and(path(request_path))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(body)),
+            );
+        }
+
+        let ok = json!({ "ok": true });
+        let mut writes = vec![
+            (
+                "POST",
+                format!("/v1/apps/{}/machines", APP),
+                created_machine(),
+            ),
+            ("POST", format!("/v1/apps/{}/volumes", APP), volume()),
+            (
+                "PUT",
+                format!("/v1/apps/{}/volumes/{}/extend", APP, VOLUME_ID),
+                json!({ "volume": volume(), "needs_restart": true }),
+            ),
+            (
+                "DELETE",
+                format!("/v1/apps/{}/volumes/{}", APP, VOLUME_ID),
+                volume(),
+            ),
+            (
+                "GET",
+                format!("/v1/apps/{}/volumes/{}/snapshots", APP, VOLUME_ID),
+                json!([{
+                    "id": "vs_9kQ2",
+                    "size": 1_073_741_824u64,
+                    "digest": "c2FtcGxlLWRpZ2VzdA==",
+                    "status": "created",
+                    "created_at": "2026-10-01T00:00:00Z"
+                }]),
+            ),
+        ];
+        let mut known = machines.as_array().cloned().unwrap_or_default();
+        known.push(created_machine());
+        for machine in &known {
+            let id = machine["id"].as_str().unwrap_or_default();
+            let base = format!("/v1/apps/{}/machines/{}", APP, id);
+            writes.extend([
+                ("GET", base.clone(), machine.clone()),
+                ("POST", base.clone(), machine.clone()),
+                ("DELETE", base.clone(), ok.clone()),
+                ("GET", format!("{}/wait", base), ok.clone()),
+                ("GET", format!("{}/events", base), events()),
+                (
+                    "GET",
+                    format!("{}/metadata", base),
+                    machine["config"]["metadata"].clone(),
+                ),
+                (
+                    "POST",
+                    format!("{}/exec", base),
+                    json!({ "exit_code": 0, "stdout": "ok\n", "stderr": "" }),
+                ),
+                ("GET", format!("{}/lease", base), lease()),
+                ("POST", format!("{}/lease", base), lease()),
+                ("DELETE", format!("{}/lease", base), ok.clone()),
+            ]);
+            for action in ["start", "stop", "signal", "restart", "cordon", "uncordon"] {
+                writes.push(("POST", format!("{}/{}", base, action), ok.clone()));
+            }
+        }
+        for (verb, request_path, body) in writes {
+            self.fixture(
+                Mock::given(method(verb))
+                    .and(path(request_path))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(body)),
+            );
+        }
+        // Metadata keys are free-form
+        for verb in ["POST", "DELETE"] {
+            self.fixture(
+                Mock::given(method(verb))
+                    .and(path_regex(format!(
+                        r"^/v1/apps/{}/machines/[0-9a-f]+/metadata/[^/]+$",
+                        APP
+                    )))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(ok.clone())),
+            );
+        }
+    }
+}
+
+/// A GraphQL connection with one page of `nodes`.
+fn connection(nodes: Vec<Value>) -> Value {
+    json!({
+        "totalCount": nodes.len(),
+        "nodes": nodes,
+        "pageInfo": { "hasNextPage": false, "endCursor": null }
+    })
+}
+
+fn viewer() -> Value {
+    let user = fixtures::user();
+    json!({
+        "id": user["id"],
+        "email": user["email"],
+        "name": user["name"],
+        "organizations": { "nodes": user["organizations"] }
+    })
+}
+
+/// Everything a document selecting `app(name:)` may ask for.
+fn app() -> Value {
+    let mut app = fixtures::app();
+    let machines = fixtures::machines();
+    app["machines"] = connection(machines.as_array().cloned().unwrap_or_default());
+    app["allocations"] = json!([]);
+    app["releases"] = connection(vec![json!({
+        "id": "rel_12",
+        "version": 12,
+        "status": "complete",
+        "description": "Deploy image",
+        "user": { "email": "dev@acme.test" },
+        "createdAt": "2026-10-01T12:00:00Z"
+    })]);
+    app["secrets"] = fixtures::secrets();
+    app["ipAddresses"] = connection(vec![ip_address()]);
+    app["certificates"] = connection(vec![certificate()]);
+    app["certificate"] = certificate();
+    app["builds"] = connection(vec![json!({
+        "id": "build_7",
+        "status": "succeeded",
+        "image": "registry.fly.io/web:deployment-01HQ8Z",
+        "logsUrl": "https://fly.io/apps/web/builds/build_7",
+        "inProgress": false,
+        "createdBy": { "email": "dev@acme.test" },
+        "createdAt": "2026-10-01T11:55:00Z",
+        "updatedAt": "2026-10-01T11:58:30Z"
+    })]);
+    app["latestImageDetails"] = fixtures::latest_image();
+    app["backupRegions"] = json!([{ "code": "ewr" }]);
+    app["limitedAccessTokens"] = connection(vec![json!({
+        "id": "tok_deploy",
+        "name": "ci",
+        "expiresAt": "2027-10-01T00:00:00Z"
+    })]);
+    app["machine"] = json!({
+        "events": { "nodes": [{
+            "id": "evt_1",
+            "kind": "exit",
+            "status": "stopped",
+            "source": "flyd",
+            "timestamp": "2026-10-02T08:29:58Z",
+            "exitCode": 0,
+            "oomKilled": false,
+            "requestedStop": true
+        }] }
+    });
+    app
+}
+
+/// Everything a document selecting `organization(slug:)` may ask for.
+fn organization() -> Value {
+    let mut org = fixtures::organization();
+    org["type"] = json!("SHARED");
+    org["billingStatus"] = json!("CURRENT");
+    org["viewerRole"] = json!("admin");
+    org["members"] = json!({
+        "totalCount": 1,
+        "edges": [{
+            "role": "admin",
+            "node": { "id": "user_dev", "name": "Dev", "email": "dev@acme.test" }
+        }]
+    });
+    org["apps"] = connection(vec![fixtures::app()]);
+    org["creditBalance"] = json!(500);
+    org["creditBalanceFormatted"] = json!("$5.00");
+    org["currentInvoice"] = json!({
+        "amount": 1250,
+        "amountFormatted": "$12.50",
+        "periodStart": "2026-10-01T00:00:00Z",
+        "periodEnd": "2026-10-31T23:59:59Z",
+        "usage": [{ "category": "machines", "amount": 1250, "amountFormatted": "$12.50" }]
+    });
+    org["remoteBuilderImage"] = json!("flyio/rchab:sha-4c3b1f2");
+    org["remoteBuilderApp"] = json!({ "name": "fly-builder-acme" });
+    org["wireGuardPeers"] = connection(vec![json!({
+        "id": "peer_laptop",
+        "name": "laptop",
+        "network": "default",
+        "region": "iad",
+        "peerip": "fdaa:0:1:a7b:8c::1",
+        "pubkey": "bGFwdG9wLXB1YmxpYy1rZXk="
+    })]);
+    org["limitedAccessTokens"] = connection(Vec::new());
+    org
+}
+
+fn platform() -> Value {
+    json!({
+        "regions": [
+            { "code": "iad", "name": "Ashburn, Virginia (US)", "gatewayAvailable": true, "requiresPaidPlan": false, "latitude": 39.0, "longitude": -77.5 },
+            { "code": "cdg", "name": "Paris, France", "gatewayAvailable": true, "requiresPaidPlan": false, "latitude": 48.9, "longitude": 2.4 },
+            { "code": "lhr", "name": "London, United Kingdom", "gatewayAvailable": true, "requiresPaidPlan": false, "latitude": 51.5, "longitude": -0.1 }
+        ],
+        "vmSizes": fixtures::vm_sizes()
+    })
+}
+
+fn ip_address() -> Value {
+    json!({
+        "id": "ip_v6",
+        "address": "2a09:8280:1::a:1",
+        "type": "v6",
+        "region": "global",
+        "createdAt": "2026-09-20T09:00:00Z"
+    })
+}
+
+fn certificate() -> Value {
+    json!({
+        "id": "cert_www",
+        "hostname": "www.acme.test",
+        "clientStatus": "Ready",
+        "configured": true,
+        "acmeDnsConfigured": true,
+        "acmeAlpnConfigured": true,
+        "dnsValidationHostname": "_acme-challenge.www.acme.test",
+        "dnsValidationTarget": "www.acme.test.x1y2.flydns.net",
+        "dnsValidationInstructions": "CNAME _acme-challenge.www.acme.test => www.acme.test.x1y2.flydns.net.",
+        "createdAt": "2026-09-22T10:00:00Z",
+        "issued": { "nodes": [{ "type": "rsa", "expiresAt": "2026-12-21T10:00:00Z" }] }
+    })
+}
+
+fn volume() -> Value {
+    json!({
+        "id": VOLUME_ID,
+        "name": "data",
+        "state": "created",
+        "size_gb": 1,
+        "region": "iad",
+        "zone": "7d2c",
+        "encrypted": true,
+        "attached_machine_id": null,
+        "created_at": "2026-09-25T14:00:00Z"
+    })
+}
+
+/// The machine `POST /apps/{app}/machines` creates.
+fn created_machine() -> Value {
+    let mut machine = fixtures::machines()[0].clone();
+    machine["id"] = json!(NEW_MACHINE_ID);
+    machine["name"] = json!("web-iad-2");
+    machine
+}
+
+/// Machines API events, newest first, with millisecond timestamps.
+fn events() -> Value {
+    json!([
+        { "id": "evt_2", "type": "start", "status": "started", "source": "user", "timestamp": 1_791_000_060_000i64 },
+        {
+            "id": "evt_1",
+            "type": "exit",
+            "status": "stopped",
+            "source": "flyd",
+            "timestamp": 1_791_000_000_000i64,
+            "request": { "exit_event": { "exit_code": 137, "oom_killed": true, "requested_stop": false } }
+        }
+    ])
+}
+
+fn lease() -> Value {
+    json!({
+        "status": "success",
+        "data": {
+            "nonce": "nonce_7f3a",
+            "expires_at": 1_791_000_030,
+            "owner": "dev@acme.test",
+            "description": "fgp-fly",
+            "version": "01HQ8ZLEASE"
+        }
+    })
+}
+
+fn logs() -> Value {
+    json!([
+        { "attributes": {
+            "timestamp": "2026-10-02T08:00:00Z",
+            "message": "Listening on 0.0.0.0:8080",
+            "level": "info",
+            "region": "iad",
+            "instance": MACHINE_ID
+        } },
+        { "attributes": {
+            "timestamp": "2026-10-02T08:00:05Z",
+            "message": "GET /health 500",
+            "level": "error",
+            "region": "iad",
+            "instance": MACHINE_ID
+        } }
+    ])
+}
+
+fn prometheus() -> Value {
+    json!({
+        "status": "success",
+        "data": { "result": [{
+            "metric": { "instance": MACHINE_ID, "region": "iad" },
+            "values": [[1_790_000_000.0, "0.25"], [1_790_000_060.0, "0.5"]]
+        }] }
+    })
+}
@@ -0,0 +1,146 @@
+//! Realistic canned responses for `MockFlyApi` (feature `test-support`).
+//!
+//! One deployed app, `web` in the `acme` org, with two machines (one
+//! started in `iad`, one stopped in `cdg`) and two secrets.
+
+use serde_json::{json, Value};
+
+use super::mock::MockFlyApi;
+
+pub const APP: &str = "web";
+pub const ORG: &str = "acme";
+
+pub fn organization() -> Value {
+    json!({"id": "org_acme", "name": "Acme Inc", "slug": ORG})
+}
+
+pub fn release() -> Value {
+    json!({
+        "id": "rel_12",
+        "version": 12,
+        "status": "complete",
+        "description": "Deploy image",
+        "user": "dev@acme.test",
+        "createdAt": "2026-10-01T12:00:00Z"
+    })
+}
+
+pub fn app() -> Value {
+    json!({
+        "id": "app_web",
+        "name": APP,
+        "status": "deployed",
+        "organization": organization(),
+        "hostname": "web.fly.dev",
+        "deployed": true,
+        "currentRelease": release()
+    })
+}
+
+pub fn apps_page() -> Value {
+    json!({"items": [app()], "next_cursor": null})
+}
+
+pub fn machines() -> Value {
+    json!([
+        {
+            "id": "148e21ea7d5e89",
+            "name": "web-iad-1",
+            "state": "started",
+            "region": "iad",
+            "instance_id": "01HQ8Z3K4M5N6P7Q8R9S0T1U2V",
+            "private_ip": "fdaa:0:1:a7b:1::2",
+            "config": {
+                "image": "registry.fly.io/web:deployment-01HQ8Z",
+                "guest": {"cpu_kind": "shared", "cpus": 1, "memory_mb": 256},
+                "services": [{"protocol": "tcp", "internal_port": 8080}],
+                "metadata": {"fly_platform_version": "v2", "fly_process_group": "app"}
+            },
+            "image_ref": {
+                "registry": "registry.fly.io",
+                "repository": "web",
+                "tag": "deployment-01HQ8Z",
+                "digest": "sha256:0b1c2d"
+            },
+            "created_at": "2026-10-01T12:01:00Z",
+            "updated_at": "2026-10-01T12:02:00Z",
+            "checks": [{"name": "servicecheck-00-http-8080", "status": "passing"}]
+        },
+        {
+            "id": "3d8d9e1b2c4f67",
+            "name": "web-cdg-1",
+            "state": "stopped",
+            "region": "cdg",
+            "instance_id": "01HQ8Z3K4M5N6P7Q8R9S0T1U2W",
+            "private_ip": "fdaa:0:1:a7b:2::3",
+            "config": {
+                "image": "registry.fly.io/web:deployment-01HQ8Z",
+                "guest": {"cpu_kind": "shared", "cpus": 1, "memory_mb": 256},
+                "metadata": {"fly_platform_version": "v2", "fly_process_group": "app"}
+            },
+            "created_at": "2026-10-01T12:01:00Z",
+            "updated_at": "2026-10-02T08:30:00Z",
+            "checks": []
+        }
+    ])
+}
+
+pub fn app_status() -> Value {
+    json!({"app": app(), "machines": machines(), "allocations": []})
+}
+
+pub fn secrets() -> Value {
+    json!([
+        {"name": "DATABASE_URL", "digest": "a1b2c3d4", "createdAt": "2026-09-20T09:00:00Z"},
+        {"name": "SESSION_KEY", "digest": "e5f6a7b8", "createdAt": "2026-09-21T10:00:00Z"}
+    ])
+}
+
+/// Newer than the image the machines run.
+pub fn latest_image() -> Value {
+    json!({
+        "registry": "registry.fly.io",
+        "repository": "web",
+        "tag": "deployment-01HR2A",
+        "digest": "sha256:9f8e7d"
+    })
+}
+
+pub fn vm_sizes() -> Value {
+    json!([
+        {"name": "shared-cpu-1x", "cpuCores": 1.0, "memoryMb": 256, "priceMonth": 1.94, "priceSecond": 0.00000075},
+        {"name": "performance-2x", "cpuCores": 2.0, "memoryMb": 4096, "priceMonth": 62.0, "priceSecond": 0.0000239},
+        {"name": "a100-40gb", "cpuCores": 8.0, "memoryMb": 32768, "priceMonth": 1800.0, "priceSecond": 0.000694}
+    ])
+}
+
+pub fn user() -> Value {
+    json!({
+        "id": "user_dev",
+        "email": "dev@acme.test",
+        "name": "Dev",
+        "organizations": [organization()]
+    })
+}
+
+impl MockFlyApi {
+    /// Mock preloaded with the fixtures above for the read-only methods.
+    pub fn with_fixtures() -> Self {
+        Self::new()
+            .respond("ping", json!(true))
+            .respond("get_viewer_id", json!("user_dev"))
+            .respond("get_user", user())
+            .respond("list_apps", apps_page())
+            .respond("get_app_status", app_status())
+            .respond("list_machines", machines())
+            .respond("list_machines_with_config", machines())
+            .respond("list_secrets", secrets())
+            .respond(
+                "list_releases",
+                json!({"items": [release()], "next_cursor": null}),
+            )
+            .respond("get_app_org_slug", json!(ORG))
+            .respond("get_latest_image", latest_image())
+            .respond("list_vm_sizes", vm_sizes())
+    }
+}
@@ -0,0 +1,551 @@
+//! Object-safe interface over the Fly.io GraphQL client.
+//!
+//! The service and its helpers talk to `dyn FlyApi` rather than `FlyClient`
+//! directly, so a `MockFlyApi` (feature `test-support`) can stand in for the
+//! network.
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde_json::Value;
+
+use super::auth::Credentials;
+use super::client::FlyClient;
+use crate::models::{
+    App, AppStatus, BillingSummary, Build, Certificate, DeployToken, ImageRef, IpAddress, LogPage,
+    Machine, MachineEvent, OrganizationDetail, Page, Region, Release, RemoteBuilder, Secret,
+    StopReason, UserInfo, VmSize, WireGuardPeer, WireGuardPeerCreated,
+};
+
+/// Fly.io GraphQL operations used by the service.
+pub trait FlyApi: Send + Sync {
+    /// Credentials this client authenticates with.
+    fn credentials(&self) -> &Credentials;
+
+    /// Check if the client can connect to Fly.io API.
+    fn ping(&self) -> BoxFuture<'_, Result<bool>>;
+
+    /// Get the authenticated viewer's ID.
+    fn get_viewer_id(&self) -> BoxFuture<'_, Result<String>>;
+
+    /// List a page of apps for the authenticated user, optionally only
+    /// those in one organization.
+    fn list_apps<'a>(
+        &'a self,
+        limit: Option<i32>,
+        after: Option<&'a str>,
+        organization_id: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Page<App>>>;
+
+    /// Get status for a specific app.
+    fn get_app_status<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<AppStatus>>;
+
+    /// List a page of release history for an app, newest first.
+    fn list_releases<'a>(
+        &'a self,
+        app_name: &'a str,
+        limit: Option<i32>,
+        after: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Page<Release>>>;
+
+    /// List all machines for an app.
+    fn list_machines<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<Machine>>>;
+
+    /// List machines for an app including their config (image, env, size).
+    fn list_machines_with_config<'a>(
+        &'a self,
+        app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Machine>>>;
+
+    /// List recent events for a machine, newest first.
+    fn list_machine_events<'a>(
+        &'a self,
+        app_name: &'a str,
+        machine_id: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<MachineEvent>>>;
+
+    /// Determine why a machine last stopped from its latest exit event.
+    fn get_machine_stopped_reason<'a>(
+        &'a self,
+        app_name: &'a str,
+        machine_id: &'a str,
+    ) -> BoxFuture<'a, Result<StopReason>>;
+
+    /// Fetch a page of app logs, starting after `next_token` when given.
+    fn get_logs<'a>(
+        &'a self,
+        app_name: &'a str,
+        region: Option<&'a str>,
+        instance: Option<&'a str>,
+        next_token: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<LogPage>>;
+
+    /// Get current user info.
+    fn get_user(&self) -> BoxFuture<'_, Result<UserInfo>>;
+
+    /// List all Fly.io regions.
+    fn list_regions(&self) -> BoxFuture<'_, Result<Vec<Region>>>;
+
+    /// List VM size presets with their prices.
+    fn list_vm_sizes(&self) -> BoxFuture<'_, Result<Vec<VmSize>>>;
+
+    /// Codes of an app's backup regions.
+    fn get_backup_regions<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<String>>>;
+
+    /// List secrets for an app (names only, values are not exposed).
+    fn list_secrets<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<Secret>>>;
+
+    /// Set a secret for an app.
+    fn set_secret<'a>(
+        &'a self,
+        app_name: &'a str,
+        key: &'a str,
+        value: &'a str,
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    /// Set several secrets at once, creating a single release.
+    fn set_secrets<'a>(
+        &'a self,
+        app_name: &'a str,
+        secrets: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    /// Delete a secret from an app.
+    fn delete_secret<'a>(&'a self, app_name: &'a str, key: &'a str)
+        -> BoxFuture<'a, Result<Value>>;
+
+    /// Remove several secrets at once, creating a single release.
+    fn unset_secrets<'a>(
+        &'a self,
+        app_name: &'a str,
+        keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    /// List IP addresses allocated to an app.
+    fn list_ips<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<IpAddress>>>;
+
+    /// Allocate an IP address (v4, v6, shared_v4 or private_v6).
+    fn allocate_ip<'a>(
+        &'a self,
+        app_name: &'a str,
+        ip_type: &'a str,
+        region: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    /// Release an IP address from an app.
+    fn release_ip<'a>(
+        &'a self,
+        app_name: &'a str,
+        address: &'a str,
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    /// List all TLS certificates for an app.
+    fn list_certificates<'a>(
+        &'a self,
+        app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Certificate>>>;
+
+    /// Get a certificate's current validation and issuance status.
+    fn check_certificate<'a>(
+        &'a self,
+        app_name: &'a str,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Certificate>>;
+
+    /// Add a certificate for a custom hostname.
+    fn add_certificate<'a>(
+        &'a self,
+        app_name: &'a str,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Certificate>>;
+
+    /// Remove a certificate from an app.
+    fn delete_certificate<'a>(
+        &'a self,
+        app_name: &'a str,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    /// Restart an app (restarts all machines).
+    fn restart_app<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Value>>;
+
+    /// Create an app in an organization.
+    fn create_app<'a>(
+        &'a self,
+        name: &'a str,
+        org_slug: &'a str,
+        region: Option<&'a str>,
+        network: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<App>>;
+
+    /// Delete an app and all of its resources.
+    fn delete_app<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Value>>;
+
+    /// Move an app to another organization.
+    fn move_app<'a>(&'a self, app_name: &'a str, org_slug: &'a str) -> BoxFuture<'a, Result<App>>;
+
+    /// List every organization the viewer belongs to.
+    fn list_organizations(&self) -> BoxFuture<'_, Result<Vec<OrganizationDetail>>>;
+
+    /// Get an organization with its apps and members.
+    fn get_organization<'a>(&'a self, slug: &'a str) -> BoxFuture<'a, Result<OrganizationDetail>>;
+
+    /// Get an organization's credit balance and current invoice.
+    fn get_billing<'a>(&'a self, slug: &'a str) -> BoxFuture<'a, Result<BillingSummary>>;
+
+    /// Get the slug of the organization that owns an app.
+    fn get_app_org_slug<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<String>>;
+
+    /// List an app's most recent builds, newest first.
+    fn list_builds<'a>(
+        &'a self,
+        app_name: &'a str,
+        limit: Option<i32>,
+    ) -> BoxFuture<'a, Result<Vec<Build>>>;
+
+    /// An organization's remote builder app and image.
+    fn get_remote_builder<'a>(&'a self, org_slug: &'a str) -> BoxFuture<'a, Result<RemoteBuilder>>;
+
+    /// Latest image pushed for an app, if any.
+    fn get_latest_image<'a>(&'a self, app_name: &'a str)
+        -> BoxFuture<'a, Result<Option<ImageRef>>>;
+
+    /// List the deploy tokens issued for an app, or for an org when `app_name` is `None`.
+    fn list_deploy_tokens<'a>(
+        &'a self,
+        app_name: Option<&'a str>,
+        org_slug: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Vec<DeployToken>>>;
+
+    /// Create a deploy token scoped to an app, or to a whole org when `app_name` is `None`.
+    fn create_deploy_token<'a>(
+        &'a self,
+        app_name: Option<&'a str>,
+        org_slug: Option<&'a str>,
+        name: &'a str,
+        expiry: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(DeployToken, String)>>;
+
+    /// Revoke a deploy token by ID.
+    fn revoke_deploy_token<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Value>>;
+
+    /// List Fly Postgres cluster apps visible to the token.
+    fn list_postgres_clusters(&self) -> BoxFuture<'_, Result<Vec<App>>>;
+
+    /// List WireGuard peers in an organization.
+    fn list_wireguard_peers<'a>(
+        &'a self,
+        org_slug: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<WireGuardPeer>>>;
+
+    /// Register a WireGuard peer with `pubkey` in an organization.
+    fn add_wireguard_peer<'a>(
+        &'a self,
+        org_slug: &'a str,
+        name: &'a str,
+        region: &'a str,
+        pubkey: &'a str,
+        network: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<WireGuardPeerCreated>>;
+
+    /// Remove a WireGuard peer by name.
+    fn remove_wireguard_peer<'a>(
+        &'a self,
+        org_slug: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Value>>;
+}
+
+impl FlyApi for FlyClient {
+    fn credentials(&self) -> &Credentials {
+        FlyClient::credentials(self)
+    }
+
+    fn ping(&self) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(FlyClient::ping(self))
+    }
+
+    fn get_viewer_id(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(FlyClient::get_viewer_id(self))
+    }
+
+    fn list_apps<'a>(
+        &'a self,
+        limit: Option<i32>,
+        after: Option<&'a str>,
+        organization_id: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Page<App>>> {
+        Box::pin(FlyClient::list_apps(self, limit, after, organization_id))
+    }
+
+    fn get_app_status<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<AppStatus>> {
+        Box::pin(FlyClient::get_app_status(self, app_name))
+    }
+
+    fn list_releases<'a>(
+        &'a self,
+        app_name: &'a str,
+        limit: Option<i32>,
+        after: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Page<Release>>> {
+        Box::pin(FlyClient::list_releases(self, app_name, limit, after))
+    }
+
+    fn list_machines<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<Machine>>> {
+        Box::pin(FlyClient::list_machines(self, app_name))
+    }
+
+    fn list_machines_with_config<'a>(
+        &'a self,
+        app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Machine>>> {
+        Box::pin(FlyClient::list_machines_with_config(self, app_name))
+    }
+
+    fn list_machine_events<'a>(
+        &'a self,
+        app_name: &'a str,
+        machine_id: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<MachineEvent>>> {
+        Box::pin(FlyClient::list_machine_events(self, app_name, machine_id))
+    }
+
+    fn get_machine_stopped_reason<'a>(
+        &'a self,
+        app_name: &'a str,
+        machine_id: &'a str,
+    ) -> BoxFuture<'a, Result<StopReason>> {
+        Box::pin(FlyClient::get_machine_stopped_reason(
+            self, app_name, machine_id,
+        ))
+    }
+
+    fn get_logs<'a>(
+        &'a self,
+        app_name: &'a str,
+        region: Option<&'a str>,
+        instance: Option<&'a str>,
+        next_token: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<LogPage>> {
+        Box::pin(FlyClient::get_logs(
+            self, app_name, region, instance, next_token,
+        ))
+    }
+
+    fn get_user(&self) -> BoxFuture<'_, Result<UserInfo>> {
+        Box::pin(FlyClient::get_user(self))
+    }
+
+    fn list_regions(&self) -> BoxFuture<'_, Result<Vec<Region>>> {
+        Box::pin(FlyClient::list_regions(self))
+    }
+
+    fn list_vm_sizes(&self) -> BoxFuture<'_, Result<Vec<VmSize>>> {
+        Box::pin(FlyClient::list_vm_sizes(self))
+    }
+
+    fn get_backup_regions<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(FlyClient::get_backup_regions(self, app_name))
+    }
+
+    fn list_secrets<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<Secret>>> {
+        Box::pin(FlyClient::list_secrets(self, app_name))
+    }
+
+    fn set_secret<'a>(
+        &'a self,
+        app_name: &'a str,
+        key: &'a str,
+        value: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::set_secret(self, app_name, key, value))
+    }
+
+    fn set_secrets<'a>(
+        &'a self,
+        app_name: &'a str,
+        secrets: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::set_secrets(self, app_name, secrets))
+    }
+
+    fn delete_secret<'a>(
+        &'a self,
+        app_name: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::delete_secret(self, app_name, key))
+    }
+
+    fn unset_secrets<'a>(
+        &'a self,
+        app_name: &'a str,
+        keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::unset_secrets(self, app_name, keys))
+    }
+
+    fn list_ips<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Vec<IpAddress>>> {
+        Box::pin(FlyClient::list_ips(self, app_name))
+    }
+
+    fn allocate_ip<'a>(
+        &'a self,
+        app_name: &'a str,
+        ip_type: &'a str,
+        region: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::allocate_ip(self, app_name, ip_type, region))
+    }
+
+    fn release_ip<'a>(
+        &'a self,
+        app_name: &'a str,
+        address: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::release_ip(self, app_name, address))
+    }
+
+    fn list_certificates<'a>(
+        &'a self,
+        app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Certificate>>> {
+        Box::pin(FlyClient::list_certificates(self, app_name))
+    }
+
+    fn check_certificate<'a>(
+        &'a self,
+        app_name: &'a str,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Certificate>> {
+        Box::pin(FlyClient::check_certificate(self, app_name, hostname))
+    }
+
+    fn add_certificate<'a>(
+        &'a self,
+        app_name: &'a str,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Certificate>> {
+        Box::pin(FlyClient::add_certificate(self, app_name, hostname))
+    }
+
+    fn delete_certificate<'a>(
+        &'a self,
+        app_name: &'a str,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::delete_certificate(self, app_name, hostname))
+    }
+
+    fn restart_app<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::restart_app(self, app_name))
+    }
+
+    fn create_app<'a>(
+        &'a self,
+        name: &'a str,
+        org_slug: &'a str,
+        region: Option<&'a str>,
+        network: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<App>> {
+        Box::pin(FlyClient::create_app(self, name, org_slug, region, network))
+    }
+
+    fn delete_app<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::delete_app(self, app_name))
+    }
+
+    fn move_app<'a>(&'a self, app_name: &'a str, org_slug: &'a str) -> BoxFuture<'a, Result<App>> {
+        Box::pin(FlyClient::move_app(self, app_name, org_slug))
+    }
+
+    fn list_organizations(&self) -> BoxFuture<'_, Result<Vec<OrganizationDetail>>> {
+        Box::pin(FlyClient::list_organizations(self))
+    }
+
+    fn get_organization<'a>(&'a self, slug: &'a str) -> BoxFuture<'a, Result<OrganizationDetail>> {
+        Box::pin(FlyClient::get_organization(self, slug))
+    }
+
+    fn get_billing<'a>(&'a self, slug: &'a str) -> BoxFuture<'a, Result<BillingSummary>> {
+        Box::pin(FlyClient::get_billing(self, slug))
+    }
+
+    fn get_app_org_slug<'a>(&'a self, app_name: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(FlyClient::get_app_org_slug(self, app_name))
+    }
+
+    fn list_builds<'a>(
+        &'a self,
+        app_name: &'a str,
+        limit: Option<i32>,
+    ) -> BoxFuture<'a, Result<Vec<Build>>> {
+        Box::pin(FlyClient::list_builds(self, app_name, limit))
+    }
+
+    fn get_remote_builder<'a>(&'a self, org_slug: &'a str) -> BoxFuture<'a, Result<RemoteBuilder>> {
+        Box::pin(FlyClient::get_remote_builder(self, org_slug))
+    }
+
+    fn get_latest_image<'a>(
+        &'a self,
+        app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Option<ImageRef>>> {
+        Box::pin(FlyClient::get_latest_image(self, app_name))
+    }
+
+    fn list_deploy_tokens<'a>(
+        &'a self,
+        app_name: Option<&'a str>,
+        org_slug: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Vec<DeployToken>>> {
+        Box::pin(FlyClient::list_deploy_tokens(self, app_name, org_slug))
+    }
+
+    fn create_deploy_token<'a>(
+        &'a self,
+        app_name: Option<&'a str>,
+        org_slug: Option<&'a str>,
+        name: &'a str,
+        expiry: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(DeployToken, String)>> {
+        Box::pin(FlyClient::create_deploy_token(
+            self, app_name, org_slug, name, expiry,
+        ))
+    }
+
+    fn revoke_deploy_token<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::revoke_deploy_token(self, id))
+    }
+
+    fn list_postgres_clusters(&self) -> BoxFuture<'_, Result<Vec<App>>> {
+        Box::pin(FlyClient::list_postgres_clusters(self))
+    }
+
+    fn list_wireguard_peers<'a>(
+        &'a self,
+        org_slug: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<WireGuardPeer>>> {
+        Box::pin(FlyClient::list_wireguard_peers(self, org_slug))
+    }
+
+    fn add_wireguard_peer<'a>(
+        &'a self,
+        org_slug: &'a str,
+        name: &'a str,
+        region: &'a str,
+        pubkey: &'a str,
+        network: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<WireGuardPeerCreated>> {
+        Box::pin(FlyClient::add_wireguard_peer(
+            self, org_slug, name, region, pubkey, network,
+        ))
+    }
+
+    fn remove_wireguard_peer<'a>(
+        &'a self,
+        org_slug: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(FlyClient::remove_wireguard_peer(self, org_slug, name))
+    }
+}
@@ -0,0 +1,868 @@
+//! Fly Machines REST API client.
+//!
+//! The GraphQL API only exposes a machine summary; the Machines API returns
+//! full configs (guest, image ref, services, mounts, env).
+//!
+//! Plain GETs are conditional: a response that carried an `ETag` is kept,
+//! and the next read of the same path sends `If-None-Match`, so a
+//! `304 Not Modified` reuses the kept body instead of downloading it again.
+//! Watchers polling unchanged machines mostly get 304s.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method, StatusCode, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::auth::Credentials;
+use super::client_config::FlyClientConfig;
+use super::middleware::Middlewares;
+use super::rate_limit::RateLimiter;
+use super::response;
+use super::timeout;
+use crate::error::FlyError;
+use crate::models::{
+    ExecResult, Machine, MachineConfig, MachineCreateConfig, MachineEvent, MachineLease, Volume,
+    VolumeSnapshot,
+};
+
+/// Header carrying the nonce of a lease held on the target machine.
+const LEASE_NONCE_HEADER: &str = "fly-machine-lease-nonce";
+
+/// Longest single wait request made by `wait_until`.
+const WAIT_CHUNK_SECS: u64 = 25;
+
+/// Most responses kept for conditional GETs; the cache is cleared when full.
+const MAX_ETAG_ENTRIES: usize = 512;
+
+/// `/segment/...` with every segment percent-encoded, so an app name,
+/// machine id or metadata key can't reach a different endpoint.
+fn api_path(segments: &[&str]) -> Result<String> {
+    api_path_with_query(segments, &[])
+}
+
+/// `api_path` followed by an encoded query string.
+fn api_path_with_query(segments: &[&str], query: &[(&str, &str)]) -> Result<String> {
+    // The url crate silently drops dot segments rather than encoding them
+    if let Some(segment) = segments.iter().find(|s| matches!(**s, "" | "." | "..")) {
+        return Err(FlyError::Validation {
+            code: "validation",
+            param: None,
+            message: format!("Invalid Machines API path segment {:?}", segment),
+            suggestions: Vec::new(),
+        }
+        .into());
+    }
+    let mut url = Url::parse("http://machines.invalid/").expect("static base URL");
+    url.path_segments_mut()
+        .expect("base URL has a path")
+        .clear()
+        .extend(segments);
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    Ok(match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    })
+}
+
+/// A response body kept with the `ETag` it was served with.
+struct Tagged {
+    etag: String,
+    body: String,
+}
+
+/// Fly Machines API client with persistent connection.
+pub struct MachinesClient {
+    client: Client,
+    credentials: Arc<Credentials>,
+    base_url: String,
+    limiter: RateLimiter,
+    middleware: Middlewares,
+    /// Last tagged response per GET path.
+    etags: Mutex<HashMap<String, Tagged>>,
+}
+
+impl MachinesClient {
+    /// Create a new Machines API client.
+    pub fn new(credentials: Arc<Credentials>, config: &FlyClientConfig) -> Result<Self> {
+        let client = config.http_client()?;
+
+        Ok(Self {
+            client,
+            credentials,
+            base_url: config.machines_base(),
+            limiter: RateLimiter::from_env(),
+            middleware: config.middleware.clone(),
+            etags: Mutex::default(),
+        })
+    }
+
+    /// Execute a Machines API request, passing a lease nonce if one is held.
+    async fn request<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+        nonce: Option<&str>,
+    ) -> Result<T> {
+        self.request_with_timeout(method, path, body, nonce, None)
+            .await
+    }
+
+    /// Execute a Machines API request, overriding the client's default timeout.
+    ///
+    /// An explicit `timeout` wins over the call's `timeout_ms`.
+    async fn request_with_timeout<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+        nonce: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<T> {
+        self.send(method, path, body, nonce, timeout, false).await
+    }
+
+    /// Send a request, conditionally on a kept `ETag` if `conditional`.
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+        nonce: Option<&str>,
+        timeout: Option<Duration>,
+        conditional: bool,
+    ) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut refreshed = false;
+
+        let response = loop {
+            let mut request = self
+                .client
+                .request(method.clone(), &url)
+                .header("Authorization", self.credentials.authorization().await?);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            if let Some(nonce) = nonce {
+                request = request.header(LEASE_NONCE_HEADER, nonce);
+            }
+            if let Some(timeout) = timeout.or_else(timeout::current) {
+                request = request.timeout(timeout);
+            }
+            if conditional {
+                if let Some(etag) = self.etag(path) {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+                }
+            }
+
+            self.limiter.acquire().await;
+            let response = self
+                .middleware
+                .send("machines", request)
+                .await
+                .context("Failed to send Machines API request")?;
+
+            // Retry once with a fresh token if the current one was rejected
+            if response.status() == StatusCode::UNAUTHORIZED
+                && !refreshed
+                && self.credentials.refresh().await?
+            {
+                refreshed = true;
+                continue;
+            }
+            break response;
+        };
+
+        if conditional && response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(text) = self.tagged_body(path) {
+                return Ok(response::parse("machines", &text)?);
+            }
+            // Evicted since the request went out; fetch it in full
+            return Box::pin(self.send(method, path, body, nonce, timeout, false)).await;
+        }
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow::Error::new(FlyError::from_status(status, text))
+                .context(format!("Machines API {} {} failed", method, path)));
+        }
+
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let text = response.text().await.context("Failed to read response")?;
+        if let Some(etag) = etag.filter(|_| conditional) {
+            self.keep(path, etag, &text);
+        }
+
+        // Some endpoints reply with an empty body on success
+        let text = if text.trim().is_empty() {
+            "null"
+        } else {
+            &text
+        };
+
+        Ok(response::parse("machines", text)?)
+    }
+
+    /// GET a Machines API path, conditionally if it was read before.
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T> {
+        self.send(Method::GET, path, None, None, None, true).await
+    }
+
+    fn etags(&self) -> std::sync::MutexGuard<'_, HashMap<String, Tagged>> {
+        self.etags.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn etag(&self, path: &str) -> Option<String> {
+        self.etags().get(path).map(|tagged| tagged.etag.clone())
+    }
+
+    fn tagged_body(&self, path: &str) -> Option<String> {
+        self.etags().get(path).map(|tagged| tagged.body.clone())
+    }
+
+    fn keep(&self, path: &str, etag: String, body: &str) {
+        let mut etags = self.etags();
+        if etags.len() >= MAX_ETAG_ENTRIES && !etags.contains_key(path) {
+            etags.clear();
+        }
+        etags.insert(
+            path.to_string(),
+            Tagged {
+                etag,
+                body: body.to_string(),
+            },
+        );
+    }
+
+    /// POST to a Machines API path.
+    async fn post<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<T> {
+        self.request(Method::POST, path, body, None).await
+    }
+
+    /// POST to a Machines API path under a lease.
+    async fn post_leased<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: Option<&Value>,
+        nonce: Option<&str>,
+    ) -> Result<T> {
+        self.request(Method::POST, path, body, nonce).await
+    }
+
+    /// PUT to a Machines API path.
+    async fn put<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<T> {
+        self.request(Method::PUT, path, body, None).await
+    }
+
+    /// DELETE a Machines API path.
+    async fn delete<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T> {
+        self.request(Method::DELETE, path, None, None).await
+    }
+
+    /// DELETE a Machines API path under a lease.
+    async fn delete_leased<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        nonce: Option<&str>,
+    ) -> Result<T> {
+        self.request(Method::DELETE, path, None, nonce).await
+    }
+
+    /// Check that the Machines API answers for `org_slug`.
+    ///
+    /// Any HTTP response counts as reachable; returns its status and the
+    /// server's `Date` header for clock-skew checks.
+    pub async fn probe(&self, org_slug: &str) -> Result<(u16, Option<DateTime<Utc>>)> {
+        let request = self
+            .client
+            .get(format!("{}/apps", self.base_url))
+            .header("Authorization", self.credentials.authorization().await?)
+            .query(&[("org_slug", org_slug)]);
+        self.limiter.acquire().await;
+        let response = self
+            .middleware
+            .send("machines", timeout::apply(request))
+            .await
+            .context("Failed to send Machines API request")?;
+
+        let date = response
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .map(|at| at.with_timezone(&Utc));
+        Ok((response.status().as_u16(), date))
+    }
+
+    /// List machines for an app with full config.
+    pub async fn list_machines(&self, app_name: &str) -> Result<Vec<Machine>> {
+        self.get(&api_path(&["apps", app_name, "machines"])?).await
+    }
+
+    /// Get a single machine with full config.
+    pub async fn get_machine(&self, app_name: &str, machine_id: &str) -> Result<Machine> {
+        self.get(&api_path(&["apps", app_name, "machines", machine_id])?)
+            .await
+    }
+
+    /// List a machine's recent events, newest first.
+    pub async fn list_machine_events(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+    ) -> Result<Vec<MachineEvent>> {
+        #[derive(Deserialize)]
+        struct RawEvent {
+            #[serde(default)]
+            id: Option<String>,
+            #[serde(rename = "type")]
+            kind: String,
+            #[serde(default)]
+            status: Option<String>,
+            #[serde(default)]
+            source: Option<String>,
+            /// Milliseconds since the epoch.
+            timestamp: i64,
+            #[serde(default)]
+            request: Option<EventRequest>,
+        }
+
+        #[derive(Deserialize)]
+        struct EventRequest {
+            #[serde(default)]
+            exit_event: Option<ExitEvent>,
+        }
+
+        #[derive(Deserialize)]
+        struct ExitEvent {
+            #[serde(default)]
+            exit_code: Option<i32>,
+            #[serde(default)]
+            oom_killed: Option<bool>,
+            #[serde(default)]
+            requested_stop: Option<bool>,
+        }
+
+        let raw: Vec<RawEvent> = self
+            .get(&api_path(&[
+                "apps", app_name, "machines", machine_id, "events",
+            ])?)
+            .await?;
+
+        let mut events: Vec<MachineEvent> = raw
+            .into_iter()
+            .map(|e| {
+                let exit = e.request.and_then(|r| r.exit_event);
+                MachineEvent {
+                    id: e.id,
+                    kind: e.kind,
+                    status: e.status,
+                    source: e.source,
+                    timestamp: DateTime::from_timestamp_millis(e.timestamp)
+                        .unwrap_or_default()
+                        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    exit_code: exit.as_ref().and_then(|x| x.exit_code),
+                    oom_killed: exit.as_ref().and_then(|x| x.oom_killed),
+                    requested_stop: exit.as_ref().and_then(|x| x.requested_stop),
+                }
+            })
+            .collect();
+
+        // RFC 3339 timestamps sort lexicographically
+        events.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+
+        Ok(events)
+    }
+
+    /// Create (and start) a new machine.
+    pub async fn create_machine(
+        &self,
+        app_name: &str,
+        config: &MachineCreateConfig,
+    ) -> Result<Machine> {
+        let body = serde_json::json!({
+            "name": config.name,
+            "region": config.region,
+            "config": {
+                "image": config.image,
+                "guest": config.guest,
+                "env": config.env,
+                "services": config.services,
+            }
+        });
+
+        self.post(&api_path(&["apps", app_name, "machines"])?, Some(&body))
+            .await
+    }
+
+    /// Replace a machine's config, producing a new machine version.
+    pub async fn update_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        config: &MachineConfig,
+        nonce: Option<&str>,
+    ) -> Result<Machine> {
+        let body = serde_json::json!({ "config": config });
+        self.post_leased(
+            &api_path(&["apps", app_name, "machines", machine_id])?,
+            Some(&body),
+            nonce,
+        )
+        .await
+    }
+
+    /// Create a machine in `region` from an existing machine's config.
+    pub async fn clone_machine(
+        &self,
+        app_name: &str,
+        region: &str,
+        config: &MachineConfig,
+    ) -> Result<Machine> {
+        let body = serde_json::json!({
+            "region": region,
+            "config": config,
+        });
+
+        self.post(&api_path(&["apps", app_name, "machines"])?, Some(&body))
+            .await
+    }
+
+    /// Destroy a machine; `force` kills it first if it is running.
+    pub async fn destroy_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        force: bool,
+        nonce: Option<&str>,
+    ) -> Result<Value> {
+        self.delete_leased(
+            &api_path_with_query(
+                &["apps", app_name, "machines", machine_id],
+                &[("force", &force.to_string())],
+            )?,
+            nonce,
+        )
+        .await
+    }
+
+    /// Start a stopped machine.
+    pub async fn start_machine(&self, app_name: &str, machine_id: &str) -> Result<Value> {
+        self.post(
+            &api_path(&["apps", app_name, "machines", machine_id, "start"])?,
+            None,
+        )
+        .await
+    }
+
+    /// Gracefully stop a running machine.
+    pub async fn stop_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<Value> {
+        self.post_leased(
+            &api_path(&["apps", app_name, "machines", machine_id, "stop"])?,
+            None,
+            nonce,
+        )
+        .await
+    }
+
+    /// Kill a machine immediately with SIGKILL.
+    pub async fn kill_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<Value> {
+        let body = serde_json::json!({ "signal": "SIGKILL" });
+        self.post_leased(
+            &api_path(&["apps", app_name, "machines", machine_id, "signal"])?,
+            Some(&body),
+            nonce,
+        )
+        .await
+    }
+
+    /// Restart a machine in place.
+    pub async fn restart_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<Value> {
+        self.post_leased(
+            &api_path(&["apps", app_name, "machines", machine_id, "restart"])?,
+            None,
+            nonce,
+        )
+        .await
+    }
+
+    /// Stop the proxy from routing new requests to a machine.
+    ///
+    /// The machine keeps running, so in-flight requests finish and it can
+    /// be maintained once drained.
+    pub async fn cordon_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<Value> {
+        self.post_leased(
+            &api_path(&["apps", app_name, "machines", machine_id, "cordon"])?,
+            None,
+            nonce,
+        )
+        .await
+    }
+
+    /// Let the proxy route requests to a cordoned machine again.
+    pub async fn uncordon_machine(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<Value> {
+        self.post_leased(
+            &api_path(&["apps", app_name, "machines", machine_id, "uncordon"])?,
+            None,
+            nonce,
+        )
+        .await
+    }
+
+    /// Run a command inside a running machine and wait for it to exit.
+    ///
+    /// `timeout_secs` bounds the command itself; the HTTP request is given a
+    /// few extra seconds so the API can report the timeout.
+    pub async fn exec(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        command: &[String],
+        timeout_secs: u64,
+    ) -> Result<ExecResult> {
+        let body = serde_json::json!({
+            "command": command,
+            "timeout": timeout_secs,
+        });
+        self.request_with_timeout(
+            Method::POST,
+            &api_path(&["apps", app_name, "machines", machine_id, "exec"])?,
+            Some(&body),
+            None,
+            Some(Duration::from_secs(timeout_secs + 5)),
+        )
+        .await
+    }
+
+    /// Acquire a lease on a machine for `ttl_secs` seconds.
+    pub async fn acquire_lease(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        ttl_secs: u64,
+        description: Option<&str>,
+    ) -> Result<MachineLease> {
+        let mut body = serde_json::json!({ "ttl": ttl_secs });
+        if let Some(description) = description {
+            body["description"] = serde_json::json!(description);
+        }
+
+        let response: LeaseResponse = self
+            .post(
+                &api_path(&["apps", app_name, "machines", machine_id, "lease"])?,
+                Some(&body),
+            )
+            .await?;
+        Ok(response.data)
+    }
+
+    /// Show the lease currently held on a machine.
+    pub async fn get_lease(&self, app_name: &str, machine_id: &str) -> Result<MachineLease> {
+        let response: LeaseResponse = self
+            .get(&api_path(&[
+                "apps", app_name, "machines", machine_id, "lease",
+            ])?)
+            .await?;
+        Ok(response.data)
+    }
+
+    /// Release a lease held with `nonce`.
+    pub async fn release_lease(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        nonce: &str,
+    ) -> Result<Value> {
+        self.delete_leased(
+            &api_path(&["apps", app_name, "machines", machine_id, "lease"])?,
+            Some(nonce),
+        )
+        .await
+    }
+
+    /// Get a machine's metadata.
+    pub async fn get_metadata(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+    ) -> Result<HashMap<String, String>> {
+        self.get(&api_path(&[
+            "apps", app_name, "machines", machine_id, "metadata",
+        ])?)
+        .await
+    }
+
+    /// Set a single metadata key on a machine.
+    pub async fn set_metadata(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Value> {
+        let body = serde_json::json!({ "value": value });
+        self.post(
+            &api_path(&["apps", app_name, "machines", machine_id, "metadata", key])?,
+            Some(&body),
+        )
+        .await
+    }
+
+    /// Remove a metadata key from a machine.
+    pub async fn delete_metadata(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        key: &str,
+    ) -> Result<Value> {
+        self.delete(&api_path(&[
+            "apps", app_name, "machines", machine_id, "metadata", key,
+        ])?)
+        .await
+    }
+
+    /// List volumes for an app.
+    pub async fn list_volumes(&self, app_name: &str) -> Result<Vec<Volume>> {
+        self.get(&api_path(&["apps", app_name, "volumes"])?).await
+    }
+
+    /// Create a volume in a region.
+    pub async fn create_volume(
+        &self,
+        app_name: &str,
+        name: &str,
+        region: &str,
+        size_gb: u32,
+    ) -> Result<Volume> {
+        let body = serde_json::json!({
+            "name": name,
+            "region": region,
+            "size_gb": size_gb,
+        });
+        self.post(&api_path(&["apps", app_name, "volumes"])?, Some(&body))
+            .await
+    }
+
+    /// Grow a volume to a new size.
+    pub async fn extend_volume(
+        &self,
+        app_name: &str,
+        volume_id: &str,
+        size_gb: u32,
+    ) -> Result<Value> {
+        let body = serde_json::json!({ "size_gb": size_gb });
+        self.put(
+            &api_path(&["apps", app_name, "volumes", volume_id, "extend"])?,
+            Some(&body),
+        )
+        .await
+    }
+
+    /// Delete a volume.
+    pub async fn delete_volume(&self, app_name: &str, volume_id: &str) -> Result<Volume> {
+        self.delete(&api_path(&["apps", app_name, "volumes", volume_id])?)
+            .await
+    }
+
+    /// List snapshots of a volume.
+    pub async fn list_volume_snapshots(
+        &self,
+        app_name: &str,
+        volume_id: &str,
+    ) -> Result<Vec<VolumeSnapshot>> {
+        self.get(&api_path(&[
+            "apps",
+            app_name,
+            "volumes",
+            volume_id,
+            "snapshots",
+        ])?)
+        .await
+    }
+
+    /// Block until a machine reaches `state` or the timeout (max 60s) passes.
+    pub async fn wait_for_state(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        instance_id: Option<&str>,
+        state: &str,
+        timeout_secs: u64,
+    ) -> Result<Value> {
+        let timeout_secs = timeout_secs.min(60);
+        let timeout = timeout_secs.to_string();
+        let mut query = vec![("state", state), ("timeout", timeout.as_str())];
+        if let Some(instance_id) = instance_id {
+            query.push(("instance_id", instance_id));
+        }
+        let path =
+            api_path_with_query(&["apps", app_name, "machines", machine_id, "wait"], &query)?;
+        // The server holds the request open, so outlast its own timeout
+        self.request_with_timeout(
+            Method::GET,
+            &path,
+            None,
+            None,
+            Some(Duration::from_secs(timeout_secs + 5)),
+        )
+        .await
+    }
+
+    /// Block until a machine reaches `state`, for up to `timeout`.
+    ///
+    /// Calls the wait endpoint in short chunks and checks the machine between
+    /// them, so timeouts beyond the endpoint's own limit work. Returns the
+    /// machine's final state.
+    pub async fn wait_until(
+        &self,
+        app_name: &str,
+        machine_id: &str,
+        state: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let chunk = remaining.as_secs().clamp(1, WAIT_CHUNK_SECS);
+
+            match self
+                .wait_for_state(app_name, machine_id, None, state, chunk)
+                .await
+            {
+                Ok(_) => return Ok(state.to_string()),
+                // The chunk ran out before the machine got there
+                Err(e) if is_wait_timeout(&e) => {}
+                // Destroyed machines eventually disappear from the API
+                Err(e) if state == "destroyed" && is_not_found(&e) => return Ok(state.to_string()),
+                Err(e) => return Err(e),
+            }
+
+            let current = match self.get_machine(app_name, machine_id).await {
+                Ok(machine) => machine.state,
+                Err(e) if state == "destroyed" && is_not_found(&e) => return Ok(state.to_string()),
+                Err(e) => return Err(e),
+            };
+            if current == state {
+                return Ok(current);
+            }
+
+            if Instant::now() + poll_interval >= deadline {
+                anyhow::bail!(
+                    "Timed out after {}s waiting for machine {} to reach {} (currently {})",
+                    timeout.as_secs(),
+                    machine_id,
+                    state,
+                    current
+                );
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+/// Whether `error` is the wait endpoint giving up (408 Request Timeout).
+fn is_wait_timeout(error: &anyhow::Error) -> bool {
+    matches!(
+        FlyError::classify(error),
+        FlyError::Api {
+            status: Some(408),
+            ..
+        }
+    )
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    matches!(FlyError::classify(error), FlyError::NotFound(_))
+}
+
+/// Lease endpoints wrap the lease in a `data` envelope.
+#[derive(Deserialize)]
+struct LeaseResponse {
+    data: MachineLease,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_segments_are_encoded() {
+        assert_eq!(
+            api_path(&[
+                "apps",
+                "web",
+                "machines",
+                "abc/../stop",
+                "metadata",
+                "a b?#"
+            ])
+            .unwrap(),
+            "/apps/web/machines/abc%2F..%2Fstop/metadata/a%20b%3F%23"
+        );
+    }
+
+    #[test]
+    fn queries_are_encoded() {
+        assert_eq!(
+            api_path_with_query(
+                &["apps", "web", "machines", "m1", "wait"],
+                &[("state", "started&force=true"), ("timeout", "60")],
+            )
+            .unwrap(),
+            "/apps/web/machines/m1/wait?state=started%26force%3Dtrue&timeout=60"
+        );
+    }
+
+    #[test]
+    fn dot_and_empty_segments_are_rejected() {
+        for segment in ["", ".", ".."] {
+            assert!(api_path(&["apps", "web", "machines", segment]).is_err());
+        }
+    }
+}
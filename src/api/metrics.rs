@@ -0,0 +1,192 @@
+//! Fly managed Prometheus client.
+//!
+//! Each organization has its own Prometheus endpoint that serves the
+//! `fly_instance_*` metrics for every app in the org.
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::auth::Credentials;
+use super::client_config::FlyClientConfig;
+use super::middleware::Middlewares;
+use super::rate_limit::RateLimiter;
+use super::timeout;
+use crate::error::FlyError;
+use crate::models::MetricSeries;
+
+/// PromQL queries for an app's dashboard metrics, keyed by series name.
+///
+/// `metric` is one of `cpu`, `memory`, `network` or `all`.
+pub fn app_queries(app_name: &str, metric: &str) -> Result<Vec<(&'static str, String)>> {
+    let selector = format!("app=\"{}\"", app_name);
+
+    let cpu = || {
+        // fly_instance_cpu counts centiseconds per mode
+        (
+            "cpu",
+            format!(
+                "sum(rate(fly_instance_cpu{{{}, mode!=\"idle\"}}[1m])) by (instance, region) / 100",
+                selector
+            ),
+        )
+    };
+    let memory = || {
+        (
+            "memory_bytes",
+            format!(
+                "sum(fly_instance_memory_mem_total{{{0}}} - fly_instance_memory_mem_available{{{0}}}) by (instance, region)",
+                selector
+            ),
+        )
+    };
+    let network = || {
+        vec![
+            (
+                "net_recv_bytes_per_sec",
+                format!(
+                    "sum(rate(fly_instance_net_recv_bytes{{{}}}[1m])) by (instance, region)",
+                    selector
+                ),
+            ),
+            (
+                "net_sent_bytes_per_sec",
+                format!(
+                    "sum(rate(fly_instance_net_sent_bytes{{{}}}[1m])) by (instance, region)",
+                    selector
+                ),
+            ),
+        ]
+    };
+
+    Ok(match metric {
+        "cpu" => vec![cpu()],
+        "memory" => vec![memory()],
+        "network" => network(),
+        "all" => {
+            let mut queries = vec![cpu(), memory()];
+            queries.extend(network());
+            queries
+        }
+        _ => anyhow::bail!(
+            "Unknown metric: {}. Valid metrics are: cpu, memory, network, all",
+            metric
+        ),
+    })
+}
+
+/// Fly Prometheus API client with persistent connection.
+pub struct MetricsClient {
+    client: Client,
+    credentials: Arc<Credentials>,
+    api_url: String,
+    limiter: RateLimiter,
+    middleware: Middlewares,
+}
+
+impl MetricsClient {
+    /// Create a new Prometheus API client.
+    pub fn new(credentials: Arc<Credentials>, config: &FlyClientConfig) -> Result<Self> {
+        let client = config.http_client()?;
+
+        Ok(Self {
+            client,
+            credentials,
+            api_url: config.api_url.clone(),
+            limiter: RateLimiter::from_env(),
+            middleware: config.middleware.clone(),
+        })
+    }
+
+    /// Run a PromQL range query over `[start, end]` (unix seconds).
+    pub async fn query_range(
+        &self,
+        org_slug: &str,
+        query: &str,
+        start: i64,
+        end: i64,
+        step_secs: u64,
+    ) -> Result<Vec<MetricSeries>> {
+        let url = format!(
+            "{}/prometheus/{}/api/v1/query_range",
+            self.api_url, org_slug
+        );
+        let params = [
+            ("query", query.to_string()),
+            ("start", start.to_string()),
+            ("end", end.to_string()),
+            ("step", step_secs.to_string()),
+        ];
+
+        let authorization = self.credentials.authorization().await?;
+        self.limiter.acquire().await;
+        let request = self
+            .client
+            .get(&url)
+            .header("Authorization", authorization)
+            .query(&params);
+        let response = self
+            .middleware
+            .send("prometheus", timeout::apply(request))
+            .await
+            .context("Failed to send Prometheus request")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow::Error::new(FlyError::from_status(status, text))
+                .context("Prometheus query failed"));
+        }
+
+        #[derive(Deserialize)]
+        struct QueryResponse {
+            status: String,
+            #[serde(default)]
+            error: Option<String>,
+            #[serde(default)]
+            data: Option<QueryData>,
+        }
+
+        #[derive(Deserialize)]
+        struct QueryData {
+            result: Vec<RangeResult>,
+        }
+
+        #[derive(Deserialize)]
+        struct RangeResult {
+            metric: HashMap<String, String>,
+            // Prometheus encodes sample values as strings
+            values: Vec<(f64, String)>,
+        }
+
+        let result: QueryResponse = response
+            .json()
+            .await
+            .context("Failed to parse Prometheus response")?;
+
+        if result.status != "success" {
+            anyhow::bail!(
+                "Prometheus query failed: {}",
+                result.error.unwrap_or(result.status)
+            );
+        }
+
+        Ok(result
+            .data
+            .map(|d| d.result)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| MetricSeries {
+                instance: r.metric.get("instance").cloned(),
+                region: r.metric.get("region").cloned(),
+                points: r
+                    .values
+                    .into_iter()
+                    .filter_map(|(ts, v)| v.parse().ok().map(|v| (ts as i64, v)))
+                    .collect(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,136 @@
+//! Hooks around every Fly API request.
+//!
+//! Embedders add `Middleware` to `FlyClientConfig::middleware` before
+//! building the service. Each hook sees every request the GraphQL,
+//! Machines, logs and Prometheus clients send, in the order added:
+//! `before_send` can add headers or answer the request itself (fault
+//! injection, replay), and `after_receive` sees the full response for
+//! logging or recording.
+//!
+//! With no middleware configured, requests go out untouched and response
+//! bodies are streamed as before; with any, each body is read once up front
+//! so hooks can see it, then handed on unchanged.
+
+use anyhow::Context;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Request, RequestBuilder, Response, StatusCode};
+use std::fmt;
+use std::sync::Arc;
+
+use super::stats;
+
+/// A complete response, as seen by `after_receive` or returned by
+/// `before_send` in place of a real one.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    fn into_response(self) -> Response {
+        let mut response = http::Response::new(self.body);
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        Response::from(response)
+    }
+}
+
+/// Hooks run around each API request. `api` is `graphql`, `machines`,
+/// `logs` or `prometheus`.
+pub trait Middleware: Send + Sync {
+    /// Inspect or change a request before it's sent. Returning a reply
+    /// skips the network; later middleware's `before_send` is skipped too.
+    fn before_send(&self, _api: &'static str, _request: &mut Request) -> Option<Reply> {
+        None
+    }
+
+    /// See a request and the reply it got, real or not.
+    fn after_receive(&self, _api: &'static str, _request: &Request, _reply: &Reply) {}
+}
+
+/// Adds fixed headers to every request (`FGP_FLY_EXTRA_HEADERS`).
+#[derive(Debug)]
+pub struct ExtraHeaders(pub HeaderMap);
+
+impl ExtraHeaders {
+    /// Parse `Name: value` pairs separated by `;`.
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        for pair in spec.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, value) = pair
+                .split_once(':')
+                .with_context(|| format!("Expected `Name: value`, got {:?}", pair))?;
+            let name = HeaderName::try_from(name.trim())
+                .with_context(|| format!("Invalid header name {:?}", name.trim()))?;
+            let value = HeaderValue::try_from(value.trim())
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            headers.append(name, value);
+        }
+        Ok(Self(headers))
+    }
+}
+
+impl Middleware for ExtraHeaders {
+    fn before_send(&self, _api: &'static str, request: &mut Request) -> Option<Reply> {
+        for (name, value) in &self.0 {
+            request.headers_mut().insert(name, value.clone());
+        }
+        None
+    }
+}
+
+/// Middleware chain shared by a config's clients.
+#[derive(Clone, Default)]
+pub struct Middlewares(Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Middlewares({})", self.0.len())
+    }
+}
+
+impl Middlewares {
+    pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.0.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Send `request` through the chain, recording it in `stats`.
+    pub(super) async fn send(
+        &self,
+        api: &'static str,
+        request: RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        if self.is_empty() {
+            return stats::timed(api, request.send()).await;
+        }
+
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        let canned = self.0.iter().find_map(|m| m.before_send(api, &mut request));
+        let seen = request.try_clone();
+
+        let reply = match canned {
+            Some(reply) => stats::timed(api, async { Ok(reply.into_response()) }).await?,
+            None => stats::timed(api, client.execute(request)).await?,
+        };
+        let Some(seen) = seen else {
+            return Ok(reply);
+        };
+
+        let reply = Reply {
+            status: reply.status(),
+            headers: reply.headers().clone(),
+            body: reply.bytes().await?.to_vec(),
+        };
+        for middleware in &self.0 {
+            middleware.after_receive(api, &seen, &reply);
+        }
+        Ok(reply.into_response())
+    }
+}
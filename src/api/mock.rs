@@ -0,0 +1,393 @@
+//! In-memory `FlyApi` with canned responses (feature `test-support`).
+//!
+//! Responses are keyed by trait method name and given as the JSON the
+//! method's return type deserializes from (see `fixtures`):
+//!
+//! ```ignore
+//! let api = MockFlyApi::with_fixtures().fail("list_secrets", "App not found: web");
+//! let service = FlyService::with_api(config, Arc::new(api))?;
+//! ```
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::auth::Credentials;
+use super::fly_api::FlyApi;
+use crate::models::{
+    App, AppStatus, BillingSummary, Build, Certificate, DeployToken, ImageRef, IpAddress, LogPage,
+    Machine, MachineEvent, OrganizationDetail, Page, Region, Release, RemoteBuilder, Secret,
+    StopReason, UserInfo, VmSize, WireGuardPeer, WireGuardPeerCreated,
+};
+
+/// `FlyApi` that answers from canned responses and records every call.
+pub struct MockFlyApi {
+    credentials: Credentials,
+    responses: Mutex<HashMap<String, Result<Value, String>>>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl Default for MockFlyApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockFlyApi {
+    pub fn new() -> Self {
+        Self {
+            credentials: Credentials::new("mock-token".to_string(), None),
+            responses: Mutex::new(HashMap::new()),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answer every call to `method` with `value`.
+    pub fn respond(self, method: &str, value: Value) -> Self {
+        self.set(method, Ok(value));
+        self
+    }
+
+    /// Fail every call to `method` with `message`.
+    pub fn fail(self, method: &str, message: &str) -> Self {
+        self.set(method, Err(message.to_string()));
+        self
+    }
+
+    /// Replace the response for `method` after construction.
+    pub fn set(&self, method: &str, response: Result<Value, String>) {
+        self.responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(method.to_string(), response);
+    }
+
+    /// Method names called so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn canned<T: DeserializeOwned + Send + 'static>(
+        &self,
+        method: &str,
+    ) -> BoxFuture<'_, Result<T>> {
+        self.calls
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(method.to_string());
+        let response = self
+            .responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(method)
+            .cloned();
+        let method = method.to_string();
+
+        Box::pin(async move {
+            match response {
+                Some(Ok(value)) => serde_json::from_value(value).map_err(|e| {
+                    anyhow::anyhow!("Canned response for {} is invalid: {}", method, e)
+                }),
+                Some(Err(message)) => Err(anyhow::anyhow!(message)),
+                None => Err(anyhow::anyhow!("MockFlyApi has no response for {}", method)),
+            }
+        })
+    }
+}
+
+impl FlyApi for MockFlyApi {
+    fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    fn ping(&self) -> BoxFuture<'_, Result<bool>> {
+        self.canned("ping")
+    }
+
+    fn get_viewer_id(&self) -> BoxFuture<'_, Result<String>> {
+        self.canned("get_viewer_id")
+    }
+
+    fn list_apps<'a>(
+        &'a self,
+        _limit: Option<i32>,
+        _after: Option<&'a str>,
+        _organization_id: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Page<App>>> {
+        self.canned("list_apps")
+    }
+
+    fn get_app_status<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<AppStatus>> {
+        self.canned("get_app_status")
+    }
+
+    fn list_releases<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _limit: Option<i32>,
+        _after: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Page<Release>>> {
+        self.canned("list_releases")
+    }
+
+    fn list_machines<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<Vec<Machine>>> {
+        self.canned("list_machines")
+    }
+
+    fn list_machines_with_config<'a>(
+        &'a self,
+        _app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Machine>>> {
+        self.canned("list_machines_with_config")
+    }
+
+    fn list_machine_events<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _machine_id: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<MachineEvent>>> {
+        self.canned("list_machine_events")
+    }
+
+    fn get_machine_stopped_reason<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _machine_id: &'a str,
+    ) -> BoxFuture<'a, Result<StopReason>> {
+        self.canned("get_machine_stopped_reason")
+    }
+
+    fn get_logs<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _region: Option<&'a str>,
+        _instance: Option<&'a str>,
+        _next_token: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<LogPage>> {
+        self.canned("get_logs")
+    }
+
+    fn get_user(&self) -> BoxFuture<'_, Result<UserInfo>> {
+        self.canned("get_user")
+    }
+
+    fn list_regions(&self) -> BoxFuture<'_, Result<Vec<Region>>> {
+        self.canned("list_regions")
+    }
+
+    fn list_vm_sizes(&self) -> BoxFuture<'_, Result<Vec<VmSize>>> {
+        self.canned("list_vm_sizes")
+    }
+
+    fn get_backup_regions<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        self.canned("get_backup_regions")
+    }
+
+    fn list_secrets<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<Vec<Secret>>> {
+        self.canned("list_secrets")
+    }
+
+    fn set_secret<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _key: &'a str,
+        _value: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("set_secret")
+    }
+
+    fn set_secrets<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _secrets: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("set_secrets")
+    }
+
+    fn delete_secret<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _key: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("delete_secret")
+    }
+
+    fn unset_secrets<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("unset_secrets")
+    }
+
+    fn list_ips<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<Vec<IpAddress>>> {
+        self.canned("list_ips")
+    }
+
+    fn allocate_ip<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _ip_type: &'a str,
+        _region: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("allocate_ip")
+    }
+
+    fn release_ip<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _address: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("release_ip")
+    }
+
+    fn list_certificates<'a>(
+        &'a self,
+        _app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Certificate>>> {
+        self.canned("list_certificates")
+    }
+
+    fn check_certificate<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Certificate>> {
+        self.canned("check_certificate")
+    }
+
+    fn add_certificate<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Certificate>> {
+        self.canned("add_certificate")
+    }
+
+    fn delete_certificate<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _hostname: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("delete_certificate")
+    }
+
+    fn restart_app<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<Value>> {
+        self.canned("restart_app")
+    }
+
+    fn create_app<'a>(
+        &'a self,
+        _name: &'a str,
+        _org_slug: &'a str,
+        _region: Option<&'a str>,
+        _network: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<App>> {
+        self.canned("create_app")
+    }
+
+    fn delete_app<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<Value>> {
+        self.canned("delete_app")
+    }
+
+    fn move_app<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _org_slug: &'a str,
+    ) -> BoxFuture<'a, Result<App>> {
+        self.canned("move_app")
+    }
+
+    fn list_organizations(&self) -> BoxFuture<'_, Result<Vec<OrganizationDetail>>> {
+        self.canned("list_organizations")
+    }
+
+    fn get_organization<'a>(&'a self, _slug: &'a str) -> BoxFuture<'a, Result<OrganizationDetail>> {
+        self.canned("get_organization")
+    }
+
+    fn get_billing<'a>(&'a self, _slug: &'a str) -> BoxFuture<'a, Result<BillingSummary>> {
+        self.canned("get_billing")
+    }
+
+    fn get_app_org_slug<'a>(&'a self, _app_name: &'a str) -> BoxFuture<'a, Result<String>> {
+        self.canned("get_app_org_slug")
+    }
+
+    fn list_builds<'a>(
+        &'a self,
+        _app_name: &'a str,
+        _limit: Option<i32>,
+    ) -> BoxFuture<'a, Result<Vec<Build>>> {
+        self.canned("list_builds")
+    }
+
+    fn get_remote_builder<'a>(
+        &'a self,
+        _org_slug: &'a str,
+    ) -> BoxFuture<'a, Result<RemoteBuilder>> {
+        self.canned("get_remote_builder")
+    }
+
+    fn get_latest_image<'a>(
+        &'a self,
+        _app_name: &'a str,
+    ) -> BoxFuture<'a, Result<Option<ImageRef>>> {
+        self.canned("get_latest_image")
+    }
+
+    fn list_deploy_tokens<'a>(
+        &'a self,
+        _app_name: Option<&'a str>,
+        _org_slug: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Vec<DeployToken>>> {
+        self.canned("list_deploy_tokens")
+    }
+
+    fn create_deploy_token<'a>(
+        &'a self,
+        _app_name: Option<&'a str>,
+        _org_slug: Option<&'a str>,
+        _name: &'a str,
+        _expiry: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(DeployToken, String)>> {
+        self.canned("create_deploy_token")
+    }
+
+    fn revoke_deploy_token<'a>(&'a self, _id: &'a str) -> BoxFuture<'a, Result<Value>> {
+        self.canned("revoke_deploy_token")
+    }
+
+    fn list_postgres_clusters(&self) -> BoxFuture<'_, Result<Vec<App>>> {
+        self.canned("list_postgres_clusters")
+    }
+
+    fn list_wireguard_peers<'a>(
+        &'a self,
+        _org_slug: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<WireGuardPeer>>> {
+        self.canned("list_wireguard_peers")
+    }
+
+    fn add_wireguard_peer<'a>(
+        &'a self,
+        _org_slug: &'a str,
+        _name: &'a str,
+        _region: &'a str,
+        _pubkey: &'a str,
+        _network: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<WireGuardPeerCreated>> {
+        self.canned("add_wireguard_peer")
+    }
+
+    fn remove_wireguard_peer<'a>(
+        &'a self,
+        _org_slug: &'a str,
+        _name: &'a str,
+    ) -> BoxFuture<'a, Result<Value>> {
+        self.canned("remove_wireguard_peer")
+    }
+}
@@ -0,0 +1,72 @@
+//! Machines API stand-in on a local wiremock server (feature `test-support`).
+//!
+//! Tests mount the responses they expect on `server` and hand `client()` to
+//! the code under test:
+//!
+//! ```ignore
+//! let api = MockMachines::start().await;
+//! Mock::given(method("GET"))
+//!     .and(path(api.path(&["apps", "web", "machines"])))
+//!     .respond_with(ResponseTemplate::new(200).set_body_json(json!([machine_json("m1", "started", "iad", "web:v1")])))
+//!     .mount(&api.server)
+//!     .await;
+//! ```
+
+use serde_json::Value;
+use std::sync::Arc;
+use wiremock::MockServer;
+
+use super::auth::Credentials;
+use super::client_config::FlyClientConfig;
+use super::machines::MachinesClient;
+use crate::models::Machine;
+
+/// A wiremock server and a `MachinesClient` pointed at it.
+pub struct MockMachines {
+    pub server: MockServer,
+    client: MachinesClient,
+}
+
+impl MockMachines {
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        let config = FlyClientConfig {
+            machines_url: format!("{}/v1", server.uri()),
+            ..FlyClientConfig::default()
+        };
+        let credentials = Arc::new(Credentials::new("fo1_test".into(), None));
+        let client = MachinesClient::new(credentials, &config).expect("client");
+        Self { server, client }
+    }
+
+    pub fn client(&self) -> &MachinesClient {
+        &self.client
+    }
+
+    /// Request path of a Machines API endpoint, e.g. `["apps", "web", "machines"]`.
+    pub fn path(&self, segments: &[&str]) -> String {
+        format!("/v1/{}", segments.join("/"))
+    }
+}
+
+/// A machine as the Machines API returns it.
+pub fn machine_json(id: &str, state: &str, region: &str, image: &str) -> Value {
+    serde_json::json!({
+        "id": id,
+        "name": format!("web-{}", id),
+        "state": state,
+        "region": region,
+        "instance_id": format!("{}-instance", id),
+        "config": {
+            "image": image,
+            "guest": { "cpu_kind": "shared", "cpus": 1, "memory_mb": 256 },
+            "metadata": { "fly_process_group": "app" }
+        },
+        "created_at": "2026-10-01T12:00:00Z"
+    })
+}
+
+/// `machine_json` parsed into a `Machine`.
+pub fn machine(id: &str, state: &str, region: &str, image: &str) -> Machine {
+    serde_json::from_value(machine_json(id, state, region, image)).expect("machine")
+}
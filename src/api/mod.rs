@@ -1,5 +1,45 @@
 //! Fly.io API client module.
 
+mod auth;
 mod client;
+mod client_config;
+#[cfg(feature = "test-support")]
+#[allow(dead_code)] // Used by tests only
+pub mod fake_fly;
+#[cfg(feature = "test-support")]
+#[allow(dead_code)] // Used by tests only
+pub mod fixtures;
+mod fly_api;
+mod machines;
+mod metrics;
+mod middleware;
+#[cfg(feature = "test-support")]
+#[allow(dead_code)] // Used by tests only
+mod mock;
+#[cfg(feature = "test-support")]
+#[allow(dead_code)] // Used by tests only
+pub mod mock_machines;
+mod operations;
+mod queries;
+mod rate_limit;
+mod recording;
+mod response;
+mod retry;
+pub mod stats;
+mod timeout;
+mod warnings;
 
-pub use client::FlyClient;
+pub use auth::{run_token_command, Credentials, TokenKind, TokenScope};
+pub use client::{collect_all, FlyClient, PAGE_SIZE};
+pub use client_config::FlyClientConfig;
+pub use fly_api::FlyApi;
+pub use machines::MachinesClient;
+pub use metrics::{app_queries, MetricsClient};
+#[allow(unused_imports)] // For embedders
+pub use middleware::{Middleware, Reply};
+#[cfg(feature = "test-support")]
+#[allow(unused_imports)]
+pub use mock::MockFlyApi;
+pub use recording::FixtureMode;
+pub use timeout::with_request_timeout;
+pub use warnings::{report_warnings, with_warnings};
@@ -0,0 +1,446 @@
+//! Typed GraphQL operations.
+//!
+//! Hand-written documents and the structs their responses deserialize into
+//! can drift apart: rename a field on one side and a `#[serde(default)]`
+//! field quietly reads as empty. Here the document is generated from the
+//! response structs instead. `graphql_object!` declares a struct along with
+//! the selection set that fills it, and `graphql_operation!` pairs an
+//! operation header with its variables and response type, so a field is
+//! always selected under the name it's read by.
+//!
+//! Generated documents are minified and hashed by `queries::prepare` like
+//! the hand-written ones. Field names are the struct's, camelCased, so
+//! fields here must not use `#[serde(rename)]`.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::{Allocation, App, Machine, Organization, Release, UserInfo};
+
+/// A type read from a GraphQL response, and what to select to get it.
+pub trait Selection {
+    /// Write the fields to select, each preceded by a space. Scalars
+    /// select nothing.
+    fn fields(_out: &mut String) {}
+}
+
+impl Selection for String {}
+impl Selection for bool {}
+impl Selection for i32 {}
+impl Selection for i64 {}
+impl Selection for f64 {}
+impl Selection for Value {}
+
+impl<T: Selection> Selection for Option<T> {
+    fn fields(out: &mut String) {
+        T::fields(out)
+    }
+}
+
+impl<T: Selection> Selection for Vec<T> {
+    fn fields(out: &mut String) {
+        T::fields(out)
+    }
+}
+
+/// A GraphQL operation: its variables, response type and document.
+pub trait Operation {
+    type Variables: Serialize + Send;
+    type Data: Selection + DeserializeOwned + Send;
+
+    /// The document, built from the response type once and kept.
+    fn document() -> &'static str;
+}
+
+/// Write `T`'s selection set, if it has one.
+fn selection_set<T: Selection>(out: &mut String) {
+    let mut fields = String::new();
+    T::fields(&mut fields);
+    if !fields.is_empty() {
+        out.push_str(" {");
+        out.push_str(&fields);
+        out.push_str(" }");
+    }
+}
+
+/// `header` followed by `T`'s selection set.
+fn document<T: Selection>(header: &str) -> String {
+    let mut out = header.to_string();
+    selection_set::<T>(&mut out);
+    out
+}
+
+/// `created_at` -> `createdAt`.
+fn camel_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Declare a response struct and its selection set.
+///
+/// A field may take arguments, written as a string after its name:
+/// `apps("first: $first"): AppConnection`. A leading `..name: Type` entry
+/// flattens `Type`'s fields into this one.
+macro_rules! graphql_object {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(.. $flat:ident: $flat_ty:ty,)?
+            $(
+                $(#[$field_meta:meta])*
+                $field:ident $(($args:literal))?: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        $vis struct $name {
+            $(
+                #[serde(flatten)]
+                pub $flat: $flat_ty,
+            )?
+            $(
+                $(#[$field_meta])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl Selection for $name {
+            fn fields(out: &mut String) {
+                $(<$flat_ty as Selection>::fields(out);)?
+                $(
+                    out.push(' ');
+                    out.push_str(&camel_case(stringify!($field)));
+                    $(
+                        out.push('(');
+                        out.push_str($args);
+                        out.push(')');
+                    )?
+                    selection_set::<$ty>(out);
+                )*
+            }
+        }
+    };
+}
+
+/// Declare an operation from its header, variables and response type.
+macro_rules! graphql_operation {
+    (
+        $(#[$meta:meta])*
+        $vis:vis $name:ident($header:literal): $variables:ty => $data:ty;
+    ) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl Operation for $name {
+            type Variables = $variables;
+            type Data = $data;
+
+            fn document() -> &'static str {
+                static DOCUMENT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                DOCUMENT.get_or_init(|| document::<$data>($header))
+            }
+        }
+    };
+}
+
+graphql_object! {
+    /// GraphQL connection pagination info.
+    pub struct PageInfo {
+        has_next_page: bool,
+        #[serde(default)]
+        end_cursor: Option<String>,
+    }
+}
+
+impl PageInfo {
+    pub fn next_cursor(self) -> Option<String> {
+        if self.has_next_page {
+            self.end_cursor
+        } else {
+            None
+        }
+    }
+}
+
+graphql_object! {
+    pub struct OrganizationNode {
+        id: String,
+        name: String,
+        slug: String,
+    }
+}
+
+impl From<OrganizationNode> for Organization {
+    fn from(node: OrganizationNode) -> Self {
+        Organization {
+            id: node.id,
+            name: node.name,
+            slug: node.slug,
+        }
+    }
+}
+
+graphql_object! {
+    pub struct ReleaseNode {
+        id: String,
+        version: i32,
+        status: String,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        created_at: Option<String>,
+    }
+}
+
+impl From<ReleaseNode> for Release {
+    fn from(node: ReleaseNode) -> Self {
+        Release {
+            id: node.id,
+            version: node.version,
+            status: node.status,
+            description: node.description,
+            user: None,
+            created_at: node.created_at,
+        }
+    }
+}
+
+graphql_object! {
+    pub struct AppNode {
+        id: String,
+        name: String,
+        #[serde(default)]
+        status: String,
+        #[serde(default)]
+        deployed: bool,
+        #[serde(default)]
+        hostname: Option<String>,
+        #[serde(default)]
+        organization: Option<OrganizationNode>,
+        #[serde(default)]
+        current_release: Option<ReleaseNode>,
+        #[serde(default)]
+        created_at: Option<String>,
+    }
+}
+
+impl From<AppNode> for App {
+    fn from(node: AppNode) -> Self {
+        App {
+            id: node.id,
+            name: node.name,
+            status: node.status,
+            deployed: node.deployed,
+            hostname: node.hostname,
+            organization: node.organization.map(Organization::from),
+            current_release: node.current_release.map(Release::from),
+            created_at: node.created_at,
+        }
+    }
+}
+
+graphql_object! {
+    pub struct MachineNode {
+        id: String,
+        name: String,
+        state: String,
+        region: String,
+    }
+}
+
+impl From<MachineNode> for Machine {
+    fn from(node: MachineNode) -> Self {
+        Machine {
+            id: node.id,
+            name: node.name,
+            state: node.state,
+            region: node.region,
+            instance_id: None,
+            private_ip: None,
+            config: None,
+            image_ref: None,
+            created_at: None,
+            updated_at: None,
+            checks: Vec::new(),
+            cordoned: false,
+        }
+    }
+}
+
+graphql_object! {
+    pub struct AllocationNode {
+        id: String,
+        status: String,
+        region: String,
+        #[serde(default)]
+        version: Option<i32>,
+    }
+}
+
+impl From<AllocationNode> for Allocation {
+    fn from(node: AllocationNode) -> Self {
+        Allocation {
+            id: node.id,
+            status: node.status,
+            region: node.region,
+            version: node.version,
+        }
+    }
+}
+
+// fly.apps
+
+graphql_operation! {
+    /// A page of apps, optionally in one organization.
+    pub ListApps("query($first: Int, $after: String, $organizationId: ID)"):
+        ListAppsVariables => ListAppsData;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListAppsVariables {
+    pub first: i32,
+    pub after: Option<String>,
+    pub organization_id: Option<String>,
+}
+
+graphql_object! {
+    pub struct ListAppsData {
+        apps("first: $first, after: $after, organizationId: $organizationId"): AppConnection,
+    }
+}
+
+graphql_object! {
+    pub struct AppConnection {
+        // Some apps may return null due to authorization errors
+        nodes: Vec<Option<AppNode>>,
+        page_info: PageInfo,
+    }
+}
+
+// fly.status
+
+graphql_operation! {
+    /// An app with its machines and legacy allocations.
+    pub GetAppStatus("query($name: String!)"): AppNameVariables => GetAppStatusData;
+}
+
+#[derive(Serialize)]
+pub struct AppNameVariables {
+    pub name: String,
+}
+
+graphql_object! {
+    pub struct GetAppStatusData {
+        app("name: $name"): Option<AppStatusNode>,
+    }
+}
+
+graphql_object! {
+    pub struct AppStatusNode {
+        ..app: AppNode,
+        machines: MachineNodes,
+        #[serde(default)]
+        allocations: Vec<AllocationNode>,
+    }
+}
+
+graphql_object! {
+    pub struct MachineNodes {
+        nodes: Vec<MachineNode>,
+    }
+}
+
+// fly.machines
+
+graphql_operation! {
+    /// A page of an app's machines.
+    pub ListMachinesPage("query($name: String!, $first: Int, $after: String)"):
+        AppPageVariables => ListMachinesPageData;
+}
+
+#[derive(Serialize)]
+pub struct AppPageVariables {
+    pub name: String,
+    pub first: i32,
+    pub after: Option<String>,
+}
+
+graphql_object! {
+    pub struct ListMachinesPageData {
+        app("name: $name"): AppMachines,
+    }
+}
+
+graphql_object! {
+    pub struct AppMachines {
+        machines("first: $first, after: $after"): MachineConnection,
+    }
+}
+
+graphql_object! {
+    pub struct MachineConnection {
+        nodes: Vec<MachineNode>,
+        page_info: PageInfo,
+    }
+}
+
+// fly.user
+
+graphql_operation! {
+    /// The authenticated user and their organizations.
+    pub GetUser("query"): () => GetUserData;
+}
+
+graphql_object! {
+    pub struct GetUserData {
+        viewer: ViewerNode,
+    }
+}
+
+graphql_object! {
+    pub struct ViewerNode {
+        id: String,
+        #[serde(default)]
+        email: Option<String>,
+        #[serde(default)]
+        name: Option<String>,
+        organizations: OrganizationNodes,
+    }
+}
+
+graphql_object! {
+    pub struct OrganizationNodes {
+        nodes: Vec<OrganizationNode>,
+    }
+}
+
+impl From<ViewerNode> for UserInfo {
+    fn from(node: ViewerNode) -> Self {
+        UserInfo {
+            id: node.id,
+            email: node.email,
+            name: node.name,
+            organizations: node
+                .organizations
+                .nodes
+                .into_iter()
+                .map(Organization::from)
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,681 @@
+//! GraphQL documents sent by `FlyClient`.
+//!
+//! Every document lives here rather than inline in its client method.
+//! Selection sets that several documents share are fragments, spliced in
+//! with `concat!`, so e.g. every app node asks for the same fields.
+//!
+//! `prepare` minifies a document once and caches it with its SHA-256 hash.
+//! With `FGP_FLY_PERSISTED_QUERIES` set, the client sends just the hash
+//! (Apollo's automatic persisted queries) and falls back to the full text
+//! when the server doesn't know it or doesn't support them.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+macro_rules! app_fields {
+    () => {
+        r#"
+fragment AppFields on App {
+    id
+    name
+    status
+    deployed
+    hostname
+    organization {
+        id
+        name
+        slug
+    }
+}
+"#
+    };
+}
+
+macro_rules! release_fields {
+    () => {
+        r#"
+fragment ReleaseFields on Release {
+    id
+    version
+    status
+}
+"#
+    };
+}
+
+macro_rules! organization_fields {
+    () => {
+        r#"
+fragment OrganizationFields on Organization {
+    id
+    name
+    slug
+    type
+    billingStatus
+    viewerRole
+}
+"#
+    };
+}
+
+pub const GET_VIEWER_ID: &str = r#"
+query {
+    viewer {
+        id
+    }
+}
+"#;
+
+pub const LIST_RELEASES: &str = r#"
+query($name: String!, $first: Int, $after: String) {
+    app(name: $name) {
+        releases(first: $first, after: $after) {
+            nodes {
+                id
+                version
+                status
+                description
+                user {
+                    email
+                }
+                createdAt
+            }
+            pageInfo {
+                hasNextPage
+                endCursor
+            }
+        }
+    }
+}
+"#;
+
+pub const LIST_MACHINES_WITH_CONFIG: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        machines {
+            nodes {
+                id
+                name
+                state
+                region
+                config
+            }
+        }
+    }
+}
+"#;
+
+pub const LIST_MACHINE_EVENTS: &str = r#"
+query($name: String!, $machineId: String!) {
+    app(name: $name) {
+        machine(id: $machineId) {
+            events {
+                nodes {
+                    id
+                    kind
+                    status
+                    source
+                    timestamp
+                    ... on MachineEventExit {
+                        exitCode
+                        oomKilled
+                        requestedStop
+                    }
+                }
+            }
+        }
+    }
+}
+"#;
+
+pub const LIST_REGIONS: &str = r#"
+query {
+    platform {
+        regions {
+            code
+            name
+            gatewayAvailable
+            requiresPaidPlan
+            latitude
+            longitude
+        }
+    }
+}
+"#;
+
+pub const LIST_VM_SIZES: &str = r#"
+query {
+    platform {
+        vmSizes {
+            name
+            cpuCores
+            memoryMb
+            priceMonth
+            priceSecond
+        }
+    }
+}
+"#;
+
+pub const GET_BACKUP_REGIONS: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        backupRegions {
+            code
+        }
+    }
+}
+"#;
+
+pub const LIST_SECRETS: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        secrets {
+            name
+            digest
+            createdAt
+        }
+    }
+}
+"#;
+
+pub const SET_SECRETS: &str = concat!(
+    r#"
+mutation($input: SetSecretsInput!) {
+    setSecrets(input: $input) {
+        release {
+            ...ReleaseFields
+        }
+    }
+}
+"#,
+    release_fields!(),
+);
+
+pub const UNSET_SECRETS: &str = concat!(
+    r#"
+mutation($input: UnsetSecretsInput!) {
+    unsetSecrets(input: $input) {
+        release {
+            ...ReleaseFields
+        }
+    }
+}
+"#,
+    release_fields!(),
+);
+
+pub const LIST_IPS: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        ipAddresses {
+            nodes {
+                id
+                address
+                type
+                region
+                createdAt
+            }
+        }
+    }
+}
+"#;
+
+pub const ALLOCATE_IP: &str = r#"
+mutation($input: AllocateIPAddressInput!) {
+    allocateIpAddress(input: $input) {
+        ipAddress {
+            id
+            address
+            type
+            region
+            createdAt
+        }
+        app {
+            sharedIpAddress
+        }
+    }
+}
+"#;
+
+pub const RELEASE_IP: &str = r#"
+mutation($input: ReleaseIPAddressInput!) {
+    releaseIpAddress(input: $input) {
+        app {
+            name
+        }
+    }
+}
+"#;
+
+pub const LIST_CERTIFICATES_PAGE: &str = r#"
+query($name: String!, $first: Int, $after: String) {
+    app(name: $name) {
+        certificates(first: $first, after: $after) {
+            nodes {
+                id
+                hostname
+                clientStatus
+                configured
+                acmeDnsConfigured
+                acmeAlpnConfigured
+                dnsValidationHostname
+                dnsValidationTarget
+                dnsValidationInstructions
+                createdAt
+                issued {
+                    nodes {
+                        type
+                        expiresAt
+                    }
+                }
+            }
+            pageInfo {
+                hasNextPage
+                endCursor
+            }
+        }
+    }
+}
+"#;
+
+pub const CHECK_CERTIFICATE: &str = r#"
+query($name: String!, $hostname: String!) {
+    app(name: $name) {
+        certificate(hostname: $hostname) {
+            id
+            hostname
+            clientStatus
+            configured
+            acmeDnsConfigured
+            acmeAlpnConfigured
+            dnsValidationHostname
+            dnsValidationTarget
+            dnsValidationInstructions
+            createdAt
+            issued {
+                nodes {
+                    type
+                    expiresAt
+                }
+            }
+        }
+    }
+}
+"#;
+
+pub const ADD_CERTIFICATE: &str = r#"
+mutation($appId: ID!, $hostname: String!) {
+    addCertificate(appId: $appId, hostname: $hostname) {
+        certificate {
+            id
+            hostname
+            clientStatus
+            configured
+            acmeDnsConfigured
+            acmeAlpnConfigured
+            dnsValidationHostname
+            dnsValidationTarget
+            dnsValidationInstructions
+            createdAt
+            issued {
+                nodes {
+                    type
+                    expiresAt
+                }
+            }
+        }
+    }
+}
+"#;
+
+pub const DELETE_CERTIFICATE: &str = r#"
+mutation($appId: ID!, $hostname: String!) {
+    deleteCertificate(appId: $appId, hostname: $hostname) {
+        app {
+            name
+        }
+        certificate {
+            id
+            hostname
+        }
+    }
+}
+"#;
+
+pub const RESTART_APP: &str = r#"
+mutation($input: RestartAppInput!) {
+    restartApp(input: $input) {
+        app {
+            id
+            name
+            status
+        }
+    }
+}
+"#;
+
+pub const GET_ORGANIZATION_ID: &str = r#"
+query($slug: String!) {
+    organization(slug: $slug) {
+        id
+    }
+}
+"#;
+
+pub const CREATE_APP: &str = concat!(
+    r#"
+mutation($input: CreateAppInput!) {
+    createApp(input: $input) {
+        app {
+            ...AppFields
+        }
+    }
+}
+"#,
+    app_fields!(),
+);
+
+pub const DELETE_APP: &str = r#"
+mutation($appId: ID!) {
+    deleteApp(appId: $appId) {
+        organization {
+            id
+            slug
+        }
+    }
+}
+"#;
+
+pub const MOVE_APP: &str = concat!(
+    r#"
+mutation($input: MoveAppInput!) {
+    moveApp(input: $input) {
+        app {
+            ...AppFields
+        }
+    }
+}
+"#,
+    app_fields!(),
+);
+
+pub const LIST_ORGANIZATIONS_PAGE: &str = concat!(
+    r#"
+query($first: Int, $after: String) {
+    organizations(first: $first, after: $after) {
+        nodes {
+            ...OrganizationFields
+            members {
+                totalCount
+            }
+            apps {
+                totalCount
+            }
+        }
+        pageInfo {
+            hasNextPage
+            endCursor
+        }
+    }
+}
+"#,
+    organization_fields!(),
+);
+
+pub const GET_ORGANIZATION: &str = concat!(
+    r#"
+query($slug: String!) {
+    organization(slug: $slug) {
+        ...OrganizationFields
+        members {
+            totalCount
+            edges {
+                role
+                node {
+                    id
+                    name
+                    email
+                }
+            }
+        }
+        apps {
+            totalCount
+            nodes {
+                id
+                name
+                status
+                deployed
+                hostname
+            }
+        }
+    }
+}
+"#,
+    organization_fields!(),
+);
+
+pub const GET_BILLING: &str = r#"
+query($slug: String!) {
+    organization(slug: $slug) {
+        slug
+        viewerRole
+        billingStatus
+        creditBalance
+        creditBalanceFormatted
+        currentInvoice {
+            amount
+            amountFormatted
+            periodStart
+            periodEnd
+            usage: lines {
+                category
+                amount
+                amountFormatted
+            }
+        }
+    }
+}
+"#;
+
+pub const GET_APP_ORG_SLUG: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        organization {
+            slug
+        }
+    }
+}
+"#;
+
+pub const LIST_BUILDS: &str = r#"
+query($name: String!, $first: Int) {
+    app(name: $name) {
+        builds(first: $first) {
+            nodes {
+                id
+                status
+                image
+                logsUrl
+                inProgress
+                createdBy {
+                    email
+                }
+                createdAt
+                updatedAt
+            }
+        }
+    }
+}
+"#;
+
+pub const GET_REMOTE_BUILDER: &str = r#"
+query($slug: String!) {
+    organization(slug: $slug) {
+        remoteBuilderImage
+        remoteBuilderApp {
+            name
+        }
+    }
+}
+"#;
+
+pub const GET_LATEST_IMAGE: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        latestImageDetails {
+            registry
+            repository
+            tag
+            digest
+        }
+    }
+}
+"#;
+
+pub const CREATE_DEPLOY_TOKEN: &str = r#"
+mutation($input: CreateLimitedAccessTokenInput!) {
+    createLimitedAccessToken(input: $input) {
+        limitedAccessToken {
+            id
+            name
+            expiresAt
+            tokenHeader
+        }
+    }
+}
+"#;
+
+pub const REVOKE_DEPLOY_TOKEN: &str = r#"
+mutation($input: DeleteLimitedAccessTokenInput!) {
+    deleteLimitedAccessToken(input: $input) {
+        token
+    }
+}
+"#;
+
+pub const LIST_WIREGUARD_PEERS: &str = r#"
+query($slug: String!) {
+    organization(slug: $slug) {
+        wireGuardPeers {
+            nodes {
+                id
+                name
+                network
+                region
+                peerip
+                pubkey
+            }
+        }
+    }
+}
+"#;
+
+pub const ADD_WIREGUARD_PEER: &str = r#"
+mutation($input: AddWireGuardPeerInput!) {
+    addWireGuardPeer(input: $input) {
+        network
+        endpointip
+        peerip
+        pubkey
+    }
+}
+"#;
+
+pub const REMOVE_WIREGUARD_PEER: &str = r#"
+mutation($input: RemoveWireGuardPeerInput!) {
+    removeWireGuardPeer(input: $input) {
+        organization {
+            id
+        }
+    }
+}
+"#;
+
+pub const GET_APP_IDS: &str = r#"
+query($name: String!) {
+    app(name: $name) {
+        id
+        organization {
+            id
+        }
+    }
+}
+"#;
+
+pub const LIST_POSTGRES_CLUSTERS: &str = concat!(
+    r#"
+query($after: String) {
+    apps(role: "postgres_cluster", first: 100, after: $after) {
+        nodes {
+            ...AppFields
+        }
+        pageInfo {
+            hasNextPage
+            endCursor
+        }
+    }
+}
+"#,
+    app_fields!(),
+);
+
+/// A document ready to send.
+pub struct Prepared {
+    /// The document with insignificant whitespace removed.
+    pub text: String,
+    /// Hex SHA-256 of `text`, its persisted query ID.
+    pub hash: String,
+}
+
+/// Minified form of `document`, computed once per document.
+pub fn prepare(document: &'static str) -> Arc<Prepared> {
+    static PREPARED: OnceLock<Mutex<HashMap<&'static str, Arc<Prepared>>>> = OnceLock::new();
+    let mut prepared = PREPARED
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    prepared
+        .entry(document)
+        .or_insert_with(|| {
+            let text = minify(document);
+            let hash = Sha256::digest(text.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            Arc::new(Prepared { text, hash })
+        })
+        .clone()
+}
+
+/// Drop whitespace and commas GraphQL ignores, keeping one space between
+/// names and leaving string literals alone.
+fn minify(document: &str) -> String {
+    let punctuator = |c: char| "{}()[]:=!$@|&.".contains(c);
+    let mut out = String::with_capacity(document.len());
+    let mut pending_space = false;
+    let mut chars = document.chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() || c == ',' {
+            pending_space = true;
+            continue;
+        }
+        if pending_space && !out.is_empty() && !punctuator(c) && !out.ends_with(punctuator) {
+            out.push(' ');
+        }
+        pending_space = false;
+        out.push(c);
+        if c == '"' {
+            while let Some(c) = chars.next() {
+                out.push(c);
+                match c {
+                    '\\' => out.extend(chars.next()),
+                    '"' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,83 @@
+//! Client-side token-bucket rate limiting for outgoing API requests.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default sustained request rate.
+const DEFAULT_RATE: f64 = 5.0;
+/// Default number of requests allowed in a burst.
+const DEFAULT_BURST: f64 = 10.0;
+
+/// Token bucket shared by every request a client sends.
+///
+/// Callers that find the bucket empty reserve a token anyway and sleep until
+/// it would have been refilled, so waiters are released in arrival order.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Tokens added per second; zero disables throttling.
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `rate` requests per second with `burst` headroom.
+    pub fn new(rate: f64, burst: f64) -> Self {
+        let burst = burst.max(1.0);
+        Self {
+            rate: rate.max(0.0),
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Build a limiter from `FGP_FLY_RATE_LIMIT_*` env vars, falling back to defaults.
+    pub fn from_env() -> Self {
+        let rate = env_f64("FGP_FLY_RATE_LIMIT_RPS").unwrap_or(DEFAULT_RATE);
+        let burst = env_f64("FGP_FLY_RATE_LIMIT_BURST").unwrap_or(DEFAULT_BURST);
+        Self::new(rate, burst)
+    }
+
+    /// Wait until a request may be sent.
+    pub async fn acquire(&self) {
+        if self.rate == 0.0 {
+            return;
+        }
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+            bucket.refilled_at = now;
+
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        };
+
+        tracing::debug!(
+            wait_ms = wait.as_millis() as u64,
+            "Rate limit reached, delaying request"
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn env_f64(key: &str) -> Option<f64> {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|v: &f64| v.is_finite())
+}
@@ -0,0 +1,208 @@
+//! Record-and-replay of Fly API responses, for offline development.
+//!
+//! With `FGP_FLY_FIXTURE_MODE=record`, every response the clients get is
+//! written to a fixture file under `FGP_FLY_FIXTURE_DIR` (default
+//! `~/.fgp/services/fly/fixtures`). With `replay`, requests never reach the
+//! network: each is answered from its fixture, or with a 501 naming the
+//! missing file, so a daemon run against recorded fixtures behaves the
+//! same every time.
+//!
+//! A fixture is keyed by the API, method, path, query and request body, so
+//! the same GraphQL document with the same variables replays the same
+//! response wherever the endpoints point. Tokens are never written; request
+//! bodies aren't either (only their hash is in the key), but response
+//! bodies are saved as-is.
+
+use anyhow::{Context, Result};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, SET_COOKIE, TRANSFER_ENCODING,
+};
+use reqwest::{Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::middleware::{Middleware, Reply};
+
+const DEFAULT_FIXTURE_DIR: &str = "~/.fgp/services/fly/fixtures";
+
+/// Whether responses are being recorded or replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureMode {
+    Record,
+    Replay,
+}
+
+impl FixtureMode {
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode.trim() {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            other => anyhow::bail!(
+                "Invalid FGP_FLY_FIXTURE_MODE {:?} (expected record or replay)",
+                other
+            ),
+        }
+    }
+
+    /// `FGP_FLY_FIXTURE_MODE`, if set.
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("FGP_FLY_FIXTURE_MODE") {
+            Ok(mode) if !mode.trim().is_empty() => Self::parse(&mode).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// `FGP_FLY_FIXTURE_DIR`, or the default under `~/.fgp`.
+pub fn fixture_dir_from_env() -> PathBuf {
+    let dir = std::env::var("FGP_FLY_FIXTURE_DIR")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_FIXTURE_DIR.into());
+    PathBuf::from(shellexpand::tilde(&dir).to_string())
+}
+
+/// One recorded response.
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    /// `METHOD path?query`, for people browsing the directory.
+    request: String,
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// JSON bodies are kept as JSON so fixtures can be read and edited.
+    body: Value,
+}
+
+/// Where the fixture for `request` lives under `dir`.
+fn fixture_path(dir: &Path, api: &str, request: &Request) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(describe(request));
+    if let Some(body) = request.body().and_then(|b| b.as_bytes()) {
+        hasher.update(body);
+    }
+    let hash: String = hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    dir.join(api).join(format!("{}.json", hash))
+}
+
+fn describe(request: &Request) -> String {
+    let url = request.url();
+    match url.query() {
+        Some(query) => format!("{} {}?{}", request.method(), url.path(), query),
+        None => format!("{} {}", request.method(), url.path()),
+    }
+}
+
+/// Writes every response to its fixture file.
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn record(&self, api: &str, request: &Request, reply: &Reply) -> Result<()> {
+        let path = fixture_path(&self.dir, api, request);
+        let headers = reply
+            .headers
+            .iter()
+            .filter(|(name, _)| ![SET_COOKIE, CONTENT_LENGTH, TRANSFER_ENCODING].contains(name))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = serde_json::from_slice(&reply.body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&reply.body).into()));
+        let fixture = Fixture {
+            request: describe(request),
+            status: reply.status.as_u16(),
+            headers,
+            body,
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(&fixture)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+impl Middleware for Recorder {
+    fn after_receive(&self, api: &'static str, request: &Request, reply: &Reply) {
+        // A 304 answers a conditional read; the 200 it stands for is kept
+        if reply.status == StatusCode::NOT_MODIFIED {
+            return;
+        }
+        if let Err(e) = self.record(api, request, reply) {
+            tracing::warn!("Failed to record fixture: {:#}", e);
+        }
+    }
+}
+
+/// Answers every request from its fixture file.
+#[derive(Debug)]
+pub struct Replayer {
+    dir: PathBuf,
+}
+
+impl Replayer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn replay(&self, api: &str, request: &Request) -> Result<Reply> {
+        let path = fixture_path(&self.dir, api, request);
+        let text = std::fs::read_to_string(&path).with_context(|| {
+            format!(
+                "No fixture for {} {} at {}",
+                api,
+                describe(request),
+                path.display()
+            )
+        })?;
+        let fixture: Fixture = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid fixture {}", path.display()))?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &fixture.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::try_from(value.as_str()),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        let body = match fixture.body {
+            Value::String(text) => text.into_bytes(),
+            json => json.to_string().into_bytes(),
+        };
+        Ok(Reply {
+            status: StatusCode::from_u16(fixture.status)?,
+            headers,
+            body,
+        })
+    }
+}
+
+impl Middleware for Replayer {
+    fn before_send(&self, api: &'static str, request: &mut Request) -> Option<Reply> {
+        Some(self.replay(api, request).unwrap_or_else(|e| {
+            let message = format!("{:#}", e);
+            tracing::warn!("{}", message);
+            Reply {
+                status: StatusCode::NOT_IMPLEMENTED,
+                headers: HeaderMap::new(),
+                body: message.into_bytes(),
+            }
+        }))
+    }
+}
@@ -0,0 +1,89 @@
+//! Response body parsing with safe error reporting.
+//!
+//! Parse failures never echo the body. When `FGP_FLY_DEBUG_BODIES` is set,
+//! the body is written, with sensitive fields redacted, to a file under
+//! `~/.fgp/services/fly/debug/` and the error points at it.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::redact;
+
+const DEBUG_DIR: &str = "~/.fgp/services/fly/debug";
+
+/// A response body that didn't match the expected shape.
+#[derive(Debug)]
+pub struct ParseError {
+    /// Which API produced the body, e.g. `graphql`.
+    pub api: &'static str,
+    pub message: String,
+    pub body_len: usize,
+    /// Where the redacted body was saved, if debug bodies are enabled.
+    pub debug_file: Option<PathBuf>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to parse {} response ({} bytes): {}",
+            self.api, self.body_len, self.message
+        )?;
+        if let Some(path) = &self.debug_file {
+            write!(f, " (body saved to {})", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Deserialize a response body from `api`.
+pub fn parse<T: for<'de> Deserialize<'de>>(api: &'static str, text: &str) -> Result<T, ParseError> {
+    serde_json::from_str(text).map_err(|e| ParseError {
+        api,
+        message: e.to_string(),
+        body_len: text.len(),
+        debug_file: save_debug_body(api, text),
+    })
+}
+
+/// Whether `FGP_FLY_DEBUG_BODIES` asks for bodies to be kept.
+fn debug_bodies_enabled() -> bool {
+    matches!(
+        std::env::var("FGP_FLY_DEBUG_BODIES").as_deref(),
+        Ok("1") | Ok("true") | Ok("yes")
+    )
+}
+
+/// Write the redacted body to the debug directory.
+fn save_debug_body(api: &str, text: &str) -> Option<PathBuf> {
+    if !debug_bodies_enabled() {
+        return None;
+    }
+
+    let body = match serde_json::from_str::<Value>(text) {
+        Ok(mut value) => {
+            redact::sanitize_json(&mut value);
+            serde_json::to_string_pretty(&value).unwrap_or_default()
+        }
+        Err(_) => redact::scrub(text),
+    };
+
+    let dir = PathBuf::from(shellexpand::tilde(DEBUG_DIR).to_string());
+    let path = dir.join(format!(
+        "{}-{}.json",
+        api,
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f")
+    ));
+    let written = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, body));
+    match written {
+        Ok(()) => Some(path),
+        Err(e) => {
+            tracing::warn!("Failed to save debug body to {}: {}", path.display(), e);
+            None
+        }
+    }
+}
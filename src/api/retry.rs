@@ -0,0 +1,86 @@
+//! Retry policy with exponential backoff for transient API failures.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+/// Retry settings for outgoing API requests.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Randomize each delay between zero and the backoff ("full jitter").
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Build a policy from `FGP_FLY_RETRY_*` env vars, falling back to defaults.
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+
+        if let Some(attempts) = env_u64("FGP_FLY_RETRY_MAX_ATTEMPTS") {
+            policy.max_attempts = attempts.max(1) as u32;
+        }
+        if let Some(ms) = env_u64("FGP_FLY_RETRY_BASE_MS") {
+            policy.base_delay = Duration::from_millis(ms);
+        }
+        if let Some(ms) = env_u64("FGP_FLY_RETRY_MAX_MS") {
+            policy.max_delay = Duration::from_millis(ms);
+        }
+        if let Ok(value) = std::env::var("FGP_FLY_RETRY_JITTER") {
+            policy.jitter = !matches!(value.as_str(), "0" | "false" | "no");
+        }
+
+        policy
+    }
+
+    /// Delay before retry number `attempt` (1-based).
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        if !self.jitter || backoff.is_zero() {
+            return backoff;
+        }
+
+        let millis = backoff.as_millis() as u64;
+        Duration::from_millis(random_u64() % (millis + 1))
+    }
+}
+
+/// Whether an HTTP status is worth retrying.
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Whether a transport error is worth retrying.
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request()
+}
+
+fn env_u64(key: &str) -> Option<u64> {
+    std::env::var(key).ok().and_then(|v| v.trim().parse().ok())
+}
+
+/// Cheap randomness for jitter without pulling in an RNG crate.
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
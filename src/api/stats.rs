@@ -0,0 +1,233 @@
+//! Request counters and latencies for the Fly API clients.
+//!
+//! Every outgoing request is timed through `timed`, which records the
+//! latency, the status code (or transport failure) and the number of
+//! requests in flight. The counters are process-wide so all profiles and
+//! clients report into one place.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Idle connections each client keeps open per host, unless configured.
+pub const POOL_MAX_IDLE_PER_HOST: usize = 5;
+
+static POOL_MAX_IDLE: AtomicUsize = AtomicUsize::new(POOL_MAX_IDLE_PER_HOST);
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+pub const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Counters for one API (`graphql`, `machines`, `logs` or `prometheus`).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApiStats {
+    pub requests: u64,
+    /// Requests that failed before a response arrived.
+    pub transport_errors: u64,
+    pub retries: u64,
+    /// Responses by HTTP status code.
+    pub status_codes: BTreeMap<u16, u64>,
+    pub in_flight: u64,
+    pub peak_in_flight: u64,
+    pub latency_ms_total: f64,
+    pub latency_ms_max: f64,
+    /// Cumulative counts per `LATENCY_BUCKETS` bound.
+    #[serde(skip)]
+    pub latency_buckets: Vec<u64>,
+}
+
+impl ApiStats {
+    /// Mean latency of completed requests.
+    pub fn latency_ms_avg(&self) -> f64 {
+        let completed = self.requests.saturating_sub(self.in_flight);
+        if completed == 0 {
+            0.0
+        } else {
+            self.latency_ms_total / completed as f64
+        }
+    }
+}
+
+/// Snapshot of every API's counters.
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub since: chrono::DateTime<chrono::Utc>,
+    pub pool_max_idle_per_host: usize,
+    pub apis: BTreeMap<&'static str, Value>,
+}
+
+struct Stats {
+    since: chrono::DateTime<chrono::Utc>,
+    apis: Mutex<BTreeMap<&'static str, ApiStats>>,
+}
+
+fn stats() -> &'static Stats {
+    static STATS: OnceLock<Stats> = OnceLock::new();
+    STATS.get_or_init(|| Stats {
+        since: chrono::Utc::now(),
+        apis: Mutex::new(BTreeMap::new()),
+    })
+}
+
+fn update(api: &'static str, f: impl FnOnce(&mut ApiStats)) {
+    let mut apis = stats().apis.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = apis.entry(api).or_insert_with(|| ApiStats {
+        latency_buckets: vec![0; LATENCY_BUCKETS.len()],
+        ..Default::default()
+    });
+    f(entry);
+}
+
+/// Send a request, recording its latency and outcome under `api`.
+pub async fn timed<F>(api: &'static str, send: F) -> reqwest::Result<reqwest::Response>
+where
+    F: Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    update(api, |s| {
+        s.requests += 1;
+        s.in_flight += 1;
+        s.peak_in_flight = s.peak_in_flight.max(s.in_flight);
+    });
+
+    let started = Instant::now();
+    let result = send.await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    update(api, |s| {
+        s.in_flight -= 1;
+        match &result {
+            Ok(response) => {
+                *s.status_codes
+                    .entry(response.status().as_u16())
+                    .or_default() += 1
+            }
+            Err(_) => s.transport_errors += 1,
+        }
+        let ms = elapsed * 1000.0;
+        s.latency_ms_total += ms;
+        s.latency_ms_max = s.latency_ms_max.max(ms);
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(s.latency_buckets.iter_mut()) {
+            if elapsed <= *bound {
+                *count += 1;
+            }
+        }
+    });
+
+    result
+}
+
+/// Count a retry of a request to `api`.
+/// Record the pool size clients were built with, for `snapshot`.
+pub fn set_pool_max_idle_per_host(max: usize) {
+    POOL_MAX_IDLE.store(max, Ordering::Relaxed);
+}
+
+pub fn record_retry(api: &'static str) {
+    update(api, |s| s.retries += 1);
+}
+
+/// Current counters for every API that has been called.
+pub fn raw() -> BTreeMap<&'static str, ApiStats> {
+    stats()
+        .apis
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Counters with derived averages, for `fly.client_stats`.
+pub fn snapshot() -> StatsSnapshot {
+    let apis = raw()
+        .into_iter()
+        .map(|(api, s)| {
+            let mut value = serde_json::to_value(&s).unwrap_or_default();
+            value["latency_ms_avg"] = serde_json::json!(s.latency_ms_avg());
+            (api, value)
+        })
+        .collect();
+
+    StatsSnapshot {
+        since: stats().since,
+        pool_max_idle_per_host: POOL_MAX_IDLE.load(Ordering::Relaxed),
+        apis,
+    }
+}
+
+/// Counters in the Prometheus text exposition format.
+pub fn prometheus_text() -> String {
+    let apis = raw();
+    let mut out = String::new();
+
+    let mut counter = |name: &str, help: &str, value: &dyn Fn(&ApiStats) -> u64| {
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} counter\n",
+            name, help, name
+        ));
+        for (api, s) in &apis {
+            out.push_str(&format!("{}{{api=\"{}\"}} {}\n", name, api, value(s)));
+        }
+    };
+    counter(
+        "fgp_fly_api_requests_total",
+        "Requests sent to the Fly API.",
+        &|s| s.requests,
+    );
+    counter(
+        "fgp_fly_api_transport_errors_total",
+        "Requests that failed before a response arrived.",
+        &|s| s.transport_errors,
+    );
+    counter("fgp_fly_api_retries_total", "Retried requests.", &|s| {
+        s.retries
+    });
+
+    out.push_str("# HELP fgp_fly_api_responses_total Responses by HTTP status code.\n");
+    out.push_str("# TYPE fgp_fly_api_responses_total counter\n");
+    for (api, s) in &apis {
+        for (code, count) in &s.status_codes {
+            out.push_str(&format!(
+                "fgp_fly_api_responses_total{{api=\"{}\",code=\"{}\"}} {}\n",
+                api, code, count
+            ));
+        }
+    }
+
+    out.push_str("# HELP fgp_fly_api_in_flight Requests currently in flight.\n");
+    out.push_str("# TYPE fgp_fly_api_in_flight gauge\n");
+    for (api, s) in &apis {
+        out.push_str(&format!(
+            "fgp_fly_api_in_flight{{api=\"{}\"}} {}\n",
+            api, s.in_flight
+        ));
+    }
+
+    out.push_str("# HELP fgp_fly_api_request_duration_seconds Fly API request latency.\n");
+    out.push_str("# TYPE fgp_fly_api_request_duration_seconds histogram\n");
+    for (api, s) in &apis {
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&s.latency_buckets) {
+            out.push_str(&format!(
+                "fgp_fly_api_request_duration_seconds_bucket{{api=\"{}\",le=\"{}\"}} {}\n",
+                api, bound, count
+            ));
+        }
+        let completed = s.requests.saturating_sub(s.in_flight);
+        out.push_str(&format!(
+            "fgp_fly_api_request_duration_seconds_bucket{{api=\"{}\",le=\"+Inf\"}} {}\n",
+            api, completed
+        ));
+        out.push_str(&format!(
+            "fgp_fly_api_request_duration_seconds_sum{{api=\"{}\"}} {}\n",
+            api,
+            s.latency_ms_total / 1000.0
+        ));
+        out.push_str(&format!(
+            "fgp_fly_api_request_duration_seconds_count{{api=\"{}\"}} {}\n",
+            api, completed
+        ));
+    }
+
+    out
+}
@@ -0,0 +1,35 @@
+//! Per-call HTTP request timeouts.
+//!
+//! Clients are built with a default timeout; a call can override it for
+//! every request it makes by running inside `with_request_timeout`.
+
+use std::future::Future;
+use std::time::Duration;
+
+/// Timeout used when neither the config nor the call sets one.
+pub(super) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    static REQUEST_TIMEOUT: Duration;
+}
+
+/// Run `future` with every API request it makes limited to `timeout`.
+pub async fn with_request_timeout<F: Future>(timeout: Option<Duration>, future: F) -> F::Output {
+    match timeout {
+        Some(timeout) => REQUEST_TIMEOUT.scope(timeout, future).await,
+        None => future.await,
+    }
+}
+
+/// Timeout override for the current call, if any.
+pub(super) fn current() -> Option<Duration> {
+    REQUEST_TIMEOUT.try_with(|t| *t).ok()
+}
+
+/// Apply the current call's override to a request.
+pub(super) fn apply(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match current() {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
@@ -0,0 +1,43 @@
+//! Collection of partial-result warnings across a request.
+//!
+//! GraphQL can answer with data and field-level errors at once. Queries
+//! report those errors here, and the service gathers everything reported
+//! while handling one call so it can return them as `warnings`.
+
+use std::cell::RefCell;
+use std::future::Future;
+
+use crate::models::Warning;
+
+tokio::task_local! {
+    static WARNINGS: RefCell<Vec<Warning>>;
+}
+
+/// Run `future`, returning its output and every warning reported inside it.
+pub async fn with_warnings<F: Future>(future: F) -> (F::Output, Vec<Warning>) {
+    WARNINGS
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = future.await;
+            let warnings = WARNINGS.with(|w| w.take());
+            (output, warnings)
+        })
+        .await
+}
+
+/// Report warnings to the enclosing `with_warnings`.
+///
+/// Outside of one (background tasks) they are only logged.
+pub fn report_warnings(warnings: Vec<Warning>) {
+    if warnings.is_empty() {
+        return;
+    }
+
+    let mut pending = Some(warnings);
+    let collected =
+        WARNINGS.try_with(|w| w.borrow_mut().extend(pending.take().into_iter().flatten()));
+    if collected.is_err() {
+        for warning in pending.into_iter().flatten() {
+            tracing::debug!("GraphQL partial error: {}", warning.message);
+        }
+    }
+}
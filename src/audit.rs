@@ -0,0 +1,146 @@
+//! Append-only audit log of mutating daemon calls.
+//!
+//! Every call that changes Fly state (see `Method::mutating`) is appended
+//! as one JSON entry to the `audit` log of the daemon's state store (set
+//! `FGP_FLY_AUDIT_LOG=off` to disable). Secret values are redacted before
+//! writing. Entries from the
+//! JSONL file older versions wrote, `~/.fgp/services/fly/audit.jsonl` or
+//! the path in `FGP_FLY_AUDIT_LOG`, are copied into an empty log once.
+
+use chrono::Utc;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::FlyError;
+use crate::redact::{self, SecretParam};
+use crate::state::StateStore;
+
+const LEGACY_AUDIT_PATH: &str = "~/.fgp/services/fly/audit.jsonl";
+
+/// Name of the audit log in the state store.
+const AUDIT_LOG: &str = "audit";
+
+/// Audit log kept in the state store.
+#[derive(Clone)]
+pub struct AuditLog {
+    store: Option<Arc<dyn StateStore>>,
+}
+
+impl AuditLog {
+    /// Audit into `store`, unless `FGP_FLY_AUDIT_LOG` is `off`.
+    pub fn new(store: Arc<dyn StateStore>) -> Self {
+        let setting =
+            std::env::var("FGP_FLY_AUDIT_LOG").unwrap_or_else(|_| LEGACY_AUDIT_PATH.into());
+        let legacy = match setting.trim() {
+            "" | "off" => return Self { store: None },
+            path => PathBuf::from(shellexpand::tilde(path).to_string()),
+        };
+        if let Err(e) = import_legacy(&*store, &legacy) {
+            tracing::warn!("Failed to import audit log {}: {:#}", legacy.display(), e);
+        }
+        Self { store: Some(store) }
+    }
+
+    /// Append an entry for a call made as `profile`, with the values of the
+    /// method's `secrets` params redacted; `error` is `None` on success.
+    ///
+    /// Write failures are logged rather than failing the call, since the
+    /// change has already been made.
+    pub fn record(
+        &self,
+        method: &str,
+        profile: &str,
+        params: &HashMap<String, Value>,
+        secrets: &[SecretParam],
+        error: Option<&FlyError>,
+    ) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        let method = if method.starts_with("fly.") {
+            method.to_string()
+        } else {
+            format!("fly.{}", method)
+        };
+        let outcome = match error {
+            None => serde_json::json!({ "ok": true }),
+            Some(error) => serde_json::json!({ "ok": false, "error": error.to_value()["error"] }),
+        };
+        let entry = serde_json::json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "method": method,
+            "app": params.get("app"),
+            "caller": {
+                "profile": profile,
+                "pid": std::process::id(),
+            },
+            "params": redact::redact_params(secrets, params),
+            "result": outcome,
+        });
+
+        if let Err(e) = store.append(AUDIT_LOG, &[entry]) {
+            tracing::warn!("Failed to write audit log: {:#}", e);
+        }
+    }
+
+    /// Most recent entries first, optionally filtered by method and app.
+    pub fn recent(
+        &self,
+        limit: usize,
+        method: Option<&str>,
+        app: Option<&str>,
+    ) -> anyhow::Result<Vec<Value>> {
+        let Some(store) = &self.store else {
+            anyhow::bail!("Audit log is disabled (FGP_FLY_AUDIT_LOG=off)");
+        };
+        let method = method.map(|m| {
+            if m.starts_with("fly.") {
+                m.to_string()
+            } else {
+                format!("fly.{}", m)
+            }
+        });
+
+        Ok(store
+            .entries(AUDIT_LOG)?
+            .into_iter()
+            .rev()
+            .filter(|entry| match &method {
+                Some(m) => entry["method"].as_str() == Some(m.as_str()),
+                None => true,
+            })
+            .filter(|entry| match app {
+                Some(app) => entry["app"].as_str() == Some(app),
+                None => true,
+            })
+            .take(limit)
+            .collect())
+    }
+}
+
+/// Copy the entries of a JSONL audit file into the store's audit log, if
+/// the log is still empty.
+fn import_legacy(store: &dyn StateStore, path: &Path) -> anyhow::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !store.entries(AUDIT_LOG)?.is_empty() {
+        return Ok(());
+    }
+    let entries: Vec<Value> = text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    store.append(AUDIT_LOG, &entries)?;
+    tracing::info!(
+        "Imported {} audit entries from {}",
+        entries.len(),
+        path.display()
+    );
+    Ok(())
+}
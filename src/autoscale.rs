@@ -0,0 +1,336 @@
+//! Metric-driven autoscaling of machine counts.
+//!
+//! Each enabled app gets a daemon task that reads a load metric from Fly's
+//! Prometheus on an interval and scales the machine count towards
+//! `current * observed / target`, clamped to the rule's bounds. Scaling uses
+//! the same clone/destroy path as `fly.scale`, and a cooldown stops the count
+//! from flapping while new machines warm up.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::api::{FlyApi, MachinesClient, MetricsClient};
+use crate::scale;
+
+/// Decisions kept per app for `status`.
+const HISTORY_LEN: usize = 20;
+
+/// Seconds of metrics averaged per evaluation.
+const SAMPLE_WINDOW_SECS: i64 = 120;
+
+/// Load signal the autoscaler tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoscaleMetric {
+    /// Busy CPU cores per machine.
+    Cpu,
+    /// Concurrent requests per machine, as seen by the Fly proxy.
+    Concurrency,
+}
+
+impl AutoscaleMetric {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "cpu" => Ok(Self::Cpu),
+            "concurrency" => Ok(Self::Concurrency),
+            _ => anyhow::bail!(
+                "Unknown metric: {}. Valid metrics are: cpu, concurrency",
+                value
+            ),
+        }
+    }
+
+    /// PromQL for the per-machine average of this metric.
+    fn query(self, app_name: &str) -> String {
+        match self {
+            Self::Cpu => format!(
+                "avg(sum(rate(fly_instance_cpu{{app=\"{}\", mode!=\"idle\"}}[1m])) by (instance)) / 100",
+                app_name
+            ),
+            Self::Concurrency => format!(
+                "avg(sum(fly_app_concurrency{{app=\"{}\"}}) by (instance))",
+                app_name
+            ),
+        }
+    }
+}
+
+/// Scaling rule for one app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoscaleRule {
+    pub app: String,
+    pub min: usize,
+    pub max: usize,
+    pub metric: AutoscaleMetric,
+    /// Desired per-machine value of `metric`.
+    pub target: f64,
+    pub interval_secs: u64,
+    pub cooldown_secs: u64,
+}
+
+impl AutoscaleRule {
+    /// Check the bounds and target before enabling the rule.
+    pub fn validate(&self) -> Result<()> {
+        if self.min == 0 {
+            anyhow::bail!("min must be at least 1");
+        }
+        if self.max < self.min {
+            anyhow::bail!("max ({}) must not be below min ({})", self.max, self.min);
+        }
+        if !self.target.is_finite() || self.target <= 0.0 {
+            anyhow::bail!("target must be positive");
+        }
+        Ok(())
+    }
+
+    /// Machine count that brings the per-machine value to `target`.
+    fn desired(&self, current: usize, observed: f64) -> usize {
+        let wanted = (current.max(1) as f64 * observed / self.target).ceil() as usize;
+        wanted.clamp(self.min, self.max)
+    }
+}
+
+/// One evaluation of a rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct Decision {
+    pub at: DateTime<Utc>,
+    /// `scale_up`, `scale_down`, `hold`, `cooldown` or `error`.
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed: Option<f64>,
+    pub current: usize,
+    pub desired: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Rule plus recent decisions, as reported by `status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoscaleStatus {
+    #[serde(flatten)]
+    pub rule: AutoscaleRule,
+    pub enabled_at: DateTime<Utc>,
+    pub last_scaled_at: Option<DateTime<Utc>>,
+    /// Most recent first.
+    pub decisions: Vec<Decision>,
+}
+
+struct AppState {
+    status: Arc<Mutex<AutoscaleStatus>>,
+    task: JoinHandle<()>,
+}
+
+/// Clients an autoscaler task needs.
+#[derive(Clone)]
+pub struct AutoscaleClients {
+    pub client: Arc<dyn FlyApi>,
+    pub machines: Arc<MachinesClient>,
+    pub metrics: Arc<MetricsClient>,
+}
+
+/// Registry of enabled autoscaling rules.
+#[derive(Default)]
+pub struct Autoscaler {
+    apps: Mutex<BTreeMap<String, AppState>>,
+}
+
+impl Autoscaler {
+    /// Enable `rule`, replacing any rule already set for the app.
+    pub fn enable(&self, runtime: &Handle, clients: AutoscaleClients, rule: AutoscaleRule) {
+        let app = rule.app.clone();
+        let status = Arc::new(Mutex::new(AutoscaleStatus {
+            rule: rule.clone(),
+            enabled_at: Utc::now(),
+            last_scaled_at: None,
+            decisions: Vec::new(),
+        }));
+
+        let task = {
+            let status = status.clone();
+            runtime.spawn(async move {
+                let mut history = VecDeque::new();
+                loop {
+                    let last_scaled = status
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .last_scaled_at;
+                    let decision = evaluate(&clients, &rule, last_scaled).await;
+                    log_decision(&rule.app, &decision);
+
+                    history.push_front(decision.clone());
+                    history.truncate(HISTORY_LEN);
+                    {
+                        let mut status = status.lock().unwrap_or_else(|e| e.into_inner());
+                        if matches!(decision.action, "scale_up" | "scale_down") {
+                            status.last_scaled_at = Some(decision.at);
+                        }
+                        status.decisions = history.iter().cloned().collect();
+                    }
+
+                    tokio::time::sleep(Duration::from_secs(rule.interval_secs)).await;
+                }
+            })
+        };
+
+        let mut apps = self.apps.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = apps.insert(app, AppState { status, task }) {
+            old.task.abort();
+        }
+    }
+
+    /// Disable the rule for `app`. Returns whether one was enabled.
+    pub fn disable(&self, app: &str) -> bool {
+        let mut apps = self.apps.lock().unwrap_or_else(|e| e.into_inner());
+        match apps.remove(app) {
+            Some(state) => {
+                state.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Disable every rule.
+    pub fn disable_all(&self) {
+        let mut apps = self.apps.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, state) in std::mem::take(&mut *apps) {
+            state.task.abort();
+        }
+    }
+
+    /// Enabled rules with their recent decisions, optionally for one app.
+    pub fn status(&self, app: Option<&str>) -> Vec<AutoscaleStatus> {
+        let apps = self.apps.lock().unwrap_or_else(|e| e.into_inner());
+        apps.iter()
+            .filter(|(name, _)| app.map(|a| a == name.as_str()).unwrap_or(true))
+            .map(|(_, state)| {
+                state
+                    .status
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone()
+            })
+            .collect()
+    }
+}
+
+/// Read the metric, decide, and scale if needed.
+async fn evaluate(
+    clients: &AutoscaleClients,
+    rule: &AutoscaleRule,
+    last_scaled: Option<DateTime<Utc>>,
+) -> Decision {
+    let mut decision = Decision {
+        at: Utc::now(),
+        action: "hold",
+        observed: None,
+        current: 0,
+        desired: 0,
+        error: None,
+    };
+
+    let result: Result<()> = async {
+        let machines = clients.machines.list_machines(&rule.app).await?;
+        decision.current = machines.len();
+        decision.desired = decision.current.clamp(rule.min, rule.max);
+
+        let observed = observe(clients, rule).await?;
+        decision.observed = observed;
+        if let Some(observed) = observed {
+            decision.desired = rule.desired(decision.current, observed);
+        }
+
+        if decision.desired == decision.current {
+            return Ok(());
+        }
+        let cooling = last_scaled
+            .map(|at| (decision.at - at).num_seconds() < rule.cooldown_secs as i64)
+            .unwrap_or(false);
+        if cooling {
+            decision.action = "cooldown";
+            return Ok(());
+        }
+
+        decision.action = if decision.desired > decision.current {
+            "scale_up"
+        } else {
+            "scale_down"
+        };
+        let report =
+            scale::scale_count(&clients.machines, &rule.app, decision.desired, None).await?;
+        if let Some(failed) = report.steps.iter().find(|s| s.status == "failed") {
+            anyhow::bail!(
+                "Scaling stopped at {} machines: {}",
+                report.count,
+                failed.error.as_deref().unwrap_or("unknown error")
+            );
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        decision.error = Some(format!("{:#}", e));
+        if decision.action == "hold" {
+            decision.action = "error";
+        }
+    }
+    decision
+}
+
+/// Latest per-machine value of the rule's metric, if any machine reports it.
+async fn observe(clients: &AutoscaleClients, rule: &AutoscaleRule) -> Result<Option<f64>> {
+    let org = clients.client.get_app_org_slug(&rule.app).await?;
+    let end = Utc::now().timestamp();
+    let series = clients
+        .metrics
+        .query_range(
+            &org,
+            &rule.metric.query(&rule.app),
+            end - SAMPLE_WINDOW_SECS,
+            end,
+            60,
+        )
+        .await?;
+
+    Ok(series
+        .first()
+        .and_then(|s| s.points.last())
+        .map(|(_, value)| *value)
+        .filter(|value| value.is_finite()))
+}
+
+fn log_decision(app: &str, decision: &Decision) {
+    match decision.action {
+        "hold" => tracing::debug!(
+            "Autoscale {}: holding at {} machines (observed {:?})",
+            app,
+            decision.current,
+            decision.observed
+        ),
+        "error" => tracing::warn!(
+            "Autoscale {}: evaluation failed: {}",
+            app,
+            decision.error.as_deref().unwrap_or_default()
+        ),
+        action => tracing::info!(
+            "Autoscale {}: {} from {} to {} machines (observed {:?}){}",
+            app,
+            action,
+            decision.current,
+            decision.desired,
+            decision.observed,
+            decision
+                .error
+                .as_deref()
+                .map(|e| format!(", failed: {}", e))
+                .unwrap_or_default()
+        ),
+    }
+}
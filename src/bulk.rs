@@ -0,0 +1,136 @@
+//! Start or stop every machine in an app at once.
+//!
+//! Machines are picked by region and metadata, those already in the target
+//! state are skipped, and the rest are started or stopped with a cap on
+//! requests in flight. One machine failing doesn't stop the others; each
+//! gets its own outcome.
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::api::MachinesClient;
+use crate::models::Machine;
+
+/// What to do to each machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    Start,
+    Stop,
+}
+
+impl BulkAction {
+    /// Whether `machine` needs the action at all.
+    fn applies_to(self, machine: &Machine) -> bool {
+        match self {
+            BulkAction::Start => machine.state != "started",
+            BulkAction::Stop => machine.state == "started",
+        }
+    }
+}
+
+/// Which machines an action covers; empty fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct MachineFilter {
+    pub region: Option<String>,
+    /// Every key must be set to this value in the machine's metadata.
+    pub metadata: HashMap<String, String>,
+}
+
+impl MachineFilter {
+    fn matches(&self, machine: &Machine) -> bool {
+        if self.region.as_ref().is_some_and(|r| *r != machine.region) {
+            return false;
+        }
+        let metadata = machine.config.as_ref().map(|c| &c.metadata);
+        self.metadata
+            .iter()
+            .all(|(key, value)| metadata.and_then(|m| m.get(key)) == Some(value))
+    }
+}
+
+/// Outcome for a single machine.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineOutcome {
+    pub machine_id: String,
+    pub name: String,
+    pub region: String,
+    /// State before the action.
+    pub state: String,
+    /// `started`, `stopped`, `skipped` or `failed`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a bulk start or stop.
+#[derive(Debug, Serialize)]
+pub struct BulkReport {
+    pub app: String,
+    pub action: BulkAction,
+    /// Machines the filter matched.
+    pub matched: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub machines: Vec<MachineOutcome>,
+}
+
+/// Apply `action` to the app's machines matching `filter`, at most
+/// `concurrency` at a time.
+pub async fn run(
+    machines: &MachinesClient,
+    app_name: &str,
+    action: BulkAction,
+    filter: &MachineFilter,
+    concurrency: usize,
+) -> Result<BulkReport> {
+    let all = machines.list_machines(app_name).await?;
+    let (pending, skipped): (Vec<Machine>, Vec<Machine>) = all
+        .into_iter()
+        .filter(|m| filter.matches(m))
+        .partition(|m| action.applies_to(m));
+
+    let mut outcomes: Vec<MachineOutcome> = stream::iter(pending)
+        .map(|machine| async move {
+            let result = match action {
+                BulkAction::Start => machines.start_machine(app_name, &machine.id).await,
+                BulkAction::Stop => machines.stop_machine(app_name, &machine.id, None).await,
+            };
+            let (status, error) = match result {
+                Ok(_) if action == BulkAction::Start => ("started", None),
+                Ok(_) => ("stopped", None),
+                Err(e) => ("failed", Some(format!("{:#}", e))),
+            };
+            outcome(machine, status, error)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    outcomes.extend(skipped.into_iter().map(|m| outcome(m, "skipped", None)));
+    outcomes.sort_by(|a, b| a.machine_id.cmp(&b.machine_id));
+
+    let count = |status: &str| outcomes.iter().filter(|o| o.status == status).count();
+    Ok(BulkReport {
+        app: app_name.to_string(),
+        action,
+        matched: outcomes.len(),
+        failed: count("failed"),
+        skipped: count("skipped"),
+        succeeded: outcomes.len() - count("failed") - count("skipped"),
+        machines: outcomes,
+    })
+}
+
+fn outcome(machine: Machine, status: &'static str, error: Option<String>) -> MachineOutcome {
+    MachineOutcome {
+        machine_id: machine.id,
+        name: machine.name,
+        region: machine.region,
+        state: machine.state,
+        status,
+        error,
+    }
+}
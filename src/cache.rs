@@ -0,0 +1,70 @@
+//! In-memory TTL cache for read-only daemon methods.
+//!
+//! Dashboards tend to poll the same handful of methods; serving those from
+//! memory for a short while keeps the Fly API quiet. Methods opt in with
+//! `Method::cached`, and mutations drop the responses they make stale
+//! (`Method::invalidates`).
+
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Responses keyed by method and parameters.
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl ResponseCache {
+    /// Build a cache key from the canonical method name and its parameters.
+    ///
+    /// Parameters are sorted so equivalent calls share an entry; `no_cache`,
+    /// `timeout_ms` and `deadline_ms` don't change the result and are left
+    /// out of the key.
+    pub fn key(method: &str, params: &HashMap<String, Value>) -> String {
+        let method = method.strip_prefix("fly.").unwrap_or(method);
+        let sorted: BTreeMap<&String, &Value> = params
+            .iter()
+            .filter(|(k, _)| !matches!(k.as_str(), "no_cache" | "timeout_ms" | "deadline_ms"))
+            .collect();
+        format!(
+            "{}:{}",
+            method,
+            serde_json::to_string(&sorted).unwrap_or_default()
+        )
+    }
+
+    /// Return a cached value if it hasn't expired.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(key)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Store a value for `ttl`, dropping any expired entries.
+    pub fn insert(&self, key: String, value: Value, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, (expires_at, _)| *expires_at > now);
+        entries.insert(key, (now + ttl, value));
+    }
+
+    /// Drop the cached responses of `methods`, after a mutation changed
+    /// what they return.
+    pub fn invalidate(&self, methods: &[&str]) {
+        if methods.is_empty() {
+            return;
+        }
+        let prefixes: Vec<String> = methods
+            .iter()
+            .map(|m| format!("{}:", m.strip_prefix("fly.").unwrap_or(m)))
+            .collect();
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| !prefixes.iter().any(|p| key.starts_with(p.as_str())));
+    }
+}
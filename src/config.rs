@@ -0,0 +1,261 @@
+//! Token and profile configuration.
+//!
+//! The default token comes from `FLY_API_TOKEN` (or the older
+//! `FLY_ACCESS_TOKEN`), falling back to flyctl's `~/.fly/config.yml`.
+//! Additional named profiles are read from a JSON file:
+//!
+//! ```json
+//! {
+//!   "default": "work",
+//!   "profiles": {
+//!     "work": {"token": "fo1_...", "orgs": ["acme"]},
+//!     "personal": {"token_env": "PERSONAL_FLY_TOKEN"},
+//!     "sso": {"token_command": "my-sso-helper fly-token"}
+//!   }
+//! }
+//! ```
+//!
+//! A `token_command` prints either a bare token or
+//! `{"token": "...", "expires_at": "<RFC 3339>"}`; it is re-run before the
+//! token expires and whenever the API rejects it. Calls that pass an `org`
+//! listed in a profile's `orgs` run as that profile.
+//!
+//! A profile's `scope` (`full`, `deploy` or `read_only`) says what its token
+//! may do; `FGP_FLY_TOKEN_SCOPE` sets it for the default token. Without one
+//! it's inferred from the token kind, see `api::TokenScope`.
+//!
+//! Replaying fixtures (`FGP_FLY_FIXTURE_MODE=replay`) needs no token; the
+//! default profile gets a placeholder if none is configured.
+//!
+//! Endpoints, proxy, CA and timeout settings come from `FGP_FLY_*` env vars
+//! (see `api::FlyClientConfig`), as do concurrency limits (see `limits`) and
+//! the start mode (`FGP_FLY_START_MODE`) and health check interval
+//! (`FGP_FLY_HEALTH_INTERVAL_MS`), see `connection`.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::api::{run_token_command, FixtureMode, FlyClientConfig, TokenScope};
+use crate::connection::{self, StartMode};
+use crate::limits::LimitsConfig;
+
+/// Name of the profile built from the environment or flyctl config.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Token used when replaying fixtures without one; never sent anywhere.
+const REPLAY_TOKEN: &str = "fixture-replay";
+
+const PROFILES_PATH: &str = "~/.fgp/services/fly/profiles.json";
+
+/// Resolved tokens for every configured profile.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub default_profile: String,
+    pub profiles: HashMap<String, Profile>,
+    /// Endpoints and HTTP settings for every profile's clients.
+    pub client: FlyClientConfig,
+    /// How many calls may run at once.
+    pub limits: LimitsConfig,
+    /// Whether startup requires a working API connection.
+    pub start_mode: StartMode,
+    /// How often the API is pinged in the background for health checks.
+    pub health_interval: Duration,
+}
+
+/// Credentials for one profile.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Initial API token (or `token_command` output).
+    pub token: String,
+    /// Command that prints a fresh token, if the profile has one.
+    pub token_command: Option<String>,
+    /// Org slugs whose calls run as this profile.
+    pub orgs: Vec<String>,
+    /// What the token may do, if configured rather than inferred.
+    pub scope: Option<TokenScope>,
+}
+
+#[derive(Deserialize)]
+struct ProfilesFile {
+    #[serde(default)]
+    default: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, ProfileEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileEntry {
+    #[serde(default)]
+    token: Option<String>,
+    /// Read the token from this env var instead of storing it in the file.
+    #[serde(default)]
+    token_env: Option<String>,
+    /// Run this shell command to obtain (and later refresh) the token.
+    #[serde(default)]
+    token_command: Option<String>,
+    #[serde(default)]
+    orgs: Vec<String>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+impl Config {
+    /// Load the default token and any profiles file.
+    ///
+    /// The profiles file path can be overridden with `FGP_FLY_PROFILES`.
+    pub fn load() -> Result<Self> {
+        let mut profiles = HashMap::new();
+        if let Some(token) = default_token() {
+            profiles.insert(
+                DEFAULT_PROFILE.to_string(),
+                Profile {
+                    token,
+                    token_command: None,
+                    orgs: Vec::new(),
+                    scope: match std::env::var("FGP_FLY_TOKEN_SCOPE") {
+                        Ok(scope) => Some(TokenScope::parse(&scope)?),
+                        Err(_) => None,
+                    },
+                },
+            );
+        }
+
+        let path = std::env::var("FGP_FLY_PROFILES").unwrap_or_else(|_| PROFILES_PATH.into());
+        let path = PathBuf::from(shellexpand::tilde(&path).to_string());
+
+        let mut default_profile = DEFAULT_PROFILE.to_string();
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let file: ProfilesFile = serde_json::from_str(&text)
+                .with_context(|| format!("Invalid profiles file {}", path.display()))?;
+
+            for (name, entry) in file.profiles {
+                let token = match (entry.token, entry.token_env, &entry.token_command) {
+                    (Some(token), _, _) => token,
+                    (None, Some(var), _) => std::env::var(&var).with_context(|| {
+                        format!("Profile {} reads its token from unset {}", name, var)
+                    })?,
+                    (None, None, Some(command)) => run_token_command(command)
+                        .with_context(|| format!("Profile {} token_command failed", name))?,
+                    (None, None, None) => {
+                        anyhow::bail!("Profile {} has no token, token_env or token_command", name)
+                    }
+                };
+                let scope = entry
+                    .scope
+                    .as_deref()
+                    .map(TokenScope::parse)
+                    .transpose()
+                    .with_context(|| format!("Profile {} has an invalid scope", name))?;
+                profiles.insert(
+                    name,
+                    Profile {
+                        token,
+                        token_command: entry.token_command,
+                        orgs: entry.orgs,
+                        scope,
+                    },
+                );
+            }
+
+            if let Some(default) = file.default {
+                default_profile = default;
+            }
+        }
+
+        if profiles.is_empty() && FixtureMode::from_env()? == Some(FixtureMode::Replay) {
+            profiles.insert(
+                DEFAULT_PROFILE.to_string(),
+                Profile {
+                    token: REPLAY_TOKEN.to_string(),
+                    token_command: None,
+                    orgs: Vec::new(),
+                    scope: None,
+                },
+            );
+        }
+        if profiles.is_empty() {
+            anyhow::bail!(
+                "No Fly.io API token found: set FLY_API_TOKEN, run `fly auth login`, or add profiles to {}",
+                path.display()
+            );
+        }
+        if !profiles.contains_key(&default_profile) {
+            anyhow::bail!("Default profile {} is not configured", default_profile);
+        }
+
+        Self::with_profiles(default_profile, profiles)
+    }
+
+    /// One profile per account in `tokens`, keyed by a name that's also the
+    /// org slug it serves. The `default` account, if any, is the default
+    /// profile; otherwise the first by name is.
+    pub fn from_tokens(tokens: HashMap<String, String>) -> Result<Self> {
+        let default_profile = if tokens.contains_key(DEFAULT_PROFILE) {
+            DEFAULT_PROFILE.to_string()
+        } else {
+            tokens
+                .keys()
+                .min()
+                .cloned()
+                .context("No Fly.io API tokens given")?
+        };
+        let profiles = tokens
+            .into_iter()
+            .map(|(name, token)| {
+                let profile = Profile {
+                    token,
+                    token_command: None,
+                    orgs: vec![name.clone()],
+                    scope: None,
+                };
+                (name, profile)
+            })
+            .collect();
+
+        Self::with_profiles(default_profile, profiles)
+    }
+
+    /// Config for `profiles`, with everything else from the environment.
+    fn with_profiles(default_profile: String, profiles: HashMap<String, Profile>) -> Result<Self> {
+        Ok(Self {
+            default_profile,
+            profiles,
+            client: FlyClientConfig::from_env()?,
+            limits: LimitsConfig::from_env()?,
+            start_mode: match std::env::var("FGP_FLY_START_MODE") {
+                Ok(mode) => StartMode::parse(&mode)?,
+                Err(_) => StartMode::default(),
+            },
+            health_interval: connection::health_interval_from_env()?,
+        })
+    }
+}
+
+/// Token from the environment, then from flyctl's config.
+fn default_token() -> Option<String> {
+    ["FLY_API_TOKEN", "FLY_ACCESS_TOKEN"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|token| !token.trim().is_empty())
+        .or_else(flyctl_token)
+}
+
+/// Read `access_token` from `~/.fly/config.yml`.
+///
+/// The file is flat YAML, so a line scan avoids pulling in a YAML parser.
+fn flyctl_token() -> Option<String> {
+    let path = dirs::home_dir()?.join(".fly").join("config.yml");
+    let text = std::fs::read_to_string(path).ok()?;
+
+    text.lines().find_map(|line| {
+        let value = line.strip_prefix("access_token:")?.trim();
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
@@ -0,0 +1,217 @@
+//! Fly.io API reachability, tracked outside of calls.
+//!
+//! In strict start mode the daemon refuses to start without a working API
+//! connection. In lazy mode it starts anyway, reports itself degraded, and
+//! keeps pinging in the background with backoff until the API answers, so
+//! a Fly outage doesn't keep the daemon down after it recovers.
+//!
+//! Either way a background task keeps pinging on an interval and caches the
+//! result, so health probes answer from the cache instead of waiting on a
+//! round trip to Fly. The interval comes from `FGP_FLY_HEALTH_INTERVAL_MS`
+//! (default 30000).
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::api::FlyApi;
+
+const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// First retry delay while the API is down; doubles up to the interval.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// A cached result older than this many intervals counts as stale.
+const STALE_INTERVALS: u32 = 3;
+
+/// How the daemon treats an unreachable API at startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartMode {
+    /// Fail to start.
+    #[default]
+    Strict,
+    /// Start degraded and retry in the background.
+    Lazy,
+}
+
+impl StartMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim() {
+            "strict" => Ok(Self::Strict),
+            "lazy" => Ok(Self::Lazy),
+            other => anyhow::bail!("Invalid start mode: {}. Use 'strict' or 'lazy'", other),
+        }
+    }
+}
+
+/// Background ping interval from `FGP_FLY_HEALTH_INTERVAL_MS`.
+pub fn health_interval_from_env() -> Result<Duration> {
+    match std::env::var("FGP_FLY_HEALTH_INTERVAL_MS") {
+        Ok(value) => value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+            .with_context(|| format!("Invalid FGP_FLY_HEALTH_INTERVAL_MS: {}", value)),
+        Err(_) => Ok(DEFAULT_HEALTH_INTERVAL),
+    }
+}
+
+/// Connection state as reported by `health`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConnectionStatus {
+    /// The API has answered at least once since startup.
+    pub connected: bool,
+    /// The latest ping succeeded.
+    pub healthy: bool,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<DateTime<Utc>>,
+    /// Seconds since `last_checked`, filled in when read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_secs: Option<u64>,
+    /// The latest ping is older than a few intervals.
+    pub stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    status: ConnectionStatus,
+    checked_at: Option<Instant>,
+}
+
+/// Cached connection state plus the background poller, if running.
+pub struct Connection {
+    state: Arc<Mutex<State>>,
+    interval: Duration,
+    poller: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Connection {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            state: Arc::default(),
+            interval,
+            poller: Mutex::new(None),
+        }
+    }
+
+    /// The cached status with its age.
+    pub fn status(&self) -> ConnectionStatus {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut status = state.status.clone();
+        if let Some(checked_at) = state.checked_at {
+            let age = checked_at.elapsed();
+            status.age_secs = Some(age.as_secs());
+            status.stale = age > self.interval * STALE_INTERVALS;
+        }
+        status
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .status
+            .connected
+    }
+
+    /// Record the outcome of a ping that took `latency`.
+    pub fn record(&self, result: &Result<bool>, latency: Duration) {
+        record(&self.state, result, latency);
+    }
+
+    /// Ping every interval, retrying sooner with backoff while the API
+    /// is unreachable.
+    pub fn poll_in_background(&self, runtime: &Handle, client: Arc<dyn FlyApi>) {
+        let state = self.state.clone();
+        let interval = self.interval;
+        let task = runtime.spawn(async move {
+            let mut retry = INITIAL_RETRY_DELAY.min(interval);
+            loop {
+                let start = Instant::now();
+                let result = client.ping().await;
+                let was_healthy = state
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .status
+                    .healthy;
+                record(&state, &result, start.elapsed());
+
+                let delay = match &result {
+                    Ok(true) => {
+                        if !was_healthy {
+                            tracing::info!("Fly.io API connection established");
+                        }
+                        retry = INITIAL_RETRY_DELAY.min(interval);
+                        interval
+                    }
+                    Ok(false) => {
+                        tracing::warn!("Fly.io API returned empty viewer ID");
+                        interval
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Fly.io API unreachable, retrying in {}s: {}",
+                            retry.as_secs(),
+                            e
+                        );
+                        let delay = retry;
+                        retry = (retry * 2).min(interval);
+                        delay
+                    }
+                };
+                tokio::time::sleep(delay).await;
+            }
+        });
+        if let Some(old) = self
+            .poller
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(task)
+        {
+            old.abort();
+        }
+    }
+
+    /// Stop polling, e.g. on daemon shutdown.
+    pub fn stop(&self) {
+        if let Some(task) = self.poller.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
+
+fn record(state: &Mutex<State>, result: &Result<bool>, latency: Duration) {
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Utc::now();
+    state.checked_at = Some(Instant::now());
+    let status = &mut state.status;
+    status.attempts += 1;
+    status.last_checked = Some(now);
+    status.healthy = matches!(result, Ok(true));
+    match result {
+        Ok(true) => {
+            if !status.connected {
+                status.connected_at = Some(now);
+            }
+            status.connected = true;
+            status.latency_ms = Some(latency.as_secs_f64() * 1000.0);
+            status.last_error = None;
+        }
+        Ok(false) => status.last_error = Some("Empty viewer ID".into()),
+        Err(e) => status.last_error = Some(e.to_string()),
+    }
+}
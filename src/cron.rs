@@ -0,0 +1,277 @@
+//! Five-field cron expressions for scheduled operations.
+//!
+//! `minute hour day-of-month month day-of-week`, evaluated in UTC. Each
+//! field takes `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps
+//! (`*/15`, `9-17/2`); day-of-week runs 0-6 from Sunday, with 7 also
+//! Sunday. As in classic cron, when both day fields are restricted (don't
+//! start with `*`) a day matching either one fires; otherwise a day must
+//! match both, so `*/2` in one day field still combines with the other. `@hourly`, `@daily`, `@weekly`, `@monthly`
+//! and `@yearly` are accepted as shorthands.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Timelike, Utc};
+
+/// Days searched for the next run before giving up (e.g. `0 0 31 2 *`).
+const SEARCH_DAYS: i64 = 366 * 5;
+
+/// A parsed cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day fields don't start with `*` (`*/2` isn't restricted).
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!(
+                "Invalid cron expression {:?}: expected 5 fields (minute hour day month weekday)",
+                expression
+            );
+        };
+
+        let parse = |field: &str, name: &str, min: u32, max: u32| {
+            parse_field(field, min, max).with_context(|| {
+                format!(
+                    "Invalid {} field {:?} in cron {:?}",
+                    name, field, expression
+                )
+            })
+        };
+        let mut weekdays = parse(weekday, "weekday", 0, 7)?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse(minute, "minute", 0, 59)?,
+            hours: parse(hour, "hour", 0, 23)?,
+            days: parse(day, "day", 1, 31)?,
+            months: parse(month, "month", 1, 12)?,
+            weekdays,
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    /// First time strictly after `after` that matches, to the minute.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date_naive();
+        for _ in 0..SEARCH_DAYS {
+            if self.matches_day(date) {
+                let from = if date == start.date_naive() {
+                    (start.hour(), start.minute())
+                } else {
+                    (0, 0)
+                };
+                if let Some((hour, minute)) = self.first_time_from(from) {
+                    let time = NaiveTime::from_hms_opt(hour, minute, 0)?;
+                    return Some(date.and_time(time).and_utc());
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_day(&self, date: chrono::NaiveDate) -> bool {
+        if !bit(self.months, date.month()) {
+            return false;
+        }
+        let day = bit(self.days, date.day());
+        let weekday = bit(self.weekdays, date.weekday().num_days_from_sunday());
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// Earliest matching hour and minute at or after `(hour, minute)`.
+    fn first_time_from(&self, (hour, minute): (u32, u32)) -> Option<(u32, u32)> {
+        (hour..24).filter(|h| bit(self.hours, *h)).find_map(|h| {
+            let first_minute = if h == hour { minute } else { 0 };
+            (first_minute..60)
+                .find(|m| bit(self.minutes, *m))
+                .map(|m| (h, m))
+        })
+    }
+}
+
+fn bit(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Bit set of the values a field allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().context("step is not a number")?;
+                if step == 0 {
+                    anyhow::bail!("step must be at least 1");
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (low, high) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((low, high)) => (
+                    low.parse().context("range start is not a number")?,
+                    high.parse().context("range end is not a number")?,
+                ),
+                None => {
+                    let value: u32 = range.parse().context("not a number")?;
+                    // `5/15` means from 5 to the end, every 15
+                    if part.contains('/') {
+                        (value, max)
+                    } else {
+                        (value, value)
+                    }
+                }
+            },
+        };
+        if low < min || high > max || low > high {
+            anyhow::bail!("{}-{} is outside {}-{}", low, high, min, max);
+        }
+        for value in (low..=high).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn next(expression: &str, after: &str) -> String {
+        Cron::parse(expression)
+            .unwrap()
+            .next_after(at(after))
+            .unwrap()
+            .to_rfc3339()
+    }
+
+    #[test]
+    fn fields_parse_ranges_steps_and_lists() {
+        assert_eq!(parse_field("*", 0, 59).unwrap(), (1 << 60) - 1);
+        assert_eq!(parse_field("1-3", 0, 59).unwrap(), 0b1110);
+        assert_eq!(
+            parse_field("*/15", 0, 59).unwrap(),
+            1 | 1 << 15 | 1 << 30 | 1 << 45
+        );
+        assert_eq!(
+            parse_field("9-17/4", 0, 23).unwrap(),
+            1 << 9 | 1 << 13 | 1 << 17
+        );
+        assert_eq!(parse_field("50/5", 0, 59).unwrap(), 1 << 50 | 1 << 55);
+        assert_eq!(
+            parse_field("1,15,30", 1, 31).unwrap(),
+            1 << 1 | 1 << 15 | 1 << 30
+        );
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+            "@fortnightly",
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn next_run_is_strictly_after() {
+        assert_eq!(
+            next("*/15 * * * *", "2026-10-16T12:15:00Z"),
+            "2026-10-16T12:30:00+00:00"
+        );
+        assert_eq!(
+            next("0 20 * * 1-5", "2026-10-16T20:00:30Z"),
+            "2026-10-19T20:00:00+00:00"
+        );
+        assert_eq!(
+            next("@monthly", "2026-12-15T00:00:00Z"),
+            "2027-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 0 * * 7", "2026-10-16T00:00:00Z"),
+            "2026-10-18T00:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2026-10-16T00:00:00Z"),
+            "2028-02-29T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 1st (a Thursday in October 2026) or any Monday
+        assert_eq!(
+            next("0 9 1 * 1", "2026-09-30T12:00:00Z"),
+            "2026-10-01T09:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 9 1 * 1", "2026-10-01T12:00:00Z"),
+            "2026-10-05T09:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn starred_step_day_fields_match_both() {
+        // Odd days that are also Mondays: 2026-10-05 is the first after the 1st
+        assert_eq!(
+            next("0 9 */2 * 1", "2026-10-01T12:00:00Z"),
+            "2026-10-05T09:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 9 */2 * 1", "2026-10-05T12:00:00Z"),
+            "2026-10-19T09:00:00+00:00"
+        );
+        // A 10th falling on a Sunday or Friday
+        assert_eq!(
+            next("0 9 10 * */5", "2026-10-01T00:00:00Z"),
+            "2027-01-10T09:00:00+00:00"
+        );
+        assert!(Cron::parse("0 0 31 2 *")
+            .unwrap()
+            .next_after(at("2026-10-16T00:00:00Z"))
+            .is_none());
+    }
+}
@@ -0,0 +1,1387 @@
+//! Image deployment orchestration over the Machines API.
+//!
+//! Rolls an app's machines to a new image using one of several strategies
+//! and reports per-machine progress.
+//!
+//! Blue/green deploys leave the running (blue) machines alone until a full
+//! set of green machines on the new image is healthy. Greens are cordoned
+//! as soon as they're created, so they take no traffic while booting; the
+//! flip uncordons them and cordons the blues, and only then are the blues
+//! destroyed. If any green isn't healthy within the timeout, or the flip
+//! fails, the greens are destroyed and the blues keep serving.
+//!
+//! Canary deploys update a share of the machines first. With a bake time,
+//! the canaries then serve for a while and are compared with the rest of
+//! the app on 5xx rate and p95 latency from Fly's Prometheus; a regression
+//! puts them back on their old image, otherwise the rest are updated. When
+//! the rest of the app has no metrics to compare with (an app with a single
+//! machine, say), the canaries are held to absolute limits instead.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::{MachinesClient, MetricsClient};
+use crate::models::Machine;
+use crate::restart;
+
+/// Seconds to wait for an updated machine to report `started`.
+const START_TIMEOUT_SECS: u64 = 60;
+
+/// Default time green machines get to start and pass their checks.
+pub const HEALTH_TIMEOUT_SECS: u64 = 300;
+
+/// Delay between canary bake checks.
+const BAKE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Seconds of metrics each bake check looks at.
+const BAKE_WINDOW_SECS: i64 = 120;
+
+/// Deployments kept for `fly.deploy.status`.
+const DEPLOY_HISTORY_LEN: usize = 20;
+
+/// How machines are rolled to the new image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Update every machine without waiting.
+    Immediate,
+    /// Update one machine at a time, waiting for each to start.
+    Rolling,
+    /// Update a share of machines first, optionally bake them, then the rest.
+    Canary,
+    /// Boot a parallel set of machines before replacing the old ones.
+    Bluegreen,
+}
+
+impl Strategy {
+    /// Parse a strategy name.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "immediate" => Ok(Strategy::Immediate),
+            "rolling" => Ok(Strategy::Rolling),
+            "canary" => Ok(Strategy::Canary),
+            "bluegreen" => Ok(Strategy::Bluegreen),
+            _ => anyhow::bail!(
+                "Unknown strategy: {}. Valid strategies are: immediate, rolling, canary, bluegreen",
+                name
+            ),
+        }
+    }
+}
+
+/// Outcome for a single machine.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineProgress {
+    pub machine_id: String,
+    pub region: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Green machine that replaced this one, for blue/green deploys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a deployment.
+#[derive(Debug, Serialize)]
+pub struct DeployReport {
+    pub app: String,
+    pub image: String,
+    pub strategy: Strategy,
+    pub status: &'static str,
+    pub machines: Vec<MachineProgress>,
+}
+
+/// Tunables for a deployment.
+#[derive(Clone)]
+pub struct DeployOptions {
+    /// Time green machines get to become healthy in a blue/green deploy.
+    pub health_timeout: Duration,
+    pub canary: CanaryOptions,
+    /// Where canary bake metrics come from; required when baking.
+    pub metrics: Option<BakeMetrics>,
+}
+
+impl Default for DeployOptions {
+    fn default() -> Self {
+        Self {
+            health_timeout: Duration::from_secs(HEALTH_TIMEOUT_SECS),
+            canary: CanaryOptions::default(),
+            metrics: None,
+        }
+    }
+}
+
+/// How a canary deploy picks, bakes and judges its canaries.
+#[derive(Debug, Clone, Serialize)]
+pub struct CanaryOptions {
+    /// Share of machines updated first, rounded up to at least one.
+    pub percent: u32,
+    /// How long the canaries run before the rest follow; 0 skips the bake.
+    pub bake_secs: u64,
+    /// Roll back if the canaries' 5xx rate exceeds the other machines' by
+    /// more than this fraction.
+    pub max_error_rate_increase: f64,
+    /// Roll back if the canaries' p95 latency exceeds the other machines'
+    /// by more than this factor.
+    pub max_latency_ratio: f64,
+    /// Without a baseline to compare with, roll back if the canaries' 5xx
+    /// rate exceeds this fraction.
+    pub max_error_rate: f64,
+    /// Without a baseline to compare with, roll back if the canaries' p95
+    /// latency exceeds this many milliseconds.
+    pub max_p95_ms: f64,
+    /// Roll back when no check during the bake could read the canaries'
+    /// metrics, rather than promoting them unjudged.
+    pub require_bake_data: bool,
+}
+
+impl Default for CanaryOptions {
+    fn default() -> Self {
+        Self {
+            percent: 10,
+            bake_secs: 0,
+            max_error_rate_increase: 0.01,
+            max_latency_ratio: 1.5,
+            max_error_rate: 0.05,
+            max_p95_ms: 1000.0,
+            require_bake_data: true,
+        }
+    }
+}
+
+/// Prometheus access for judging canaries.
+#[derive(Clone)]
+pub struct BakeMetrics {
+    pub client: Arc<MetricsClient>,
+    pub org: String,
+}
+
+/// Roll every machine of an app to `image`.
+pub async fn deploy(
+    machines: &MachinesClient,
+    app_name: &str,
+    image: &str,
+    strategy: Strategy,
+    options: &DeployOptions,
+    tracker: &Tracker,
+) -> Result<DeployReport> {
+    let targets = match machines.list_machines(app_name).await {
+        Ok(targets) => targets,
+        Err(e) => {
+            tracker.fail(&e);
+            return Err(e);
+        }
+    };
+    deploy_machines(
+        machines, app_name, image, strategy, &targets, options, tracker,
+    )
+    .await
+}
+
+/// Roll a chosen subset of an app's machines to `image`.
+pub async fn deploy_machines(
+    machines: &MachinesClient,
+    app_name: &str,
+    image: &str,
+    strategy: Strategy,
+    targets: &[Machine],
+    options: &DeployOptions,
+    tracker: &Tracker,
+) -> Result<DeployReport> {
+    let result = match strategy {
+        Strategy::Bluegreen => {
+            bluegreen(
+                machines,
+                app_name,
+                image,
+                targets,
+                options.health_timeout,
+                tracker,
+            )
+            .await
+        }
+        Strategy::Canary => canary(machines, app_name, image, targets, options, tracker).await,
+        Strategy::Immediate | Strategy::Rolling => {
+            let wait = strategy == Strategy::Rolling;
+            let progress = update_all(machines, app_name, image, targets, wait, tracker).await;
+            Ok(report(
+                app_name,
+                image,
+                strategy,
+                overall(&progress),
+                progress,
+            ))
+        }
+    };
+    match &result {
+        Ok(report) => tracker.finish(report),
+        Err(e) => tracker.fail(e),
+    }
+    result
+}
+
+/// Update `targets` in order. With `wait`, each must start before the next
+/// is touched, and a failure skips the rest.
+async fn update_all(
+    machines: &MachinesClient,
+    app_name: &str,
+    image: &str,
+    targets: &[Machine],
+    wait: bool,
+    tracker: &Tracker,
+) -> Vec<MachineProgress> {
+    tracker.phase("updating");
+    let mut progress = Vec::with_capacity(targets.len());
+    let mut aborted = false;
+
+    for machine in targets {
+        if aborted {
+            progress.push(skipped(machine, "Aborted after earlier failure"));
+            continue;
+        }
+
+        let result = update_machine_image(machines, app_name, machine, image, wait).await;
+        if result.status == "failed" && wait {
+            aborted = true;
+        }
+        progress.push(result);
+        tracker.machines(&progress);
+    }
+    progress
+}
+
+fn overall(progress: &[MachineProgress]) -> &'static str {
+    if progress.iter().any(|p| p.status == "failed") {
+        "failed"
+    } else {
+        "complete"
+    }
+}
+
+fn report(
+    app_name: &str,
+    image: &str,
+    strategy: Strategy,
+    status: &'static str,
+    machines: Vec<MachineProgress>,
+) -> DeployReport {
+    DeployReport {
+        app: app_name.to_string(),
+        image: image.to_string(),
+        strategy,
+        status,
+        machines,
+    }
+}
+
+/// Update one machine's image, optionally waiting for it to start.
+pub async fn update_machine_image(
+    machines: &MachinesClient,
+    app_name: &str,
+    machine: &Machine,
+    image: &str,
+    wait: bool,
+) -> MachineProgress {
+    let Some(mut config) = machine.config.clone() else {
+        return failed(machine, None, "Machine has no config".into());
+    };
+
+    let previous_image = config.image.clone();
+    if previous_image.as_deref() == Some(image) {
+        return MachineProgress {
+            machine_id: machine.id.clone(),
+            region: machine.region.clone(),
+            status: "unchanged",
+            previous_image,
+            version: machine.instance_id.clone(),
+            replacement: None,
+            error: None,
+        };
+    }
+
+    config.image = Some(image.to_string());
+
+    let updated = match machines
+        .update_machine(app_name, &machine.id, &config, None)
+        .await
+    {
+        Ok(updated) => updated,
+        Err(e) => return failed(machine, previous_image, e.to_string()),
+    };
+
+    // Stopped machines stay stopped, so there is nothing to wait for
+    if wait && machine.state == "started" {
+        if let Err(e) = machines
+            .wait_for_state(
+                app_name,
+                &machine.id,
+                updated.instance_id.as_deref(),
+                "started",
+                START_TIMEOUT_SECS,
+            )
+            .await
+        {
+            return failed(machine, previous_image, e.to_string());
+        }
+    }
+
+    MachineProgress {
+        machine_id: machine.id.clone(),
+        region: machine.region.clone(),
+        status: "updated",
+        previous_image,
+        version: updated.instance_id,
+        replacement: None,
+        error: None,
+    }
+}
+
+/// Replace `blues` with healthy green machines on `image`, or leave them
+/// serving and remove the greens if that can't be done.
+async fn bluegreen(
+    machines: &MachinesClient,
+    app_name: &str,
+    image: &str,
+    blues: &[Machine],
+    health_timeout: Duration,
+    tracker: &Tracker,
+) -> Result<DeployReport> {
+    let report = |status, machines| report(app_name, image, Strategy::Bluegreen, status, machines);
+    if let Some(machine) = blues.iter().find(|m| m.config.is_none()) {
+        anyhow::bail!("Machine {} has no config", machine.id);
+    }
+    if let Some(machine) = blues
+        .iter()
+        .find(|m| m.config.as_ref().is_some_and(|c| !c.mounts.is_empty()))
+    {
+        anyhow::bail!(
+            "Machine {} has mounted volumes; blue/green deploys can't copy volumes",
+            machine.id
+        );
+    }
+    if blues
+        .iter()
+        .all(|m| m.config.as_ref().and_then(|c| c.image.as_deref()) == Some(image))
+    {
+        let unchanged = blues
+            .iter()
+            .map(|m| update_machine_image(machines, app_name, m, image, false));
+        return Ok(report(
+            "complete",
+            futures::future::join_all(unchanged).await,
+        ));
+    }
+
+    // Boot the greens, each cordoned before it can take traffic
+    tracker.phase("booting_greens");
+    let deadline = Instant::now() + health_timeout;
+    let mut greens: Vec<Green> = Vec::with_capacity(blues.len());
+    for blue in blues {
+        let green = match boot_green(machines, app_name, blue, image).await {
+            Ok(id) => Green::booted(blue, id),
+            Err(e) => Green::failed(blue, e),
+        };
+        let booted = green.error.is_none();
+        greens.push(green);
+        if !booted {
+            break;
+        }
+    }
+    if greens.iter().all(|green| green.error.is_none()) {
+        tracker.phase("health_gating");
+        for green in &mut greens {
+            let id = green.id.clone().unwrap_or_default();
+            if let Err(e) = restart::wait_healthy(machines, app_name, &id, None, deadline).await {
+                green.error = Some(e.context(format!("Green machine {} is not healthy", id)));
+                break;
+            }
+        }
+    }
+
+    let gated = greens.len() == blues.len() && greens.iter().all(|g| g.error.is_none());
+    let flipped = if gated {
+        tracker.phase("flipping");
+        flip(machines, app_name, &greens).await
+    } else {
+        Err(anyhow::anyhow!("Health gating failed"))
+    };
+    if let Err(e) = flipped {
+        tracker.phase("rolling_back");
+        let progress = rollback(machines, app_name, blues, &greens, gated, e).await;
+        return Ok(report("rolled_back", progress));
+    }
+
+    // Traffic is on the greens; retire the blues
+    tracker.phase("retiring_blues");
+    let mut progress = Vec::with_capacity(blues.len());
+    for Green { blue, id, .. } in &greens {
+        let mut outcome = MachineProgress {
+            machine_id: blue.id.clone(),
+            region: blue.region.clone(),
+            status: "replaced",
+            previous_image: blue.config.as_ref().and_then(|c| c.image.clone()),
+            version: None,
+            replacement: id.clone(),
+            error: None,
+        };
+        if let Err(e) = machines
+            .destroy_machine(app_name, &blue.id, true, None)
+            .await
+        {
+            // The green is serving; the blue is cordoned and can be removed by hand
+            outcome.error = Some(format!("Failed to destroy blue machine: {}", e));
+        }
+        progress.push(outcome);
+        tracker.machines(&progress);
+    }
+    let status = if progress.iter().any(|p| p.error.is_some()) {
+        "complete_with_errors"
+    } else {
+        "complete"
+    };
+    Ok(report(status, progress))
+}
+
+/// The green machine booted to replace a blue.
+struct Green<'a> {
+    blue: &'a Machine,
+    /// Set once the green exists, and kept if it then fails its checks so
+    /// a rollback still removes it.
+    id: Option<String>,
+    /// Why the green couldn't be booted or isn't healthy.
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> Green<'a> {
+    fn booted(blue: &'a Machine, id: String) -> Self {
+        Self {
+            blue,
+            id: Some(id),
+            error: None,
+        }
+    }
+
+    fn failed(blue: &'a Machine, error: anyhow::Error) -> Self {
+        Self {
+            blue,
+            id: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Create a cordoned copy of `blue` running `image`.
+async fn boot_green(
+    machines: &MachinesClient,
+    app_name: &str,
+    blue: &Machine,
+    image: &str,
+) -> Result<String> {
+    let Some(mut config) = blue.config.clone() else {
+        anyhow::bail!("Machine {} has no config", blue.id);
+    };
+    config.image = Some(image.to_string());
+    let green = machines
+        .clone_machine(app_name, &blue.region, &config)
+        .await?;
+    if let Err(e) = machines.cordon_machine(app_name, &green.id, None).await {
+        let _ = machines
+            .destroy_machine(app_name, &green.id, true, None)
+            .await;
+        return Err(e.context(format!("Failed to cordon green machine {}", green.id)));
+    }
+    Ok(green.id)
+}
+
+/// Route traffic to the greens: uncordon them, then cordon the blues.
+async fn flip(machines: &MachinesClient, app_name: &str, greens: &[Green<'_>]) -> Result<()> {
+    for id in greens.iter().filter_map(|g| g.id.as_deref()) {
+        machines.uncordon_machine(app_name, id, None).await?;
+    }
+    for Green { blue, .. } in greens {
+        if blue.state == "started" {
+            machines.cordon_machine(app_name, &blue.id, None).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Destroy every green and, if the flip was attempted, put traffic back on
+/// the blues.
+async fn rollback(
+    machines: &MachinesClient,
+    app_name: &str,
+    blues: &[Machine],
+    greens: &[Green<'_>],
+    flip_attempted: bool,
+    cause: anyhow::Error,
+) -> Vec<MachineProgress> {
+    let mut progress = Vec::with_capacity(blues.len());
+    for blue in blues {
+        let green = greens.iter().find(|g| g.blue.id == blue.id);
+        // Blame the green that failed; the rest were rolled back because of it
+        let mut errors = match green.and_then(|g| g.error.as_ref()) {
+            Some(e) => vec![format!("{:#}", e)],
+            None => vec![format!("{:#}", cause)],
+        };
+        if flip_attempted && blue.state == "started" && !blue.cordoned {
+            if let Err(e) = machines.uncordon_machine(app_name, &blue.id, None).await {
+                errors.push(format!("Failed to uncordon blue machine: {}", e));
+            }
+        }
+        if let Some(id) = green.and_then(|g| g.id.as_deref()) {
+            if let Err(e) = machines.destroy_machine(app_name, id, true, None).await {
+                errors.push(format!("Failed to destroy green machine {}: {}", id, e));
+            }
+        }
+        progress.push(MachineProgress {
+            machine_id: blue.id.clone(),
+            region: blue.region.clone(),
+            status: "rolled_back",
+            previous_image: blue.config.as_ref().and_then(|c| c.image.clone()),
+            version: None,
+            replacement: None,
+            error: Some(errors.join("; ")),
+        });
+    }
+    progress
+}
+
+/// Update a share of the machines, bake them against the rest, then
+/// update the rest or put the canaries back on their old image.
+async fn canary(
+    machines: &MachinesClient,
+    app_name: &str,
+    image: &str,
+    targets: &[Machine],
+    options: &DeployOptions,
+    tracker: &Tracker,
+) -> Result<DeployReport> {
+    let settings = &options.canary;
+    let metrics = match (&options.metrics, settings.bake_secs) {
+        (_, 0) => None,
+        (Some(metrics), _) => Some(metrics),
+        (None, _) => anyhow::bail!("Baking canaries needs Prometheus metrics for the app"),
+    };
+
+    // Running machines first: only they serve traffic to judge
+    let mut ordered: Vec<&Machine> = targets.iter().collect();
+    ordered.sort_by_key(|m| m.state != "started");
+    let count = (targets.len() * settings.percent.clamp(1, 100) as usize).div_ceil(100);
+    let (canaries, rest) = ordered.split_at(count.min(targets.len()));
+    let canaries: Vec<Machine> = canaries.iter().map(|m| (*m).clone()).collect();
+    let rest: Vec<Machine> = rest.iter().map(|m| (*m).clone()).collect();
+
+    let mut progress = update_all(machines, app_name, image, &canaries, true, tracker).await;
+    let mut verdict = match progress.iter().find(|p| p.status == "failed") {
+        Some(failed) => Err(format!(
+            "Canary {} failed: {}",
+            failed.machine_id,
+            failed.error.as_deref().unwrap_or("unknown error")
+        )),
+        None => Ok(()),
+    };
+
+    if let (Ok(()), Some(metrics)) = (&verdict, metrics) {
+        let ids: Vec<&str> = canaries.iter().map(|m| m.id.as_str()).collect();
+        verdict = bake(metrics, app_name, &ids, settings, tracker).await;
+    }
+
+    if let Err(reason) = verdict {
+        tracker.phase("rolling_back");
+        for outcome in progress.iter_mut() {
+            revert(machines, app_name, outcome, &reason).await;
+        }
+        progress.extend(
+            rest.iter()
+                .map(|m| skipped(m, "Canary rolled back; not updated")),
+        );
+        tracker.machines(&progress);
+        return Ok(report(
+            app_name,
+            image,
+            Strategy::Canary,
+            "rolled_back",
+            progress,
+        ));
+    }
+
+    // The canaries held up; the rest follow without waiting
+    let promoted = update_all(machines, app_name, image, &rest, false, tracker).await;
+    progress.extend(promoted);
+    tracker.machines(&progress);
+    Ok(report(
+        app_name,
+        image,
+        Strategy::Canary,
+        overall(&progress),
+        progress,
+    ))
+}
+
+/// Watch the canaries for `bake_secs`, comparing them with the rest of the
+/// app. Fails with the reason at the first regression, or at the end if no
+/// check saw canary metrics and `require_bake_data` is set.
+async fn bake(
+    metrics: &BakeMetrics,
+    app_name: &str,
+    canaries: &[&str],
+    settings: &CanaryOptions,
+    tracker: &Tracker,
+) -> std::result::Result<(), String> {
+    tracker.phase("baking");
+    let started = Instant::now();
+    let bake = Duration::from_secs(settings.bake_secs);
+    tracker.update(|status| {
+        status.bake_ends_at = Some(Utc::now() + chrono::Duration::seconds(bake.as_secs() as i64))
+    });
+
+    let mut judged = false;
+    let mut last_error = None;
+    while started.elapsed() < bake {
+        let wait = BAKE_INTERVAL.min(bake - started.elapsed());
+        tokio::time::sleep(wait).await;
+
+        let check = match BakeCheck::observe(metrics, app_name, canaries, settings).await {
+            Ok(check) => check,
+            Err(e) => {
+                // Missing metrics aren't a regression; the next check may see them
+                tracing::warn!("Canary bake check for {} failed: {:#}", app_name, e);
+                last_error = Some(format!("{:#}", e));
+                continue;
+            }
+        };
+        judged |= check.has_canary_data();
+        let regression = check.regression.clone();
+        tracker.update(|status| status.bake_checks.push(check));
+        if let Some(regression) = regression {
+            return Err(regression);
+        }
+    }
+
+    if !judged && settings.require_bake_data {
+        let cause = match last_error {
+            Some(error) => format!("last error: {}", error),
+            None => "the canaries served no traffic".to_string(),
+        };
+        return Err(format!(
+            "No bake check could judge the canaries ({}); pass require_bake_data=false to promote without metrics",
+            cause
+        ));
+    }
+    Ok(())
+}
+
+/// Put a canary back on the image it had before the deploy. Canaries that
+/// failed or were already on the image are left as reported.
+async fn revert(
+    machines: &MachinesClient,
+    app_name: &str,
+    outcome: &mut MachineProgress,
+    reason: &str,
+) {
+    let (true, Some(previous)) = (outcome.status == "updated", outcome.previous_image.clone())
+    else {
+        return;
+    };
+    let error = match machines.get_machine(app_name, &outcome.machine_id).await {
+        Ok(current) => {
+            update_machine_image(machines, app_name, &current, &previous, true)
+                .await
+                .error
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    match error {
+        None => {
+            outcome.status = "rolled_back";
+            outcome.error = Some(reason.to_string());
+        }
+        Some(error) => {
+            outcome.status = "failed";
+            outcome.error = Some(format!("{}; failed to roll back: {}", reason, error));
+        }
+    }
+}
+
+/// One comparison of the canaries with the rest of the app.
+#[derive(Debug, Clone, Serialize)]
+pub struct BakeCheck {
+    pub at: DateTime<Utc>,
+    /// Share of canary responses that were 5xx, if they served any.
+    pub canary_error_rate: Option<f64>,
+    pub baseline_error_rate: Option<f64>,
+    pub canary_p95_ms: Option<f64>,
+    pub baseline_p95_ms: Option<f64>,
+    /// Why the canaries were judged worse, if they were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regression: Option<String>,
+}
+
+impl BakeCheck {
+    async fn observe(
+        metrics: &BakeMetrics,
+        app_name: &str,
+        canaries: &[&str],
+        settings: &CanaryOptions,
+    ) -> Result<Self> {
+        let canary = format!("app=\"{}\", instance=~\"{}\"", app_name, canaries.join("|"));
+        let baseline = format!("app=\"{}\", instance!~\"{}\"", app_name, canaries.join("|"));
+        let error_rate = |selector: &str| {
+            format!(
+                "sum(rate(fly_app_http_responses_count{{{}, status=~\"5..\"}}[1m])) / sum(rate(fly_app_http_responses_count{{{}}}[1m]))",
+                selector, selector
+            )
+        };
+        let p95 = |selector: &str| {
+            format!(
+                "histogram_quantile(0.95, sum(rate(fly_app_http_response_time_seconds_bucket{{{}}}[1m])) by (le)) * 1000",
+                selector
+            )
+        };
+
+        let mut check = BakeCheck {
+            at: Utc::now(),
+            canary_error_rate: latest(metrics, &error_rate(&canary)).await?,
+            baseline_error_rate: latest(metrics, &error_rate(&baseline)).await?,
+            canary_p95_ms: latest(metrics, &p95(&canary)).await?,
+            baseline_p95_ms: latest(metrics, &p95(&baseline)).await?,
+            regression: None,
+        };
+        check.regression = check.judge(settings);
+        Ok(check)
+    }
+
+    /// Why the canaries are worse than the baseline, if they are. Each
+    /// measure falls back to its absolute limit when the baseline has no
+    /// value for it.
+    fn judge(&self, settings: &CanaryOptions) -> Option<String> {
+        if let Some(canary) = self.canary_error_rate {
+            match self.baseline_error_rate {
+                Some(baseline) if canary - baseline > settings.max_error_rate_increase => {
+                    return Some(format!(
+                        "Canary error rate {:.2}% exceeds the baseline's {:.2}% by more than {:.2} points",
+                        canary * 100.0,
+                        baseline * 100.0,
+                        settings.max_error_rate_increase * 100.0
+                    ));
+                }
+                None if canary > settings.max_error_rate => {
+                    return Some(format!(
+                        "Canary error rate {:.2}% exceeds {:.2}% with no baseline to compare with",
+                        canary * 100.0,
+                        settings.max_error_rate * 100.0
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if let Some(canary) = self.canary_p95_ms {
+            match self.baseline_p95_ms {
+                Some(baseline) if canary > baseline * settings.max_latency_ratio => {
+                    return Some(format!(
+                        "Canary p95 latency {:.0}ms exceeds {}x the baseline's {:.0}ms",
+                        canary, settings.max_latency_ratio, baseline
+                    ));
+                }
+                None if canary > settings.max_p95_ms => {
+                    return Some(format!(
+                        "Canary p95 latency {:.0}ms exceeds {:.0}ms with no baseline to compare with",
+                        canary, settings.max_p95_ms
+                    ));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Whether the canaries had any metrics to judge.
+    fn has_canary_data(&self) -> bool {
+        self.canary_error_rate.is_some() || self.canary_p95_ms.is_some()
+    }
+}
+
+/// Latest value of a single-series query, if there is one.
+async fn latest(metrics: &BakeMetrics, query: &str) -> Result<Option<f64>> {
+    let end = Utc::now().timestamp();
+    let series = metrics
+        .client
+        .query_range(&metrics.org, query, end - BAKE_WINDOW_SECS, end, 30)
+        .await?;
+    Ok(series
+        .first()
+        .and_then(|s| s.points.last())
+        .map(|(_, value)| *value)
+        .filter(|value| value.is_finite()))
+}
+
+/// Live progress of one deployment, as reported by `fly.deploy.status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeployStatus {
+    pub id: String,
+    pub app: String,
+    pub image: String,
+    pub strategy: Strategy,
+    /// `running`, then the report's final status.
+    pub status: &'static str,
+    /// What the deploy is doing now, e.g. `baking` or `rolling_back`.
+    pub phase: &'static str,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub machines: Vec<MachineProgress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bake_ends_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bake_checks: Vec<BakeCheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Handle a running deployment updates its status through.
+#[derive(Clone)]
+pub struct Tracker(Arc<Mutex<DeployStatus>>);
+
+impl Tracker {
+    fn update(&self, f: impl FnOnce(&mut DeployStatus)) {
+        f(&mut self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn phase(&self, phase: &'static str) {
+        self.update(|status| status.phase = phase);
+    }
+
+    fn machines(&self, progress: &[MachineProgress]) {
+        self.update(|status| status.machines = progress.to_vec());
+    }
+
+    fn finish(&self, report: &DeployReport) {
+        self.update(|status| {
+            status.status = report.status;
+            status.phase = "done";
+            status.finished_at = Some(Utc::now());
+            status.machines = report.machines.clone();
+        });
+    }
+
+    pub fn fail(&self, error: &anyhow::Error) {
+        self.update(|status| {
+            status.status = "failed";
+            status.phase = "done";
+            status.finished_at = Some(Utc::now());
+            status.error = Some(format!("{:#}", error));
+        });
+    }
+
+    pub fn snapshot(&self) -> DeployStatus {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Recent deployments, newest last.
+#[derive(Default)]
+pub struct Deployments {
+    recent: Mutex<VecDeque<Tracker>>,
+    next_id: AtomicU64,
+}
+
+impl Deployments {
+    /// Start tracking a deployment.
+    pub fn begin(&self, app_name: &str, image: &str, strategy: Strategy) -> Tracker {
+        let id = format!(
+            "deploy-{}-{}",
+            Utc::now().format("%Y%m%d%H%M%S"),
+            self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let tracker = Tracker(Arc::new(Mutex::new(DeployStatus {
+            id,
+            app: app_name.to_string(),
+            image: image.to_string(),
+            strategy,
+            status: "running",
+            phase: "starting",
+            started_at: Utc::now(),
+            finished_at: None,
+            machines: Vec::new(),
+            bake_ends_at: None,
+            bake_checks: Vec::new(),
+            error: None,
+        })));
+
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.push_back(tracker.clone());
+        while recent.len() > DEPLOY_HISTORY_LEN {
+            recent.pop_front();
+        }
+        tracker
+    }
+
+    /// Tracked deployments, newest first, optionally for one app.
+    pub fn list(&self, app: Option<&str>) -> Vec<DeployStatus> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent
+            .iter()
+            .rev()
+            .map(Tracker::snapshot)
+            .filter(|status| app.map(|a| a == status.app).unwrap_or(true))
+            .collect()
+    }
+}
+
+fn failed(machine: &Machine, previous_image: Option<String>, error: String) -> MachineProgress {
+    MachineProgress {
+        machine_id: machine.id.clone(),
+        region: machine.region.clone(),
+        status: "failed",
+        previous_image,
+        version: None,
+        replacement: None,
+        error: Some(error),
+    }
+}
+
+fn skipped(machine: &Machine, reason: &str) -> MachineProgress {
+    MachineProgress {
+        machine_id: machine.id.clone(),
+        region: machine.region.clone(),
+        status: "skipped",
+        previous_image: machine.config.as_ref().and_then(|c| c.image.clone()),
+        version: None,
+        replacement: None,
+        error: Some(reason.to_string()),
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use super::*;
+    use crate::api::mock_machines::{machine, machine_json, MockMachines};
+    use serde_json::{json, Value};
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    const APP: &str = "web";
+
+    fn check(
+        canary_error_rate: Option<f64>,
+        baseline_error_rate: Option<f64>,
+        canary_p95_ms: Option<f64>,
+        baseline_p95_ms: Option<f64>,
+    ) -> BakeCheck {
+        BakeCheck {
+            at: Utc::now(),
+            canary_error_rate,
+            baseline_error_rate,
+            canary_p95_ms,
+            baseline_p95_ms,
+            regression: None,
+        }
+    }
+
+    #[test]
+    fn canaries_are_compared_with_the_baseline() {
+        let settings = CanaryOptions::default();
+
+        assert_eq!(
+            check(Some(0.015), Some(0.01), Some(140.0), Some(100.0)).judge(&settings),
+            None
+        );
+        let errors = check(Some(0.05), Some(0.01), None, None).judge(&settings);
+        assert!(errors.unwrap().contains("exceeds the baseline's 1.00%"));
+        let latency = check(Some(0.0), Some(0.0), Some(160.0), Some(100.0)).judge(&settings);
+        assert!(latency.unwrap().contains("1.5x the baseline's 100ms"));
+    }
+
+    #[test]
+    fn canaries_without_a_baseline_get_absolute_limits() {
+        let settings = CanaryOptions::default();
+
+        // A few errors on a lone machine are within the absolute limit
+        assert_eq!(
+            check(Some(0.02), None, Some(800.0), None).judge(&settings),
+            None
+        );
+        let errors = check(Some(0.10), None, None, None).judge(&settings);
+        assert!(errors.unwrap().contains("no baseline"));
+        let latency = check(Some(0.0), None, Some(2500.0), None).judge(&settings);
+        assert!(latency.unwrap().contains("exceeds 1000ms"));
+        // Latency still falls back when only the error rate has a baseline
+        let mixed = check(Some(0.0), Some(0.0), Some(2500.0), None).judge(&settings);
+        assert!(mixed.is_some());
+    }
+
+    #[tokio::test]
+    async fn revert_puts_a_canary_back_on_its_previous_image() {
+        let api = MockMachines::start().await;
+        let machine_path = api.path(&["apps", APP, "machines", "m1"]);
+        Mock::given(method("GET"))
+            .and(path(machine_path.as_str()))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(machine_json("m1", "started", "iad", "web:v2")),
+            )
+            .mount(&api.server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(machine_path.as_str()))
+            .and(body_string_contains("\"image\":\"web:v1\""))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(machine_json("m1", "started", "iad", "web:v1")),
+            )
+            .expect(1)
+            .mount(&api.server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(api.path(&["apps", APP, "machines", "m1", "wait"])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+            .mount(&api.server)
+            .await;
+
+        let mut outcome = MachineProgress {
+            previous_image: Some("web:v1".into()),
+            ..updated("m1")
+        };
+        revert(
+            api.client(),
+            APP,
+            &mut outcome,
+            "Canary error rate too high",
+        )
+        .await;
+
+        assert_eq!(outcome.status, "rolled_back");
+        assert_eq!(outcome.error.as_deref(), Some("Canary error rate too high"));
+    }
+
+    #[tokio::test]
+    async fn revert_reports_a_canary_it_could_not_put_back() {
+        let api = MockMachines::start().await;
+        let machine_path = api.path(&["apps", APP, "machines", "m1"]);
+        Mock::given(method("GET"))
+            .and(path(machine_path.as_str()))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(machine_json("m1", "started", "iad", "web:v2")),
+            )
+            .mount(&api.server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(machine_path.as_str()))
+            .respond_with(
+                ResponseTemplate::new(422).set_body_json(json!({ "error": "invalid config" })),
+            )
+            .mount(&api.server)
+            .await;
+
+        let mut outcome = MachineProgress {
+            previous_image: Some("web:v1".into()),
+            ..updated("m1")
+        };
+        revert(
+            api.client(),
+            APP,
+            &mut outcome,
+            "Canary error rate too high",
+        )
+        .await;
+
+        assert_eq!(outcome.status, "failed");
+        assert!(outcome
+            .error
+            .unwrap()
+            .starts_with("Canary error rate too high; failed to roll back"));
+    }
+
+    #[tokio::test]
+    async fn revert_leaves_canaries_that_were_not_updated() {
+        let api = MockMachines::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&api.server)
+            .await;
+
+        let mut unchanged = MachineProgress {
+            status: "unchanged",
+            previous_image: Some("web:v2".into()),
+            ..updated("m1")
+        };
+        revert(api.client(), APP, &mut unchanged, "regression").await;
+        assert_eq!(unchanged.status, "unchanged");
+        assert_eq!(unchanged.error, None);
+    }
+
+    #[tokio::test]
+    async fn a_failed_canary_rolls_back_the_others_and_skips_the_rest() {
+        let api = MockMachines::start().await;
+        let targets = [
+            machine("m1", "started", "iad", "web:v1"),
+            machine("m2", "started", "ord", "web:v1"),
+            machine("m3", "started", "cdg", "web:v1"),
+        ];
+        // m1 updates and starts, m2 is rejected; m3 is left for after the canaries
+        Mock::given(method("POST"))
+            .and(path(api.path(&["apps", APP, "machines", "m1"])))
+            .and(body_string_contains("web:v2"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(machine_json("m1", "started", "iad", "web:v2")),
+            )
+            .expect(1)
+            .mount(&api.server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(api.path(&["apps", APP, "machines", "m1"])))
+            .and(body_string_contains("web:v1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(machine_json("m1", "started", "iad", "web:v1")),
+            )
+            .expect(1)
+            .mount(&api.server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(api.path(&["apps", APP, "machines", "m1"])))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(machine_json("m1", "started", "iad", "web:v2")),
+            )
+            .mount(&api.server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(api.path(&["apps", APP, "machines", "m1", "wait"])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+            .mount(&api.server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(api.path(&["apps", APP, "machines", "m2"])))
+            .respond_with(
+                ResponseTemplate::new(422).set_body_json(json!({ "error": "invalid image" })),
+            )
+            .mount(&api.server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(api.path(&["apps", APP, "machines", "m3"])))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&api.server)
+            .await;
+
+        let options = DeployOptions {
+            canary: CanaryOptions {
+                percent: 50,
+                ..CanaryOptions::default()
+            },
+            ..DeployOptions::default()
+        };
+        let tracker = Deployments::default().begin(APP, "web:v2", Strategy::Canary);
+        let report = deploy_machines(
+            api.client(),
+            APP,
+            "web:v2",
+            Strategy::Canary,
+            &targets,
+            &options,
+            &tracker,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.status, "rolled_back");
+        let statuses: Vec<&str> = report.machines.iter().map(|m| m.status).collect();
+        assert_eq!(statuses, ["rolled_back", "failed", "skipped"]);
+        assert!(report.machines[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("Canary m2 failed"));
+        assert_eq!(tracker.snapshot().status, "rolled_back");
+    }
+
+    /// Answer `verb` requests to a machine endpoint of `APP` with `response`,
+    /// expecting `times` calls if given.
+    async fn mount(
+        api: &MockMachines,
+        verb: &str,
+        segments: &[&str],
+        response: ResponseTemplate,
+        times: Option<u64>,
+    ) {
+        let mut full = vec!["apps", APP, "machines"];
+        full.extend_from_slice(segments);
+        let mock = Mock::given(method(verb))
+            .and(path(api.path(&full)))
+            .respond_with(response);
+        match times {
+            Some(times) => mock.expect(times),
+            None => mock,
+        }
+        .mount(&api.server)
+        .await;
+    }
+
+    fn ok(body: Value) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(body)
+    }
+
+    /// Blue machines `b1` (iad) and `b2` (ord) on `web:v1`, and clones of
+    /// them booting as `g1` and `g2`, cordoned once created.
+    async fn blues_and_greens(api: &MockMachines) -> Vec<Machine> {
+        for (green, region) in [("g1", "iad"), ("g2", "ord")] {
+            Mock::given(method("POST"))
+                .and(path(api.path(&["apps", APP, "machines"])))
+                .and(body_string_contains(format!("\"region\":\"{}\"", region)))
+                .and(body_string_contains("web:v2"))
+                .respond_with(ok(machine_json(green, "created", region, "web:v2")))
+                .expect(1)
+                .mount(&api.server)
+                .await;
+            mount(api, "POST", &[green, "cordon"], ok(json!({})), Some(1)).await;
+            mount(
+                api,
+                "GET",
+                &[green, "wait"],
+                ok(json!({ "ok": true })),
+                None,
+            )
+            .await;
+        }
+        vec![
+            machine("b1", "started", "iad", "web:v1"),
+            machine("b2", "started", "ord", "web:v1"),
+        ]
+    }
+
+    async fn bluegreen_deploy(api: &MockMachines, blues: &[Machine], timeout: u64) -> DeployReport {
+        let options = DeployOptions {
+            health_timeout: Duration::from_secs(timeout),
+            ..DeployOptions::default()
+        };
+        let tracker = Deployments::default().begin(APP, "web:v2", Strategy::Bluegreen);
+        deploy_machines(
+            api.client(),
+            APP,
+            "web:v2",
+            Strategy::Bluegreen,
+            blues,
+            &options,
+            &tracker,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn bluegreen_flips_to_healthy_greens_and_destroys_the_blues() {
+        let api = MockMachines::start().await;
+        let blues = blues_and_greens(&api).await;
+        for (blue, green, region) in [("b1", "g1", "iad"), ("b2", "g2", "ord")] {
+            let healthy = ok(machine_json(green, "started", region, "web:v2"));
+            mount(&api, "GET", &[green], healthy, None).await;
+            mount(&api, "POST", &[green, "uncordon"], ok(json!({})), Some(1)).await;
+            mount(&api, "POST", &[blue, "cordon"], ok(json!({})), Some(1)).await;
+            mount(&api, "DELETE", &[blue], ok(json!({})), Some(1)).await;
+            mount(&api, "DELETE", &[green], ok(json!({})), Some(0)).await;
+        }
+
+        let report = bluegreen_deploy(&api, &blues, 30).await;
+
+        assert_eq!(report.status, "complete");
+        let replaced: Vec<(&str, Option<&str>)> = report
+            .machines
+            .iter()
+            .map(|m| (m.status, m.replacement.as_deref()))
+            .collect();
+        assert_eq!(
+            replaced,
+            [("replaced", Some("g1")), ("replaced", Some("g2"))]
+        );
+        assert_eq!(report.machines[0].previous_image.as_deref(), Some("web:v1"));
+    }
+
+    #[tokio::test]
+    async fn bluegreen_rolls_back_when_a_green_is_unhealthy() {
+        let api = MockMachines::start().await;
+        let blues = blues_and_greens(&api).await;
+        mount(
+            &api,
+            "GET",
+            &["g1"],
+            ok(machine_json("g1", "started", "iad", "web:v2")),
+            None,
+        )
+        .await;
+        let mut failing = machine_json("g2", "started", "ord", "web:v2");
+        failing["checks"] = json!([{ "name": "http", "status": "critical" }]);
+        mount(&api, "GET", &["g2"], ok(failing), None).await;
+        for (blue, green) in [("b1", "g1"), ("b2", "g2")] {
+            // The greens are removed and traffic never leaves the blues
+            mount(&api, "DELETE", &[green], ok(json!({})), Some(1)).await;
+            mount(&api, "POST", &[green, "uncordon"], ok(json!({})), Some(0)).await;
+            mount(&api, "POST", &[blue, "cordon"], ok(json!({})), Some(0)).await;
+            mount(&api, "POST", &[blue, "uncordon"], ok(json!({})), Some(0)).await;
+            mount(&api, "DELETE", &[blue], ok(json!({})), Some(0)).await;
+        }
+
+        let report = bluegreen_deploy(&api, &blues, 1).await;
+
+        assert_eq!(report.status, "rolled_back");
+        assert!(report.machines.iter().all(|m| m.status == "rolled_back"));
+        assert!(report.machines[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Green machine g2 is not healthy"));
+        assert_eq!(
+            report.machines[0].error.as_deref(),
+            Some("Health gating failed")
+        );
+    }
+
+    #[tokio::test]
+    async fn bluegreen_puts_traffic_back_on_the_blues_when_the_flip_fails() {
+        let api = MockMachines::start().await;
+        let blues = blues_and_greens(&api).await;
+        for (blue, green, region) in [("b1", "g1", "iad"), ("b2", "g2", "ord")] {
+            let healthy = ok(machine_json(green, "started", region, "web:v2"));
+            mount(&api, "GET", &[green], healthy, None).await;
+            mount(&api, "POST", &[blue, "uncordon"], ok(json!({})), Some(1)).await;
+            mount(&api, "DELETE", &[green], ok(json!({})), Some(1)).await;
+            mount(&api, "DELETE", &[blue], ok(json!({})), Some(0)).await;
+        }
+        mount(&api, "POST", &["g1", "uncordon"], ok(json!({})), Some(1)).await;
+        let rejected = ResponseTemplate::new(422).set_body_json(json!({ "error": "lease held" }));
+        mount(&api, "POST", &["g2", "uncordon"], rejected, Some(1)).await;
+
+        let report = bluegreen_deploy(&api, &blues, 30).await;
+
+        assert_eq!(report.status, "rolled_back");
+        assert!(report
+            .machines
+            .iter()
+            .all(|m| m.status == "rolled_back" && m.replacement.is_none()));
+    }
+
+    fn updated(id: &str) -> MachineProgress {
+        MachineProgress {
+            machine_id: id.into(),
+            region: "iad".into(),
+            status: "updated",
+            previous_image: None,
+            version: None,
+            replacement: None,
+            error: None,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Connectivity and credential diagnostics, like `flyctl doctor`.
+//!
+//! Every check runs even if an earlier one fails, so one report shows
+//! everything that needs fixing.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::time::Instant;
+
+use crate::api::{FlyApi, MachinesClient, TokenKind};
+use crate::error::FlyError;
+
+/// Clock skew beyond which token expiry checks become unreliable.
+const SKEW_WARN_SECS: i64 = 30;
+const SKEW_FAIL_SECS: i64 = 300;
+
+/// Tokens expiring sooner than this are reported as a warning.
+const EXPIRY_WARN_HOURS: i64 = 24;
+
+/// Outcome of one check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    /// Not run because something it depends on failed.
+    Skip,
+}
+
+/// Result of one diagnostic check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub duration_ms: u64,
+}
+
+/// All checks plus a summary.
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    /// No check failed (warnings allowed).
+    pub ok: bool,
+    pub passed: usize,
+    pub warnings: usize,
+    pub failed: usize,
+    pub checks: Vec<Check>,
+}
+
+/// Run every check against one profile's clients.
+pub async fn run(client: &dyn FlyApi, machines: &MachinesClient) -> DoctorReport {
+    let mut checks = Vec::new();
+
+    // GraphQL reachability and token validity in one round trip
+    let started = Instant::now();
+    let viewer = client.get_viewer_id().await;
+    let elapsed = started.elapsed();
+    let (status, detail) = match &viewer {
+        Err(e) if !matches!(FlyError::classify(e), FlyError::Auth(_)) => (
+            CheckStatus::Fail,
+            format!("GraphQL API unreachable: {}", FlyError::classify(e)),
+        ),
+        // An auth error still proves the API is reachable
+        _ => (
+            CheckStatus::Pass,
+            format!("GraphQL API answered in {}ms", elapsed.as_millis()),
+        ),
+    };
+    checks.push(check("graphql", status, detail, elapsed));
+    checks.push(token_check(client, &viewer, elapsed));
+
+    let started = Instant::now();
+    let orgs = client.list_organizations().await;
+    let elapsed = started.elapsed();
+    let org_slug = match orgs {
+        Ok(orgs) if orgs.is_empty() => {
+            checks.push(check(
+                "org_access",
+                CheckStatus::Warn,
+                "Token cannot see any organization (deploy tokens are scoped to one app)".into(),
+                elapsed,
+            ));
+            None
+        }
+        Ok(orgs) => {
+            let slugs: Vec<&str> = orgs.iter().map(|o| o.slug.as_str()).collect();
+            checks.push(check(
+                "org_access",
+                CheckStatus::Pass,
+                format!("Access to {} org(s): {}", orgs.len(), slugs.join(", ")),
+                elapsed,
+            ));
+            orgs.into_iter().next().map(|o| o.slug)
+        }
+        Err(e) => {
+            checks.push(check(
+                "org_access",
+                CheckStatus::Fail,
+                format!("Failed to list organizations: {}", FlyError::classify(&e)),
+                elapsed,
+            ));
+            None
+        }
+    };
+
+    let started = Instant::now();
+    let probe = machines
+        .probe(org_slug.as_deref().unwrap_or("personal"))
+        .await;
+    let elapsed = started.elapsed();
+    let server_time = match probe {
+        Ok((status, date)) => {
+            let (status_kind, detail) = match status {
+                200..=299 => (
+                    CheckStatus::Pass,
+                    format!("Machines API answered in {}ms", elapsed.as_millis()),
+                ),
+                401 | 403 => (
+                    CheckStatus::Warn,
+                    format!(
+                        "Machines API reachable but rejected the token (HTTP {})",
+                        status
+                    ),
+                ),
+                _ => (
+                    CheckStatus::Warn,
+                    format!("Machines API reachable but answered HTTP {}", status),
+                ),
+            };
+            checks.push(check("machines_api", status_kind, detail, elapsed));
+            date
+        }
+        Err(e) => {
+            checks.push(check(
+                "machines_api",
+                CheckStatus::Fail,
+                format!("Machines API unreachable: {}", FlyError::classify(&e)),
+                elapsed,
+            ));
+            None
+        }
+    };
+    checks.push(clock_check(server_time));
+
+    let passed = count(&checks, CheckStatus::Pass);
+    let warnings = count(&checks, CheckStatus::Warn);
+    let failed = count(&checks, CheckStatus::Fail);
+    DoctorReport {
+        ok: failed == 0,
+        passed,
+        warnings,
+        failed,
+        checks,
+    }
+}
+
+/// Token type, expiry and whether the API accepted it.
+fn token_check(
+    client: &dyn FlyApi,
+    viewer: &anyhow::Result<String>,
+    elapsed: std::time::Duration,
+) -> Check {
+    let credentials = client.credentials();
+    let kind = match credentials.kind() {
+        TokenKind::Macaroon => "macaroon",
+        TokenKind::Personal => "personal access",
+        TokenKind::OAuth => "session",
+    };
+
+    let (mut status, mut detail) = match viewer {
+        Ok(id) => (
+            CheckStatus::Pass,
+            format!("Valid {} token for viewer {}", kind, id),
+        ),
+        // Scoped macaroons (deploy tokens) can't query the viewer
+        Err(_) if credentials.kind() == TokenKind::Macaroon => (
+            CheckStatus::Warn,
+            format!(
+                "{} token has no viewer access; it is likely scoped to specific apps",
+                kind
+            ),
+        ),
+        Err(e) => match FlyError::classify(e) {
+            FlyError::Auth(message) => (
+                CheckStatus::Fail,
+                format!("{} token was rejected: {}", kind, message),
+            ),
+            _ => (
+                CheckStatus::Skip,
+                "GraphQL API unreachable; token not verified".into(),
+            ),
+        },
+    };
+
+    if let Some(expires_at) = credentials.expires_at() {
+        let left = expires_at - Utc::now();
+        if left <= Duration::zero() {
+            status = CheckStatus::Fail;
+            detail.push_str(&format!("; expired at {}", expires_at.to_rfc3339()));
+        } else {
+            if left < Duration::hours(EXPIRY_WARN_HOURS) && status == CheckStatus::Pass {
+                status = CheckStatus::Warn;
+            }
+            detail.push_str(&format!("; expires at {}", expires_at.to_rfc3339()));
+        }
+    }
+
+    check("token", status, detail, elapsed)
+}
+
+/// Compare the local clock with the Machines API's `Date` header.
+fn clock_check(server_time: Option<DateTime<Utc>>) -> Check {
+    let Some(server_time) = server_time else {
+        return check(
+            "clock_skew",
+            CheckStatus::Skip,
+            "No server time available from the Machines API".into(),
+            std::time::Duration::ZERO,
+        );
+    };
+
+    // `Date` has one-second resolution
+    let skew = (Utc::now() - server_time).num_seconds();
+    let status = match skew.abs() {
+        s if s > SKEW_FAIL_SECS => CheckStatus::Fail,
+        s if s > SKEW_WARN_SECS => CheckStatus::Warn,
+        _ => CheckStatus::Pass,
+    };
+    check(
+        "clock_skew",
+        status,
+        format!(
+            "Local clock is {}s {} the API",
+            skew.abs(),
+            if skew >= 0 { "ahead of" } else { "behind" }
+        ),
+        std::time::Duration::ZERO,
+    )
+}
+
+fn check(
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    elapsed: std::time::Duration,
+) -> Check {
+    Check {
+        name,
+        status,
+        detail,
+        duration_ms: elapsed.as_millis() as u64,
+    }
+}
+
+fn count(checks: &[Check], status: CheckStatus) -> usize {
+    checks.iter().filter(|c| c.status == status).count()
+}
@@ -0,0 +1,145 @@
+//! App hostname, IPs and custom domains, with the DNS records to create.
+//!
+//! Mirrors the advice `flyctl certs add` prints: subdomains CNAME to the
+//! app's `fly.dev` hostname, apex domains need A/AAAA records, and the
+//! `_acme-challenge` CNAME lets a certificate issue before traffic moves.
+
+use serde::Serialize;
+
+use crate::models::{Certificate, IpAddress};
+
+/// A DNS record the user should create at their DNS provider.
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: &'static str,
+    pub value: String,
+    /// `routing` (sends traffic to the app) or `acme_challenge`.
+    pub purpose: &'static str,
+}
+
+/// One custom domain with its certificate state and DNS guidance.
+#[derive(Debug, Clone, Serialize)]
+pub struct Domain {
+    pub hostname: String,
+    /// Apex domains can't use a CNAME for routing.
+    pub apex: bool,
+    pub certificate_status: Option<String>,
+    pub configured: bool,
+    pub dns_records: Vec<DnsRecord>,
+}
+
+/// Everything needed to point domains at an app.
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainReport {
+    pub app: String,
+    pub hostname: String,
+    pub ips: Vec<IpAddress>,
+    pub domains: Vec<Domain>,
+    /// Notes when the app's IPs limit what can be routed, e.g. no IPv4.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+/// Build the report from the app's hostname, IPs and certificates.
+pub fn report(
+    app: &str,
+    hostname: Option<String>,
+    ips: Vec<IpAddress>,
+    certs: Vec<Certificate>,
+) -> DomainReport {
+    let hostname = hostname.unwrap_or_else(|| format!("{}.fly.dev", app));
+    let ipv4 = ips
+        .iter()
+        .find(|ip| ip.ip_type == "v4")
+        .or_else(|| ips.iter().find(|ip| ip.ip_type == "shared_v4"))
+        .map(|ip| ip.address.clone());
+    let ipv6 = ips
+        .iter()
+        .find(|ip| ip.ip_type == "v6")
+        .map(|ip| ip.address.clone());
+
+    let mut notes = Vec::new();
+    if ipv4.is_none() {
+        notes.push(
+            "No public IPv4; allocate one with fly.ips (type shared_v4 or v4) for apex domains"
+                .to_string(),
+        );
+    }
+    if ipv6.is_none() {
+        notes.push("No public IPv6; allocate one with fly.ips (type v6)".to_string());
+    }
+
+    let domains = certs
+        .into_iter()
+        .map(|cert| {
+            let apex = is_apex(&cert.hostname);
+            let mut records = Vec::new();
+
+            if apex {
+                if let Some(v4) = &ipv4 {
+                    records.push(routing(&cert.hostname, "A", v4));
+                }
+                if let Some(v6) = &ipv6 {
+                    records.push(routing(&cert.hostname, "AAAA", v6));
+                }
+            } else {
+                records.push(routing(&cert.hostname, "CNAME", &hostname));
+            }
+
+            if let (Some(name), Some(target)) =
+                (&cert.dns_validation_hostname, &cert.dns_validation_target)
+            {
+                if !cert.configured {
+                    records.push(DnsRecord {
+                        name: name.clone(),
+                        record_type: "CNAME",
+                        value: target.clone(),
+                        purpose: "acme_challenge",
+                    });
+                }
+            }
+
+            Domain {
+                hostname: cert.hostname,
+                apex,
+                certificate_status: cert.client_status,
+                configured: cert.configured,
+                dns_records: records,
+            }
+        })
+        .collect();
+
+    DomainReport {
+        app: app.to_string(),
+        hostname,
+        ips,
+        domains,
+        notes,
+    }
+}
+
+fn routing(name: &str, record_type: &'static str, value: &str) -> DnsRecord {
+    DnsRecord {
+        name: name.to_string(),
+        record_type,
+        value: value.to_string(),
+        purpose: "routing",
+    }
+}
+
+/// Whether `hostname` is a registrable domain rather than a subdomain.
+///
+/// Without a public suffix list this treats `example.com` as apex and also
+/// recognizes two-part suffixes like `example.co.uk`.
+fn is_apex(hostname: &str) -> bool {
+    let labels: Vec<&str> = hostname.trim_end_matches('.').split('.').collect();
+    match labels.as_slice() {
+        [_, _] => true,
+        [_, second, tld] => {
+            tld.len() == 2 && matches!(*second, "co" | "com" | "org" | "net" | "ac" | "gov")
+        }
+        _ => false,
+    }
+}
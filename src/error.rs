@@ -0,0 +1,223 @@
+//! Structured errors for Fly API failures.
+//!
+//! API clients raise `FlyError` for HTTP and GraphQL failures; the dispatch
+//! error path classifies whatever it receives into one and hands callers a
+//! JSON error object with a code and a retryable flag.
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::fmt;
+
+use crate::models::GraphQLError;
+use crate::params::ParamError;
+use crate::redact;
+
+/// Classified failure of a Fly API call or daemon request.
+#[derive(Debug, Clone)]
+pub enum FlyError {
+    /// Missing, invalid or unauthorized token.
+    Auth(String),
+    /// The app, machine or other resource doesn't exist.
+    NotFound(String),
+    /// Fly rejected the request for exceeding rate limits.
+    RateLimited(String),
+    /// The API couldn't be reached (connection failure or timeout).
+    Unavailable(String),
+    /// Any other API failure; `status` is set for HTTP errors.
+    Api {
+        status: Option<u16>,
+        message: String,
+    },
+    /// The profile's token isn't allowed to make this call.
+    InsufficientScope(String),
+    /// Bad input, either caught locally or rejected by the API.
+    Validation {
+        /// Specific code, e.g. `missing_param`.
+        code: &'static str,
+        param: Option<String>,
+        message: String,
+        /// Close matches for an unknown method or param name.
+        suggestions: Vec<String>,
+    },
+}
+
+impl FlyError {
+    /// Classify a non-success HTTP response.
+    pub fn from_status(status: StatusCode, message: impl Into<String>) -> Self {
+        let message = format!("{} - {}", status, redact::scrub(&message.into()));
+        match status.as_u16() {
+            401 | 403 => FlyError::Auth(message),
+            404 => FlyError::NotFound(message),
+            429 => FlyError::RateLimited(message),
+            400 | 422 => FlyError::Validation {
+                code: "validation",
+                param: None,
+                message,
+                suggestions: Vec::new(),
+            },
+            code => FlyError::Api {
+                status: Some(code),
+                message,
+            },
+        }
+    }
+
+    /// Classify the errors of a GraphQL response that carried no data.
+    pub fn from_graphql(errors: &[GraphQLError]) -> Self {
+        let message = redact::scrub(
+            &errors
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        let code = errors
+            .iter()
+            .find_map(|e| e.extensions.as_ref()?.get("code")?.as_str())
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        let lower = message.to_lowercase();
+
+        if code == "UNAUTHORIZED"
+            || lower.contains("unauthorized")
+            || lower.contains("not authorized")
+        {
+            FlyError::Auth(message)
+        } else if code == "NOT_FOUND"
+            || lower.contains("not found")
+            || lower.contains("could not find")
+        {
+            FlyError::NotFound(message)
+        } else if code == "BAD_USER_INPUT" || code == "VALIDATION" {
+            FlyError::Validation {
+                code: "validation",
+                param: None,
+                message,
+                suggestions: Vec::new(),
+            }
+        } else {
+            FlyError::Api {
+                status: None,
+                message,
+            }
+        }
+    }
+
+    /// Find the most specific classification for an error chain.
+    pub fn classify(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<FlyError>() {
+                // Keep the context added on the way up, e.g. which endpoint failed
+                return e.clone().with_message(format!("{:#}", error));
+            }
+            if let Some(e) = cause.downcast_ref::<ParamError>() {
+                return FlyError::Validation {
+                    code: e.code,
+                    param: Some(e.param.clone()),
+                    message: e.message.clone(),
+                    suggestions: e.suggestions.clone(),
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if let Some(status) = e.status() {
+                    return FlyError::from_status(status, format!("{:#}", error));
+                }
+                if e.is_connect() || e.is_timeout() {
+                    return FlyError::Unavailable(format!("{:#}", error));
+                }
+            }
+        }
+
+        FlyError::Api {
+            status: None,
+            message: format!("{:#}", error),
+        }
+    }
+
+    /// Error code reported to callers.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FlyError::Auth(_) => "auth",
+            FlyError::NotFound(_) => "not_found",
+            FlyError::RateLimited(_) => "rate_limited",
+            FlyError::Unavailable(_) | FlyError::Api { .. } => "api_error",
+            FlyError::InsufficientScope(_) => "insufficient_scope",
+            FlyError::Validation { code, .. } => code,
+        }
+    }
+
+    /// Whether the same request may succeed if tried again later.
+    pub fn retryable(&self) -> bool {
+        match self {
+            FlyError::RateLimited(_) | FlyError::Unavailable(_) => true,
+            FlyError::Api { status, .. } => matches!(status, Some(status) if *status >= 500),
+            _ => false,
+        }
+    }
+
+    /// Mask `secrets` (and anything token-shaped) in the message.
+    pub fn redact(self, secrets: &[String]) -> Self {
+        let message = redact::redact(self.message(), secrets);
+        self.with_message(message)
+    }
+
+    fn with_message(mut self, text: String) -> Self {
+        match &mut self {
+            FlyError::Auth(message)
+            | FlyError::NotFound(message)
+            | FlyError::RateLimited(message)
+            | FlyError::Unavailable(message)
+            | FlyError::InsufficientScope(message)
+            | FlyError::Api { message, .. }
+            | FlyError::Validation { message, .. } => *message = text,
+        }
+        self
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            FlyError::Auth(message)
+            | FlyError::NotFound(message)
+            | FlyError::RateLimited(message)
+            | FlyError::Unavailable(message)
+            | FlyError::InsufficientScope(message)
+            | FlyError::Api { message, .. }
+            | FlyError::Validation { message, .. } => message,
+        }
+    }
+
+    /// Structured error object returned to callers.
+    pub fn to_value(&self) -> Value {
+        let mut error = serde_json::json!({
+            "code": self.code(),
+            "message": self.message(),
+            "retryable": self.retryable(),
+        });
+        match self {
+            FlyError::Api {
+                status: Some(status),
+                ..
+            } => error["status"] = serde_json::json!(status),
+            FlyError::Validation {
+                param, suggestions, ..
+            } => {
+                if let Some(param) = param {
+                    error["param"] = serde_json::json!(param);
+                }
+                if !suggestions.is_empty() {
+                    error["suggestions"] = serde_json::json!(suggestions);
+                }
+            }
+            _ => {}
+        }
+        serde_json::json!({ "error": error })
+    }
+}
+
+impl fmt::Display for FlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for FlyError {}
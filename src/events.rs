@@ -0,0 +1,162 @@
+//! Machine event feeds for `fly.machine.events` and `fly.app.events`.
+//!
+//! Events come from the Machines API, newest first. App feeds fetch every
+//! machine's events concurrently and merge them, with per-machine exit
+//! counts so crash loops stand out without reading the whole feed.
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+use crate::api::MachinesClient;
+use crate::models::{Machine, MachineEvent, StopReason};
+
+/// Machines queried at once for an app feed.
+const APP_CONCURRENCY: usize = 8;
+
+/// Which events to keep.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// Event kinds (`start`, `exit`, ...); empty keeps every kind.
+    pub kinds: Vec<String>,
+    /// Drop events before this time.
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &MachineEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind) {
+            return false;
+        }
+        match self.since {
+            Some(since) => timestamp(event).is_some_and(|at| at >= since),
+            None => true,
+        }
+    }
+
+    /// Keep matching events, newest first, up to `limit`.
+    pub fn apply(&self, events: Vec<MachineEvent>) -> Vec<MachineEvent> {
+        events
+            .into_iter()
+            .filter(|e| self.matches(e))
+            .take(self.limit)
+            .collect()
+    }
+}
+
+/// An event tagged with the machine it happened on.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppEvent {
+    pub machine_id: String,
+    pub region: String,
+    #[serde(flatten)]
+    pub event: MachineEvent,
+}
+
+/// Event counts for one machine within the filter.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineEventSummary {
+    pub machine_id: String,
+    pub region: String,
+    pub state: String,
+    pub events: usize,
+    pub starts: usize,
+    pub exits: usize,
+    /// Exits with a non-zero code that nobody asked for.
+    pub crashes: usize,
+    pub oom_kills: usize,
+    /// Why the machine last stopped, if it exited within the filter.
+    pub last_exit: Option<StopReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Merged events across an app's machines.
+#[derive(Debug, Serialize)]
+pub struct AppEvents {
+    pub app: String,
+    pub events: Vec<AppEvent>,
+    pub count: usize,
+    pub machines: Vec<MachineEventSummary>,
+}
+
+/// Fetch and filter every machine's events, at most `APP_CONCURRENCY`
+/// machines at a time. A machine whose events can't be read is reported
+/// with `error` instead of failing the feed.
+pub async fn app_events(
+    machines: &MachinesClient,
+    app: &str,
+    list: Vec<Machine>,
+    filter: &EventFilter,
+) -> AppEvents {
+    let mut results: Vec<(Machine, anyhow::Result<Vec<MachineEvent>>)> = stream::iter(list)
+        .map(|machine| async move {
+            let events = machines.list_machine_events(app, &machine.id).await;
+            (machine, events)
+        })
+        .buffer_unordered(APP_CONCURRENCY)
+        .collect()
+        .await;
+    results.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+
+    let mut events = Vec::new();
+    let mut summaries = Vec::new();
+    for (machine, result) in results {
+        let (machine_events, error) = match result {
+            Ok(all) => (filter.apply(all), None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        summaries.push(summarize(&machine, &machine_events, error));
+        events.extend(machine_events.into_iter().map(|event| AppEvent {
+            machine_id: machine.id.clone(),
+            region: machine.region.clone(),
+            event,
+        }));
+    }
+
+    // RFC 3339 timestamps sort lexicographically
+    events.sort_by(|a, b| b.event.timestamp.cmp(&a.event.timestamp));
+    events.truncate(filter.limit);
+
+    AppEvents {
+        app: app.to_string(),
+        count: events.len(),
+        events,
+        machines: summaries,
+    }
+}
+
+fn summarize(
+    machine: &Machine,
+    events: &[MachineEvent],
+    error: Option<String>,
+) -> MachineEventSummary {
+    let exits: Vec<&MachineEvent> = events.iter().filter(|e| e.kind == "exit").collect();
+    MachineEventSummary {
+        machine_id: machine.id.clone(),
+        region: machine.region.clone(),
+        state: machine.state.clone(),
+        events: events.len(),
+        starts: events.iter().filter(|e| e.kind == "start").count(),
+        exits: exits.len(),
+        crashes: exits
+            .iter()
+            .filter(|e| {
+                !e.requested_stop.unwrap_or(false) && e.exit_code.is_some_and(|code| code != 0)
+            })
+            .count(),
+        oom_kills: exits
+            .iter()
+            .filter(|e| e.oom_killed.unwrap_or(false))
+            .count(),
+        last_exit: exits.first().map(|e| StopReason::from_exit_event(e)),
+        error,
+    }
+}
+
+fn timestamp(event: &MachineEvent) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&event.timestamp)
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
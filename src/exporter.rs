@@ -0,0 +1,116 @@
+//! Embedded Prometheus exporter (`prometheus` feature).
+//!
+//! When `FGP_FLY_METRICS_ADDR` is set (e.g. `127.0.0.1:9464`), the daemon
+//! serves `GET /metrics` with the API client counters from `api::stats` and
+//! machine state gauges for every app under `fly.watch`.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+
+use crate::api;
+use crate::watch::WatchHub;
+
+/// Largest request head accepted before the connection is dropped.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// Address from `FGP_FLY_METRICS_ADDR`, if the exporter is enabled.
+pub fn addr_from_env() -> Result<Option<SocketAddr>> {
+    match std::env::var("FGP_FLY_METRICS_ADDR") {
+        Ok(addr) if !addr.trim().is_empty() => {
+            Ok(Some(addr.trim().parse().with_context(|| {
+                format!("Invalid FGP_FLY_METRICS_ADDR: {}", addr)
+            })?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Bind `addr` and serve metrics on the runtime until the daemon exits.
+pub fn spawn(runtime: &Handle, addr: SocketAddr, watches: Arc<WatchHub>) -> Result<()> {
+    let listener = std::net::TcpListener::bind(addr)
+        .with_context(|| format!("Failed to bind metrics listener on {}", addr))?;
+    listener.set_nonblocking(true)?;
+    let listener = {
+        let _guard = runtime.enter();
+        TcpListener::from_std(listener)?
+    };
+    tracing::info!("Serving Prometheus metrics on http://{}/metrics", addr);
+
+    runtime.spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let watches = watches.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &watches).await {
+                            tracing::debug!("Metrics request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("Metrics listener accept failed: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn serve(mut stream: TcpStream, watches: &WatchHub) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST_BYTES {
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(watches)),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Full exposition: API client metrics followed by watcher gauges.
+fn render(watches: &WatchHub) -> String {
+    let mut out = api::stats::prometheus_text();
+    let states = watches.machine_states();
+
+    out.push_str("# HELP fgp_fly_watched_apps Apps with a running fly.watch watcher.\n");
+    out.push_str("# TYPE fgp_fly_watched_apps gauge\n");
+    let _ = writeln!(out, "fgp_fly_watched_apps {}", watches.list().len());
+
+    out.push_str(
+        "# HELP fgp_fly_app_machines Machines per app and state, from the latest watch poll.\n",
+    );
+    out.push_str("# TYPE fgp_fly_app_machines gauge\n");
+    for (app, counts) in &states {
+        for (state, count) in counts {
+            let _ = writeln!(
+                out,
+                "fgp_fly_app_machines{{app=\"{}\",state=\"{}\"}} {}",
+                app, state, count
+            );
+        }
+    }
+
+    out
+}
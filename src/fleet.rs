@@ -0,0 +1,117 @@
+//! Health summary across many apps.
+//!
+//! Machine lists are fetched concurrently, with a cap on in-flight requests
+//! so large orgs don't trip the rate limiter all at once.
+
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::api::MachinesClient;
+
+/// Health of a single app.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppHealth {
+    pub app: String,
+    /// `up` (some machine started), `down` (none started) or `unknown` (lookup failed).
+    pub status: &'static str,
+    pub machines: usize,
+    pub machines_by_state: BTreeMap<String, usize>,
+    /// Machine count per region.
+    pub regions: BTreeMap<String, usize>,
+    /// Machines with at least one failing health check.
+    pub failing_checks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Consolidated health of a set of apps.
+#[derive(Debug, Serialize)]
+pub struct FleetStatus {
+    pub total: usize,
+    pub up: usize,
+    pub down: usize,
+    pub unknown: usize,
+    pub machines_by_state: BTreeMap<String, usize>,
+    /// Machine count per region across all apps.
+    pub regions: BTreeMap<String, usize>,
+    pub apps: Vec<AppHealth>,
+}
+
+/// Fetch every app's machines, at most `concurrency` at a time.
+pub async fn fleet_status(
+    machines: &MachinesClient,
+    apps: Vec<String>,
+    concurrency: usize,
+) -> FleetStatus {
+    let mut results: Vec<AppHealth> = stream::iter(apps)
+        .map(|app| app_health(machines, app))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    results.sort_by(|a, b| a.app.cmp(&b.app));
+
+    let mut status = FleetStatus {
+        total: results.len(),
+        up: 0,
+        down: 0,
+        unknown: 0,
+        machines_by_state: BTreeMap::new(),
+        regions: BTreeMap::new(),
+        apps: Vec::new(),
+    };
+    for app in &results {
+        match app.status {
+            "up" => status.up += 1,
+            "down" => status.down += 1,
+            _ => status.unknown += 1,
+        }
+        for (state, count) in &app.machines_by_state {
+            *status.machines_by_state.entry(state.clone()).or_default() += count;
+        }
+        for (region, count) in &app.regions {
+            *status.regions.entry(region.clone()).or_default() += count;
+        }
+    }
+    status.apps = results;
+
+    status
+}
+
+async fn app_health(machines: &MachinesClient, app: String) -> AppHealth {
+    let list = match machines.list_machines(&app).await {
+        Ok(list) => list,
+        Err(e) => {
+            return AppHealth {
+                app,
+                status: "unknown",
+                machines: 0,
+                machines_by_state: BTreeMap::new(),
+                regions: BTreeMap::new(),
+                failing_checks: 0,
+                error: Some(format!("{:#}", e)),
+            }
+        }
+    };
+
+    let mut machines_by_state = BTreeMap::new();
+    let mut regions = BTreeMap::new();
+    for machine in &list {
+        *machines_by_state.entry(machine.state.clone()).or_default() += 1;
+        *regions.entry(machine.region.clone()).or_default() += 1;
+    }
+    let started = machines_by_state.get("started").copied().unwrap_or(0);
+
+    AppHealth {
+        app,
+        status: if started > 0 { "up" } else { "down" },
+        machines: list.len(),
+        machines_by_state,
+        regions,
+        failing_checks: list
+            .iter()
+            .filter(|m| m.checks.iter().any(|c| c.is_failing()))
+            .count(),
+        error: None,
+    }
+}
@@ -0,0 +1,578 @@
+//! `fly.toml` documents and their reconstruction from live machines.
+//!
+//! Machines carry everything `flyctl deploy` derived from the app's fly.toml
+//! (services, env, mounts, checks, guest size), so exporting groups them by
+//! process group and reads each group's config back into fly.toml sections.
+//! Values flyctl injects at deploy time, like `FLY_*` env vars and
+//! `PRIMARY_REGION`, are left out.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::models::{GuestConfig, Machine, MachineConfig, MachineService};
+
+/// Process group flyctl assigns when fly.toml has no `[processes]`.
+const DEFAULT_PROCESS_GROUP: &str = "app";
+
+/// Top-level keys this module reads; anything else is reported as ignored.
+const SUPPORTED_KEYS: &[&str] = &[
+    "app",
+    "primary_region",
+    "build",
+    "env",
+    "processes",
+    "mounts",
+    "services",
+    "http_service",
+    "checks",
+    "vm",
+];
+
+/// Parsed or reconstructed fly.toml.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlyToml {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<Build>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Process group name to command.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub processes: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<Mount>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<Service>,
+    /// Shorthand for an HTTP service on ports 80 and 443.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_service: Option<HttpService>,
+    /// Machine-level health checks by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vm: Vec<Vm>,
+}
+
+/// `[build]` section.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Build {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// `[[mounts]]` entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mount {
+    /// Volume name.
+    pub source: String,
+    pub destination: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<String>,
+}
+
+/// `[[services]]` entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Service {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub internal_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<String>,
+    /// `true`, `false`, `"stop"`, `"suspend"` or `"off"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_stop_machines: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_start_machines: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_machines_running: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tcp_checks: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http_checks: Vec<Value>,
+}
+
+/// `[http_service]` section.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HttpService {
+    pub internal_port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_https: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_stop_machines: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_start_machines: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_machines_running: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<Value>,
+    /// HTTP checks against `internal_port`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<Value>,
+}
+
+/// `[[vm]]` entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Vm {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<String>,
+}
+
+impl FlyToml {
+    /// Render as TOML text.
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Parse fly.toml text, returning the top-level keys that were ignored.
+    pub fn parse(text: &str) -> anyhow::Result<(Self, Vec<String>)> {
+        let table: toml::Table =
+            toml::from_str(text).map_err(|e| anyhow::anyhow!("Invalid fly.toml: {}", e))?;
+        Self::from_json(serde_json::to_value(table)?)
+    }
+
+    /// Read the JSON equivalent of a fly.toml, returning ignored keys.
+    pub fn from_json(value: Value) -> anyhow::Result<(Self, Vec<String>)> {
+        let ignored = value
+            .as_object()
+            .map(|map| {
+                map.keys()
+                    .filter(|k| !SUPPORTED_KEYS.contains(&k.as_str()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let config = serde_json::from_value(value)
+            .map_err(|e| anyhow::anyhow!("Invalid fly.toml: {}", e))?;
+        Ok((config, ignored))
+    }
+
+    /// Process groups the document defines, `app` without `[processes]`.
+    pub fn process_groups(&self) -> Vec<String> {
+        if self.processes.is_empty() {
+            vec![DEFAULT_PROCESS_GROUP.to_string()]
+        } else {
+            self.processes.keys().cloned().collect()
+        }
+    }
+
+    /// Overlay the document onto a machine config for `group`.
+    ///
+    /// Returns the names of the config fields that changed. Env vars flyctl
+    /// injects are kept, and mounts are never touched since they need
+    /// volumes.
+    pub fn apply_to(&self, group: &str, config: &mut MachineConfig) -> Vec<&'static str> {
+        let before = serde_json::to_value(&*config).unwrap_or_default();
+
+        if let Some(image) = self.build.as_ref().and_then(|b| b.image.clone()) {
+            config.image = Some(image);
+        }
+
+        config
+            .env
+            .retain(|k, _| k.starts_with("FLY_") || k == "PRIMARY_REGION");
+        config.env.extend(self.env.clone());
+        if let Some(region) = &self.primary_region {
+            config.env.insert("PRIMARY_REGION".into(), region.clone());
+        }
+
+        config
+            .metadata
+            .insert("fly_process_group".into(), group.to_string());
+        if let Some(cmd) = self.processes.get(group) {
+            let cmd: Vec<&str> = cmd.split_whitespace().collect();
+            config
+                .extra
+                .insert("init".into(), serde_json::json!({ "cmd": cmd }));
+        }
+
+        config.services = self
+            .services
+            .iter()
+            .filter(|s| applies_to(&s.processes, group))
+            .map(machine_service)
+            .chain(
+                self.http_service
+                    .iter()
+                    .filter(|s| applies_to(&s.processes, group))
+                    .map(http_machine_service),
+            )
+            .collect();
+
+        if self.checks.is_empty() {
+            config.extra.remove("checks");
+        } else {
+            config
+                .extra
+                .insert("checks".into(), serde_json::json!(self.checks));
+        }
+
+        if let Some(vm) = self.vm.iter().find(|vm| applies_to(&vm.processes, group)) {
+            if vm.size.is_some() {
+                config.size = vm.size.clone();
+            }
+            let guest = config.guest.get_or_insert_with(GuestConfig::default);
+            if vm.cpu_kind.is_some() {
+                guest.cpu_kind = vm.cpu_kind.clone();
+            }
+            if vm.cpus.is_some() {
+                guest.cpus = vm.cpus;
+            }
+            if vm.memory_mb.is_some() {
+                guest.memory_mb = vm.memory_mb;
+            }
+        }
+
+        let after = serde_json::to_value(&*config).unwrap_or_default();
+        let mut changed: Vec<&'static str> = [
+            "image", "env", "metadata", "init", "services", "checks", "guest", "size",
+        ]
+        .into_iter()
+        .filter(|field| before.get(field) != after.get(field))
+        .collect();
+        // A stale process group label alone isn't worth a machine update
+        if changed == ["metadata"] {
+            changed.clear();
+        }
+        changed
+    }
+
+    /// Mount destinations the document declares for `group`.
+    pub fn mount_paths(&self, group: &str) -> Vec<&str> {
+        self.mounts
+            .iter()
+            .filter(|m| applies_to(&m.processes, group))
+            .map(|m| m.destination.as_str())
+            .collect()
+    }
+}
+
+fn applies_to(processes: &[String], group: &str) -> bool {
+    processes.is_empty() || processes.iter().any(|p| p == group)
+}
+
+/// Map a fly.toml service to its Machines API form.
+fn machine_service(service: &Service) -> MachineService {
+    let mut extra = HashMap::new();
+    if let Some(autostop) = &service.auto_stop_machines {
+        extra.insert("autostop".to_string(), autostop.clone());
+    }
+    if let Some(autostart) = service.auto_start_machines {
+        extra.insert("autostart".to_string(), autostart.into());
+    }
+    if let Some(min) = service.min_machines_running {
+        extra.insert("min_machines_running".to_string(), min.into());
+    }
+    if let Some(concurrency) = &service.concurrency {
+        extra.insert("concurrency".to_string(), concurrency.clone());
+    }
+
+    let checks: Vec<Value> = service
+        .tcp_checks
+        .iter()
+        .map(|c| typed_check(c, "tcp"))
+        .chain(service.http_checks.iter().map(|c| typed_check(c, "http")))
+        .collect();
+    if !checks.is_empty() {
+        extra.insert("checks".to_string(), Value::Array(checks));
+    }
+
+    MachineService {
+        protocol: service.protocol.clone(),
+        internal_port: service.internal_port,
+        ports: service
+            .ports
+            .iter()
+            .filter_map(|p| serde_json::from_value(p.clone()).ok())
+            .collect(),
+        extra,
+    }
+}
+
+/// The service `[http_service]` expands to, as flyctl builds it.
+fn http_machine_service(http: &HttpService) -> MachineService {
+    let mut http_port = serde_json::json!({ "port": 80, "handlers": ["http"] });
+    if let Some(force_https) = http.force_https {
+        http_port["force_https"] = force_https.into();
+    }
+    let service = Service {
+        protocol: Some("tcp".into()),
+        internal_port: Some(http.internal_port),
+        auto_stop_machines: http.auto_stop_machines.clone(),
+        auto_start_machines: http.auto_start_machines,
+        min_machines_running: http.min_machines_running,
+        ports: vec![
+            http_port,
+            serde_json::json!({ "port": 443, "handlers": ["tls", "http"] }),
+        ],
+        concurrency: http.concurrency.clone(),
+        http_checks: http.checks.clone(),
+        ..Default::default()
+    };
+    machine_service(&service)
+}
+
+fn typed_check(check: &Value, kind: &str) -> Value {
+    let mut check = check.clone();
+    if let Some(map) = check.as_object_mut() {
+        map.insert("type".into(), kind.into());
+    }
+    check
+}
+
+/// A reconstructed fly.toml plus anything that couldn't be represented.
+#[derive(Debug, Clone, Serialize)]
+pub struct Export {
+    pub config: FlyToml,
+    /// Where machines in a group disagree or config was dropped.
+    pub notes: Vec<String>,
+}
+
+/// Rebuild a fly.toml from an app's machines.
+///
+/// Each process group is read from its first machine; groups whose
+/// machines differ are reported in `notes`.
+pub fn export(app: &str, machines: &[Machine]) -> Export {
+    let mut notes = Vec::new();
+    let mut groups: BTreeMap<String, Vec<&Machine>> = BTreeMap::new();
+    for machine in machines.iter().filter(|m| m.config.is_some()) {
+        groups
+            .entry(process_group(machine).to_string())
+            .or_default()
+            .push(machine);
+    }
+    if groups.is_empty() {
+        notes.push("App has no machines; only the app name was exported".to_string());
+    }
+
+    let multiple_groups = groups.len() > 1;
+    let scoped = |group: &str| -> Vec<String> {
+        if multiple_groups {
+            vec![group.to_string()]
+        } else {
+            Vec::new()
+        }
+    };
+
+    let mut config = FlyToml {
+        app: Some(app.to_string()),
+        ..Default::default()
+    };
+
+    let first = groups.values().flatten().next();
+    config.primary_region = first.map(|m| {
+        m.config
+            .as_ref()
+            .and_then(|c| c.env.get("PRIMARY_REGION").cloned())
+            .unwrap_or_else(|| m.region.clone())
+    });
+
+    let mut images: Vec<String> = Vec::new();
+    for (group, members) in &groups {
+        let machine = members[0];
+        let Some(machine_config) = machine.config.as_ref() else {
+            continue;
+        };
+
+        if members
+            .iter()
+            .any(|m| m.config.as_ref().map(shape) != Some(shape(machine_config)))
+        {
+            notes.push(format!(
+                "Machines in process group {} differ in image or size; exported {}",
+                group, machine.id
+            ));
+        }
+
+        if let Some(image) = &machine_config.image {
+            if !images.contains(image) {
+                images.push(image.clone());
+            }
+        }
+
+        // Env is app-wide in fly.toml; the first group's wins
+        if config.env.is_empty() {
+            config.env = machine_config
+                .env
+                .iter()
+                .filter(|(k, _)| !k.starts_with("FLY_") && k.as_str() != "PRIMARY_REGION")
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+        }
+
+        if let Some(cmd) = init_cmd(machine_config) {
+            config.processes.insert(group.clone(), cmd);
+        }
+
+        for mount in &machine_config.mounts {
+            let source = mount
+                .extra
+                .get("name")
+                .and_then(|v| v.as_str())
+                .map(String::from)
+                .or_else(|| mount.volume.clone());
+            match (source, &mount.path) {
+                (Some(source), Some(destination)) => config.mounts.push(Mount {
+                    source,
+                    destination: destination.clone(),
+                    processes: scoped(group),
+                }),
+                _ => notes.push(format!(
+                    "Skipped a mount in process group {} without a volume or path",
+                    group
+                )),
+            }
+        }
+
+        for service in &machine_config.services {
+            config
+                .services
+                .push(service_section(service, scoped(group)));
+        }
+
+        if let Some(Value::Object(checks)) = machine_config.extra.get("checks") {
+            for (name, check) in checks {
+                config
+                    .checks
+                    .insert(name.clone(), strip_nulls(check.clone()));
+            }
+        }
+
+        if let Some(guest) = &machine_config.guest {
+            config.vm.push(Vm {
+                size: machine_config.size.clone(),
+                cpu_kind: guest.cpu_kind.clone(),
+                cpus: guest.cpus,
+                memory_mb: guest.memory_mb,
+                processes: scoped(group),
+            });
+        }
+    }
+
+    if images.len() > 1 {
+        notes.push(format!(
+            "Process groups run different images ({}); [build] uses the first",
+            images.join(", ")
+        ));
+    }
+    config.build = images
+        .into_iter()
+        .next()
+        .map(|image| Build { image: Some(image) });
+
+    Export { config, notes }
+}
+
+/// A machine's process group, `app` when unlabelled.
+pub fn process_group(machine: &Machine) -> &str {
+    machine
+        .config
+        .as_ref()
+        .and_then(|c| c.metadata.get("fly_process_group"))
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_PROCESS_GROUP)
+}
+
+/// Image and guest size, which should match across a process group.
+fn shape(config: &MachineConfig) -> (Option<&str>, Option<&str>, Option<u32>, Option<u32>) {
+    let guest = config.guest.as_ref();
+    (
+        config.image.as_deref(),
+        guest.and_then(|g| g.cpu_kind.as_deref()),
+        guest.and_then(|g| g.cpus),
+        guest.and_then(|g| g.memory_mb),
+    )
+}
+
+/// The machine's `init.cmd` as a single command line.
+fn init_cmd(config: &MachineConfig) -> Option<String> {
+    let cmd = config.extra.get("init")?.get("cmd")?.as_array()?;
+    let parts: Vec<&str> = cmd.iter().filter_map(|v| v.as_str()).collect();
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+/// Map a Machines API service back to its fly.toml form.
+fn service_section(service: &MachineService, processes: Vec<String>) -> Service {
+    let mut section = Service {
+        protocol: service.protocol.clone(),
+        internal_port: service.internal_port,
+        processes,
+        auto_stop_machines: service.extra.get("autostop").cloned(),
+        auto_start_machines: service.extra.get("autostart").and_then(|v| v.as_bool()),
+        min_machines_running: service
+            .extra
+            .get("min_machines_running")
+            .and_then(|v| v.as_u64()),
+        concurrency: service.extra.get("concurrency").cloned().map(strip_nulls),
+        ..Default::default()
+    };
+
+    section.ports = service
+        .ports
+        .iter()
+        .map(|port| {
+            let mut map: Map<String, Value> = port.extra.clone().into_iter().collect();
+            if let Some(number) = port.port {
+                map.insert("port".into(), number.into());
+            }
+            if !port.handlers.is_empty() {
+                map.insert("handlers".into(), serde_json::json!(port.handlers));
+            }
+            strip_nulls(Value::Object(map))
+        })
+        .collect();
+
+    if let Some(Value::Array(checks)) = service.extra.get("checks") {
+        for check in checks {
+            let mut check = strip_nulls(check.clone());
+            let kind = check
+                .as_object_mut()
+                .and_then(|c| c.remove("type"))
+                .and_then(|t| t.as_str().map(String::from));
+            match kind.as_deref() {
+                Some("http") => section.http_checks.push(check),
+                _ => section.tcp_checks.push(check),
+            }
+        }
+    }
+
+    section
+}
+
+/// TOML has no null, so drop null entries before serializing.
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, strip_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .filter(|v| !v.is_null())
+                .map(strip_nulls)
+                .collect(),
+        ),
+        other => other,
+    }
+}
@@ -0,0 +1,105 @@
+//! Which image each machine runs, compared with the app's latest release.
+//!
+//! Like `flyctl image show`, a machine is out of date when its digest differs
+//! from the latest image's; tags are only compared when a digest is missing.
+
+use serde::Serialize;
+
+use crate::models::{ImageRef, Machine};
+
+/// One machine's image.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineImage {
+    pub machine_id: String,
+    pub region: String,
+    pub state: String,
+    /// Image reference from the machine config.
+    pub image: Option<String>,
+    pub registry: Option<String>,
+    pub repository: Option<String>,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+    pub update_available: bool,
+}
+
+/// Images across an app's machines.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageReport {
+    pub app: String,
+    /// Latest image pushed for the app, if the API knows one.
+    pub latest: Option<ImageRef>,
+    /// Reference machines are rolled to by `action=update`.
+    pub latest_image: Option<String>,
+    /// Any machine is behind `latest`.
+    pub update_available: bool,
+    pub machines: Vec<MachineImage>,
+}
+
+/// Build the report from the app's machines and its latest image.
+pub fn report(app: &str, machines: &[Machine], latest: Option<ImageRef>) -> ImageReport {
+    let machines: Vec<MachineImage> = machines
+        .iter()
+        .map(|machine| {
+            let current = machine.image_ref.as_ref();
+            MachineImage {
+                machine_id: machine.id.clone(),
+                region: machine.region.clone(),
+                state: machine.state.clone(),
+                image: machine.config.as_ref().and_then(|c| c.image.clone()),
+                registry: current.and_then(|r| r.registry.clone()),
+                repository: current.and_then(|r| r.repository.clone()),
+                tag: current.and_then(|r| r.tag.clone()),
+                digest: current.and_then(|r| r.digest.clone()),
+                update_available: latest
+                    .as_ref()
+                    .is_some_and(|latest| is_outdated(machine, latest)),
+            }
+        })
+        .collect();
+
+    ImageReport {
+        app: app.to_string(),
+        latest_image: latest.as_ref().and_then(reference),
+        update_available: machines.iter().any(|m| m.update_available),
+        latest,
+        machines,
+    }
+}
+
+/// Pullable reference for an image, pinned by digest when one is known.
+pub fn reference(image: &ImageRef) -> Option<String> {
+    let repository = image.repository.as_deref()?;
+    let name = match image.registry.as_deref() {
+        Some(registry) if !registry.is_empty() => format!("{}/{}", registry, repository),
+        _ => repository.to_string(),
+    };
+    match (image.digest.as_deref(), image.tag.as_deref()) {
+        (Some(digest), _) => Some(format!("{}@{}", name, digest)),
+        (None, Some(tag)) => Some(format!("{}:{}", name, tag)),
+        (None, None) => Some(name),
+    }
+}
+
+fn is_outdated(machine: &Machine, latest: &ImageRef) -> bool {
+    let Some(current) = &machine.image_ref else {
+        // Not resolved yet; fall back to the configured reference
+        let Some(image) = machine.config.as_ref().and_then(|c| c.image.as_deref()) else {
+            return false;
+        };
+        return match (&latest.digest, &latest.tag) {
+            (Some(digest), _) if image.ends_with(digest.as_str()) => false,
+            (_, Some(tag)) => !image.ends_with(&format!(":{}", tag)),
+            _ => false,
+        };
+    };
+    if current.repository.is_some()
+        && latest.repository.is_some()
+        && current.repository != latest.repository
+    {
+        return true;
+    }
+    match (&current.digest, &latest.digest) {
+        (Some(current), Some(latest)) => current != latest,
+        _ => current.tag != latest.tag,
+    }
+}
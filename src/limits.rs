@@ -0,0 +1,221 @@
+//! Concurrency limits for incoming calls.
+//!
+//! Every call takes a permit from a global pool and from the pool for its
+//! class (reads, or calls that change Fly state), so a burst of deploys or
+//! fleet-wide queries can't tie up every worker or trip Fly's rate limits.
+//! Calls wait for a permit up to their deadline, then fail as retryable.
+//!
+//! Pool sizes come from env vars:
+//!
+//! - `FGP_FLY_MAX_CONCURRENT`: all calls (default 32)
+//! - `FGP_FLY_MAX_CONCURRENT_READS`: read-only calls (default 16)
+//! - `FGP_FLY_MAX_CONCURRENT_MUTATIONS`: mutating calls (default 4)
+//! - `FGP_FLY_QUEUE_TIMEOUT_MS`: longest wait for a permit (default 30000)
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::FlyError;
+
+const DEFAULT_MAX_CONCURRENT: usize = 32;
+const DEFAULT_MAX_READS: usize = 16;
+const DEFAULT_MAX_MUTATIONS: usize = 4;
+const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pool sizes and queueing behaviour.
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    pub max_concurrent: usize,
+    pub max_reads: usize,
+    pub max_mutations: usize,
+    /// Longest a call waits for a permit when it has no earlier deadline.
+    pub queue_timeout: Duration,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            max_reads: DEFAULT_MAX_READS,
+            max_mutations: DEFAULT_MAX_MUTATIONS,
+            queue_timeout: DEFAULT_QUEUE_TIMEOUT,
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Build limits from `FGP_FLY_MAX_CONCURRENT*` env vars.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Some(n) = env_positive("FGP_FLY_MAX_CONCURRENT")? {
+            config.max_concurrent = n as usize;
+        }
+        if let Some(n) = env_positive("FGP_FLY_MAX_CONCURRENT_READS")? {
+            config.max_reads = n as usize;
+        }
+        if let Some(n) = env_positive("FGP_FLY_MAX_CONCURRENT_MUTATIONS")? {
+            config.max_mutations = n as usize;
+        }
+        if let Some(ms) = env_positive("FGP_FLY_QUEUE_TIMEOUT_MS")? {
+            config.queue_timeout = Duration::from_millis(ms);
+        }
+        Ok(config)
+    }
+}
+
+fn env_positive(key: &str) -> Result<Option<u64>> {
+    match std::env::var(key) {
+        Ok(value) => {
+            let parsed = value.trim().parse::<u64>().ok().filter(|n| *n > 0);
+            parsed
+                .with_context(|| format!("Invalid {}: {}", key, value))
+                .map(Some)
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Which class pool a call draws from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallClass {
+    Read,
+    Mutate,
+}
+
+/// Usage of one pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub limit: usize,
+    pub in_use: usize,
+    pub waiting: usize,
+}
+
+/// Counting semaphore for blocking callers.
+struct Pool {
+    name: &'static str,
+    limit: usize,
+    state: Mutex<PoolState>,
+    freed: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    in_use: usize,
+    waiting: usize,
+}
+
+impl Pool {
+    fn new(name: &'static str, limit: usize) -> Self {
+        Self {
+            name,
+            limit,
+            state: Mutex::new(PoolState::default()),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(self: &Arc<Self>, deadline: Instant) -> Result<Permit, FlyError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.waiting += 1;
+        while state.in_use >= self.limit {
+            let now = Instant::now();
+            if now >= deadline {
+                state.waiting -= 1;
+                return Err(FlyError::Unavailable(format!(
+                    "{} concurrency limit of {} reached; try again shortly",
+                    self.name, self.limit
+                )));
+            }
+            state = self
+                .freed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        state.waiting -= 1;
+        state.in_use += 1;
+        Ok(Permit { pool: self.clone() })
+    }
+
+    fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        PoolStats {
+            limit: self.limit,
+            in_use: state.in_use,
+            waiting: state.waiting,
+        }
+    }
+}
+
+/// Slots held for the duration of one call, including any work it leaves
+/// running in the background.
+pub struct Permits {
+    _class: Permit,
+    _global: Permit,
+}
+
+/// A held slot, returned to its pool on drop.
+struct Permit {
+    pool: Arc<Pool>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.pool.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_use -= 1;
+        self.pool.freed.notify_one();
+    }
+}
+
+/// The global pool plus one pool per call class.
+pub struct ConcurrencyLimits {
+    global: Arc<Pool>,
+    reads: Arc<Pool>,
+    mutations: Arc<Pool>,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            global: Arc::new(Pool::new("Global", config.max_concurrent)),
+            reads: Arc::new(Pool::new("Read", config.max_reads)),
+            mutations: Arc::new(Pool::new("Mutation", config.max_mutations)),
+            queue_timeout: config.queue_timeout,
+        }
+    }
+
+    /// Wait for a class permit and a global permit.
+    ///
+    /// The class permit is taken first so queued mutations never hold
+    /// global slots that reads could use.
+    pub fn acquire(
+        &self,
+        class: CallClass,
+        deadline: Option<Instant>,
+    ) -> Result<Permits, FlyError> {
+        let queue_deadline = Instant::now() + self.queue_timeout;
+        let deadline = deadline.map_or(queue_deadline, |d| d.min(queue_deadline));
+
+        let class_permit = match class {
+            CallClass::Read => self.reads.acquire(deadline)?,
+            CallClass::Mutate => self.mutations.acquire(deadline)?,
+        };
+        let global_permit = self.global.acquire(deadline)?;
+        Ok(Permits {
+            _class: class_permit,
+            _global: global_permit,
+        })
+    }
+
+    /// Current usage of every pool.
+    pub fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "global": self.global.stats(),
+            "read": self.reads.stats(),
+            "mutate": self.mutations.stats(),
+        })
+    }
+}
@@ -0,0 +1,130 @@
+//! App log retrieval with long-poll follow mode.
+//!
+//! Each call returns a cursor; passing it back continues where the previous
+//! page ended. With `follow`, the call waits until new entries arrive or the
+//! wait window closes, so clients can tail logs with repeated calls.
+//!
+//! The logs API only filters by region and instance, so level, message and
+//! time range filters run here on each fetched page. A page can come back
+//! empty with a cursor when nothing in it matched.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::time::Duration;
+
+use crate::api::FlyApi;
+use crate::models::{LogEntry, LogPage};
+
+/// Delay between polls while following.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Levels from least to most severe.
+const LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
+
+/// Parameters for a log fetch.
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    pub app: String,
+    pub region: Option<String>,
+    pub instance: Option<String>,
+    pub cursor: Option<String>,
+    pub follow: bool,
+    pub wait: Duration,
+    pub filter: LogFilter,
+}
+
+/// Filters applied to fetched entries.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// Minimum severity; entries without a known level only match exactly.
+    pub level: Option<String>,
+    pub message: Option<Regex>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl LogFilter {
+    /// Whether any filter is set.
+    pub fn is_empty(&self) -> bool {
+        self.level.is_none() && self.message.is_none() && self.start.is_none() && self.end.is_none()
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        if let Some(level) = &self.level {
+            let entry_level = entry.level.as_deref().unwrap_or("").to_ascii_lowercase();
+            let matched = match (severity(&entry_level), severity(level)) {
+                (Some(actual), Some(min)) => actual >= min,
+                _ => entry_level == *level,
+            };
+            if !matched {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.message {
+            if !pattern.is_match(&entry.message) {
+                return false;
+            }
+        }
+        if self.start.is_some() || self.end.is_some() {
+            let Some(at) = entry.time() else {
+                return false;
+            };
+            if self.start.is_some_and(|start| at < start) || self.end.is_some_and(|end| at > end) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn severity(level: &str) -> Option<usize> {
+    let level = match level {
+        "warning" => "warn",
+        "err" | "fatal" | "critical" => "error",
+        other => other,
+    };
+    LEVELS.iter().position(|l| *l == level)
+}
+
+/// Fetch logs once, or long-poll for new entries when following.
+pub async fn fetch_logs(client: &dyn FlyApi, query: &LogQuery) -> Result<LogPage> {
+    let deadline = tokio::time::Instant::now() + query.wait;
+    let mut cursor = query.cursor.clone();
+
+    loop {
+        let mut page = client
+            .get_logs(
+                &query.app,
+                query.region.as_deref(),
+                query.instance.as_deref(),
+                cursor.as_deref(),
+            )
+            .await?;
+        if !query.filter.is_empty() {
+            page.entries.retain(|e| query.filter.matches(e));
+        }
+
+        if !query.follow
+            || !page.entries.is_empty()
+            || tokio::time::Instant::now() + FOLLOW_POLL_INTERVAL > deadline
+        {
+            return Ok(page);
+        }
+
+        cursor = page.next_token;
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+    }
+}
+
+/// One entry as a line, in the layout `flyctl logs` prints.
+pub fn format_line(entry: &LogEntry) -> String {
+    format!(
+        "{} app[{}] {} [{}] {}",
+        entry.timestamp,
+        entry.instance.as_deref().unwrap_or("-"),
+        entry.region.as_deref().unwrap_or("-"),
+        entry.level.as_deref().unwrap_or("info"),
+        entry.message
+    )
+}
@@ -0,0 +1,523 @@
+//! Background log shipping to local files or syslog.
+//!
+//! A shipper per app tails the app's logs and writes each entry to its sink,
+//! so the daemon can act as a small log collector. By default logs are
+//! tailed through the logs API cursor. With `source: nats` (or
+//! `FGP_FLY_LOGSHIP_SOURCE=nats`) the shipper subscribes to the org's NATS
+//! log stream instead, as `fly logs` does; that stream is only reachable
+//! from inside the org's private network, at `_api.internal:4223` unless
+//! `FGP_FLY_NATS_URL` says otherwise.
+//!
+//! File sinks rotate by size: `app.log` moves to `app.log.1`, `.1` to `.2`,
+//! and so on up to `keep` files. Syslog sinks send RFC 5424 datagrams over
+//! UDP, one per entry.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::api::FlyApi;
+use crate::logs::{self, LogQuery};
+use crate::models::LogEntry;
+
+/// Syslog facility `local0`; severities are added per entry.
+const SYSLOG_FACILITY: u8 = 16;
+
+/// Org NATS server carrying the log stream, over the private network.
+const NATS_URL: &str = "nats://_api.internal:4223";
+
+/// Where a shipper reads logs from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// Poll the logs API.
+    Api,
+    /// Subscribe to the org's NATS log stream.
+    Nats,
+}
+
+impl Source {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "api" => Ok(Self::Api),
+            "nats" => Ok(Self::Nats),
+            _ => anyhow::bail!(
+                "Unknown log source: {}. Valid sources are: api, nats",
+                value
+            ),
+        }
+    }
+
+    /// `FGP_FLY_LOGSHIP_SOURCE`, or `api`.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("FGP_FLY_LOGSHIP_SOURCE") {
+            Ok(value) if !value.trim().is_empty() => Self::parse(value.trim()),
+            _ => Ok(Self::Api),
+        }
+    }
+}
+
+/// Where shipped logs go.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sink {
+    File {
+        path: PathBuf,
+        /// Rotate once the file reaches this size.
+        max_bytes: u64,
+        /// Rotated files kept besides the live one.
+        keep: usize,
+    },
+    Syslog {
+        /// `host:port` of a syslog server accepting UDP.
+        addr: String,
+    },
+}
+
+/// A running shipper, as reported by `fly.logship.status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShipperInfo {
+    pub app: String,
+    pub source: Source,
+    pub sink: Sink,
+    pub started_at: DateTime<Utc>,
+    pub entries: u64,
+    pub bytes: u64,
+    pub errors: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_entry_at: Option<String>,
+}
+
+struct Shipper {
+    info: Arc<Mutex<ShipperInfo>>,
+    task: JoinHandle<()>,
+}
+
+/// Registry of running shippers, one per app.
+#[derive(Default)]
+pub struct LogShipHub {
+    shippers: Mutex<BTreeMap<String, Shipper>>,
+}
+
+impl LogShipHub {
+    /// Start shipping `app`'s logs from `source` to `sink`, replacing any
+    /// running shipper.
+    ///
+    /// The sink is opened before returning so a bad path or address is
+    /// reported to the caller.
+    pub fn start(
+        &self,
+        runtime: &Handle,
+        client: Arc<dyn FlyApi>,
+        app: String,
+        source: Source,
+        sink: Sink,
+    ) -> Result<ShipperInfo> {
+        let writer = {
+            let _guard = runtime.enter();
+            Writer::open(&sink)?
+        };
+        let info = Arc::new(Mutex::new(ShipperInfo {
+            app: app.clone(),
+            source,
+            sink,
+            started_at: Utc::now(),
+            entries: 0,
+            bytes: 0,
+            errors: 0,
+            last_error: None,
+            last_entry_at: None,
+        }));
+
+        let task = match source {
+            Source::Api => runtime.spawn(ship(client, app.clone(), writer, info.clone())),
+            Source::Nats => runtime.spawn(ship_nats(client, app.clone(), writer, info.clone())),
+        };
+        let snapshot = info.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut shippers = self.shippers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = shippers.insert(app, Shipper { info, task }) {
+            old.task.abort();
+        }
+        Ok(snapshot)
+    }
+
+    /// Stop shipping `app`'s logs, returning its final counters.
+    pub fn stop(&self, app: &str) -> Option<ShipperInfo> {
+        let shipper = self
+            .shippers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(app)?;
+        shipper.task.abort();
+        let info = shipper.info.lock().unwrap_or_else(|e| e.into_inner());
+        Some(info.clone())
+    }
+
+    /// Stop every shipper, e.g. on daemon shutdown.
+    pub fn stop_all(&self) {
+        let mut shippers = self.shippers.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, shipper) in std::mem::take(&mut *shippers) {
+            shipper.task.abort();
+        }
+    }
+
+    /// Running shippers with their counters.
+    pub fn list(&self) -> Vec<ShipperInfo> {
+        self.shippers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|s| s.info.lock().unwrap_or_else(|e| e.into_inner()).clone())
+            .collect()
+    }
+}
+
+/// Tail loop. Fetch errors are counted and retried after a short pause.
+async fn ship(
+    client: Arc<dyn FlyApi>,
+    app: String,
+    mut writer: Writer,
+    info: Arc<Mutex<ShipperInfo>>,
+) {
+    let mut query = LogQuery {
+        app: app.clone(),
+        follow: true,
+        wait: Duration::from_secs(30),
+        ..Default::default()
+    };
+
+    loop {
+        let page = match logs::fetch_logs(&*client, &query).await {
+            Ok(page) => page,
+            Err(e) => {
+                tracing::warn!("Log shipping fetch failed for {}: {:#}", app, e);
+                record_error(&info, format!("{:#}", e));
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        if page.next_token.is_some() {
+            query.cursor = page.next_token;
+        }
+
+        for entry in &page.entries {
+            deliver(&mut writer, &app, entry, &info).await;
+        }
+    }
+}
+
+/// NATS tail loop. A dropped connection or failed subscribe is counted and
+/// retried after a short pause.
+async fn ship_nats(
+    client: Arc<dyn FlyApi>,
+    app: String,
+    mut writer: Writer,
+    info: Arc<Mutex<ShipperInfo>>,
+) {
+    loop {
+        match subscribe_nats(&*client, &app).await {
+            Ok(mut messages) => {
+                while let Some(message) = messages.next().await {
+                    match serde_json::from_slice::<NatsLog>(&message.payload) {
+                        Ok(log) => deliver(&mut writer, &app, &log.into(), &info).await,
+                        Err(e) => {
+                            tracing::debug!("Skipping unreadable NATS log for {}: {}", app, e)
+                        }
+                    }
+                }
+                tracing::warn!("NATS log stream for {} ended, resubscribing", app);
+            }
+            Err(e) => {
+                tracing::warn!("Log shipping subscribe failed for {}: {:#}", app, e);
+                record_error(&info, format!("{:#}", e));
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Subscribe to every instance's logs for `app` on the org's NATS server.
+///
+/// NATS authenticates with the org slug as user and the API token as
+/// password.
+async fn subscribe_nats(client: &dyn FlyApi, app: &str) -> Result<async_nats::Subscriber> {
+    let org = client.get_app_org_slug(app).await?;
+    let authorization = client.credentials().authorization().await?;
+    let password = nats_password(&authorization);
+    let url = std::env::var("FGP_FLY_NATS_URL").unwrap_or_else(|_| NATS_URL.into());
+
+    let nats = async_nats::ConnectOptions::with_user_and_password(org, password)
+        .connect(url.as_str())
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to connect to the NATS log stream at {} \
+                 (the daemon's host must be on the org's 6PN private network)",
+                url
+            )
+        })?;
+    let subject = format!("logs.{}.>", app);
+    nats.subscribe(subject.clone())
+        .await
+        .with_context(|| format!("Failed to subscribe to {}", subject))
+}
+
+/// Token as NATS expects it: macaroons keep their `FlyV1` scheme, other
+/// tokens are sent bare.
+fn nats_password(authorization: &str) -> String {
+    authorization
+        .strip_prefix("Bearer ")
+        .unwrap_or(authorization)
+        .to_string()
+}
+
+/// A log line as published on the NATS stream.
+#[derive(Debug, Deserialize)]
+struct NatsLog {
+    timestamp: String,
+    message: String,
+    #[serde(default)]
+    log: NatsLogLevel,
+    #[serde(default)]
+    fly: NatsFly,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NatsLogLevel {
+    #[serde(default)]
+    level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NatsFly {
+    #[serde(default)]
+    region: Option<String>,
+    #[serde(default)]
+    app: NatsApp,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NatsApp {
+    #[serde(default)]
+    instance: Option<String>,
+}
+
+impl From<NatsLog> for LogEntry {
+    fn from(log: NatsLog) -> Self {
+        LogEntry {
+            timestamp: log.timestamp,
+            message: log.message,
+            level: log.log.level,
+            region: log.fly.region,
+            instance: log.fly.app.instance,
+        }
+    }
+}
+
+/// Write one entry to the sink and count it.
+async fn deliver(writer: &mut Writer, app: &str, entry: &LogEntry, info: &Mutex<ShipperInfo>) {
+    match writer.write(app, entry).await {
+        Ok(bytes) => {
+            let mut info = info.lock().unwrap_or_else(|e| e.into_inner());
+            info.entries += 1;
+            info.bytes += bytes as u64;
+            info.last_entry_at = Some(entry.timestamp.clone());
+        }
+        Err(e) => {
+            tracing::warn!("Log shipping write failed for {}: {:#}", app, e);
+            record_error(info, format!("{:#}", e));
+        }
+    }
+}
+
+fn record_error(info: &Mutex<ShipperInfo>, error: String) {
+    let mut info = info.lock().unwrap_or_else(|e| e.into_inner());
+    info.errors += 1;
+    info.last_error = Some(error);
+}
+
+/// An open sink.
+enum Writer {
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+        max_bytes: u64,
+        keep: usize,
+    },
+    Syslog(UdpSocket),
+}
+
+impl Writer {
+    fn open(sink: &Sink) -> Result<Self> {
+        match sink {
+            Sink::File {
+                path,
+                max_bytes,
+                keep,
+            } => {
+                if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create {}", dir.display()))?;
+                }
+                let file = open_append(path)?;
+                let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+                Ok(Self::File {
+                    path: path.clone(),
+                    file,
+                    size,
+                    max_bytes: *max_bytes,
+                    keep: *keep,
+                })
+            }
+            Sink::Syslog { addr } => {
+                let target = addr
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .with_context(|| format!("Invalid syslog address: {}", addr))?;
+                let local = if target.is_ipv6() {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = std::net::UdpSocket::bind(local)
+                    .context("Failed to open UDP socket for syslog")?;
+                socket.connect(target)?;
+                socket.set_nonblocking(true)?;
+                Ok(Self::Syslog(UdpSocket::from_std(socket)?))
+            }
+        }
+    }
+
+    /// Write one entry, returning the bytes written.
+    async fn write(&mut self, app: &str, entry: &LogEntry) -> Result<usize> {
+        match self {
+            Self::File {
+                path,
+                file,
+                size,
+                max_bytes,
+                keep,
+            } => {
+                let line = format!("{}\n", logs::format_line(entry));
+                if *size > 0 && *size + line.len() as u64 > *max_bytes {
+                    rotate(path, *keep)?;
+                    *file = open_append(path)?;
+                    *size = 0;
+                }
+                file.write_all(line.as_bytes())
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                *size += line.len() as u64;
+                Ok(line.len())
+            }
+            Self::Syslog(socket) => {
+                let message = syslog_message(app, entry);
+                socket
+                    .send(message.as_bytes())
+                    .await
+                    .context("Failed to send syslog datagram")
+            }
+        }
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Shift `path.N` to `path.N+1`, dropping the oldest, then move `path` to `.1`.
+fn rotate(path: &Path, keep: usize) -> Result<()> {
+    let numbered = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    if keep == 0 {
+        return std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()));
+    }
+    let _ = std::fs::remove_file(numbered(keep));
+    for n in (1..keep).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(n + 1))
+                .with_context(|| format!("Failed to rotate {}", from.display()))?;
+        }
+    }
+    std::fs::rename(path, numbered(1))
+        .with_context(|| format!("Failed to rotate {}", path.display()))
+}
+
+/// RFC 5424 message: `<PRI>1 TIMESTAMP - APP INSTANCE - - MSG`.
+fn syslog_message(app: &str, entry: &LogEntry) -> String {
+    let severity = match entry
+        .level
+        .as_deref()
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("error") | Some("err") | Some("fatal") | Some("critical") => 3,
+        Some("warn") | Some("warning") => 4,
+        Some("debug") | Some("trace") => 7,
+        _ => 6,
+    };
+    format!(
+        "<{}>1 {} - {} {} - - {}",
+        SYSLOG_FACILITY * 8 + severity,
+        entry.timestamp,
+        app,
+        entry.instance.as_deref().unwrap_or("-"),
+        entry.message
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nats_logs_become_entries() {
+        let payload = br#"{
+            "event": {"provider": "app"},
+            "fly": {"app": {"instance": "148e21ea7d5e89", "name": "web"}, "region": "iad"},
+            "host": "a1b2",
+            "log": {"level": "error"},
+            "message": "connection refused",
+            "timestamp": "2026-10-16T12:00:00.123Z"
+        }"#;
+        let entry: LogEntry = serde_json::from_slice::<NatsLog>(payload).unwrap().into();
+
+        assert_eq!(entry.message, "connection refused");
+        assert_eq!(entry.level.as_deref(), Some("error"));
+        assert_eq!(entry.region.as_deref(), Some("iad"));
+        assert_eq!(entry.instance.as_deref(), Some("148e21ea7d5e89"));
+    }
+
+    #[test]
+    fn nats_password_drops_only_the_bearer_scheme() {
+        assert_eq!(nats_password("Bearer fo1_abc"), "fo1_abc");
+        assert_eq!(nats_password("FlyV1 fm2_abc"), "FlyV1 fm2_abc");
+    }
+
+    #[test]
+    fn sources_parse() {
+        assert_eq!(Source::parse("nats").unwrap(), Source::Nats);
+        assert_eq!(Source::parse("api").unwrap(), Source::Api);
+        assert!(Source::parse("syslog").is_err());
+    }
+}
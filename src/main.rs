@@ -11,8 +11,47 @@
 //! ```
 
 mod api;
+mod audit;
+mod autoscale;
+mod bulk;
+mod cache;
+mod config;
+mod connection;
+mod cron;
+mod deploy;
+mod doctor;
+mod domains;
+mod error;
+mod events;
+#[cfg(feature = "prometheus")]
+mod exporter;
+mod fleet;
+mod fly_toml;
+mod image;
+mod limits;
+mod logs;
+mod logship;
 mod models;
+mod monitor;
+mod params;
+mod postgres;
+mod proxy;
+mod reconcile;
+mod redact;
+mod regions;
+mod registry;
+mod restart;
+mod scale;
+mod schedule;
+mod secrets;
 mod service;
+mod sizes;
+mod state;
+mod suggest;
+mod summary;
+mod usage;
+mod watch;
+mod wireguard;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
@@ -20,6 +59,8 @@ use fgp_daemon::{cleanup_socket, FgpServer};
 use std::path::Path;
 use std::process::Command;
 
+use crate::config::Config;
+use crate::connection::StartMode;
 use crate::service::FlyService;
 
 const DEFAULT_SOCKET: &str = "~/.fgp/services/fly/daemon.sock";
@@ -44,6 +85,11 @@ enum Commands {
         /// Run in foreground (don't daemonize)
         #[arg(short, long)]
         foreground: bool,
+
+        /// `strict` fails to start without the Fly.io API; `lazy` starts
+        /// degraded and keeps retrying (default: FGP_FLY_START_MODE or strict)
+        #[arg(long, value_parser = ["strict", "lazy"])]
+        start_mode: Option<String>,
     },
 
     /// Stop the running daemon
@@ -65,13 +111,17 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Start { socket, foreground } => cmd_start(socket, foreground),
+        Commands::Start {
+            socket,
+            foreground,
+            start_mode,
+        } => cmd_start(socket, foreground, start_mode),
         Commands::Stop { socket } => cmd_stop(socket),
         Commands::Status { socket } => cmd_status(socket),
     }
 }
 
-fn cmd_start(socket: String, foreground: bool) -> Result<()> {
+fn cmd_start(socket: String, foreground: bool, start_mode: Option<String>) -> Result<()> {
     let socket_path = shellexpand::tilde(&socket).to_string();
 
     // Create parent directory
@@ -79,9 +129,11 @@ fn cmd_start(socket: String, foreground: bool) -> Result<()> {
         std::fs::create_dir_all(parent).context("Failed to create socket directory")?;
     }
 
-    // Get token BEFORE fork (env access needs parent process)
-    let token = std::env::var("FLY_ACCESS_TOKEN")
-        .context("FLY_ACCESS_TOKEN environment variable not set")?;
+    // Load tokens BEFORE fork (env access needs parent process)
+    let mut config = Config::load().context("Failed to load Fly.io credentials")?;
+    if let Some(mode) = start_mode {
+        config.start_mode = StartMode::parse(&mode)?;
+    }
 
     let pid_file = format!("{}.pid", socket_path);
 
@@ -94,7 +146,7 @@ fn cmd_start(socket: String, foreground: bool) -> Result<()> {
             .with_env_filter("fgp_fly=debug,fgp_daemon=debug")
             .init();
 
-        let service = FlyService::new(token).context("Failed to create FlyService")?;
+        let service = FlyService::new(config).context("Failed to create FlyService")?;
         let server =
             FgpServer::new(service, &socket_path).context("Failed to create FGP server")?;
         server.serve().context("Server error")?;
@@ -114,7 +166,7 @@ fn cmd_start(socket: String, foreground: bool) -> Result<()> {
                     .with_env_filter("fgp_fly=debug,fgp_daemon=debug")
                     .init();
 
-                let service = FlyService::new(token).context("Failed to create FlyService")?;
+                let service = FlyService::new(config).context("Failed to create FlyService")?;
                 let server =
                     FgpServer::new(service, &socket_path).context("Failed to create FGP server")?;
                 server.serve().context("Server error")?;
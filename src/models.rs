@@ -1,9 +1,12 @@
 //! Data models for Fly.io API responses.
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Fly.io application.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
 pub struct App {
     pub id: String,
     pub name: String,
@@ -17,6 +20,8 @@ pub struct App {
     pub deployed: bool,
     #[serde(default)]
     pub current_release: Option<Release>,
+    #[serde(default)]
+    pub created_at: Option<String>,
 }
 
 /// Fly.io organization.
@@ -27,6 +32,85 @@ pub struct Organization {
     pub slug: String,
 }
 
+/// Organization with billing status, membership and app counts.
+///
+/// `apps` and `members` are only filled in for single-organization lookups.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationDetail {
+    pub id: String,
+    pub name: String,
+    pub slug: String,
+    #[serde(rename = "type")]
+    pub org_type: String,
+    #[serde(default)]
+    pub billing_status: Option<String>,
+    #[serde(default)]
+    pub viewer_role: Option<String>,
+    pub member_count: usize,
+    pub app_count: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub apps: Vec<App>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<OrganizationMember>,
+}
+
+/// Billing summary of an organization.
+///
+/// Amounts are in cents, as Fly reports them, with a `_formatted` string
+/// for display.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct BillingSummary {
+    pub org: String,
+    #[serde(default)]
+    pub billing_status: Option<String>,
+    #[serde(default)]
+    pub credit_balance: Option<i64>,
+    #[serde(default)]
+    pub credit_balance_formatted: Option<String>,
+    /// The open invoice for the current billing period.
+    #[serde(default)]
+    pub current_invoice: Option<Invoice>,
+}
+
+/// Invoice for one billing period, with usage by category.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Invoice {
+    #[serde(default)]
+    pub amount: Option<i64>,
+    #[serde(default)]
+    pub amount_formatted: Option<String>,
+    #[serde(default)]
+    pub period_start: Option<String>,
+    #[serde(default)]
+    pub period_end: Option<String>,
+    #[serde(default)]
+    pub usage: Vec<UsageCategory>,
+}
+
+/// Charges in one usage category, e.g. compute or volumes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct UsageCategory {
+    pub category: String,
+    #[serde(default)]
+    pub amount: Option<i64>,
+    #[serde(default)]
+    pub amount_formatted: Option<String>,
+}
+
+/// Member of an organization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationMember {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    pub role: String,
+}
+
 /// Fly.io machine (VM instance).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Machine {
@@ -40,6 +124,36 @@ pub struct Machine {
     pub private_ip: Option<String>,
     #[serde(default)]
     pub config: Option<MachineConfig>,
+    #[serde(default)]
+    pub image_ref: Option<ImageRef>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    #[serde(default)]
+    pub checks: Vec<MachineCheck>,
+    /// The proxy is not routing new requests to the machine.
+    #[serde(default)]
+    pub cordoned: bool,
+}
+
+/// Result of one health check on a machine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineCheck {
+    pub name: String,
+    /// `passing`, `warning` or `critical`.
+    pub status: String,
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+impl MachineCheck {
+    /// Whether the check is anything other than passing.
+    pub fn is_failing(&self) -> bool {
+        self.status != "passing"
+    }
 }
 
 /// Machine configuration.
@@ -49,10 +163,559 @@ pub struct MachineConfig {
     pub size: Option<String>,
     #[serde(default)]
     pub image: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub guest: Option<GuestConfig>,
+    #[serde(default)]
+    pub services: Vec<MachineService>,
+    #[serde(default)]
+    pub mounts: Vec<MachineMount>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Fields not modeled above, preserved so updates round-trip.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Service exposed by a machine through the Fly proxy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineService {
+    #[serde(default)]
+    pub protocol: Option<String>,
+    #[serde(default)]
+    pub internal_port: Option<u16>,
+    #[serde(default)]
+    pub ports: Vec<MachinePort>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Public port mapping of a machine service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachinePort {
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub handlers: Vec<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Volume mounted into a machine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineMount {
+    #[serde(default)]
+    pub volume: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Partial machine config for in-place updates.
+///
+/// Map entries set to `null` are removed; `services` replaces the list.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfigPatch {
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub guest: Option<GuestConfig>,
+    #[serde(default)]
+    pub env: HashMap<String, Option<String>>,
+    #[serde(default)]
+    pub metadata: HashMap<String, Option<String>>,
+    #[serde(default)]
+    pub services: Option<Vec<MachineService>>,
+}
+
+impl MachineConfigPatch {
+    /// Merge this patch into an existing config.
+    pub fn apply_to(&self, config: &mut MachineConfig) {
+        if let Some(image) = &self.image {
+            config.image = Some(image.clone());
+        }
+
+        if let Some(patch) = &self.guest {
+            let guest = config.guest.get_or_insert_with(GuestConfig::default);
+            if patch.cpu_kind.is_some() {
+                guest.cpu_kind = patch.cpu_kind.clone();
+            }
+            if patch.cpus.is_some() {
+                guest.cpus = patch.cpus;
+            }
+            if patch.memory_mb.is_some() {
+                guest.memory_mb = patch.memory_mb;
+            }
+            if patch.gpu_kind.is_some() {
+                guest.gpu_kind = patch.gpu_kind.clone();
+            }
+            if patch.gpus.is_some() {
+                guest.gpus = patch.gpus;
+            }
+            guest.extra.extend(patch.extra.clone());
+        }
+
+        merge_map(&mut config.env, &self.env);
+        merge_map(&mut config.metadata, &self.metadata);
+
+        if let Some(services) = &self.services {
+            config.services = services.clone();
+        }
+    }
+}
+
+fn merge_map(target: &mut HashMap<String, String>, patch: &HashMap<String, Option<String>>) {
+    for (key, value) in patch {
+        match value {
+            Some(value) => {
+                target.insert(key.clone(), value.clone());
+            }
+            None => {
+                target.remove(key);
+            }
+        }
+    }
+}
+
+/// GPU kinds Fly offers and the regions each is available in.
+pub const GPU_REGIONS: &[(&str, &[&str])] = &[
+    ("a10", &["ord"]),
+    ("l40s", &["ord"]),
+    ("a100-pcie-40gb", &["ord"]),
+    ("a100-sxm4-80gb", &["ams", "iad", "mia", "sjc", "syd"]),
+];
+
+/// Machine guest (VM) resources.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuestConfig {
+    #[serde(default)]
+    pub cpu_kind: Option<String>,
+    #[serde(default)]
+    pub cpus: Option<u32>,
+    #[serde(default)]
+    pub memory_mb: Option<u32>,
+    /// GPU model, one of [`GPU_REGIONS`]; unset for CPU-only machines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<u32>,
+    /// Fields not modeled above (e.g. `kernel_args`, `host_dedication_id`),
+    /// preserved so resizing a machine doesn't drop them.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl GuestConfig {
+    /// Check the guest resources for a machine in `region`, if known.
+    pub fn validate(&self, region: Option<&str>) -> anyhow::Result<()> {
+        if let Some(kind) = self.cpu_kind.as_deref() {
+            if kind != "shared" && kind != "performance" {
+                anyhow::bail!(
+                    "config.guest.cpu_kind must be 'shared' or 'performance', got '{}'",
+                    kind
+                );
+            }
+        }
+        if self.cpus == Some(0) {
+            anyhow::bail!("config.guest.cpus must be at least 1");
+        }
+        if let Some(memory) = self.memory_mb {
+            if memory == 0 || memory % 256 != 0 {
+                anyhow::bail!("config.guest.memory_mb must be a positive multiple of 256");
+            }
+        }
+
+        let Some(kind) = self.gpu_kind.as_deref() else {
+            if self.gpus.is_some() {
+                anyhow::bail!("config.guest.gpus requires config.guest.gpu_kind");
+            }
+            return Ok(());
+        };
+        let Some((_, regions)) = GPU_REGIONS.iter().find(|(k, _)| *k == kind) else {
+            let kinds: Vec<&str> = GPU_REGIONS.iter().map(|(k, _)| *k).collect();
+            anyhow::bail!(
+                "config.guest.gpu_kind must be one of {}, got '{}'",
+                kinds.join(", "),
+                kind
+            );
+        };
+        if self.gpus == Some(0) {
+            anyhow::bail!("config.guest.gpus must be at least 1");
+        }
+        match region {
+            Some(region) if regions.contains(&region) => Ok(()),
+            Some(region) => anyhow::bail!(
+                "{} GPUs aren't available in {}; use one of: {}",
+                kind,
+                region,
+                regions.join(", ")
+            ),
+            None => anyhow::bail!(
+                "config.region is required for {} GPU machines; use one of: {}",
+                kind,
+                regions.join(", ")
+            ),
+        }
+    }
+}
+
+/// Resolved image a machine is running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageRef {
+    #[serde(default)]
+    pub registry: Option<String>,
+    #[serde(default)]
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+/// Request body for creating a machine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineCreateConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub image: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest: Option<GuestConfig>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<MachineService>,
+}
+
+impl MachineCreateConfig {
+    /// Check the config before sending it to the Machines API.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.image.trim().is_empty() {
+            anyhow::bail!("config.image must not be empty");
+        }
+
+        if let Some(guest) = &self.guest {
+            guest.validate(self.region.as_deref())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lease held on a machine for exclusive updates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineLease {
+    #[serde(default)]
+    pub nonce: Option<String>,
+    /// Unix timestamp (seconds) when the lease expires.
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+/// Output of a command run with the Machines exec endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecResult {
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub exit_signal: Option<i32>,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+}
+
+/// Machine lifecycle event (start, exit, restart, ...).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineEvent {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub kind: String,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+    pub timestamp: String,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub oom_killed: Option<bool>,
+    #[serde(default)]
+    pub requested_stop: Option<bool>,
+}
+
+/// Why a machine last stopped, derived from its latest exit event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum StopReason {
+    Clean,
+    Crashed { exit_code: i32 },
+    OomKilled,
+    Signaled { signal: String },
+    Unknown,
+}
+
+impl StopReason {
+    /// Classify an exit event.
+    ///
+    /// Exit codes above 128 follow the shell convention of `128 + signal`.
+    pub fn from_exit_event(event: &MachineEvent) -> Self {
+        if event.oom_killed.unwrap_or(false) {
+            return StopReason::OomKilled;
+        }
+        if event.requested_stop.unwrap_or(false) {
+            return StopReason::Clean;
+        }
+
+        match event.exit_code {
+            Some(0) => StopReason::Clean,
+            Some(code) if code > 128 => StopReason::Signaled {
+                signal: signal_name(code - 128),
+            },
+            Some(code) => StopReason::Crashed { exit_code: code },
+            None => StopReason::Unknown,
+        }
+    }
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal {
+        1 => "SIGHUP".into(),
+        2 => "SIGINT".into(),
+        3 => "SIGQUIT".into(),
+        6 => "SIGABRT".into(),
+        9 => "SIGKILL".into(),
+        11 => "SIGSEGV".into(),
+        13 => "SIGPIPE".into(),
+        15 => "SIGTERM".into(),
+        other => other.to_string(),
+    }
+}
+
+/// Persistent volume attached to an app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Volume {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    pub size_gb: u32,
+    pub region: String,
+    #[serde(default)]
+    pub zone: Option<String>,
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub attached_machine_id: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// Point-in-time snapshot of a volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeSnapshot {
+    pub id: String,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// IP address allocated to an app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpAddress {
+    pub id: String,
+    pub address: String,
+    #[serde(rename = "type")]
+    pub ip_type: String,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// TLS certificate for a custom domain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Certificate {
+    pub id: String,
+    pub hostname: String,
+    #[serde(default)]
+    pub client_status: Option<String>,
+    #[serde(default)]
+    pub configured: bool,
+    #[serde(default)]
+    pub acme_dns_configured: bool,
+    #[serde(default)]
+    pub acme_alpn_configured: bool,
+    #[serde(default)]
+    pub dns_validation_hostname: Option<String>,
+    #[serde(default)]
+    pub dns_validation_target: Option<String>,
+    #[serde(default)]
+    pub dns_validation_instructions: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub issued: Option<IssuedCertificates>,
+}
+
+/// Issued certificates (RSA/ECDSA) backing a hostname.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedCertificates {
+    #[serde(default)]
+    pub nodes: Vec<IssuedCertificate>,
+}
+
+/// Single issued certificate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct IssuedCertificate {
+    #[serde(rename = "type")]
+    pub cert_type: String,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+/// Limited-access (deploy) token.
+///
+/// The token secret itself is only returned once, when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct DeployToken {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+/// WireGuard peer on an organization's private network.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireGuardPeer {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub network: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    /// The peer's address on the private network.
+    #[serde(default)]
+    pub peerip: Option<String>,
+    #[serde(default)]
+    pub pubkey: Option<String>,
+}
+
+/// Gateway details returned when a peer is added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireGuardPeerCreated {
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Public address of the gateway to connect to.
+    pub endpointip: String,
+    pub peerip: String,
+    /// The gateway's public key.
+    pub pubkey: String,
+}
+
+/// Fly.io platform region.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Region {
+    pub code: String,
+    pub name: String,
+    #[serde(default)]
+    pub gateway_available: bool,
+    #[serde(default)]
+    pub requires_paid_plan: bool,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+}
+
+/// VM size preset with its list price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct VmSize {
+    pub name: String,
+    pub cpu_cores: f64,
+    pub memory_mb: i64,
+    /// USD per 30 days of running.
+    #[serde(default)]
+    pub price_month: Option<f64>,
+    /// USD per second of running.
+    #[serde(default)]
+    pub price_second: Option<f64>,
+}
+
+/// Authenticated user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub id: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub organizations: Vec<Organization>,
+}
+
+/// App secret. Values are never readable, only their digest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Secret {
+    pub name: String,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Whether running machines have picked the secret up yet; only set
+    /// by `fly.secrets action=list`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SecretStatus>,
+}
+
+/// Whether a secret has reached the app's machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretStatus {
+    /// Set after the last machine update; applies on the next deploy.
+    Staged,
+    Deployed,
+}
+
+impl Secret {
+    /// Mark the secret staged if it is newer than every machine update.
+    pub fn with_status(mut self, last_machine_update: Option<DateTime<Utc>>) -> Self {
+        self.status = match (self.created_at, last_machine_update) {
+            (Some(created), Some(updated)) if created > updated => Some(SecretStatus::Staged),
+            (_, Some(_)) => Some(SecretStatus::Deployed),
+            // No machines to compare with
+            (_, None) => None,
+        };
+        self
+    }
 }
 
 /// Fly.io release.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
 pub struct Release {
     pub id: String,
     pub version: i32,
@@ -60,11 +723,76 @@ pub struct Release {
     #[serde(default)]
     pub description: Option<String>,
     #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
     pub created_at: Option<String>,
 }
 
+/// Image build for an app, local or on a remote builder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Build {
+    pub id: String,
+    pub status: String,
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub logs_url: Option<String>,
+    #[serde(default)]
+    pub in_progress: bool,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    /// Seconds from creation to the last update, once finished.
+    #[serde(default)]
+    pub duration_secs: Option<i64>,
+}
+
+/// An organization's remote builder app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteBuilder {
+    /// Builder app name, `None` until the org's first remote build.
+    #[serde(default)]
+    pub app: Option<String>,
+    /// Image new builder machines are created from.
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+/// Release history for an app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseList {
+    pub app: String,
+    pub releases: Vec<Release>,
+    pub count: usize,
+    pub next_cursor: Option<String>,
+}
+
+/// One page of a paginated GraphQL connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor for the next page, `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Field-level errors for items the API left out of the page.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+/// One labelled time series from a Prometheus range query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricSeries {
+    #[serde(default)]
+    pub instance: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    /// `[unix_seconds, value]` pairs.
+    pub points: Vec<(i64, f64)>,
+}
+
 /// Application status response.
-#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppStatus {
     pub app: App,
@@ -75,7 +803,6 @@ pub struct AppStatus {
 }
 
 /// VM allocation (legacy).
-#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Allocation {
     pub id: String,
@@ -86,7 +813,6 @@ pub struct Allocation {
 }
 
 /// Log entry.
-#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
     pub timestamp: String,
@@ -99,6 +825,23 @@ pub struct LogEntry {
     pub instance: Option<String>,
 }
 
+impl LogEntry {
+    /// Parsed `timestamp`, if it is RFC 3339.
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+            .ok()
+            .map(|at| at.with_timezone(&Utc))
+    }
+}
+
+/// Page of log entries with a cursor for the next fetch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogPage {
+    pub entries: Vec<LogEntry>,
+    #[serde(default)]
+    pub next_token: Option<String>,
+}
+
 /// GraphQL response wrapper.
 #[derive(Debug, Deserialize)]
 pub struct GraphQLResponse<T> {
@@ -112,6 +855,43 @@ pub struct GraphQLResponse<T> {
 pub struct GraphQLError {
     pub message: String,
     #[serde(default)]
-    #[allow(dead_code)]
     pub path: Option<Vec<serde_json::Value>>, // Path can be strings or integers
+    #[serde(default)]
+    pub extensions: Option<serde_json::Value>,
+}
+
+/// Field-level GraphQL error returned alongside partial data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Warning {
+    pub message: String,
+    /// Dotted path of the field that failed, e.g. `apps.nodes.3`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+impl From<&GraphQLError> for Warning {
+    fn from(error: &GraphQLError) -> Self {
+        let path = error.path.as_ref().map(|segments| {
+            segments
+                .iter()
+                .map(|s| match s {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(".")
+        });
+        Self {
+            message: crate::redact::scrub(&error.message),
+            path,
+            code: error
+                .extensions
+                .as_ref()
+                .and_then(|e| e.get("code"))
+                .and_then(|c| c.as_str())
+                .map(String::from),
+        }
+    }
 }
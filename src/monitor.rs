@@ -0,0 +1,330 @@
+//! Crash-loop monitoring for Fly.io apps.
+//!
+//! Polls machine events for an app and flags machines that restart more than
+//! a threshold number of times inside a sliding window.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::api::FlyApi;
+use crate::models::{Machine, MachineEvent, StopReason};
+
+/// Event kinds that count as a machine (re)starting.
+const RESTART_KINDS: &[&str] = &["start", "restart"];
+
+/// Options for a monitoring run.
+#[derive(Debug, Clone)]
+pub struct MonitorOptions {
+    pub app: String,
+    pub restart_threshold: usize,
+    pub window: Duration,
+    pub duration: std::time::Duration,
+    pub interval: std::time::Duration,
+    pub webhook: Option<String>,
+}
+
+/// Alert raised when a machine is crash-looping.
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashLoopAlert {
+    pub app: String,
+    pub machine_id: String,
+    pub region: String,
+    pub restarts: usize,
+    pub window_secs: i64,
+    pub first_restart: DateTime<Utc>,
+    pub last_restart: DateTime<Utc>,
+    pub stop_reason: Option<StopReason>,
+}
+
+/// Result of a monitoring run.
+#[derive(Debug, Serialize)]
+pub struct MonitorReport {
+    pub app: String,
+    pub polls: u32,
+    pub machines_watched: usize,
+    pub restart_counts: HashMap<String, usize>,
+    pub alerts: Vec<CrashLoopAlert>,
+}
+
+/// Sliding-window restart counter.
+///
+/// A machine that restarts once (e.g. during a deploy) stays below the
+/// threshold; only more than `threshold` restarts inside `window` alert.
+pub struct CrashLoopDetector {
+    threshold: usize,
+    window: Duration,
+    restarts: HashMap<String, VecDeque<DateTime<Utc>>>,
+    /// Restart events already counted, kept while inside the window.
+    seen: HashSet<(String, String, DateTime<Utc>)>,
+}
+
+impl CrashLoopDetector {
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            restarts: HashMap::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Feed a machine event, returning the restart timestamps in the window
+    /// if this event pushed the machine over the threshold.
+    pub fn record(
+        &mut self,
+        machine_id: &str,
+        event: &MachineEvent,
+        now: DateTime<Utc>,
+    ) -> Option<Vec<DateTime<Utc>>> {
+        if !RESTART_KINDS.contains(&event.kind.as_str()) {
+            return None;
+        }
+
+        let timestamp = DateTime::parse_from_rfc3339(&event.timestamp)
+            .ok()?
+            .with_timezone(&Utc);
+        let cutoff = now - self.window;
+        if timestamp < cutoff {
+            return None;
+        }
+
+        // Events are re-fetched on every poll, only count each once. Older
+        // events are rejected above, so forgetting them is safe.
+        self.seen.retain(|(_, _, at)| *at >= cutoff);
+        let key = (machine_id.to_string(), event.kind.clone(), timestamp);
+        if !self.seen.insert(key) {
+            return None;
+        }
+
+        let history = self.restarts.entry(machine_id.to_string()).or_default();
+        history.push_back(timestamp);
+        history.make_contiguous().sort();
+        while history.front().is_some_and(|first| *first < cutoff) {
+            history.pop_front();
+        }
+
+        if history.len() > self.threshold {
+            // Reset so the next alert needs another full loop
+            let fired: Vec<_> = history.drain(..).collect();
+            return Some(fired);
+        }
+
+        None
+    }
+
+    /// Feed a machine's events (newest first, as the API returns them) and
+    /// return an alert for each time the machine crossed the threshold.
+    pub fn check_machine(
+        &mut self,
+        app: &str,
+        machine: &Machine,
+        events: &[MachineEvent],
+        now: DateTime<Utc>,
+    ) -> Vec<CrashLoopAlert> {
+        let mut alerts = Vec::new();
+        // Oldest first so the window slides forward in order
+        for event in events.iter().rev() {
+            let Some(fired) = self.record(&machine.id, event, now) else {
+                continue;
+            };
+
+            let stop_reason = events
+                .iter()
+                .find(|e| e.kind == "exit")
+                .map(StopReason::from_exit_event);
+
+            let alert = CrashLoopAlert {
+                app: app.to_string(),
+                machine_id: machine.id.clone(),
+                region: machine.region.clone(),
+                restarts: fired.len(),
+                window_secs: self.window.num_seconds(),
+                first_restart: fired[0],
+                last_restart: fired[fired.len() - 1],
+                stop_reason,
+            };
+
+            tracing::warn!(
+                "Crash loop detected: {} restarted {} times in {}s",
+                alert.machine_id,
+                alert.restarts,
+                alert.window_secs
+            );
+            alerts.push(alert);
+        }
+        alerts
+    }
+
+    /// Current restart count inside the window for a machine.
+    pub fn restart_count(&self, machine_id: &str) -> usize {
+        self.restarts.get(machine_id).map_or(0, |h| h.len())
+    }
+}
+
+/// Watch an app for crash loops until the configured duration elapses.
+pub async fn monitor_app(client: &dyn FlyApi, options: &MonitorOptions) -> Result<MonitorReport> {
+    let mut detector = CrashLoopDetector::new(options.restart_threshold, options.window);
+    let mut alerts = Vec::new();
+    let mut restart_counts = HashMap::new();
+    let mut machines_watched = 0;
+    let mut polls = 0;
+
+    let webhook_client = reqwest::Client::new();
+    let deadline = tokio::time::Instant::now() + options.duration;
+
+    loop {
+        polls += 1;
+        let now = Utc::now();
+
+        let machines = client.list_machines(&options.app).await?;
+        machines_watched = machines_watched.max(machines.len());
+
+        for machine in &machines {
+            let events = match client.list_machine_events(&options.app, &machine.id).await {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::warn!("Failed to fetch events for machine {}: {}", machine.id, e);
+                    continue;
+                }
+            };
+
+            for alert in detector.check_machine(&options.app, machine, &events, now) {
+                if let Some(url) = &options.webhook {
+                    if let Err(e) = post_alert(&webhook_client, url, &alert).await {
+                        tracing::error!("Failed to post crash-loop alert: {}", e);
+                    }
+                }
+
+                alerts.push(alert);
+            }
+
+            let count = detector.restart_count(&machine.id);
+            restart_counts.insert(machine.id.clone(), count);
+        }
+
+        if tokio::time::Instant::now() + options.interval > deadline {
+            break;
+        }
+        tokio::time::sleep(options.interval).await;
+    }
+
+    Ok(MonitorReport {
+        app: options.app.clone(),
+        polls,
+        machines_watched,
+        restart_counts,
+        alerts,
+    })
+}
+
+async fn post_alert(client: &reqwest::Client, url: &str, alert: &CrashLoopAlert) -> Result<()> {
+    let response = client
+        .post(url)
+        .json(&serde_json::json!({ "event": "crash_loop", "alert": alert }))
+        .send()
+        .await
+        .context("Failed to send webhook request")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Webhook returned {}", response.status());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        serde_json::from_value(serde_json::json!({
+            "id": "148e21ea7d5e89",
+            "name": "web-iad-1",
+            "state": "started",
+            "region": "iad",
+        }))
+        .unwrap()
+    }
+
+    fn event(kind: &str, at: DateTime<Utc>) -> MachineEvent {
+        MachineEvent {
+            id: None,
+            kind: kind.into(),
+            status: None,
+            source: None,
+            timestamp: at.to_rfc3339(),
+            exit_code: (kind == "exit").then_some(1),
+            oom_killed: None,
+            requested_stop: None,
+        }
+    }
+
+    /// `restarts` start/exit pairs, one every `every`, newest first.
+    fn restart_events(restarts: i64, every: Duration, now: DateTime<Utc>) -> Vec<MachineEvent> {
+        (0..restarts)
+            .flat_map(|i| {
+                let at = now - every * i as i32;
+                [event("start", at), event("exit", at - Duration::seconds(1))]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rapid_restarts_raise_a_crash_loop_alert() {
+        let now = Utc::now();
+        let mut detector = CrashLoopDetector::new(3, Duration::minutes(5));
+        let events = restart_events(4, Duration::seconds(30), now);
+
+        let alerts = detector.check_machine("web", &machine(), &events, now);
+
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].restarts, 4);
+        assert_eq!(alerts[0].machine_id, "148e21ea7d5e89");
+        assert!(alerts[0].stop_reason.is_some());
+    }
+
+    #[test]
+    fn a_single_restart_does_not_alert() {
+        let now = Utc::now();
+        let mut detector = CrashLoopDetector::new(3, Duration::minutes(5));
+        let events = restart_events(1, Duration::seconds(30), now);
+
+        // Events are re-fetched on every poll
+        for _ in 0..5 {
+            assert!(detector
+                .check_machine("web", &machine(), &events, now)
+                .is_empty());
+        }
+        assert_eq!(detector.restart_count("148e21ea7d5e89"), 1);
+    }
+
+    #[test]
+    fn restarts_spread_beyond_the_window_do_not_alert() {
+        let now = Utc::now();
+        let mut detector = CrashLoopDetector::new(3, Duration::minutes(5));
+        let events = restart_events(6, Duration::minutes(2), now);
+
+        assert!(detector
+            .check_machine("web", &machine(), &events, now)
+            .is_empty());
+    }
+
+    #[test]
+    fn seen_events_are_forgotten_once_outside_the_window() {
+        let start = Utc::now();
+        let mut detector = CrashLoopDetector::new(3, Duration::minutes(5));
+        detector.check_machine(
+            "web",
+            &machine(),
+            &restart_events(2, Duration::seconds(30), start),
+            start,
+        );
+        assert_eq!(detector.seen.len(), 2);
+
+        let later = start + Duration::minutes(10);
+        detector.check_machine("web", &machine(), &[event("start", later)], later);
+        assert_eq!(detector.seen.len(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+//! Parameter validation against each method's schema in the registry.
+//!
+//! Calls are checked for unknown and required params, JSON types and allowed
+//! values before they reach a handler, so handlers only see well-formed
+//! input. Failures are reported as `validation` errors (see `crate::error`);
+//! an unknown param comes with the closest known names as `suggestions`.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::registry::Method;
+use crate::suggest;
+
+/// A parameter that failed validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamError {
+    /// `unknown_param`, `missing_param`, `invalid_type` or `invalid_value`.
+    pub code: &'static str,
+    pub param: String,
+    pub message: String,
+    /// Known params the caller may have meant.
+    pub suggestions: Vec<String>,
+}
+
+impl ParamError {
+    fn unknown(param: &str, suggestions: Vec<String>) -> Self {
+        Self {
+            code: "unknown_param",
+            param: param.to_string(),
+            message: suggest::hint(format!("Unknown parameter: {}", param), &suggestions),
+            suggestions,
+        }
+    }
+
+    fn missing(param: &str) -> Self {
+        Self {
+            code: "missing_param",
+            param: param.to_string(),
+            message: format!("Missing required parameter: {}", param),
+            suggestions: Vec::new(),
+        }
+    }
+
+    fn invalid_type(param: &str, expected: &str) -> Self {
+        Self {
+            code: "invalid_type",
+            param: param.to_string(),
+            message: format!("Parameter {} must be of type {}", param, expected),
+            suggestions: Vec::new(),
+        }
+    }
+
+    fn invalid_value(param: &str, value: &str, allowed: &[&str]) -> Self {
+        Self {
+            code: "invalid_value",
+            param: param.to_string(),
+            message: format!(
+                "Invalid {}: {}. Valid values are: {}",
+                param,
+                value,
+                allowed.join(", ")
+            ),
+            suggestions: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// Allowed values for enum-like string params.
+fn allowed_values(method: &str, param: &str) -> Option<&'static [&'static str]> {
+    let values: &[&str] = match (method, param) {
+        ("fly.secrets", "action") => &[
+            "list",
+            "set",
+            "delete",
+            "set_many",
+            "unset_many",
+            "import",
+            "export_names",
+            "sync",
+            "deploy",
+        ],
+        ("fly.secrets", "format") => &["auto", "dotenv", "json"],
+        ("fly.apps", "status") => &["deployed", "suspended", "pending"],
+        ("fly.apps", "sort_by") | ("fly.machines", "sort_by") => &["name", "created", "status"],
+        ("fly.releases", "sort_by") => &["created", "status"],
+        ("fly.volumes", "action") => &["list", "create", "extend", "delete", "snapshots"],
+        ("fly.ips", "action") => &["list", "allocate", "release"],
+        ("fly.ips", "type") => &["v4", "v6", "shared_v4", "private_v6"],
+        ("fly.certs", "action") => &["list", "add", "check", "remove"],
+        ("fly.tokens", "action") => &["list", "create", "revoke"],
+        ("fly.wireguard", "action") => &["list", "create", "remove"],
+        ("fly.postgres", "action") => &[
+            "clusters",
+            "databases",
+            "create_database",
+            "users",
+            "attach",
+            "detach",
+            "connection_string",
+        ],
+        ("fly.deploy", "strategy") => &["immediate", "rolling", "canary", "bluegreen"],
+        ("fly.image", "action") => &["show", "update"],
+        ("fly.app.export", "format") => &["toml", "json"],
+        ("fly.logs", "format") => &["text", "json"],
+        ("fly.logs", "level") => &["trace", "debug", "info", "warn", "error"],
+        ("fly.image", "strategy") => &["immediate", "rolling", "canary", "bluegreen"],
+        ("fly.scale", "action") => &["count", "vm"],
+        ("fly.regions.set", "action") => &["set", "add", "remove"],
+        ("fly.platform.sizes", "cpu_kind") => &["shared", "performance"],
+        ("fly.watch", "action") => &["start", "stop", "list", "events"],
+        ("fly.alerts", "action") => &["list", "clear"],
+        ("fly.autoscale", "action") => &["enable", "disable", "status"],
+        ("fly.autoscale", "metric") => &["cpu", "concurrency"],
+        ("fly.schedule.create", "action") => &["start", "stop", "scale"],
+        ("fly.client_stats", "format") => &["json", "prometheus"],
+        ("fly.machine.destroy", "volumes") => &["keep", "delete"],
+        ("fly.restart", "strategy") => &["immediate", "rolling"],
+        ("fly.scale", "cpu_kind") => &["shared", "performance"],
+        ("fly.machine.metadata", "action") => &["get", "set", "delete"],
+        ("fly.machine.lease", "action") => &["acquire", "release", "show"],
+        ("fly.machine.wait", "state") => &["started", "stopped", "destroyed"],
+        ("fly.metrics", "metric") => &["cpu", "memory", "network", "all"],
+        _ => return None,
+    };
+    Some(values)
+}
+
+/// Check `params` against the schema for `method`.
+///
+/// Unknown params are rejected first, since a misspelled param usually
+/// also means a missing one; `null` counts as absent.
+pub fn validate<S>(method: &Method<S>, params: &HashMap<String, Value>) -> Result<(), ParamError> {
+    let mut unknown: Vec<&String> = params
+        .keys()
+        .filter(|key| !method.params().any(|p| p.name == key.as_str()))
+        .collect();
+    unknown.sort();
+    if let Some(key) = unknown.first() {
+        let suggestions = suggest::closest(key, method.params().map(|p| p.name));
+        return Err(ParamError::unknown(
+            key,
+            suggestions.into_iter().map(String::from).collect(),
+        ));
+    }
+
+    for param in method.params() {
+        let value = match params.get(param.name) {
+            None | Some(Value::Null) => {
+                if param.required {
+                    return Err(ParamError::missing(param.name));
+                }
+                continue;
+            }
+            Some(value) => value,
+        };
+
+        let type_ok = match param.kind {
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            _ => true,
+        };
+        if !type_ok {
+            return Err(ParamError::invalid_type(param.name, param.kind));
+        }
+
+        if let (Some(allowed), Some(value)) =
+            (allowed_values(method.name, param.name), value.as_str())
+        {
+            if !allowed.contains(&value) {
+                return Err(ParamError::invalid_value(param.name, value, allowed));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,343 @@
+//! Fly Postgres cluster management.
+//!
+//! Clusters are found through the GraphQL API. Database and user
+//! administration runs `psql` on the cluster's primary machine through the
+//! Machines exec endpoint, the same way `fly postgres` does for flex clusters.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::Read;
+
+use crate::api::{FlyApi, MachinesClient};
+use crate::models::Machine;
+use crate::redact;
+
+/// Seconds allowed for a single `psql` invocation.
+const PSQL_TIMEOUT_SECS: u64 = 30;
+
+/// Port Postgres listens on inside flex cluster machines (5432 is HAProxy).
+const DIRECT_PORT: u16 = 5433;
+
+/// Secret holding the connection string of an attached cluster.
+pub const DEFAULT_VARIABLE: &str = "DATABASE_URL";
+
+/// Database in a cluster.
+#[derive(Debug, Clone, Serialize)]
+pub struct Database {
+    pub name: String,
+    pub owner: String,
+}
+
+/// Role in a cluster.
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+    pub username: String,
+    pub superuser: bool,
+}
+
+/// Result of attaching a cluster to an app.
+#[derive(Debug, Serialize)]
+pub struct Attachment {
+    pub app: String,
+    pub cluster: String,
+    pub database: String,
+    pub user: String,
+    pub variable: String,
+}
+
+/// List the databases of a cluster, excluding templates.
+pub async fn list_databases(machines: &MachinesClient, cluster: &str) -> Result<Vec<Database>> {
+    let rows = psql(
+        machines,
+        cluster,
+        "SELECT datname, pg_get_userbyid(datdba) FROM pg_database WHERE NOT datistemplate ORDER BY datname",
+    )
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|mut row| Database {
+            owner: row.pop().unwrap_or_default(),
+            name: row.pop().unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Create a database owned by the operator role.
+pub async fn create_database(machines: &MachinesClient, cluster: &str, name: &str) -> Result<()> {
+    check_identifier("database", name)?;
+    psql(machines, cluster, &format!("CREATE DATABASE {}", name)).await?;
+    Ok(())
+}
+
+/// List the login roles of a cluster.
+pub async fn list_users(machines: &MachinesClient, cluster: &str) -> Result<Vec<User>> {
+    let rows = psql(
+        machines,
+        cluster,
+        "SELECT rolname, rolsuper FROM pg_roles WHERE rolcanlogin ORDER BY rolname",
+    )
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| User {
+            username: row.first().cloned().unwrap_or_default(),
+            superuser: row.get(1).map(|v| v == "t").unwrap_or(false),
+        })
+        .collect())
+}
+
+/// Attach a cluster to an app.
+///
+/// Creates a database and a role for the app (both named after it unless
+/// given), then stores the connection string in the app's `variable` secret.
+pub async fn attach(
+    client: &dyn FlyApi,
+    machines: &MachinesClient,
+    cluster: &str,
+    app_name: &str,
+    database: Option<&str>,
+    variable: &str,
+) -> Result<Attachment> {
+    let name = app_name.replace('-', "_");
+    let database = database.unwrap_or(&name).to_string();
+    check_identifier("database", &database)?;
+    check_identifier("user", &name)?;
+
+    let password = random_password()?;
+    let existing = list_databases(machines, cluster).await?;
+    if !existing.iter().any(|d| d.name == database) {
+        create_database(machines, cluster, &database).await?;
+    }
+    psql(
+        machines,
+        cluster,
+        &format!(
+            "CREATE ROLE {} WITH LOGIN PASSWORD '{}'; GRANT ALL PRIVILEGES ON DATABASE {} TO {}",
+            name, password, database, name
+        ),
+    )
+    .await
+    // Postgres echoes failing statements, which would include the password
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to create user: {}",
+            redact::redact(&format!("{:#}", e), std::slice::from_ref(&password))
+        )
+    })?;
+
+    let url = connection_string(cluster, &name, &password, Some(&database));
+    client
+        .set_secrets(app_name, &[(variable.to_string(), url)])
+        .await
+        .context("Failed to store connection string")?;
+
+    Ok(Attachment {
+        app: app_name.to_string(),
+        cluster: cluster.to_string(),
+        database,
+        user: name,
+        variable: variable.to_string(),
+    })
+}
+
+/// Detach a cluster from an app: drop its role and remove the secret.
+///
+/// The database is kept so detaching never loses data. `REASSIGN OWNED` and
+/// `DROP OWNED` only reach the database they run in, so they run in every
+/// database before the role is dropped; whatever the role owned passes to
+/// `postgres`.
+pub async fn detach(
+    client: &dyn FlyApi,
+    machines: &MachinesClient,
+    cluster: &str,
+    app_name: &str,
+    variable: &str,
+) -> Result<()> {
+    let name = app_name.replace('-', "_");
+    check_identifier("user", &name)?;
+
+    let databases = list_databases(machines, cluster).await?;
+    for (database, sql) in detach_statements(&name, &databases) {
+        psql_in(machines, cluster, database, &sql)
+            .await
+            .with_context(|| match database {
+                Some(database) => format!("Failed to release objects of {} in {}", name, database),
+                None => format!("Failed to drop user {}", name),
+            })?;
+    }
+    client
+        .unset_secrets(app_name, &[variable.to_string()])
+        .await?;
+
+    Ok(())
+}
+
+/// Operator connection string for a cluster, reachable over the private network.
+pub async fn operator_connection_string(
+    machines: &MachinesClient,
+    cluster: &str,
+    database: Option<&str>,
+) -> Result<String> {
+    let primary = primary_machine(machines, cluster).await?;
+    let result = machines
+        .exec(
+            cluster,
+            &primary.id,
+            &["printenv".into(), "OPERATOR_PASSWORD".into()],
+            PSQL_TIMEOUT_SECS,
+        )
+        .await?;
+    let password = result.stdout.trim();
+    if result.exit_code != Some(0) || password.is_empty() {
+        anyhow::bail!("Cluster {} has no OPERATOR_PASSWORD", cluster);
+    }
+
+    Ok(connection_string(cluster, "postgres", password, database))
+}
+
+fn connection_string(cluster: &str, user: &str, password: &str, database: Option<&str>) -> String {
+    format!(
+        "postgres://{}:{}@{}.flycast:5432/{}",
+        user,
+        password,
+        cluster,
+        database.unwrap_or("postgres")
+    )
+}
+
+/// SQL run by `detach`, with the database each statement runs in (`None`
+/// for the default one): the role's objects are released in every
+/// database, then the role is dropped.
+fn detach_statements<'a>(role: &str, databases: &'a [Database]) -> Vec<(Option<&'a str>, String)> {
+    let mut statements: Vec<_> = databases
+        .iter()
+        .map(|database| {
+            (
+                Some(database.name.as_str()),
+                format!("REASSIGN OWNED BY {0} TO postgres; DROP OWNED BY {0}", role),
+            )
+        })
+        .collect();
+    statements.push((None, format!("DROP ROLE {}", role)));
+    statements
+}
+
+/// Run SQL on the cluster's primary and return the rows as columns.
+async fn psql(machines: &MachinesClient, cluster: &str, sql: &str) -> Result<Vec<Vec<String>>> {
+    psql_in(machines, cluster, None, sql).await
+}
+
+/// Run SQL in `database` (default `postgres`) on the cluster's primary.
+async fn psql_in(
+    machines: &MachinesClient,
+    cluster: &str,
+    database: Option<&str>,
+    sql: &str,
+) -> Result<Vec<Vec<String>>> {
+    let primary = primary_machine(machines, cluster).await?;
+
+    // Pass the SQL and database as positional args so the shell never interprets them
+    let script = format!(
+        "PGPASSWORD=\"$OPERATOR_PASSWORD\" psql -h localhost -p {} -U postgres -d \"$2\" -v ON_ERROR_STOP=1 -tA -F '|' -c \"$1\"",
+        DIRECT_PORT
+    );
+    let command = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        script,
+        "psql".to_string(),
+        sql.to_string(),
+        database.unwrap_or("postgres").to_string(),
+    ];
+
+    let result = machines
+        .exec(cluster, &primary.id, &command, PSQL_TIMEOUT_SECS)
+        .await?;
+    if result.exit_code != Some(0) {
+        anyhow::bail!("psql failed on {}: {}", primary.id, result.stderr.trim());
+    }
+
+    Ok(result
+        .stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split('|').map(String::from).collect())
+        .collect())
+}
+
+/// The machine currently holding the primary role.
+///
+/// Flex clusters report the role through a `role` health check; fall back to
+/// the first running machine for single-node clusters.
+async fn primary_machine(machines: &MachinesClient, cluster: &str) -> Result<Machine> {
+    let all = machines.list_machines(cluster).await?;
+    let mut running: Vec<Machine> = all.into_iter().filter(|m| m.state == "started").collect();
+
+    let primary = running.iter().position(|m| {
+        m.checks
+            .iter()
+            .any(|c| c.name == "role" && c.output.as_deref().map(str::trim) == Some("primary"))
+    });
+    if running.is_empty() {
+        anyhow::bail!("Cluster {} has no running machines", cluster);
+    }
+
+    Ok(running.swap_remove(primary.unwrap_or(0)))
+}
+
+/// Only plain lowercase identifiers are accepted, since they end up in SQL.
+fn check_identifier(kind: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        anyhow::bail!(
+            "Invalid {} name: {}. Use lowercase letters, digits and underscores",
+            kind,
+            name
+        );
+    }
+    Ok(())
+}
+
+fn random_password() -> Result<String> {
+    let mut bytes = [0u8; 24];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to generate password")?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detach_releases_objects_in_every_database_before_dropping_the_role() {
+        let databases = ["postgres", "web"].map(|name| Database {
+            name: name.into(),
+            owner: "postgres".into(),
+        });
+        let statements = detach_statements("web", &databases);
+
+        assert_eq!(
+            statements,
+            [
+                (
+                    Some("postgres"),
+                    "REASSIGN OWNED BY web TO postgres; DROP OWNED BY web".to_string()
+                ),
+                (
+                    Some("web"),
+                    "REASSIGN OWNED BY web TO postgres; DROP OWNED BY web".to_string()
+                ),
+                (None, "DROP ROLE web".to_string()),
+            ]
+        );
+    }
+}
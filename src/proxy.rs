@@ -0,0 +1,214 @@
+//! Local TCP forwarders to machines' private services, like `flyctl proxy`.
+//!
+//! The daemon has no WireGuard implementation of its own: the target is a
+//! private (`fdaa:` / `.internal`) address, so the host running the daemon
+//! must already be on the org's private network, e.g. through a tunnel from
+//! `fly wireguard create` or by running on Fly itself. Starting a forwarder
+//! to an `.internal` name that doesn't resolve fails with that explanation
+//! instead of leaving a listener whose every connection fails.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// A running forwarder.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxyInfo {
+    pub id: u64,
+    pub app: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<String>,
+    /// Address the daemon listens on.
+    pub local_addr: SocketAddr,
+    /// `host:port` connections are forwarded to.
+    pub target: String,
+    pub started_at: DateTime<Utc>,
+    pub connections: u64,
+    pub active_connections: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    total: AtomicU64,
+    active: AtomicU64,
+}
+
+struct Proxy {
+    info: ProxyInfo,
+    counters: Arc<Counters>,
+    task: JoinHandle<()>,
+}
+
+/// Registry of running forwarders.
+#[derive(Default)]
+pub struct ProxyHub {
+    proxies: Mutex<BTreeMap<u64, Proxy>>,
+    next_id: AtomicU64,
+}
+
+impl ProxyHub {
+    /// Listen on `local_addr` and forward every connection to `target`.
+    ///
+    /// Binding happens before returning, so a port conflict is reported
+    /// to the caller and port 0 resolves to the port actually chosen.
+    pub fn start(
+        &self,
+        runtime: &Handle,
+        app: String,
+        machine_id: Option<String>,
+        local_addr: SocketAddr,
+        target: String,
+    ) -> Result<ProxyInfo> {
+        check_resolves(&target)?;
+        let listener = std::net::TcpListener::bind(local_addr)
+            .with_context(|| format!("Failed to listen on {}", local_addr))?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let listener = {
+            let _guard = runtime.enter();
+            TcpListener::from_std(listener)?
+        };
+
+        let info = ProxyInfo {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            app,
+            machine_id,
+            local_addr,
+            target: target.clone(),
+            started_at: Utc::now(),
+            connections: 0,
+            active_connections: 0,
+        };
+        let counters = Arc::new(Counters::default());
+
+        let task = runtime.spawn(serve(listener, target, counters.clone()));
+        self.proxies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                info.id,
+                Proxy {
+                    info: info.clone(),
+                    counters,
+                    task,
+                },
+            );
+        Ok(info)
+    }
+
+    /// Stop a forwarder and drop its open connections.
+    pub fn stop(&self, id: u64) -> Option<ProxyInfo> {
+        let proxy = self
+            .proxies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id)?;
+        proxy.task.abort();
+        Some(snapshot(&proxy))
+    }
+
+    /// Stop every forwarder, e.g. on daemon shutdown.
+    pub fn stop_all(&self) {
+        let mut proxies = self.proxies.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, proxy) in std::mem::take(&mut *proxies) {
+            proxy.task.abort();
+        }
+    }
+
+    /// Running forwarders with their connection counts.
+    pub fn list(&self) -> Vec<ProxyInfo> {
+        self.proxies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(snapshot)
+            .collect()
+    }
+}
+
+/// Fail with a hint about private networking if `target` is an
+/// `.internal` name this host can't resolve.
+fn check_resolves(target: &str) -> Result<()> {
+    let host = target.rsplit_once(':').map_or(target, |(host, _)| host);
+    if !host.ends_with(".internal") {
+        return Ok(());
+    }
+    let resolved = std::net::ToSocketAddrs::to_socket_addrs(target)
+        .map(|mut addrs| addrs.next().is_some())
+        .unwrap_or(false);
+    if !resolved {
+        anyhow::bail!(
+            "Cannot resolve {}: the daemon's host must be on the org's 6PN private network \
+             (a tunnel from `fly wireguard create`, or running on Fly); \
+             the daemon does not open a WireGuard tunnel itself",
+            host
+        );
+    }
+    Ok(())
+}
+
+fn snapshot(proxy: &Proxy) -> ProxyInfo {
+    ProxyInfo {
+        connections: proxy.counters.total.load(Ordering::Relaxed),
+        active_connections: proxy.counters.active.load(Ordering::Relaxed),
+        ..proxy.info.clone()
+    }
+}
+
+/// Accept loop. Connections live in a `JoinSet` owned by this task, so
+/// aborting it closes them too.
+async fn serve(listener: TcpListener, target: String, counters: Arc<Counters>) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((inbound, peer)) => {
+                    counters.total.fetch_add(1, Ordering::Relaxed);
+                    counters.active.fetch_add(1, Ordering::Relaxed);
+                    let target = target.clone();
+                    let counters = counters.clone();
+                    connections.spawn(async move {
+                        if let Err(e) = forward(inbound, &target).await {
+                            tracing::debug!("Proxy connection from {} failed: {:#}", peer, e);
+                        }
+                        counters.active.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(e) => tracing::warn!("Proxy accept failed: {}", e),
+            },
+            // Reap finished connections so the set doesn't grow forever
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+}
+
+async fn forward(mut inbound: TcpStream, target: &str) -> Result<()> {
+    let mut outbound = TcpStream::connect(target)
+        .await
+        .with_context(|| format!("Failed to connect to {}", target))?;
+    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unresolvable_internal_names_explain_private_networking() {
+        let error = check_resolves("fgp-fly-no-such-app.internal:5432").unwrap_err();
+        assert!(format!("{:#}", error).contains("6PN"), "{:#}", error);
+    }
+
+    #[test]
+    fn addresses_are_not_looked_up() {
+        assert!(check_resolves("[fdaa:0:1:a7b:1::2]:5432").is_ok());
+    }
+}
@@ -0,0 +1,1120 @@
+//! Declarative app reconciliation.
+//!
+//! Diffs a desired app config against the live app and applies the
+//! resulting plan step by step. `DesiredConfig` covers app-wide settings,
+//! rolling the image out with `deploy::deploy` and changing machines
+//! through the Machines API; `AppPlan` reconciles machines against a full
+//! fly.toml.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::api::{FlyApi, MachinesClient};
+use crate::deploy::{self, DeployOptions, Deployments, Strategy};
+use crate::fly_toml::{self, FlyToml};
+use crate::models::{Machine, MachineConfig, Secret};
+use crate::scale::{self, ScaleReport, ScaleStep};
+
+/// Seconds to wait for an updated machine to report `started`.
+const START_TIMEOUT_SECS: u64 = 60;
+
+/// Desired state for an app. Omitted fields are left untouched.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredConfig {
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Total number of machines.
+    #[serde(default)]
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub regions: Option<BTreeSet<String>>,
+    #[serde(default)]
+    pub env: Option<BTreeMap<String, String>>,
+    /// Secret names that must exist; values are never part of the config.
+    #[serde(default)]
+    pub secrets: Option<BTreeSet<String>>,
+    #[serde(default)]
+    pub vm_size: Option<String>,
+}
+
+impl DesiredConfig {
+    /// Reject counts and regions no placement can satisfy.
+    pub fn validate(&self) -> Result<()> {
+        let Some(regions) = &self.regions else {
+            return Ok(());
+        };
+        if regions.is_empty() {
+            anyhow::bail!("regions must list at least one region");
+        }
+        if let Some(count) = self.count.filter(|count| *count < regions.len()) {
+            anyhow::bail!(
+                "count ({}) must be at least the number of regions ({})",
+                count,
+                regions.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of the live app relevant to reconciliation.
+#[derive(Debug, Clone, Default)]
+pub struct LiveState {
+    pub images: BTreeSet<String>,
+    pub count: usize,
+    pub regions: BTreeSet<String>,
+    /// Machines in each region.
+    pub placement: BTreeMap<String, usize>,
+    pub env: BTreeMap<String, String>,
+    pub secrets: BTreeSet<String>,
+    pub vm_sizes: BTreeSet<String>,
+}
+
+impl LiveState {
+    /// Build live state from machine configs and the app's secrets.
+    pub fn from_parts(machines: &[Machine], secrets: &[Secret]) -> Self {
+        let mut state = LiveState {
+            count: machines.len(),
+            ..Default::default()
+        };
+
+        for machine in machines {
+            state.regions.insert(machine.region.clone());
+            *state.placement.entry(machine.region.clone()).or_default() += 1;
+            if let Some(config) = &machine.config {
+                if let Some(image) = &config.image {
+                    state.images.insert(image.clone());
+                }
+                if let Some(size) = scale::size_of(config) {
+                    state.vm_sizes.insert(size);
+                }
+            }
+        }
+
+        // Env is per machine; treat the first machine as representative
+        if let Some(config) = machines.first().and_then(|m| m.config.as_ref()) {
+            state.env = config
+                .env
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+        }
+
+        state.secrets = secrets.iter().map(|s| s.name.clone()).collect();
+
+        state
+    }
+}
+
+/// Resource a plan step touches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    Image,
+    /// Machine count and regions, reconciled together.
+    Placement,
+    Env,
+    Secrets,
+    VmSize,
+}
+
+/// Single change needed to reach the desired state.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanStep {
+    pub resource: Resource,
+    pub action: String,
+    pub current: Value,
+    pub desired: Value,
+}
+
+/// Outcome of applying a plan step.
+#[derive(Debug, Serialize)]
+pub struct StepResult {
+    pub resource: Resource,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Compute the steps needed to move `current` to `desired`.
+pub fn compute_plan(current: &LiveState, desired: &DesiredConfig) -> Vec<PlanStep> {
+    let mut plan = Vec::new();
+
+    if let Some(image) = &desired.image {
+        if current.images.len() != 1 || !current.images.contains(image) {
+            plan.push(PlanStep {
+                resource: Resource::Image,
+                action: "deploy".into(),
+                current: serde_json::json!(current.images),
+                desired: serde_json::json!(image),
+            });
+        }
+    }
+
+    let count_changes = desired.count.is_some_and(|count| count != current.count);
+    let regions_change = desired
+        .regions
+        .as_ref()
+        .is_some_and(|regions| *regions != current.regions);
+    if count_changes || regions_change {
+        let targets =
+            placement_targets(&current.placement, desired.count, desired.regions.as_ref());
+        plan.push(PlanStep {
+            resource: Resource::Placement,
+            action: "rebalance".into(),
+            current: serde_json::json!(current.placement),
+            desired: serde_json::json!(targets),
+        });
+    }
+
+    if let Some(env) = &desired.env {
+        if *env != current.env {
+            let set: BTreeMap<_, _> = env
+                .iter()
+                .filter(|(k, v)| current.env.get(*k) != Some(*v))
+                .collect();
+            let unset: Vec<_> = current
+                .env
+                .keys()
+                .filter(|k| !env.contains_key(*k))
+                .collect();
+            plan.push(PlanStep {
+                resource: Resource::Env,
+                action: "update".into(),
+                current: serde_json::json!(current.env),
+                desired: serde_json::json!({ "set": set, "unset": unset }),
+            });
+        }
+    }
+
+    if let Some(secrets) = &desired.secrets {
+        let missing: Vec<_> = secrets.difference(&current.secrets).collect();
+        if !missing.is_empty() {
+            plan.push(PlanStep {
+                resource: Resource::Secrets,
+                action: "manual".into(),
+                current: serde_json::json!(current.secrets),
+                desired: serde_json::json!({ "missing": missing }),
+            });
+        }
+    }
+
+    if let Some(size) = &desired.vm_size {
+        if current.vm_sizes.len() != 1 || !current.vm_sizes.contains(size) {
+            plan.push(PlanStep {
+                resource: Resource::VmSize,
+                action: "resize".into(),
+                current: serde_json::json!(current.vm_sizes),
+                desired: serde_json::json!(size),
+            });
+        }
+    }
+
+    plan
+}
+
+/// Machines per region that give the desired count and regions while
+/// moving as few machines as possible. New regions start with one machine,
+/// then machines are added to the smallest regions or taken from the
+/// largest until the count is met; every region keeps at least one unless
+/// the count is 0.
+pub fn placement_targets(
+    current: &BTreeMap<String, usize>,
+    count: Option<usize>,
+    regions: Option<&BTreeSet<String>>,
+) -> BTreeMap<String, usize> {
+    let mut targets: BTreeMap<String, usize> = match regions {
+        Some(regions) => regions
+            .iter()
+            .map(|region| (region.clone(), current.get(region).copied().unwrap_or(1)))
+            .collect(),
+        None => current.clone(),
+    };
+    let Some(count) = count else {
+        return targets;
+    };
+    if count == 0 {
+        targets.values_mut().for_each(|n| *n = 0);
+        return targets;
+    }
+
+    let mut total: usize = targets.values().sum();
+    while total < count {
+        let Some(smallest) = targets.values_mut().min_by_key(|n| **n) else {
+            break;
+        };
+        *smallest += 1;
+        total += 1;
+    }
+    while total > count {
+        let Some(largest) = targets.values_mut().filter(|n| **n > 1).max_by_key(|n| **n) else {
+            break;
+        };
+        *largest -= 1;
+        total -= 1;
+    }
+    targets
+}
+
+/// Fetch the live state of an app.
+pub async fn fetch_live_state(client: &dyn FlyApi, app_name: &str) -> Result<LiveState> {
+    let machines = client.list_machines_with_config(app_name).await?;
+    let secrets = client.list_secrets(app_name).await?;
+    Ok(LiveState::from_parts(&machines, &secrets))
+}
+
+/// Apply a plan, continuing past failed steps so every result is reported.
+///
+/// Steps run in plan order. The image is rolled out to the existing
+/// machines first, so machines the placement step creates clone the new
+/// image; env and VM size changes come after it and so cover those
+/// machines too. Image rollouts are tracked in `deploys`.
+pub async fn apply_plan(
+    machines: &MachinesClient,
+    deploys: &Deployments,
+    app_name: &str,
+    desired: &DesiredConfig,
+    plan: &[PlanStep],
+) -> Vec<StepResult> {
+    let mut results = Vec::with_capacity(plan.len());
+
+    for step in plan {
+        let outcome = match step.resource {
+            Resource::Image => {
+                let image = desired.image.as_deref().unwrap_or_default();
+                deploy_image(machines, deploys, app_name, image).await
+            }
+            Resource::Placement => match serde_json::from_value(step.desired.clone()) {
+                Ok(targets) => scaled(rebalance(machines, app_name, &targets).await),
+                Err(e) => Err(e.into()),
+            },
+            Resource::Env => {
+                let env = desired.env.clone().unwrap_or_default();
+                scaled(set_env(machines, app_name, &env).await)
+            }
+            Resource::VmSize => {
+                let size = desired.vm_size.as_deref().unwrap_or_default();
+                match scale::guest_for_size(size) {
+                    Some(guest) => scaled(scale::scale_vm(machines, app_name, &guest, None).await),
+                    None => Err(anyhow::anyhow!("Unknown VM size: {}", size)),
+                }
+            }
+            // Secret values are never part of the desired config
+            Resource::Secrets => {
+                results.push(StepResult {
+                    resource: step.resource,
+                    status: "manual",
+                    result: Some(step.desired.clone()),
+                    error: None,
+                });
+                continue;
+            }
+        };
+
+        results.push(match outcome {
+            Ok(result) => StepResult {
+                resource: step.resource,
+                status: "applied",
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => StepResult {
+                resource: step.resource,
+                status: "failed",
+                result: None,
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    results
+}
+
+/// Roll every machine to `image` one at a time, failing if any machine
+/// couldn't be updated.
+async fn deploy_image(
+    machines: &MachinesClient,
+    deploys: &Deployments,
+    app_name: &str,
+    image: &str,
+) -> Result<Value> {
+    let tracker = deploys.begin(app_name, image, Strategy::Rolling);
+    let report = deploy::deploy(
+        machines,
+        app_name,
+        image,
+        Strategy::Rolling,
+        &DeployOptions::default(),
+        &tracker,
+    )
+    .await?;
+    if let Some(failed) = report.machines.iter().find(|m| m.status == "failed") {
+        anyhow::bail!(
+            "Deploying to machine {} failed: {}",
+            failed.machine_id,
+            failed.error.as_deref().unwrap_or("unknown error")
+        );
+    }
+    Ok(serde_json::to_value(report)?)
+}
+
+/// Move an app's machines to `targets` machines per region in one pass.
+/// Machines are created first so capacity never drops, then the extras are
+/// destroyed, stopped and newest first; if a create failed, nothing is
+/// destroyed.
+async fn rebalance(
+    machines: &MachinesClient,
+    app_name: &str,
+    targets: &BTreeMap<String, usize>,
+) -> Result<ScaleReport> {
+    let all = machines.list_machines(app_name).await?;
+    let mut current: BTreeMap<String, usize> = BTreeMap::new();
+    for machine in &all {
+        *current.entry(machine.region.clone()).or_default() += 1;
+    }
+    let mut steps = Vec::new();
+
+    for (region, &target) in targets {
+        if target > current.get(region).copied().unwrap_or(0) {
+            let grown = scale::scale_count(machines, app_name, target, Some(region)).await?;
+            steps.extend(grown.steps);
+        }
+    }
+
+    let create_failed = steps.iter().any(|s| s.status == "failed");
+    for (region, &count) in &current {
+        let target = targets.get(region).copied().unwrap_or(0);
+        if count <= target {
+            continue;
+        }
+        if create_failed {
+            steps.push(ScaleStep {
+                action: "destroy",
+                machine_id: None,
+                region: region.clone(),
+                status: "skipped",
+                error: Some("Kept because a new machine failed to start".into()),
+            });
+            continue;
+        }
+        let shrunk = scale::scale_count(machines, app_name, target, Some(region)).await?;
+        steps.extend(shrunk.steps);
+    }
+
+    let succeeded = |action: &str| {
+        steps
+            .iter()
+            .filter(|s| s.action == action && s.status == "ok")
+            .count()
+    };
+    let count = all.len() + succeeded("create") - succeeded("destroy");
+    Ok(ScaleReport {
+        app: app_name.to_string(),
+        previous_count: all.len(),
+        count,
+        steps,
+    })
+}
+
+/// A scaling report as a step result, failing if any machine failed.
+fn scaled(report: Result<ScaleReport>) -> Result<Value> {
+    let report = report?;
+    if let Some(failed) = report.steps.iter().find(|s| s.status == "failed") {
+        anyhow::bail!(
+            "{} of machine {} failed: {}",
+            failed.action,
+            failed.machine_id.as_deref().unwrap_or("(new)"),
+            failed.error.as_deref().unwrap_or("unknown error")
+        );
+    }
+    Ok(serde_json::to_value(report)?)
+}
+
+/// Replace the env of every machine with `env`.
+async fn set_env(
+    machines: &MachinesClient,
+    app_name: &str,
+    env: &BTreeMap<String, String>,
+) -> Result<ScaleReport> {
+    let all = machines.list_machines(app_name).await?;
+    let mut steps = Vec::with_capacity(all.len());
+
+    for machine in &all {
+        let Some(mut config) = machine.config.clone() else {
+            continue;
+        };
+        config.env = env.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        let result = machines
+            .update_machine(app_name, &machine.id, &config, None)
+            .await;
+        steps.push(ScaleStep {
+            action: "update",
+            machine_id: Some(machine.id.clone()),
+            region: machine.region.clone(),
+            status: if result.is_ok() { "ok" } else { "failed" },
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    Ok(ScaleReport {
+        app: app_name.to_string(),
+        previous_count: all.len(),
+        count: all.len(),
+        steps,
+    })
+}
+
+/// Machine-level action in an app plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MachineAction {
+    Create,
+    Update,
+    Destroy,
+}
+
+/// One machine to create, update or destroy.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineChange {
+    pub action: MachineAction,
+    pub process_group: String,
+    pub region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<String>,
+    /// Config fields an update changes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<&'static str>,
+    /// Why the change can't be applied as planned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<String>,
+    /// Config a create or update sends.
+    #[serde(skip)]
+    pub config: Option<MachineConfig>,
+    #[serde(skip)]
+    was_started: bool,
+}
+
+/// Regions gained or lost across all process groups.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RegionChanges {
+    pub add: BTreeSet<String>,
+    pub remove: BTreeSet<String>,
+}
+
+/// Secrets the desired config needs that the app doesn't have.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SecretChanges {
+    pub set: BTreeSet<String>,
+}
+
+/// Number of machine changes by action.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlanSummary {
+    pub create: usize,
+    pub update: usize,
+    pub destroy: usize,
+    /// Changes that can't be applied as planned.
+    pub blocked: usize,
+}
+
+/// Everything needed to bring an app's machines in line with a fly.toml.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppPlan {
+    pub app: String,
+    pub summary: PlanSummary,
+    pub machines: Vec<MachineChange>,
+    pub regions: RegionChanges,
+    pub secrets: SecretChanges,
+    /// Parts of the desired config the plan leaves alone.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+impl AppPlan {
+    /// Number of machine changes.
+    pub fn changes(&self) -> usize {
+        self.machines.len()
+    }
+}
+
+/// Outcome of one machine change.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineChangeResult {
+    pub action: MachineAction,
+    pub process_group: String,
+    pub region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<String>,
+    /// `applied`, `failed` or `skipped`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Diff a fly.toml against an app's machines.
+///
+/// Each process group keeps its machines, updated in place; groups that
+/// lost all machines or are new get one machine cloned from an existing
+/// one. With `regions`, every group gets a machine in each listed region
+/// and machines elsewhere are destroyed; without it placement is left as
+/// is. Groups missing from a fly.toml that defines `[processes]` are
+/// destroyed.
+pub fn plan_app(
+    app_name: &str,
+    desired: &FlyToml,
+    regions: Option<&BTreeSet<String>>,
+    live: &[Machine],
+) -> Result<AppPlan> {
+    let template = live.iter().find(|m| m.config.is_some()).ok_or_else(|| {
+        anyhow::anyhow!(
+            "App {} has no machine to clone from; create one with fly.machine.create first",
+            app_name
+        )
+    })?;
+
+    let mut by_group: BTreeMap<&str, Vec<&Machine>> = BTreeMap::new();
+    for machine in live {
+        by_group
+            .entry(fly_toml::process_group(machine))
+            .or_default()
+            .push(machine);
+    }
+
+    let live_regions: BTreeSet<String> = live.iter().map(|m| m.region.clone()).collect();
+    let mut planned_regions = BTreeSet::new();
+    let mut machines = Vec::new();
+    let mut notes = Vec::new();
+
+    for group in desired.process_groups() {
+        let members = by_group.get(group.as_str()).cloned().unwrap_or_default();
+
+        let keep: Vec<&Machine> = members
+            .iter()
+            .copied()
+            .filter(|m| regions.is_none_or(|r| r.contains(&m.region)))
+            .collect();
+        for machine in members
+            .iter()
+            .filter(|m| !keep.iter().any(|k| k.id == m.id))
+        {
+            machines.push(destroy(&group, machine));
+        }
+
+        let mut wanted: Vec<String> = match regions {
+            Some(regions) => regions
+                .iter()
+                .filter(|r| !keep.iter().any(|m| &m.region == *r))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        if keep.is_empty() && wanted.is_empty() {
+            wanted.push(
+                desired
+                    .primary_region
+                    .clone()
+                    .unwrap_or_else(|| template.region.clone()),
+            );
+        }
+
+        for machine in &keep {
+            planned_regions.insert(machine.region.clone());
+            let Some(mut config) = machine.config.clone() else {
+                continue;
+            };
+            let fields = desired.apply_to(&group, &mut config);
+            if !fields.is_empty() {
+                machines.push(MachineChange {
+                    action: MachineAction::Update,
+                    process_group: group.clone(),
+                    region: machine.region.clone(),
+                    machine_id: Some(machine.id.clone()),
+                    fields,
+                    blocked: None,
+                    config: Some(config),
+                    was_started: machine.state == "started",
+                });
+            }
+        }
+
+        let source = keep.first().copied().unwrap_or(template);
+        for region in wanted {
+            planned_regions.insert(region.clone());
+            let mut config = source.config.clone();
+            if let Some(config) = config.as_mut() {
+                desired.apply_to(&group, config);
+            }
+            let blocked = config
+                .as_ref()
+                .is_some_and(|c| !c.mounts.is_empty())
+                .then(|| "Machines with mounted volumes need a volume created first".to_string());
+            machines.push(MachineChange {
+                action: MachineAction::Create,
+                process_group: group.clone(),
+                region,
+                machine_id: None,
+                fields: Vec::new(),
+                blocked,
+                config,
+                was_started: false,
+            });
+        }
+
+        let live_paths: BTreeSet<&str> = members
+            .first()
+            .and_then(|m| m.config.as_ref())
+            .map(|c| c.mounts.iter().filter_map(|m| m.path.as_deref()).collect())
+            .unwrap_or_default();
+        let desired_paths: BTreeSet<&str> = desired.mount_paths(&group).into_iter().collect();
+        if !members.is_empty() && live_paths != desired_paths {
+            notes.push(format!(
+                "Mounts for process group {} differ and are not changed by apply",
+                group
+            ));
+        }
+    }
+
+    if !desired.processes.is_empty() {
+        let groups = desired.process_groups();
+        for (group, members) in &by_group {
+            if !groups.iter().any(|g| g == group) {
+                for machine in members {
+                    machines.push(destroy(group, machine));
+                }
+            }
+        }
+    }
+
+    let count = |action: MachineAction| machines.iter().filter(|m| m.action == action).count();
+    let summary = PlanSummary {
+        create: count(MachineAction::Create),
+        update: count(MachineAction::Update),
+        destroy: count(MachineAction::Destroy),
+        blocked: machines.iter().filter(|m| m.blocked.is_some()).count(),
+    };
+
+    Ok(AppPlan {
+        app: app_name.to_string(),
+        summary,
+        secrets: SecretChanges::default(),
+        regions: RegionChanges {
+            add: planned_regions.difference(&live_regions).cloned().collect(),
+            remove: live_regions.difference(&planned_regions).cloned().collect(),
+        },
+        machines,
+        notes,
+    })
+}
+
+/// Names in `required` the app has no secret for.
+pub fn missing_secrets(required: &BTreeSet<String>, live: &[Secret]) -> BTreeSet<String> {
+    required
+        .iter()
+        .filter(|name| !live.iter().any(|s| &s.name == *name))
+        .cloned()
+        .collect()
+}
+
+fn destroy(group: &str, machine: &Machine) -> MachineChange {
+    MachineChange {
+        action: MachineAction::Destroy,
+        process_group: group.to_string(),
+        region: machine.region.clone(),
+        machine_id: Some(machine.id.clone()),
+        fields: Vec::new(),
+        blocked: None,
+        config: None,
+        was_started: false,
+    }
+}
+
+/// Apply an app plan: creates first, then updates, then destroys.
+///
+/// Updated machines that were running must start again before the next
+/// one is touched; after a failure the remaining changes are skipped.
+/// Destroys only run with `prune`.
+pub async fn apply_app_plan(
+    machines: &MachinesClient,
+    plan: &AppPlan,
+    prune: bool,
+) -> Vec<MachineChangeResult> {
+    let mut ordered: Vec<&MachineChange> = plan.machines.iter().collect();
+    ordered.sort_by_key(|change| match change.action {
+        MachineAction::Create => 0,
+        MachineAction::Update => 1,
+        MachineAction::Destroy => 2,
+    });
+
+    let mut results = Vec::with_capacity(ordered.len());
+    let mut aborted = false;
+
+    for change in ordered {
+        let skip = if aborted {
+            Some("Aborted after earlier failure".to_string())
+        } else if let Some(blocked) = &change.blocked {
+            Some(blocked.clone())
+        } else if change.action == MachineAction::Destroy && !prune {
+            Some("Pass prune=true to destroy machines".to_string())
+        } else {
+            None
+        };
+        if let Some(reason) = skip {
+            results.push(change_result(change, None, "skipped", Some(reason)));
+            continue;
+        }
+
+        let outcome = apply_change(machines, &plan.app, change).await;
+        results.push(match outcome {
+            Ok(machine_id) => change_result(change, machine_id, "applied", None),
+            Err(e) => {
+                aborted = true;
+                change_result(change, None, "failed", Some(e.to_string()))
+            }
+        });
+    }
+
+    results
+}
+
+async fn apply_change(
+    machines: &MachinesClient,
+    app_name: &str,
+    change: &MachineChange,
+) -> Result<Option<String>> {
+    match change.action {
+        MachineAction::Create => {
+            let config = change
+                .config
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("No config to create a machine from"))?;
+            let machine = machines
+                .clone_machine(app_name, &change.region, config)
+                .await?;
+            Ok(Some(machine.id))
+        }
+        MachineAction::Update => {
+            let (Some(id), Some(config)) = (&change.machine_id, &change.config) else {
+                anyhow::bail!("Update is missing its machine or config");
+            };
+            let updated = machines.update_machine(app_name, id, config, None).await?;
+            if change.was_started {
+                machines
+                    .wait_for_state(
+                        app_name,
+                        id,
+                        updated.instance_id.as_deref(),
+                        "started",
+                        START_TIMEOUT_SECS,
+                    )
+                    .await?;
+            }
+            Ok(None)
+        }
+        MachineAction::Destroy => {
+            let Some(id) = &change.machine_id else {
+                anyhow::bail!("Destroy is missing its machine");
+            };
+            machines.destroy_machine(app_name, id, true, None).await?;
+            Ok(None)
+        }
+    }
+}
+
+fn change_result(
+    change: &MachineChange,
+    created_id: Option<String>,
+    status: &'static str,
+    error: Option<String>,
+) -> MachineChangeResult {
+    MachineChangeResult {
+        action: change.action,
+        process_group: change.process_group.clone(),
+        region: change.region.clone(),
+        machine_id: created_id.or_else(|| change.machine_id.clone()),
+        status,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn machine(id: &str, region: &str, cpus: u32, env: Value) -> Machine {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": id,
+            "state": "started",
+            "region": region,
+            "config": {
+                "image": "registry.fly.io/web:v1",
+                "guest": { "cpu_kind": "shared", "cpus": cpus, "memory_mb": 256 * cpus },
+                "env": env,
+            },
+        }))
+        .unwrap()
+    }
+
+    fn live() -> LiveState {
+        let machines = [
+            machine("m1", "iad", 1, json!({ "PORT": "8080", "DEBUG": "1" })),
+            machine("m2", "ord", 1, json!({ "PORT": "8080", "DEBUG": "1" })),
+        ];
+        let secrets: Vec<Secret> =
+            serde_json::from_value(json!([{ "name": "DATABASE_URL" }])).unwrap();
+        LiveState::from_parts(&machines, &secrets)
+    }
+
+    #[test]
+    fn plan_is_the_diff_between_live_and_desired() {
+        let desired: DesiredConfig = serde_json::from_value(json!({
+            "image": "registry.fly.io/web:v2",
+            "count": 3,
+            "regions": ["iad", "lhr"],
+            "env": { "PORT": "8080", "LOG_LEVEL": "info" },
+            "secrets": ["DATABASE_URL", "API_KEY"],
+            "vm_size": "shared-cpu-2x",
+        }))
+        .unwrap();
+
+        let plan = compute_plan(&live(), &desired);
+        let steps: Vec<(Resource, &str, Value)> = plan
+            .iter()
+            .map(|s| (s.resource, s.action.as_str(), s.desired.clone()))
+            .collect();
+
+        assert_eq!(
+            steps,
+            vec![
+                (Resource::Image, "deploy", json!("registry.fly.io/web:v2")),
+                (
+                    Resource::Placement,
+                    "rebalance",
+                    json!({ "iad": 2, "lhr": 1 })
+                ),
+                (
+                    Resource::Env,
+                    "update",
+                    json!({ "set": { "LOG_LEVEL": "info" }, "unset": ["DEBUG"] })
+                ),
+                (
+                    Resource::Secrets,
+                    "manual",
+                    json!({ "missing": ["API_KEY"] })
+                ),
+                (Resource::VmSize, "resize", json!("shared-cpu-2x")),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_is_empty_when_live_matches_desired() {
+        let desired: DesiredConfig = serde_json::from_value(json!({
+            "image": "registry.fly.io/web:v1",
+            "count": 2,
+            "regions": ["iad", "ord"],
+            "env": { "PORT": "8080", "DEBUG": "1" },
+            "secrets": ["DATABASE_URL"],
+            "vm_size": "shared-cpu-1x",
+        }))
+        .unwrap();
+
+        assert!(compute_plan(&live(), &desired).is_empty());
+        assert!(compute_plan(&live(), &DesiredConfig::default()).is_empty());
+    }
+
+    fn placement(pairs: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        pairs.iter().map(|(r, n)| (r.to_string(), *n)).collect()
+    }
+
+    fn regions(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn placement_spreads_the_count_across_the_desired_regions() {
+        let current = placement(&[("iad", 1), ("ord", 1)]);
+
+        // Count and regions together, not one after the other
+        assert_eq!(
+            placement_targets(&current, Some(2), Some(&regions(&["lhr"]))),
+            placement(&[("lhr", 2)])
+        );
+        assert_eq!(
+            placement_targets(&current, Some(5), None),
+            placement(&[("iad", 3), ("ord", 2)])
+        );
+        assert_eq!(
+            placement_targets(&current, None, Some(&regions(&["iad", "lhr"]))),
+            placement(&[("iad", 1), ("lhr", 1)])
+        );
+        assert_eq!(
+            placement_targets(&placement(&[("iad", 3), ("ord", 1)]), Some(2), None),
+            placement(&[("iad", 1), ("ord", 1)])
+        );
+        assert_eq!(
+            placement_targets(&current, Some(0), None),
+            placement(&[("iad", 0), ("ord", 0)])
+        );
+    }
+
+    #[test]
+    fn counts_below_the_number_of_regions_are_rejected() {
+        let desired: DesiredConfig =
+            serde_json::from_value(json!({ "count": 1, "regions": ["iad", "lhr"] })).unwrap();
+        assert!(desired.validate().is_err());
+        let empty: DesiredConfig = serde_json::from_value(json!({ "regions": [] })).unwrap();
+        assert!(empty.validate().is_err());
+        let ok: DesiredConfig =
+            serde_json::from_value(json!({ "count": 2, "regions": ["iad", "lhr"] })).unwrap();
+        assert!(ok.validate().is_ok());
+    }
+
+    #[cfg(feature = "test-support")]
+    mod apply {
+        use super::super::*;
+        use crate::api::mock_machines::{machine, machine_json, MockMachines};
+        use serde_json::json;
+        use wiremock::matchers::{body_string_contains, method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        const APP: &str = "web";
+
+        async fn list(api: &MockMachines, image: &str, times: Option<u64>) {
+            let mock = Mock::given(method("GET"))
+                .and(path(api.path(&["apps", APP, "machines"])))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                    machine_json("m1", "started", "iad", image),
+                    machine_json("m2", "started", "ord", image),
+                ])));
+            match times {
+                Some(n) => mock.up_to_n_times(n).mount(&api.server).await,
+                None => mock.mount(&api.server).await,
+            }
+        }
+
+        fn plan_for(desired: &DesiredConfig) -> Vec<PlanStep> {
+            let live = LiveState::from_parts(
+                &[
+                    machine("m1", "started", "iad", "web:v1"),
+                    machine("m2", "started", "ord", "web:v1"),
+                ],
+                &[],
+            );
+            compute_plan(&live, desired)
+        }
+
+        #[tokio::test]
+        async fn image_and_placement_converge_on_the_desired_regions() {
+            let api = MockMachines::start().await;
+            // The deploy sees v1; once it has run, every listing shows v2
+            list(&api, "web:v1", Some(1)).await;
+            list(&api, "web:v2", None).await;
+            for (id, region) in [("m1", "iad"), ("m2", "ord")] {
+                Mock::given(method("POST"))
+                    .and(path(api.path(&["apps", APP, "machines", id])))
+                    .and(body_string_contains("web:v2"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(machine_json(id, "started", region, "web:v2")),
+                    )
+                    .expect(1)
+                    .mount(&api.server)
+                    .await;
+                Mock::given(method("GET"))
+                    .and(path(api.path(&["apps", APP, "machines", id, "wait"])))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+                    .mount(&api.server)
+                    .await;
+                Mock::given(method("DELETE"))
+                    .and(path(api.path(&["apps", APP, "machines", id])))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+                    .expect(1)
+                    .mount(&api.server)
+                    .await;
+            }
+            // Clones copy the rolled-out image
+            Mock::given(method("POST"))
+                .and(path(api.path(&["apps", APP, "machines"])))
+                .and(body_string_contains("\"region\":\"lhr\""))
+                .and(body_string_contains("web:v2"))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(machine_json("m3", "started", "lhr", "web:v2")),
+                )
+                .expect(2)
+                .mount(&api.server)
+                .await;
+
+            let desired: DesiredConfig = serde_json::from_value(json!({
+                "image": "web:v2",
+                "count": 2,
+                "regions": ["lhr"],
+            }))
+            .unwrap();
+            let plan = plan_for(&desired);
+            let deploys = Deployments::default();
+            let results = apply_plan(api.client(), &deploys, APP, &desired, &plan).await;
+
+            let statuses: Vec<_> = results.iter().map(|r| (r.resource, r.status)).collect();
+            assert_eq!(
+                statuses,
+                vec![
+                    (Resource::Image, "applied"),
+                    (Resource::Placement, "applied")
+                ]
+            );
+            assert_eq!(results[1].result.as_ref().unwrap()["count"], json!(2));
+            assert_eq!(deploys.list(Some(APP)).len(), 1);
+        }
+
+        #[tokio::test]
+        async fn failed_steps_are_reported_and_later_steps_still_run() {
+            let api = MockMachines::start().await;
+            list(&api, "web:v1", None).await;
+            Mock::given(method("POST"))
+                .and(path(api.path(&["apps", APP, "machines"])))
+                .respond_with(
+                    ResponseTemplate::new(422).set_body_json(json!({ "error": "no capacity" })),
+                )
+                .mount(&api.server)
+                .await;
+            // A machine that failed to start keeps the old ones around
+            Mock::given(method("DELETE"))
+                .respond_with(ResponseTemplate::new(200))
+                .expect(0)
+                .mount(&api.server)
+                .await;
+
+            let desired: DesiredConfig = serde_json::from_value(json!({
+                "regions": ["iad", "lhr"],
+                "secrets": ["API_KEY"],
+                "vm_size": "enormous-cpu-64x",
+            }))
+            .unwrap();
+            let plan = plan_for(&desired);
+            let results =
+                apply_plan(api.client(), &Deployments::default(), APP, &desired, &plan).await;
+
+            let statuses: Vec<_> = results.iter().map(|r| (r.resource, r.status)).collect();
+            assert_eq!(
+                statuses,
+                vec![
+                    (Resource::Placement, "failed"),
+                    (Resource::Secrets, "manual"),
+                    (Resource::VmSize, "failed"),
+                ]
+            );
+            assert_eq!(
+                results[2].error.as_deref(),
+                Some("Unknown VM size: enormous-cpu-64x")
+            );
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Where an app runs, relative to the rest of the Fly platform.
+//!
+//! Fly doesn't publish inter-region latency, so round trips are estimated
+//! from great-circle distance (light in fiber covers ~100km per ms of RTT).
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::api::{FlyApi, MachinesClient};
+use crate::models::{Machine, Region};
+use crate::scale::{ScaleReport, ScaleStep};
+
+/// How many unused regions to suggest around the primary.
+const NEARBY_COUNT: usize = 3;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// A region with its place in an app's deployment.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppRegion {
+    #[serde(flatten)]
+    pub region: Region,
+    /// Machines the app runs in this region.
+    pub machines: usize,
+    /// Estimated round trip to the primary region, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub est_rtt_ms: Option<u32>,
+}
+
+/// Regions an app runs in, its backups, and nearby candidates.
+#[derive(Debug, Serialize)]
+pub struct AppPlacement {
+    pub app: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_region: Option<String>,
+    pub regions: Vec<AppRegion>,
+    pub backup_regions: Vec<String>,
+    /// Closest regions the app doesn't run in yet.
+    pub nearby: Vec<AppRegion>,
+}
+
+/// Describe an app's placement across the platform's regions.
+///
+/// The primary region is the machines' `PRIMARY_REGION`, falling back to the
+/// region with the most machines.
+pub async fn app_placement(
+    client: &dyn FlyApi,
+    machines: &MachinesClient,
+    app_name: &str,
+) -> Result<AppPlacement> {
+    let platform = client.list_regions().await?;
+    let backup_regions = client.get_backup_regions(app_name).await?;
+    let list = machines.list_machines(app_name).await?;
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for machine in &list {
+        *counts.entry(machine.region.clone()).or_default() += 1;
+    }
+
+    let primary_region = list
+        .iter()
+        .filter_map(|m| m.config.as_ref()?.env.get("PRIMARY_REGION").cloned())
+        .next()
+        .or_else(|| {
+            counts
+                .iter()
+                .max_by_key(|(_, count)| **count)
+                .map(|(region, _)| region.clone())
+        });
+    let primary = primary_region
+        .as_deref()
+        .and_then(|code| platform.iter().find(|r| r.code == code))
+        .cloned();
+
+    let placed = |region: &Region| AppRegion {
+        region: region.clone(),
+        machines: counts.get(&region.code).copied().unwrap_or(0),
+        est_rtt_ms: primary.as_ref().and_then(|p| est_rtt_ms(p, region)),
+    };
+
+    let regions = platform
+        .iter()
+        .filter(|r| counts.contains_key(&r.code))
+        .map(placed)
+        .collect();
+
+    let mut nearby: Vec<AppRegion> = platform
+        .iter()
+        .filter(|r| !counts.contains_key(&r.code) && r.code != "global")
+        .map(placed)
+        .filter(|r| r.est_rtt_ms.is_some())
+        .collect();
+    nearby.sort_by_key(|r| r.est_rtt_ms);
+    nearby.truncate(NEARBY_COUNT);
+
+    Ok(AppPlacement {
+        app: app_name.to_string(),
+        primary_region,
+        regions,
+        backup_regions,
+        nearby,
+    })
+}
+
+/// Move an app's machines so it runs in exactly `target` regions.
+///
+/// New regions get `count` clones of an existing machine's config; machines
+/// in dropped regions are destroyed, but only once every clone succeeded so
+/// a failed rollout never shrinks the app.
+pub async fn set_regions(
+    machines: &MachinesClient,
+    app_name: &str,
+    target: &BTreeSet<String>,
+    count: usize,
+) -> Result<ScaleReport> {
+    if target.is_empty() {
+        anyhow::bail!("An app must keep at least one region");
+    }
+
+    let all = machines.list_machines(app_name).await?;
+    let current: BTreeSet<String> = all.iter().map(|m| m.region.clone()).collect();
+    let mut steps = Vec::new();
+
+    let added: Vec<&String> = target.difference(&current).collect();
+    if !added.is_empty() {
+        // Prefer a running machine as the template
+        let template = all
+            .iter()
+            .find(|m| m.state == "started")
+            .or_else(|| all.first())
+            .ok_or_else(|| anyhow::anyhow!("App {} has no machine to clone from", app_name))?;
+        let config = template
+            .config
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Machine {} has no config", template.id))?;
+        if !config.mounts.is_empty() {
+            anyhow::bail!("Cannot clone machines with mounted volumes; create volumes first");
+        }
+
+        for region in added {
+            for _ in 0..count {
+                let step = match machines.clone_machine(app_name, region, &config).await {
+                    Ok(machine) => ScaleStep {
+                        action: "create",
+                        machine_id: Some(machine.id),
+                        region: machine.region,
+                        status: "ok",
+                        error: None,
+                    },
+                    Err(e) => ScaleStep {
+                        action: "create",
+                        machine_id: None,
+                        region: region.clone(),
+                        status: "failed",
+                        error: Some(e.to_string()),
+                    },
+                };
+                steps.push(step);
+            }
+        }
+    }
+
+    let create_failed = steps.iter().any(|s| s.status == "failed");
+    let removed: Vec<&Machine> = all.iter().filter(|m| !target.contains(&m.region)).collect();
+    for machine in removed {
+        if create_failed {
+            steps.push(ScaleStep {
+                action: "destroy",
+                machine_id: Some(machine.id.clone()),
+                region: machine.region.clone(),
+                status: "skipped",
+                error: Some("Kept because a new region failed to start".into()),
+            });
+            continue;
+        }
+
+        let result = machines
+            .destroy_machine(app_name, &machine.id, true, None)
+            .await;
+        steps.push(ScaleStep {
+            action: "destroy",
+            machine_id: Some(machine.id.clone()),
+            region: machine.region.clone(),
+            status: if result.is_ok() { "ok" } else { "failed" },
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    let succeeded = |action: &str| {
+        steps
+            .iter()
+            .filter(|s| s.action == action && s.status == "ok")
+            .count()
+    };
+    let count = all.len() + succeeded("create") - succeeded("destroy");
+
+    Ok(ScaleReport {
+        app: app_name.to_string(),
+        previous_count: all.len(),
+        count,
+        steps,
+    })
+}
+
+/// Current regions of an app, for resolving `add`/`remove` into a target set.
+pub async fn current_regions(
+    machines: &MachinesClient,
+    app_name: &str,
+) -> Result<BTreeSet<String>> {
+    let all = machines.list_machines(app_name).await?;
+    Ok(all.into_iter().map(|m| m.region).collect())
+}
+
+/// Estimated round trip between two regions, if both have coordinates.
+fn est_rtt_ms(from: &Region, to: &Region) -> Option<u32> {
+    let (lat1, lon1) = (from.latitude?.to_radians(), from.longitude?.to_radians());
+    let (lat2, lon2) = (to.latitude?.to_radians(), to.longitude?.to_radians());
+
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    let km = 2.0 * EARTH_RADIUS_KM * a.sqrt().asin();
+
+    Some((km / 100.0).round() as u32)
+}
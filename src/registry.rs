@@ -0,0 +1,350 @@
+//! Registry of daemon methods.
+//!
+//! Each method is registered once, with its handler and parameter schema;
+//! `method_list()` publishes the schema and `params::validate` checks calls
+//! against it. A method answers to its `fly.*` name, the same name without
+//! the `fly.` prefix, and any aliases. Whether a call changes Fly state (and
+//! so is audited and needs a writable token), how long its response is
+//! cached and which of its params hold secrets are declared here too.
+
+use anyhow::Result;
+use fgp_daemon::service::{MethodInfo, ParamInfo};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::redact::{SecretKind, SecretParam};
+use crate::suggest;
+
+/// A method handler on the service `S`.
+pub type Handler<S> = fn(&S, HashMap<String, Value>) -> Result<Value>;
+
+/// One parameter of a method's schema.
+pub struct Param {
+    pub name: &'static str,
+    /// JSON type: `string`, `integer`, `number`, `boolean`, `object` or `array`.
+    pub kind: &'static str,
+    pub required: bool,
+    pub default: Option<Value>,
+}
+
+impl Param {
+    const fn optional(name: &'static str, kind: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            required: false,
+            default: None,
+        }
+    }
+}
+
+/// Every method can run against a named profile (`account` is another name
+/// for it, and `org` picks the profile serving that org) and with its own
+/// limits.
+static COMMON_PARAMS: [Param; 5] = [
+    Param::optional("profile", "string"),
+    Param::optional("account", "string"),
+    Param::optional("org", "string"),
+    Param::optional("timeout_ms", "integer"),
+    Param::optional("deadline_ms", "integer"),
+];
+
+/// Which calls of a method change Fly state.
+enum Mutates {
+    Never,
+    Always,
+    /// Calls whose `action` is one of these.
+    Actions(&'static [&'static str]),
+    /// Calls the function picks out from their params.
+    When(fn(&HashMap<String, Value>) -> bool),
+}
+
+/// How long a method's responses are cached.
+struct CachePolicy {
+    ttl: Duration,
+    /// Used instead of `ttl` for calls naming an `app`.
+    app_ttl: Option<Duration>,
+}
+
+/// A registered method.
+pub struct Method<S> {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub handler: Handler<S>,
+    params: Vec<Param>,
+    aliases: Vec<&'static str>,
+    listed: bool,
+    /// Whether the common `org` param picks the profile.
+    routes_by_org: bool,
+    mutates: Mutates,
+    cache: Option<CachePolicy>,
+    /// Cached methods whose responses calls of this one make stale.
+    invalidates: &'static [&'static str],
+    secrets: Vec<SecretParam>,
+}
+
+impl<S> Method<S> {
+    pub fn required(&mut self, name: &'static str, kind: &'static str) -> &mut Self {
+        self.params.push(Param {
+            required: true,
+            ..Param::optional(name, kind)
+        });
+        self
+    }
+
+    pub fn optional(&mut self, name: &'static str, kind: &'static str) -> &mut Self {
+        self.params.push(Param::optional(name, kind));
+        self
+    }
+
+    pub fn default(&mut self, name: &'static str, kind: &'static str, value: Value) -> &mut Self {
+        self.params.push(Param {
+            default: Some(value),
+            ..Param::optional(name, kind)
+        });
+        self
+    }
+
+    /// Another name the method answers to.
+    pub fn alias(&mut self, name: &'static str) -> &mut Self {
+        self.aliases.push(name);
+        self
+    }
+
+    /// Every call changes Fly state.
+    pub fn mutating(&mut self) -> &mut Self {
+        self.mutates = Mutates::Always;
+        self
+    }
+
+    /// Calls with one of these `action`s change Fly state; the default
+    /// action must be a read.
+    pub fn mutating_actions(&mut self, actions: &'static [&'static str]) -> &mut Self {
+        self.mutates = Mutates::Actions(actions);
+        self
+    }
+
+    /// Calls for which `f` returns true change Fly state.
+    pub fn mutating_when(&mut self, f: fn(&HashMap<String, Value>) -> bool) -> &mut Self {
+        self.mutates = Mutates::When(f);
+        self
+    }
+
+    /// Cache responses for `ttl`; `no_cache: true` skips the cache.
+    pub fn cached(&mut self, ttl: Duration) -> &mut Self {
+        self.cache = Some(CachePolicy { ttl, app_ttl: None });
+        self
+    }
+
+    /// Cache calls naming an `app` for `ttl` instead, since per-app results
+    /// go stale sooner. Call after `cached`.
+    pub fn cached_for_app(&mut self, ttl: Duration) -> &mut Self {
+        if let Some(cache) = &mut self.cache {
+            cache.app_ttl = Some(ttl);
+        }
+        self
+    }
+
+    /// Calls change what these cached methods return, so their responses
+    /// are dropped once a call finishes.
+    pub fn invalidates(&mut self, methods: &'static [&'static str]) -> &mut Self {
+        self.invalidates = methods;
+        self
+    }
+
+    /// The param at `path` is a secret value. Paths are dotted to reach into
+    /// object params, e.g. `config.secrets`.
+    pub fn secret(&mut self, path: &'static str) -> &mut Self {
+        self.secrets.push(SecretParam {
+            path,
+            kind: SecretKind::Value,
+        });
+        self
+    }
+
+    /// The param at `path` maps secret names to values; names are kept.
+    pub fn secret_map(&mut self, path: &'static str) -> &mut Self {
+        self.secrets.push(SecretParam {
+            path,
+            kind: SecretKind::Map,
+        });
+        self
+    }
+
+    /// The param at `path` is a secrets file in the call's `format`.
+    pub fn secret_file(&mut self, path: &'static str) -> &mut Self {
+        self.secrets.push(SecretParam {
+            path,
+            kind: SecretKind::File,
+        });
+        self
+    }
+
+    /// The method's own `org` param names the org it acts on (e.g. where an
+    /// app moves to), so it doesn't pick the profile the call runs as.
+    pub fn org_is_target(&mut self) -> &mut Self {
+        self.routes_by_org = false;
+        self
+    }
+
+    /// Whether the call's `org` param picks the profile it runs as.
+    pub fn routes_by_org(&self) -> bool {
+        self.routes_by_org
+    }
+
+    /// Serve the method but leave it out of `method_list()`.
+    pub fn unlisted(&mut self) -> &mut Self {
+        self.listed = false;
+        self
+    }
+
+    /// Whether a call changes Fly state, and so is audited and needs a
+    /// token that can write.
+    pub fn is_mutating(&self, params: &HashMap<String, Value>) -> bool {
+        match &self.mutates {
+            Mutates::Never => false,
+            Mutates::Always => true,
+            Mutates::Actions(actions) => params
+                .get("action")
+                .and_then(|v| v.as_str())
+                .is_some_and(|action| actions.contains(&action)),
+            Mutates::When(f) => f(params),
+        }
+    }
+
+    /// Cache lifetime for a call, or `None` if its responses are never cached.
+    pub fn cache_ttl(&self, params: &HashMap<String, Value>) -> Option<Duration> {
+        let cache = self.cache.as_ref()?;
+        match cache.app_ttl {
+            Some(ttl) if params.contains_key("app") => Some(ttl),
+            _ => Some(cache.ttl),
+        }
+    }
+
+    /// Cached methods a call of this one makes stale.
+    pub fn invalidated(&self) -> &'static [&'static str] {
+        self.invalidates
+    }
+
+    /// Params holding secret values, to keep out of logs and errors.
+    pub fn secrets(&self) -> &[SecretParam] {
+        &self.secrets
+    }
+
+    /// The method's own params followed by the common ones it doesn't
+    /// declare itself.
+    pub fn params(&self) -> impl Iterator<Item = &Param> {
+        let common = COMMON_PARAMS
+            .iter()
+            .filter(|c| !self.params.iter().any(|p| p.name == c.name));
+        self.params.iter().chain(common)
+    }
+
+    fn info(&self) -> MethodInfo {
+        let mut description = self.description.to_string();
+        if !self.aliases.is_empty() {
+            description.push_str(&format!(" (also: {})", self.aliases.join(", ")));
+        }
+
+        MethodInfo {
+            name: self.name.into(),
+            description,
+            params: self
+                .params()
+                .map(|p| ParamInfo {
+                    name: p.name.into(),
+                    param_type: p.kind.into(),
+                    required: p.required,
+                    default: p.default.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Every method the daemon serves, in `method_list()` order.
+pub struct Registry<S> {
+    methods: Vec<Method<S>>,
+}
+
+impl<S> Default for Registry<S> {
+    fn default() -> Self {
+        Self {
+            methods: Vec::new(),
+        }
+    }
+}
+
+impl<S> Registry<S> {
+    /// Register a method under its canonical name.
+    pub fn add(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        handler: Handler<S>,
+    ) -> &mut Method<S> {
+        self.methods.push(Method {
+            name,
+            description,
+            handler,
+            params: Vec::new(),
+            aliases: Vec::new(),
+            listed: true,
+            routes_by_org: true,
+            mutates: Mutates::Never,
+            cache: None,
+            invalidates: &[],
+            secrets: Vec::new(),
+        });
+        self.methods.last_mut().expect("just pushed")
+    }
+
+    /// Look up a method by canonical name or alias, with or without the
+    /// `fly.` prefix.
+    pub fn resolve(&self, name: &str) -> Option<&Method<S>> {
+        let matches =
+            |candidate: &str| candidate == name || candidate.strip_prefix("fly.") == Some(name);
+        self.methods
+            .iter()
+            .find(|m| matches(m.name) || m.aliases.iter().any(|a| matches(a)))
+    }
+
+    /// Listed methods whose name or an alias is close to `name`, for
+    /// "did you mean" hints. Names are compared without the `fly.` prefix.
+    pub fn suggest(&self, name: &str) -> Vec<String> {
+        let bare = |name: &'static str| name.strip_prefix("fly.").unwrap_or(name);
+        let names: Vec<(&str, &str)> = self
+            .methods
+            .iter()
+            .filter(|m| m.listed)
+            .flat_map(|m| {
+                std::iter::once(m.name)
+                    .chain(m.aliases.iter().copied())
+                    .map(move |n| (bare(n), m.name))
+            })
+            .collect();
+
+        let mut suggestions: Vec<String> = Vec::new();
+        let input = name.strip_prefix("fly.").unwrap_or(name);
+        for candidate in suggest::closest(input, names.iter().map(|(n, _)| *n)) {
+            let (_, canonical) = names
+                .iter()
+                .find(|(n, _)| *n == candidate)
+                .expect("candidate");
+            if !suggestions.iter().any(|s| s == canonical) {
+                suggestions.push(canonical.to_string());
+            }
+        }
+        suggestions
+    }
+
+    /// Schema of every method, as published by `method_list()`.
+    pub fn method_list(&self) -> Vec<MethodInfo> {
+        self.methods
+            .iter()
+            .filter(|m| m.listed)
+            .map(Method::info)
+            .collect()
+    }
+}
@@ -0,0 +1,276 @@
+//! Rolling app restarts over the Machines API.
+//!
+//! Restarts running machines in batches and waits for each batch to start
+//! and pass its health checks before moving on to the next.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::api::MachinesClient;
+use crate::models::Machine;
+
+/// Delay between health check polls.
+const CHECK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How an app's machines are restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartStrategy {
+    /// Restart every machine at once through the GraphQL API.
+    Immediate,
+    /// Restart machines in batches, gated on health checks.
+    Rolling,
+}
+
+impl RestartStrategy {
+    /// Parse a strategy name.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "immediate" => Ok(RestartStrategy::Immediate),
+            "rolling" => Ok(RestartStrategy::Rolling),
+            _ => anyhow::bail!(
+                "Unknown strategy: {}. Valid strategies are: immediate, rolling",
+                name
+            ),
+        }
+    }
+}
+
+/// Outcome for a single machine.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineRestart {
+    pub machine_id: String,
+    pub region: String,
+    /// `restarted`, `failed` or `skipped`.
+    pub status: &'static str,
+    pub batch: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a rolling restart.
+#[derive(Debug, Serialize)]
+pub struct RestartReport {
+    pub app: String,
+    pub strategy: RestartStrategy,
+    pub batch_size: usize,
+    pub status: &'static str,
+    pub machines: Vec<MachineRestart>,
+}
+
+/// Restart an app's running machines `batch_size` at a time.
+///
+/// Each machine gets `timeout` to start and report passing checks. A failed
+/// batch stops the rollout; later machines are reported as skipped.
+pub async fn rolling_restart(
+    machines: &MachinesClient,
+    app_name: &str,
+    batch_size: usize,
+    timeout: Duration,
+) -> Result<RestartReport> {
+    let batch_size = batch_size.max(1);
+    let all = machines.list_machines(app_name).await?;
+
+    let mut results = Vec::with_capacity(all.len());
+    let (running, stopped): (Vec<&Machine>, Vec<&Machine>) =
+        all.iter().partition(|m| m.state == "started");
+    for machine in stopped {
+        results.push(skipped(machine, 0, "Machine is not running"));
+    }
+
+    let mut aborted = false;
+    for (index, batch) in running.chunks(batch_size).enumerate() {
+        let batch_number = index + 1;
+        if aborted {
+            results.extend(
+                batch
+                    .iter()
+                    .map(|m| skipped(m, batch_number, "Aborted after earlier failure")),
+            );
+            continue;
+        }
+
+        // Restart the whole batch first so the machines come back in parallel
+        let mut pending = Vec::with_capacity(batch.len());
+        for machine in batch {
+            let started = Instant::now();
+            match machines.restart_machine(app_name, &machine.id, None).await {
+                Ok(_) => pending.push((*machine, started)),
+                Err(e) => results.push(failed(machine, batch_number, started, e.to_string())),
+            }
+        }
+
+        for (machine, started) in pending {
+            let deadline = started + timeout;
+            let outcome = match wait_healthy(
+                machines,
+                app_name,
+                &machine.id,
+                Some(machine),
+                deadline,
+            )
+            .await
+            {
+                Ok(()) => MachineRestart {
+                    machine_id: machine.id.clone(),
+                    region: machine.region.clone(),
+                    status: "restarted",
+                    batch: batch_number,
+                    elapsed_ms: Some(started.elapsed().as_millis() as u64),
+                    error: None,
+                },
+                Err(e) => failed(machine, batch_number, started, e.to_string()),
+            };
+            results.push(outcome);
+        }
+
+        aborted = results.iter().any(|r| r.status == "failed");
+    }
+
+    let status = if aborted { "failed" } else { "complete" };
+
+    Ok(RestartReport {
+        app: app_name.to_string(),
+        strategy: RestartStrategy::Rolling,
+        batch_size,
+        status,
+        machines: results,
+    })
+}
+
+/// Wait for a machine to start and pass its checks.
+///
+/// For a machine that was just restarted, pass it as it was before the
+/// restart in `restarted`: the wait then starts once the machine shows it
+/// went through the restart, and checks last run before it came back up
+/// don't count.
+pub async fn wait_healthy(
+    machines: &MachinesClient,
+    app_name: &str,
+    machine_id: &str,
+    restarted: Option<&Machine>,
+    deadline: Instant,
+) -> Result<()> {
+    if let Some(before) = restarted {
+        wait_for_restart(machines, app_name, before, deadline).await?;
+    }
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    machines
+        .wait_until(
+            app_name,
+            machine_id,
+            "started",
+            remaining,
+            CHECK_POLL_INTERVAL,
+        )
+        .await?;
+    wait_for_checks(
+        machines,
+        app_name,
+        machine_id,
+        restarted.is_some(),
+        deadline,
+    )
+    .await
+}
+
+/// Poll until the machine has left the state it was in before a restart:
+/// it's no longer started, or it has moved on since (a new instance or a
+/// later update).
+async fn wait_for_restart(
+    machines: &MachinesClient,
+    app_name: &str,
+    before: &Machine,
+    deadline: Instant,
+) -> Result<()> {
+    loop {
+        let machine = machines.get_machine(app_name, &before.id).await?;
+        if machine.state != "started"
+            || machine.instance_id != before.instance_id
+            || machine.updated_at != before.updated_at
+        {
+            return Ok(());
+        }
+
+        if Instant::now() + CHECK_POLL_INTERVAL >= deadline {
+            anyhow::bail!(
+                "Timed out waiting for machine {} to restart; it still reports its previous run",
+                before.id
+            );
+        }
+        tokio::time::sleep(CHECK_POLL_INTERVAL).await;
+    }
+}
+
+/// Poll a machine until none of its checks are failing. With `fresh_only`,
+/// checks last updated before the machine's latest transition are treated
+/// as not yet run.
+async fn wait_for_checks(
+    machines: &MachinesClient,
+    app_name: &str,
+    machine_id: &str,
+    fresh_only: bool,
+    deadline: Instant,
+) -> Result<()> {
+    loop {
+        let machine = machines.get_machine(app_name, machine_id).await?;
+        let since = fresh_only
+            .then(|| timestamp(machine.updated_at.as_deref()))
+            .flatten();
+        let failing: Vec<&str> = machine
+            .checks
+            .iter()
+            .filter(|c| {
+                let stale = since
+                    .zip(timestamp(c.updated_at.as_deref()))
+                    .is_some_and(|(since, updated)| updated < since);
+                stale || c.is_failing()
+            })
+            .map(|c| c.name.as_str())
+            .collect();
+        if failing.is_empty() {
+            return Ok(());
+        }
+
+        if Instant::now() + CHECK_POLL_INTERVAL >= deadline {
+            anyhow::bail!(
+                "Timed out waiting for checks on machine {}: {} still failing or not rerun",
+                machine_id,
+                failing.join(", ")
+            );
+        }
+        tokio::time::sleep(CHECK_POLL_INTERVAL).await;
+    }
+}
+
+fn timestamp(value: Option<&str>) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value?)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn failed(machine: &Machine, batch: usize, started: Instant, error: String) -> MachineRestart {
+    MachineRestart {
+        machine_id: machine.id.clone(),
+        region: machine.region.clone(),
+        status: "failed",
+        batch,
+        elapsed_ms: Some(started.elapsed().as_millis() as u64),
+        error: Some(error),
+    }
+}
+
+fn skipped(machine: &Machine, batch: usize, reason: &str) -> MachineRestart {
+    MachineRestart {
+        machine_id: machine.id.clone(),
+        region: machine.region.clone(),
+        status: "skipped",
+        batch,
+        elapsed_ms: None,
+        error: Some(reason.to_string()),
+    }
+}
@@ -0,0 +1,213 @@
+//! Machine count and VM size scaling over the Machines API.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::api::MachinesClient;
+use crate::models::{GuestConfig, Machine, MachineConfig};
+
+/// Memory per CPU of Fly's named VM sizes, by CPU kind.
+const SIZE_MEMORY_PER_CPU: &[(&str, u32)] = &[("shared", 256), ("performance", 2048)];
+
+/// Outcome of one scaling step.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScaleStep {
+    pub action: &'static str,
+    pub machine_id: Option<String>,
+    pub region: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a scaling operation.
+#[derive(Debug, Serialize)]
+pub struct ScaleReport {
+    pub app: String,
+    pub previous_count: usize,
+    pub count: usize,
+    pub steps: Vec<ScaleStep>,
+}
+
+/// Set the number of machines, optionally within a single region.
+///
+/// New machines clone the config of an existing one (preferring the same
+/// region). Scale-down removes stopped machines before running ones.
+pub async fn scale_count(
+    machines: &MachinesClient,
+    app_name: &str,
+    target: usize,
+    region: Option<&str>,
+) -> Result<ScaleReport> {
+    let all = machines.list_machines(app_name).await?;
+    let mut current: Vec<&Machine> = all.iter().filter(|m| in_region(m, region)).collect();
+    let previous_count = current.len();
+
+    let mut steps = Vec::new();
+
+    if target > current.len() {
+        let template = current
+            .first()
+            .copied()
+            .or_else(|| all.first())
+            .ok_or_else(|| anyhow::anyhow!("App {} has no machine to clone from", app_name))?;
+        let config = template
+            .config
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Machine {} has no config", template.id))?;
+        if !config.mounts.is_empty() {
+            anyhow::bail!("Cannot clone machines with mounted volumes; create volumes first");
+        }
+        let target_region = region.unwrap_or(&template.region).to_string();
+
+        for _ in current.len()..target {
+            let step = match machines
+                .clone_machine(app_name, &target_region, &config)
+                .await
+            {
+                Ok(machine) => ScaleStep {
+                    action: "create",
+                    machine_id: Some(machine.id),
+                    region: machine.region,
+                    status: "ok",
+                    error: None,
+                },
+                Err(e) => ScaleStep {
+                    action: "create",
+                    machine_id: None,
+                    region: target_region.clone(),
+                    status: "failed",
+                    error: Some(e.to_string()),
+                },
+            };
+            steps.push(step);
+        }
+    } else if target < current.len() {
+        // Stopped machines go first, then the newest
+        current.sort_by(|a, b| {
+            (a.state == "started")
+                .cmp(&(b.state == "started"))
+                .then_with(|| b.created_at.cmp(&a.created_at))
+        });
+
+        for machine in current.iter().take(previous_count - target) {
+            let result = machines
+                .destroy_machine(app_name, &machine.id, true, None)
+                .await;
+            steps.push(ScaleStep {
+                action: "destroy",
+                machine_id: Some(machine.id.clone()),
+                region: machine.region.clone(),
+                status: if result.is_ok() { "ok" } else { "failed" },
+                error: result.err().map(|e| e.to_string()),
+            });
+        }
+    }
+
+    let created = steps
+        .iter()
+        .filter(|s| s.action == "create" && s.status == "ok")
+        .count();
+    let destroyed = steps
+        .iter()
+        .filter(|s| s.action == "destroy" && s.status == "ok")
+        .count();
+
+    Ok(ScaleReport {
+        app: app_name.to_string(),
+        previous_count,
+        count: previous_count + created - destroyed,
+        steps,
+    })
+}
+
+/// Change the guest size of every machine, optionally within a region.
+pub async fn scale_vm(
+    machines: &MachinesClient,
+    app_name: &str,
+    guest: &GuestConfig,
+    region: Option<&str>,
+) -> Result<ScaleReport> {
+    let all = machines.list_machines(app_name).await?;
+    let targets: Vec<&Machine> = all.iter().filter(|m| in_region(m, region)).collect();
+
+    let mut steps = Vec::with_capacity(targets.len());
+
+    for machine in &targets {
+        let Some(mut config) = machine.config.clone() else {
+            steps.push(ScaleStep {
+                action: "resize",
+                machine_id: Some(machine.id.clone()),
+                region: machine.region.clone(),
+                status: "failed",
+                error: Some("Machine has no config".into()),
+            });
+            continue;
+        };
+
+        let current = config.guest.get_or_insert_with(GuestConfig::default);
+        if guest.cpu_kind.is_some() {
+            current.cpu_kind = guest.cpu_kind.clone();
+        }
+        if guest.cpus.is_some() {
+            current.cpus = guest.cpus;
+        }
+        if guest.memory_mb.is_some() {
+            current.memory_mb = guest.memory_mb;
+        }
+
+        let result = machines
+            .update_machine(app_name, &machine.id, &config, None)
+            .await;
+        steps.push(ScaleStep {
+            action: "resize",
+            machine_id: Some(machine.id.clone()),
+            region: machine.region.clone(),
+            status: if result.is_ok() { "ok" } else { "failed" },
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    Ok(ScaleReport {
+        app: app_name.to_string(),
+        previous_count: targets.len(),
+        count: targets.len(),
+        steps,
+    })
+}
+
+/// Guest for a named VM size such as `shared-cpu-2x` or `performance-4x`.
+pub fn guest_for_size(size: &str) -> Option<GuestConfig> {
+    let (kind, cpus) = size.strip_suffix('x')?.rsplit_once('-')?;
+    let kind = kind.strip_suffix("-cpu").unwrap_or(kind);
+    let cpus: u32 = cpus.parse().ok().filter(|n: &u32| n.is_power_of_two())?;
+    let (_, memory) = SIZE_MEMORY_PER_CPU.iter().find(|(k, _)| *k == kind)?;
+    Some(GuestConfig {
+        cpu_kind: Some(kind.to_string()),
+        cpus: Some(cpus),
+        memory_mb: Some(memory * cpus),
+        ..Default::default()
+    })
+}
+
+/// Named VM size a machine runs as: its guest if that matches one, else
+/// the size it was created with.
+pub fn size_of(config: &MachineConfig) -> Option<String> {
+    let named = config.guest.as_ref().and_then(|guest| {
+        let kind = guest.cpu_kind.as_deref()?;
+        let size = match kind {
+            "shared" => format!("shared-cpu-{}x", guest.cpus?),
+            _ => format!("{}-{}x", kind, guest.cpus?),
+        };
+        let preset = guest_for_size(&size)?;
+        (preset.memory_mb == guest.memory_mb).then_some(size)
+    });
+    named.or_else(|| config.size.clone())
+}
+
+fn in_region(machine: &Machine, region: Option<&str>) -> bool {
+    match region {
+        Some(region) => machine.region == region,
+        None => true,
+    }
+}
@@ -0,0 +1,344 @@
+//! Recurring machine operations on a cron schedule.
+//!
+//! A schedule starts, stops or scales an app's machines whenever its cron
+//! expression (UTC) fires, e.g. stopping staging at 20:00 on weekdays. Each
+//! active schedule is a daemon task that sleeps until its next run. Schedules
+//! are saved in the daemon's state store and picked up again when it starts.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::api::MachinesClient;
+use crate::bulk::{self, BulkAction, MachineFilter};
+use crate::cron::Cron;
+use crate::scale;
+use crate::state::{self, StateStore};
+
+/// Key of the saved schedules in the state store.
+const SCHEDULES_KEY: &str = "schedules";
+
+/// Runs kept per schedule for `list`.
+const HISTORY_LEN: usize = 10;
+
+/// Machines started or stopped at once by a scheduled run.
+const BULK_CONCURRENCY: usize = 4;
+
+/// What a schedule does when it fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleAction {
+    Start,
+    Stop,
+    /// Set the machine count to `count`.
+    Scale,
+}
+
+impl ScheduleAction {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "start" => Ok(Self::Start),
+            "stop" => Ok(Self::Stop),
+            "scale" => Ok(Self::Scale),
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: start, stop, scale",
+                value
+            ),
+        }
+    }
+}
+
+/// A recurring operation on one app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub name: String,
+    /// Cron expression, in UTC.
+    pub cron: String,
+    pub app: String,
+    pub action: ScheduleAction,
+    /// Machine count for `scale`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// For `start` and `stop`, only machines with these metadata values.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    /// Profile the schedule runs as.
+    pub profile: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Schedule {
+    /// Check the cron expression and action arguments.
+    pub fn validate(&self) -> Result<Cron> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("name must not be empty");
+        }
+        let cron = Cron::parse(&self.cron)?;
+        if cron.next_after(Utc::now()).is_none() {
+            anyhow::bail!("Cron expression {:?} never fires", self.cron);
+        }
+        match self.action {
+            ScheduleAction::Scale if self.count.is_none() => {
+                anyhow::bail!("Missing required parameter: count for action=scale")
+            }
+            ScheduleAction::Scale if !self.metadata.is_empty() => {
+                anyhow::bail!("metadata only applies to action=start or action=stop")
+            }
+            _ => Ok(cron),
+        }
+    }
+}
+
+/// One execution of a schedule.
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    pub at: DateTime<Utc>,
+    /// `schedule` or `manual`.
+    pub trigger: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Schedule plus its next and recent runs, as reported by `list`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleStatus {
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    /// False when the schedule's profile isn't configured; it's kept on
+    /// disk but doesn't run.
+    pub active: bool,
+    pub next_run: Option<DateTime<Utc>>,
+    /// Most recent first.
+    pub runs: VecDeque<Run>,
+}
+
+struct Entry {
+    status: Arc<Mutex<ScheduleStatus>>,
+    machines: Option<Arc<MachinesClient>>,
+    task: Option<JoinHandle<()>>,
+}
+
+/// Registry of schedules and their tasks.
+pub struct Scheduler {
+    store: Arc<dyn StateStore>,
+    schedules: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl Scheduler {
+    /// Scheduler saving its schedules to `store`.
+    pub fn new(store: Arc<dyn StateStore>) -> Self {
+        Self {
+            store,
+            schedules: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Schedules saved by a previous run of the daemon.
+    pub fn load(&self) -> Result<Vec<Schedule>> {
+        state::load(&*self.store, SCHEDULES_KEY)
+    }
+
+    /// Add `schedule`, replacing any with the same name. Without `machines`
+    /// it's kept but never runs.
+    pub fn add(
+        &self,
+        runtime: &Handle,
+        machines: Option<Arc<MachinesClient>>,
+        schedule: Schedule,
+    ) -> Result<ScheduleStatus> {
+        let cron = schedule.validate()?;
+        let name = schedule.name.clone();
+        let status = Arc::new(Mutex::new(ScheduleStatus {
+            active: machines.is_some(),
+            next_run: machines.as_ref().and_then(|_| cron.next_after(Utc::now())),
+            schedule,
+            runs: VecDeque::new(),
+        }));
+
+        let task = machines.clone().map(|machines| {
+            let status = status.clone();
+            runtime.spawn(async move {
+                while let Some(next) = cron.next_after(Utc::now()) {
+                    lock(&status).next_run = Some(next);
+                    let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                    tokio::time::sleep(wait).await;
+
+                    let schedule = lock(&status).schedule.clone();
+                    record(&status, execute(&machines, &schedule, "schedule").await);
+                }
+                let mut status = lock(&status);
+                status.next_run = None;
+                tracing::warn!("Schedule {} will never run again", status.schedule.name);
+            })
+        });
+
+        let snapshot = lock(&status).clone();
+        let mut schedules = lock(&self.schedules);
+        let entry = Entry {
+            status,
+            machines,
+            task,
+        };
+        if let Some(old) = schedules.insert(name, entry) {
+            if let Some(task) = old.task {
+                task.abort();
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Delete the schedule called `name`. Returns whether there was one.
+    pub fn delete(&self, name: &str) -> bool {
+        let mut schedules = lock(&self.schedules);
+        match schedules.remove(name) {
+            Some(entry) => {
+                if let Some(task) = entry.task {
+                    task.abort();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Schedules with their next and recent runs, optionally for one app.
+    pub fn list(&self, app: Option<&str>) -> Vec<ScheduleStatus> {
+        let schedules = lock(&self.schedules);
+        schedules
+            .values()
+            .map(|entry| lock(&entry.status).clone())
+            .filter(|status| app.map(|a| a == status.schedule.app).unwrap_or(true))
+            .collect()
+    }
+
+    /// Run the schedule called `name` once, now, outside its cron times.
+    pub fn run_now(&self, name: &str) -> Result<impl Future<Output = Run>> {
+        let schedules = lock(&self.schedules);
+        let entry = schedules
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Schedule not found: {}", name))?;
+        let schedule = lock(&entry.status).schedule.clone();
+        let machines = entry.machines.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Schedule {} is inactive: profile {} is not configured",
+                name,
+                schedule.profile
+            )
+        })?;
+        let status = entry.status.clone();
+        Ok(async move {
+            let run = execute(&machines, &schedule, "manual").await;
+            record(&status, run.clone());
+            run
+        })
+    }
+
+    /// Save every schedule to the state store.
+    pub fn save(&self) -> Result<()> {
+        let schedules: Vec<Schedule> = lock(&self.schedules)
+            .values()
+            .map(|entry| lock(&entry.status).schedule.clone())
+            .collect();
+        self.store
+            .put(SCHEDULES_KEY, &serde_json::to_value(schedules)?)
+    }
+
+    /// Stop and forget every schedule; saved ones are left in the store.
+    pub fn stop_all(&self) {
+        let mut schedules = lock(&self.schedules);
+        for (_, entry) in std::mem::take(&mut *schedules) {
+            if let Some(task) = entry.task {
+                task.abort();
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn record(status: &Mutex<ScheduleStatus>, run: Run) {
+    let mut status = lock(status);
+    status.runs.push_front(run);
+    status.runs.truncate(HISTORY_LEN);
+}
+
+/// Carry out the schedule's action once.
+async fn execute(machines: &MachinesClient, schedule: &Schedule, trigger: &'static str) -> Run {
+    let at = Utc::now();
+    let (result, error) = match act(machines, schedule).await {
+        Ok((result, error)) => (Some(result), error),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+
+    match &error {
+        None => tracing::info!(
+            "Schedule {}: {:?} on {} succeeded ({})",
+            schedule.name,
+            schedule.action,
+            schedule.app,
+            trigger
+        ),
+        Some(error) => tracing::warn!(
+            "Schedule {}: {:?} on {} failed ({}): {}",
+            schedule.name,
+            schedule.action,
+            schedule.app,
+            trigger,
+            error
+        ),
+    }
+    Run {
+        at,
+        trigger,
+        ok: error.is_none(),
+        result,
+        error,
+    }
+}
+
+/// The action's report, and what went wrong if it only partly succeeded.
+async fn act(machines: &MachinesClient, schedule: &Schedule) -> Result<(Value, Option<String>)> {
+    let action = match schedule.action {
+        ScheduleAction::Start => BulkAction::Start,
+        ScheduleAction::Stop => BulkAction::Stop,
+        ScheduleAction::Scale => {
+            let count = schedule.count.unwrap_or_default();
+            let report =
+                scale::scale_count(machines, &schedule.app, count, schedule.region.as_deref())
+                    .await?;
+            let error = report
+                .steps
+                .iter()
+                .find(|s| s.status == "failed")
+                .map(|failed| {
+                    format!(
+                        "Scaling stopped at {} machines: {}",
+                        report.count,
+                        failed.error.as_deref().unwrap_or("unknown error")
+                    )
+                });
+            return Ok((serde_json::to_value(report)?, error));
+        }
+    };
+    let filter = MachineFilter {
+        region: schedule.region.clone(),
+        metadata: schedule.metadata.clone(),
+    };
+    let report = bulk::run(machines, &schedule.app, action, &filter, BULK_CONCURRENCY).await?;
+    let error = (report.failed > 0)
+        .then(|| format!("{} of {} machines failed", report.failed, report.matched));
+    Ok((serde_json::to_value(report)?, error))
+}
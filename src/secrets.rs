@@ -0,0 +1,254 @@
+//! Secret files for `fly.secrets action=import`, the name comparison
+//! behind `action=sync`, and rolling secrets out to machines.
+//!
+//! Imports accept dotenv files (`KEY=value`, optional `export ` prefix, `#`
+//! comments, single- or double-quoted values) and flat JSON objects of
+//! string values. Secret names follow the rules `flyctl secrets import`
+//! enforces: letters, digits and underscores, not starting with a digit.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::api::MachinesClient;
+use crate::models::Secret;
+
+/// Longest wait for an updated machine to start again.
+const START_TIMEOUT_SECS: u64 = 60;
+
+/// Roll an app's secrets out by updating each machine with its current
+/// config, one at a time; machines only read secrets when they're updated.
+/// Returns the ids of the machines updated.
+///
+/// Stops at the first machine that fails to update or start again.
+pub async fn deploy(machines: &MachinesClient, app_name: &str) -> Result<Vec<String>> {
+    let mut updated = Vec::new();
+    for machine in machines.list_machines(app_name).await? {
+        let Some(config) = &machine.config else {
+            continue;
+        };
+        let after = machines
+            .update_machine(app_name, &machine.id, config, None)
+            .await
+            .with_context(|| format!("Failed to update machine {}", machine.id))?;
+        // Stopped machines stay stopped and pick secrets up when they start
+        if machine.state == "started" {
+            machines
+                .wait_for_state(
+                    app_name,
+                    &machine.id,
+                    after.instance_id.as_deref(),
+                    "started",
+                    START_TIMEOUT_SECS,
+                )
+                .await
+                .with_context(|| format!("Machine {} did not start again", machine.id))?;
+        }
+        updated.push(machine.id);
+    }
+    Ok(updated)
+}
+
+/// Format of imported secret content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Dotenv,
+    Json,
+}
+
+impl Format {
+    /// Parse a `format` param; `auto` picks JSON when the content is an object.
+    pub fn detect(format: &str, content: &str) -> Result<Self> {
+        match format {
+            "dotenv" => Ok(Self::Dotenv),
+            "json" => Ok(Self::Json),
+            "auto" if content.trim_start().starts_with('{') => Ok(Self::Json),
+            "auto" => Ok(Self::Dotenv),
+            _ => anyhow::bail!("Invalid format: {}. Use 'auto', 'dotenv' or 'json'", format),
+        }
+    }
+}
+
+/// Parse secret content into `(name, value)` pairs, in file order.
+///
+/// A name repeated in a dotenv file keeps its last value, as shells do.
+pub fn parse(content: &str, format: Format) -> Result<Vec<(String, String)>> {
+    let pairs = match format {
+        Format::Dotenv => parse_dotenv(content)?,
+        Format::Json => parse_json(content)?,
+    };
+
+    let mut secrets: Vec<(String, String)> = Vec::with_capacity(pairs.len());
+    for (name, value) in pairs {
+        validate_name(&name)?;
+        match secrets.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) => existing.1 = value,
+            None => secrets.push((name, value)),
+        }
+    }
+    if secrets.is_empty() {
+        anyhow::bail!("content contains no secrets");
+    }
+    Ok(secrets)
+}
+
+fn parse_json(content: &str) -> Result<Vec<(String, String)>> {
+    let value: Value = serde_json::from_str(content)
+        .map_err(|e| anyhow::anyhow!("Invalid JSON content: {}", e))?;
+    let Value::Object(map) = value else {
+        anyhow::bail!("JSON content must be an object of secret names to values");
+    };
+    map.into_iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => Ok((name, value)),
+            _ => Err(anyhow::anyhow!("Secret {} must be a string", name)),
+        })
+        .collect()
+}
+
+fn parse_dotenv(content: &str) -> Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, rest)) = line.split_once('=') else {
+            anyhow::bail!("Line {}: expected KEY=value", index + 1);
+        };
+        let name = name.trim().to_string();
+        let rest = rest.trim_start();
+
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                // Quoted values may span lines until the closing quote
+                let mut raw = rest[1..].to_string();
+                let value = loop {
+                    if let Some(end) = closing_quote(&raw, quote) {
+                        break raw[..end].to_string();
+                    }
+                    match lines.next() {
+                        Some((_, next)) => {
+                            raw.push('\n');
+                            raw.push_str(next);
+                        }
+                        None => anyhow::bail!("Line {}: unterminated {} quote", index + 1, quote),
+                    }
+                };
+                if quote == '"' {
+                    unescape(&value)
+                } else {
+                    value
+                }
+            }
+            // Unquoted values end at an inline comment
+            _ => match rest.find(" #") {
+                Some(end) => rest[..end].trim_end().to_string(),
+                None => rest.trim_end().to_string(),
+            },
+        };
+        pairs.push((name, value));
+    }
+    Ok(pairs)
+}
+
+/// Byte offset of the first unescaped `quote` in `text`.
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        anyhow::bail!(
+            "Invalid secret name: {:?}. Use letters, digits and underscores",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// How a target app's secret names compare with a source app's.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SecretDiff {
+    /// Set on both apps with the same digest.
+    pub in_sync: Vec<String>,
+    /// Set on both apps with different values.
+    pub differs: Vec<String>,
+    /// Set on the source only; the target needs a value for each.
+    pub missing: Vec<String>,
+    /// Set on the target only.
+    pub extra: Vec<String>,
+}
+
+impl SecretDiff {
+    pub fn is_in_sync(&self) -> bool {
+        self.differs.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Compare two apps' secrets by name and digest.
+///
+/// Digests are hashes of the value, so equal digests mean equal values.
+/// A secret whose digest is unknown on either side counts as differing.
+pub fn diff(source: &[Secret], target: &[Secret]) -> SecretDiff {
+    let target_digests: BTreeMap<&str, Option<&str>> = target
+        .iter()
+        .map(|s| (s.name.as_str(), s.digest.as_deref()))
+        .collect();
+    let source_names: BTreeSet<&str> = source.iter().map(|s| s.name.as_str()).collect();
+
+    let mut diff = SecretDiff::default();
+    for secret in source {
+        let name = secret.name.clone();
+        match target_digests.get(secret.name.as_str()) {
+            None => diff.missing.push(name),
+            Some(Some(digest)) if secret.digest.as_deref() == Some(*digest) => {
+                diff.in_sync.push(name)
+            }
+            Some(_) => diff.differs.push(name),
+        }
+    }
+    diff.extra = target_digests
+        .keys()
+        .filter(|name| !source_names.contains(*name))
+        .map(|name| name.to_string())
+        .collect();
+    for names in [&mut diff.in_sync, &mut diff.differs, &mut diff.missing] {
+        names.sort();
+    }
+    diff
+}
@@ -1,33 +1,385 @@
 //! FGP service implementation for Fly.io.
 
 use anyhow::Result;
-use fgp_daemon::service::{HealthStatus, MethodInfo, ParamInfo};
+use chrono::{DateTime, Utc};
+use fgp_daemon::service::{HealthStatus, MethodInfo};
 use fgp_daemon::FgpService;
+use regex::Regex;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::runtime::Runtime;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+use tokio::sync::oneshot;
 
-use crate::api::FlyClient;
+use crate::api::{
+    self, collect_all, Credentials, FlyApi, FlyClient, FlyClientConfig, MachinesClient,
+    MetricsClient, TokenScope, PAGE_SIZE,
+};
+use crate::audit::AuditLog;
+use crate::autoscale::{AutoscaleClients, AutoscaleMetric, AutoscaleRule, Autoscaler};
+use crate::bulk::{self, BulkAction, MachineFilter};
+use crate::cache::ResponseCache;
+use crate::config::{Config, Profile};
+use crate::connection::{Connection, ConnectionStatus, StartMode};
+use crate::deploy::{self, BakeMetrics, CanaryOptions, DeployOptions, Deployments, Strategy};
+use crate::doctor;
+use crate::domains;
+use crate::error::FlyError;
+use crate::events::{self, EventFilter};
+use crate::fleet;
+use crate::fly_toml::{self, FlyToml};
+use crate::image;
+use crate::limits::{CallClass, ConcurrencyLimits, Permits};
+use crate::logs::{self, LogFilter, LogQuery};
+use crate::logship::{self, LogShipHub, Sink};
+use crate::models::{
+    GuestConfig, MachineConfigPatch, MachineCreateConfig, ReleaseList, SecretStatus, Warning,
+};
+use crate::monitor::{self, MonitorOptions};
+use crate::params;
+use crate::postgres;
+use crate::proxy::ProxyHub;
+use crate::reconcile::{self, AppPlan, DesiredConfig};
+use crate::redact;
+use crate::regions;
+use crate::registry::Registry;
+use crate::restart::{self, RestartStrategy};
+use crate::scale;
+use crate::schedule::{Schedule, ScheduleAction, Scheduler};
+use crate::secrets;
+use crate::sizes;
+use crate::state::{self, SavedAutoscale, SavedWatch, StateStore};
+use crate::suggest;
+use crate::summary;
+use crate::usage;
+use crate::watch::{CrashLoopRule, WatchHub};
+use crate::wireguard;
+
+/// Prometheus rejects range queries returning more points than this.
+const MAX_METRIC_POINTS: u64 = 11_000;
+
+/// Most calls accepted by one `fly.batch`.
+const MAX_BATCH_CALLS: usize = 50;
+
+/// Upper bound on `fly.batch` concurrency.
+const MAX_BATCH_CONCURRENCY: usize = 16;
+
+/// API clients authenticated as one profile, each with its own connection
+/// pool, and the profile's background health check.
+struct Clients {
+    client: Arc<dyn FlyApi>,
+    machines: Arc<MachinesClient>,
+    metrics: Arc<MetricsClient>,
+    /// Org slugs whose calls run as this profile.
+    orgs: Vec<String>,
+    /// Configured token scope; inferred from the token when unset.
+    scope: Option<TokenScope>,
+    connection: Connection,
+}
+
+impl Clients {
+    /// Build clients for `profile`, using `api` instead of a `FlyClient` if given.
+    fn new(
+        profile: &Profile,
+        config: &FlyClientConfig,
+        api: Option<Arc<dyn FlyApi>>,
+        health_interval: Duration,
+    ) -> Result<Self> {
+        // One credential store per profile so a refresh reaches every client
+        let credentials = Arc::new(Credentials::new(
+            profile.token.clone(),
+            profile.token_command.clone(),
+        ));
+        let client = match api {
+            Some(api) => api,
+            None => Arc::new(FlyClient::new(credentials.clone(), config)?),
+        };
+        Ok(Self {
+            client,
+            machines: Arc::new(MachinesClient::new(credentials.clone(), config)?),
+            metrics: Arc::new(MetricsClient::new(credentials, config)?),
+            orgs: profile.orgs.clone(),
+            scope: profile.scope,
+            connection: Connection::new(health_interval),
+        })
+    }
+
+    /// What the profile's current token may do.
+    fn scope(&self) -> TokenScope {
+        self.scope
+            .unwrap_or_else(|| TokenScope::infer(self.client.credentials().kind()))
+    }
+}
+
+thread_local! {
+    /// Warnings reported by `run` for the call being handled on this thread.
+    static CALL_WARNINGS: RefCell<Vec<Warning>> = const { RefCell::new(Vec::new()) };
+    /// Per-request timeout override (`timeout_ms`) for the current call.
+    static CALL_TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+    /// Deadline for the whole call (`deadline_ms`), shared by every `run`.
+    static CALL_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    /// Concurrency permits held by the call being handled on this thread.
+    static CALL_PERMITS: RefCell<Option<Permits>> = const { RefCell::new(None) };
+    /// Whether the current call's `org` param picks its profile; false for
+    /// methods whose `org` is the org they act on.
+    static CALL_ORG_ROUTING: Cell<bool> = const { Cell::new(true) };
+    /// Set by a handler that left work running in the background; the call
+    /// is audited when that work finishes instead of when it returns.
+    static CALL_AUDIT_DEFERRED: Cell<bool> = const { Cell::new(false) };
+}
 
 /// FGP service for Fly.io operations.
 pub struct FlyService {
-    client: Arc<FlyClient>,
-    runtime: Runtime,
+    profiles: HashMap<String, Clients>,
+    default_profile: String,
+    /// Runtime the service created, if it wasn't given one.
+    _runtime: Option<Runtime>,
+    runtime: Handle,
+    cache: Arc<ResponseCache>,
+    state: Arc<dyn StateStore>,
+    audit: AuditLog,
+    watches: Arc<WatchHub>,
+    autoscaler: Autoscaler,
+    scheduler: Scheduler,
+    deploys: Arc<Deployments>,
+    proxies: ProxyHub,
+    shippers: LogShipHub,
+    limits: ConcurrencyLimits,
+    start_mode: StartMode,
+    registry: Registry<Self>,
 }
 
 impl FlyService {
-    /// Create a new FlyService with the given API token.
-    pub fn new(token: String) -> Result<Self> {
-        let client = FlyClient::new(token)?;
-        let runtime = Runtime::new()?;
+    /// Create a new FlyService with clients for every configured profile.
+    pub fn new(config: Config) -> Result<Self> {
+        Self::build(config, None, None, state::open_from_env())
+    }
+
+    /// Create a FlyService with one profile per account in `tokens`; see
+    /// `Config::from_tokens`.
+    #[allow(dead_code)] // For embedding; the daemon binary uses `new`
+    pub fn with_tokens(tokens: HashMap<String, String>) -> Result<Self> {
+        Self::new(Config::from_tokens(tokens)?)
+    }
+
+    /// Create a FlyService that runs its requests and background tasks on
+    /// an existing multi-thread runtime instead of starting its own.
+    #[allow(dead_code)] // For embedding; the daemon binary uses `new`
+    pub fn with_runtime(config: Config, runtime: Handle) -> Result<Self> {
+        if runtime.runtime_flavor() != RuntimeFlavor::MultiThread {
+            anyhow::bail!("FlyService needs a multi-thread Tokio runtime");
+        }
+        Self::build(config, None, Some(runtime), state::open_from_env())
+    }
+
+    /// Create a FlyService whose GraphQL calls go to `api` for every
+    /// profile, e.g. a `MockFlyApi`. Its state is kept in memory.
+    #[cfg(feature = "test-support")]
+    #[allow(dead_code)] // Used by tests only
+    pub fn with_api(config: Config, api: Arc<dyn FlyApi>) -> Result<Self> {
+        let state = Arc::new(state::MemoryStore::default());
+        Self::build(config, Some(api), None, state)
+    }
+
+    /// Create a FlyService whose state is kept in memory, e.g. one whose
+    /// `config.client` points at a `FakeFly`.
+    #[cfg(feature = "test-support")]
+    #[allow(dead_code)] // Used by tests only
+    pub fn in_memory(config: Config) -> Result<Self> {
+        let state = Arc::new(state::MemoryStore::default());
+        Self::build(config, None, None, state)
+    }
+
+    fn build(
+        config: Config,
+        api: Option<Arc<dyn FlyApi>>,
+        runtime: Option<Handle>,
+        state: Arc<dyn StateStore>,
+    ) -> Result<Self> {
+        let profiles = config
+            .profiles
+            .iter()
+            .map(|(name, profile)| {
+                let clients =
+                    Clients::new(profile, &config.client, api.clone(), config.health_interval)?;
+                Ok((name.clone(), clients))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        let (owned, runtime) = match runtime {
+            Some(handle) => (None, handle),
+            None => {
+                let owned = Runtime::new()?;
+                let handle = owned.handle().clone();
+                (Some(owned), handle)
+            }
+        };
+        let watches = Arc::new(WatchHub::default());
+        let audit = AuditLog::new(state.clone());
+        let scheduler = Scheduler::new(state.clone());
+
+        #[cfg(feature = "prometheus")]
+        if let Some(addr) = crate::exporter::addr_from_env()? {
+            crate::exporter::spawn(&runtime, addr, watches.clone())?;
+        }
+        #[cfg(not(feature = "prometheus"))]
+        if std::env::var_os("FGP_FLY_METRICS_ADDR").is_some() {
+            tracing::warn!(
+                "FGP_FLY_METRICS_ADDR is set but fgp-fly was built without the prometheus feature"
+            );
+        }
 
         Ok(Self {
-            client: Arc::new(client),
+            profiles,
+            default_profile: config.default_profile,
+            _runtime: owned,
             runtime,
+            cache: Arc::default(),
+            state,
+            audit,
+            watches,
+            autoscaler: Autoscaler::default(),
+            scheduler,
+            deploys: Arc::new(Deployments::default()),
+            proxies: ProxyHub::default(),
+            shippers: LogShipHub::default(),
+            limits: ConcurrencyLimits::new(&config.limits),
+            start_mode: config.start_mode,
+            registry: Self::registry(),
+        })
+    }
+
+    /// Profile a call runs as: the `profile` (or `account`) param, else the
+    /// profile serving its `org`, else the default profile.
+    ///
+    /// When several profiles serve the org, the default profile wins if it
+    /// is one of them, otherwise the first by name.
+    fn profile_name<'a>(&'a self, params: &'a HashMap<String, Value>) -> &'a str {
+        if let Some(name) =
+            Self::get_param_str(params, "profile").or(Self::get_param_str(params, "account"))
+        {
+            return name;
+        }
+        let org = Self::get_param_str(params, "org").filter(|_| CALL_ORG_ROUTING.with(Cell::get));
+        let Some(org) = org else {
+            return &self.default_profile;
+        };
+        let mut serving: Vec<&str> = self
+            .profiles
+            .iter()
+            .filter(|(_, clients)| clients.orgs.iter().any(|o| o == org))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if serving.contains(&self.default_profile.as_str()) {
+            return &self.default_profile;
+        }
+        serving.sort_unstable();
+        serving.first().copied().unwrap_or(&self.default_profile)
+    }
+
+    /// Clients for the profile the call runs as.
+    fn clients(&self, params: &HashMap<String, Value>) -> Result<&Clients> {
+        let name = self.profile_name(params);
+        self.profiles.get(name).ok_or_else(|| {
+            let mut known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            known.sort_unstable();
+            anyhow::anyhow!(
+                "Unknown profile: {}. Configured profiles are: {}",
+                name,
+                known.join(", ")
+            )
         })
     }
 
+    /// Background health of every profile, by name.
+    fn account_statuses(&self) -> BTreeMap<&str, ConnectionStatus> {
+        self.profiles
+            .iter()
+            .map(|(name, clients)| (name.as_str(), clients.connection.status()))
+            .collect()
+    }
+
+    /// Clients for the default profile.
+    fn default_clients(&self) -> &Clients {
+        &self.profiles[&self.default_profile]
+    }
+
+    /// Run a future on the shared runtime and wait for its result.
+    ///
+    /// The future is spawned onto the worker pool rather than driven by
+    /// `block_on` on the calling thread, so concurrent dispatches make
+    /// progress in parallel and slow calls don't stall health checks.
+    ///
+    /// Callers already on a multi-thread runtime worker wait through
+    /// `block_in_place`; a current-thread runtime can't wait without
+    /// deadlocking, so calls from one fail instead of panicking.
+    fn run<F, T>(&self, future: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let in_runtime = match Handle::try_current() {
+            Ok(current) if current.runtime_flavor() == RuntimeFlavor::MultiThread => true,
+            Ok(_) => anyhow::bail!(
+                "FlyService can't block a current-thread runtime; call it from spawn_blocking"
+            ),
+            Err(_) => false,
+        };
+        let timeout = CALL_TIMEOUT.with(Cell::get);
+        let deadline = CALL_DEADLINE.with(Cell::get);
+
+        let (tx, rx) = oneshot::channel();
+        self.runtime.spawn(async move {
+            let future = api::with_request_timeout(timeout, future);
+            let limited = async move {
+                match deadline {
+                    Some(deadline) => {
+                        tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), future)
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(
+                                    FlyError::Unavailable("Call exceeded its deadline_ms".into())
+                                        .into(),
+                                )
+                            })
+                    }
+                    None => future.await,
+                }
+            };
+            let _ = tx.send(api::with_warnings(limited).await);
+        });
+
+        let received = if in_runtime {
+            tokio::task::block_in_place(|| rx.blocking_recv())
+        } else {
+            rx.blocking_recv()
+        };
+        let (result, warnings) =
+            received.map_err(|_| anyhow::anyhow!("Request task was cancelled"))?;
+        CALL_WARNINGS.with(|w| w.borrow_mut().extend(warnings));
+        result
+    }
+
+    /// Read `timeout_ms` and `deadline_ms` for the call about to be routed.
+    fn set_call_limits(params: &HashMap<String, Value>) -> Result<()> {
+        let millis = |key: &str| -> Result<Option<Duration>> {
+            match params.get(key).and_then(|v| v.as_i64()) {
+                Some(ms) if ms > 0 => Ok(Some(Duration::from_millis(ms as u64))),
+                Some(_) => anyhow::bail!("{} must be greater than 0", key),
+                None => Ok(None),
+            }
+        };
+        let timeout = millis("timeout_ms")?;
+        let deadline = millis("deadline_ms")?.map(|ms| Instant::now() + ms);
+
+        CALL_TIMEOUT.with(|t| t.set(timeout));
+        CALL_DEADLINE.with(|d| d.set(deadline));
+        Ok(())
+    }
+
     /// Helper to get a u32 parameter with default.
     fn get_param_i32(params: &HashMap<String, Value>, key: &str, default: i32) -> i32 {
         params
@@ -42,31 +394,179 @@ impl FlyService {
         params.get(key).and_then(|v| v.as_str())
     }
 
+    /// Helper to get a bool parameter with default.
+    fn get_param_bool(params: &HashMap<String, Value>, key: &str, default: bool) -> bool {
+        params.get(key).and_then(|v| v.as_bool()).unwrap_or(default)
+    }
+
+    /// Sort and trim the list under `key` per the `sort_by` and `fields`
+    /// params.
+    ///
+    /// `sort_keys` maps each `sort_by` value to the JSON pointer it sorts
+    /// on; `created` sorts newest first, anything else ascending, and items
+    /// missing the field go last. `fields` is a comma-separated list of
+    /// item fields to keep, with dots for nested ones (`config.image`).
+    fn shape_list(
+        value: &mut Value,
+        key: &str,
+        sort_keys: &[(&str, &str)],
+        params: &HashMap<String, Value>,
+    ) -> Result<()> {
+        let Some(Value::Array(items)) = value.get_mut(key) else {
+            return Ok(());
+        };
+
+        if let Some(sort_by) = Self::get_param_str(params, "sort_by") {
+            let Some((_, pointer)) = sort_keys.iter().find(|(name, _)| *name == sort_by) else {
+                let valid: Vec<&str> = sort_keys.iter().map(|(name, _)| *name).collect();
+                anyhow::bail!(
+                    "Invalid sort_by: {}. Valid values are: {}",
+                    sort_by,
+                    valid.join(", ")
+                );
+            };
+            let newest_first = sort_by == "created";
+            items.sort_by(|a, b| {
+                let present = |v: &Value| v.pointer(pointer).filter(|v| !v.is_null()).cloned();
+                match (present(a), present(b)) {
+                    (Some(a), Some(b)) => {
+                        let order = match (a.as_f64(), b.as_f64()) {
+                            (Some(a), Some(b)) => a.total_cmp(&b),
+                            _ => a
+                                .as_str()
+                                .unwrap_or_default()
+                                .cmp(b.as_str().unwrap_or_default()),
+                        };
+                        if newest_first {
+                            order.reverse()
+                        } else {
+                            order
+                        }
+                    }
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => std::cmp::Ordering::Equal,
+                }
+            });
+        }
+
+        if let Some(fields) = Self::get_param_str(params, "fields") {
+            let fields: Vec<&str> = fields
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .collect();
+            for item in items.iter_mut() {
+                let mut projected = Value::Object(Default::default());
+                for field in &fields {
+                    let Some(found) = item.pointer(&format!("/{}", field.replace('.', "/"))) else {
+                        continue;
+                    };
+                    let mut target = &mut projected;
+                    for segment in field.split('.') {
+                        target = target
+                            .as_object_mut()
+                            .expect("projection nodes are objects")
+                            .entry(segment)
+                            .or_insert_with(|| Value::Object(Default::default()));
+                    }
+                    *target = found.clone();
+                }
+                *item = projected;
+            }
+        }
+        Ok(())
+    }
+
     /// Health check implementation.
-    fn health(&self) -> Result<Value> {
-        let client = self.client.clone();
-        let ok = self.runtime.block_on(async move { client.ping().await })?;
+    fn health(&self, params: HashMap<String, Value>) -> Result<Value> {
+        // Still connecting after a lazy start; don't wait on a dead API
+        let clients = self.clients(&params)?;
+        if self.start_mode == StartMode::Lazy && !clients.connection.is_connected() {
+            return Ok(serde_json::json!({
+                "status": "degraded",
+                "api_connected": false,
+                "version": env!("CARGO_PKG_VERSION"),
+                "start_mode": self.start_mode,
+                "connection": clients.connection.status(),
+                "accounts": self.account_statuses(),
+                "concurrency": self.limits.stats(),
+            }));
+        }
+
+        let client = clients.client.clone();
+        let ok = self.run(async move { client.ping().await })?;
 
         Ok(serde_json::json!({
             "status": if ok { "healthy" } else { "unhealthy" },
             "api_connected": ok,
             "version": env!("CARGO_PKG_VERSION"),
+            "start_mode": self.start_mode,
+            "connection": clients.connection.status(),
+            "accounts": self.account_statuses(),
+            "concurrency": self.limits.stats(),
         }))
     }
 
     /// List apps implementation.
     fn list_apps(&self, params: HashMap<String, Value>) -> Result<Value> {
         let limit = Self::get_param_i32(&params, "limit", 25);
-        let client = self.client.clone();
+        let cursor = Self::get_param_str(&params, "cursor").map(String::from);
+        let all = Self::get_param_bool(&params, "all", false);
+        let org = Self::get_param_str(&params, "org").map(String::from);
+        let name_prefix = Self::get_param_str(&params, "name_prefix").map(String::from);
+        let status = Self::get_param_str(&params, "status").map(String::from);
+        let client = self.clients(&params)?.client.clone();
 
-        let apps = self
-            .runtime
-            .block_on(async move { client.list_apps(Some(limit)).await })?;
+        let page = self.run(async move {
+            // The API filters by org ID; the rest is filtered here
+            let org_id = match &org {
+                Some(slug) => Some(client.get_organization(slug).await?.id),
+                None => None,
+            };
+            let org_id = org_id.as_deref();
 
-        Ok(serde_json::json!({
-            "apps": apps,
-            "count": apps.len(),
-        }))
+            let mut page = if all {
+                let client = &client;
+                collect_all(|cursor| async move {
+                    client
+                        .list_apps(Some(PAGE_SIZE), cursor.as_deref(), org_id)
+                        .await
+                })
+                .await?
+            } else {
+                client
+                    .list_apps(Some(limit), cursor.as_deref(), org_id)
+                    .await?
+            };
+            page.items.retain(|app| {
+                name_prefix
+                    .as_deref()
+                    .is_none_or(|prefix| app.name.starts_with(prefix))
+                    && status.as_deref().is_none_or(|status| app.status == status)
+            });
+
+            // Apps the token couldn't read come back as `warnings`
+            api::report_warnings(std::mem::take(&mut page.warnings));
+            Ok(page)
+        })?;
+
+        let mut value = serde_json::json!({
+            "count": page.items.len(),
+            "apps": page.items,
+            "next_cursor": page.next_cursor,
+        });
+        Self::shape_list(
+            &mut value,
+            "apps",
+            &[
+                ("name", "/name"),
+                ("created", "/created_at"),
+                ("status", "/status"),
+            ],
+            &params,
+        )?;
+        Ok(value)
     }
 
     /// Get app status implementation.
@@ -75,13 +575,70 @@ impl FlyService {
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
             .to_string();
 
-        let client = self.client.clone();
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
 
-        let status = self
-            .runtime
-            .block_on(async move { client.get_app_status(&app_name).await })?;
+        if Self::get_param_bool(&params, "summary", false) {
+            let machines = clients.machines.clone();
+            let rollup = self.run(async move {
+                let (status, machines) = futures::try_join!(
+                    client.get_app_status(&app_name),
+                    machines.list_machines(&app_name),
+                )?;
+                Ok(summary::summarize(&status.app, &machines))
+            })?;
+            return Ok(serde_json::to_value(rollup)?);
+        }
+
+        let status = self.run(async move { client.get_app_status(&app_name).await })?;
+
+        Ok(serde_json::to_value(status)?)
+    }
+
+    /// List releases implementation.
+    fn list_releases(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let limit = Self::get_param_i32(&params, "limit", 25);
+        let cursor = Self::get_param_str(&params, "cursor").map(String::from);
+        let all = Self::get_param_bool(&params, "all", false);
+
+        let client = self.clients(&params)?.client.clone();
+
+        let releases = self.run(async move {
+            let mut page = if all {
+                let client = &client;
+                let app_name = &app_name;
+                collect_all(|cursor| async move {
+                    client
+                        .list_releases(app_name, Some(PAGE_SIZE), cursor.as_deref())
+                        .await
+                })
+                .await?
+            } else {
+                client
+                    .list_releases(&app_name, Some(limit), cursor.as_deref())
+                    .await?
+            };
+            api::report_warnings(std::mem::take(&mut page.warnings));
 
-        Ok(status)
+            anyhow::Ok(ReleaseList {
+                app: app_name,
+                count: page.items.len(),
+                releases: page.items,
+                next_cursor: page.next_cursor,
+            })
+        })?;
+
+        let mut value = serde_json::to_value(releases)?;
+        Self::shape_list(
+            &mut value,
+            "releases",
+            &[("created", "/created_at"), ("status", "/status")],
+            &params,
+        )?;
+        Ok(value)
     }
 
     /// List machines implementation.
@@ -90,56 +647,276 @@ impl FlyService {
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
             .to_string();
 
-        let client = self.client.clone();
+        let detailed = Self::get_param_bool(&params, "detailed", false);
 
-        let machines = self
-            .runtime
-            .block_on(async move { client.list_machines(&app_name).await })?;
+        let machines = if detailed {
+            let machines_client = self.clients(&params)?.machines.clone();
+            self.run(async move { machines_client.list_machines(&app_name).await })?
+        } else {
+            let client = self.clients(&params)?.client.clone();
+            self.run(async move { client.list_machines(&app_name).await })?
+        };
 
-        Ok(serde_json::json!({
+        let mut value = serde_json::json!({
             "machines": machines,
             "count": machines.len(),
+        });
+        Self::shape_list(
+            &mut value,
+            "machines",
+            &[
+                ("name", "/name"),
+                ("created", "/created_at"),
+                ("status", "/state"),
+            ],
+            &params,
+        )?;
+        Ok(value)
+    }
+
+    /// Logs implementation (single fetch or long-poll follow).
+    fn get_logs(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+
+        let time = |name: &str| -> Result<Option<DateTime<Utc>>> {
+            Self::get_param_str(&params, name)
+                .map(|value| {
+                    DateTime::parse_from_rfc3339(value)
+                        .map(|at| at.with_timezone(&Utc))
+                        .map_err(|e| anyhow::anyhow!("Invalid {}: {} ({})", name, value, e))
+                })
+                .transpose()
+        };
+        let filter = LogFilter {
+            level: Self::get_param_str(&params, "level").map(str::to_ascii_lowercase),
+            message: Self::get_param_str(&params, "pattern")
+                .map(|pattern| {
+                    Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid pattern: {}", e))
+                })
+                .transpose()?,
+            start: time("start")?,
+            end: time("end")?,
+        };
+        let format = Self::get_param_str(&params, "format").unwrap_or("json");
+
+        let query = LogQuery {
+            app: app_name,
+            region: Self::get_param_str(&params, "region").map(String::from),
+            instance: Self::get_param_str(&params, "instance").map(String::from),
+            cursor: Self::get_param_str(&params, "cursor").map(String::from),
+            follow: Self::get_param_bool(&params, "follow", false),
+            wait: std::time::Duration::from_secs(
+                Self::get_param_i32(&params, "wait_secs", 30).clamp(0, 300) as u64,
+            ),
+            filter,
+        };
+
+        let client = self.clients(&params)?.client.clone();
+
+        let page = self.run(async move { logs::fetch_logs(&*client, &query).await })?;
+
+        if format == "text" {
+            let lines: Vec<String> = page.entries.iter().map(logs::format_line).collect();
+            return Ok(serde_json::json!({
+                "count": lines.len(),
+                "text": lines.join("\n"),
+                "cursor": page.next_token,
+            }));
+        }
+        Ok(serde_json::json!({
+            "count": page.entries.len(),
+            "entries": page.entries,
+            "cursor": page.next_token,
         }))
     }
 
     /// Get user info implementation.
-    fn get_user(&self) -> Result<Value> {
-        let client = self.client.clone();
+    fn get_user(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let client = self.clients(&params)?.client.clone();
+
+        let token = serde_json::json!({
+            "type": client.credentials().kind(),
+            "expires_at": client.credentials().expires_at().map(|at| at.to_rfc3339()),
+        });
 
-        let user = self
-            .runtime
-            .block_on(async move { client.get_user().await })?;
+        let user = self.run(async move { client.get_user().await })?;
+        let mut user = serde_json::to_value(user)?;
+        user["token"] = token;
 
         Ok(user)
     }
 
+    /// Token introspection: its type and scope, who it acts as, and the
+    /// orgs it can see with the viewer's role in each.
+    ///
+    /// Macaroon tokens may have no user behind them; `user` is then null.
+    fn whoami(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let profile = self.profile_name(&params).to_string();
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let scope = clients.scope();
+
+        let token = serde_json::json!({
+            "type": client.credentials().kind(),
+            "scope": scope,
+            "can_mutate": scope.can_mutate(),
+            "expires_at": client.credentials().expires_at().map(|at| at.to_rfc3339()),
+        });
+
+        let (user, orgs) = self.run(async move {
+            let (user, orgs) = futures::join!(client.get_user(), client.list_organizations());
+            anyhow::Ok((user.ok(), orgs?))
+        })?;
+
+        Ok(serde_json::json!({
+            "profile": profile,
+            "token": token,
+            "user": user.map(|u| serde_json::json!({
+                "id": u.id,
+                "email": u.email,
+                "name": u.name,
+            })),
+            "organizations": orgs
+                .into_iter()
+                .map(|o| serde_json::json!({
+                    "slug": o.slug,
+                    "name": o.name,
+                    "type": o.org_type,
+                    "role": o.viewer_role,
+                }))
+                .collect::<Vec<_>>(),
+        }))
+    }
+
+    /// Get viewer ID implementation.
+    fn viewer_id(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let client = self.clients(&params)?.client.clone();
+
+        let id = self.run(async move { client.get_viewer_id().await })?;
+
+        Ok(serde_json::json!({ "id": id }))
+    }
+
     /// List regions implementation.
-    fn list_regions(&self) -> Result<Value> {
-        let client = self.client.clone();
+    fn list_regions(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app").map(String::from);
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+
+        if let Some(app_name) = app_name {
+            let machines = clients.machines.clone();
+            let placement = self
+                .run(async move { regions::app_placement(&*client, &machines, &app_name).await })?;
+            return Ok(serde_json::to_value(placement)?);
+        }
+
+        let regions = self.run(async move { client.list_regions().await })?;
+
+        Ok(serde_json::json!({
+            "regions": regions,
+            "count": regions.len(),
+        }))
+    }
+
+    /// VM presets with the memory sizes each accepts and pricing hints.
+    fn platform_sizes(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let cpu_kind = Self::get_param_str(&params, "cpu_kind").map(String::from);
+        let client = self.clients(&params)?.client.clone();
+
+        let sizes = self.run(async move { client.list_vm_sizes().await })?;
+        let catalog = sizes::catalog(sizes, cpu_kind.as_deref());
+
+        Ok(serde_json::json!({
+            "count": catalog.sizes.len(),
+            "sizes": catalog.sizes,
+            "extra_memory_price_gb_month": catalog.extra_memory_price_gb_month,
+        }))
+    }
+
+    /// Monthly cost estimate for an app, by resource.
+    fn app_usage(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let machines = clients.machines.clone();
+
+        let usage = self.run(async move {
+            let (app_machines, volumes, ips) = futures::try_join!(
+                client.list_machines_with_config(&app_name),
+                machines.list_volumes(&app_name),
+                client.list_ips(&app_name),
+            )?;
+            anyhow::Ok(usage::estimate(&app_name, &app_machines, &volumes, &ips))
+        })?;
 
-        let regions = self
-            .runtime
-            .block_on(async move { client.list_regions().await })?;
+        Ok(serde_json::to_value(usage)?)
+    }
 
-        Ok(regions)
+    /// Restart an app so its machines pick up changed secrets, unless staged.
+    async fn deploy_secrets(
+        machines: &MachinesClient,
+        app_name: &str,
+        deploy: bool,
+    ) -> Result<Option<Vec<String>>> {
+        if !deploy {
+            return Ok(None);
+        }
+        Ok(Some(secrets::deploy(machines, app_name).await?))
     }
 
-    /// Secrets implementation (list/set/delete).
+    /// Secrets implementation (list/set/delete/set_many/unset_many/import/
+    /// export_names/sync/deploy).
+    ///
+    /// Changes are staged unless `deploy: true`; staged changes are rolled
+    /// out together with `action: "deploy"`.
     fn handle_secrets(&self, params: HashMap<String, Value>) -> Result<Value> {
         let app_name = Self::get_param_str(&params, "app")
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
             .to_string();
 
         let action = Self::get_param_str(&params, "action").unwrap_or("list");
+        let deploy = Self::get_param_bool(&params, "deploy", false);
+        if deploy && Self::get_param_bool(&params, "stage", false) {
+            anyhow::bail!("Pass either stage or deploy, not both");
+        }
+        let stage = !deploy;
 
-        let client = self.client.clone();
+        let client = self.clients(&params)?.client.clone();
+        let machines = self.clients(&params)?.machines.clone();
 
         match action {
             "list" => {
-                let result = self
-                    .runtime
-                    .block_on(async move { client.list_secrets(&app_name).await })?;
-                Ok(result)
+                let secrets = self.run(async move {
+                    let (secrets, machines) = futures::try_join!(
+                        client.list_secrets(&app_name),
+                        machines.list_machines(&app_name),
+                    )?;
+                    // Machines pick up secrets when they are next updated
+                    let last_update = machines
+                        .iter()
+                        .filter_map(|m| m.updated_at.as_deref())
+                        .filter_map(|t| DateTime::parse_from_rfc3339(t).ok())
+                        .map(|t| t.with_timezone(&Utc))
+                        .max();
+                    Ok(secrets
+                        .into_iter()
+                        .map(|s| s.with_status(last_update))
+                        .collect::<Vec<_>>())
+                })?;
+                let staged: Vec<&str> = secrets
+                    .iter()
+                    .filter(|s| s.status == Some(SecretStatus::Staged))
+                    .map(|s| s.name.as_str())
+                    .collect();
+                Ok(serde_json::json!({
+                    "secrets": secrets,
+                    "count": secrets.len(),
+                    "staged": staged,
+                }))
             }
             "set" => {
                 let key = Self::get_param_str(&params, "key")
@@ -153,11 +930,15 @@ impl FlyService {
                     })?
                     .to_string();
 
-                let result = self
-                    .runtime
-                    .block_on(async move { client.set_secret(&app_name, &key, &value).await })?;
+                let (result, deployed) = self.run(async move {
+                    let result = client.set_secret(&app_name, &key, &value).await?;
+                    let deployed = Self::deploy_secrets(&machines, &app_name, deploy).await?;
+                    Ok((result, deployed))
+                })?;
                 Ok(serde_json::json!({
                     "set": true,
+                    "staged": stage,
+                    "deployed": deployed,
                     "result": result
                 }))
             }
@@ -168,193 +949,3917 @@ impl FlyService {
                     })?
                     .to_string();
 
-                let result = self
-                    .runtime
-                    .block_on(async move { client.delete_secret(&app_name, &key).await })?;
+                let (result, deployed) = self.run(async move {
+                    let result = client.delete_secret(&app_name, &key).await?;
+                    let deployed = Self::deploy_secrets(&machines, &app_name, deploy).await?;
+                    Ok((result, deployed))
+                })?;
                 Ok(serde_json::json!({
                     "deleted": true,
+                    "staged": stage,
+                    "deployed": deployed,
+                    "result": result
+                }))
+            }
+            "set_many" => {
+                let secrets = Self::secret_pairs(&params)?.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: secrets for action=set_many")
+                })?;
+                if secrets.is_empty() {
+                    anyhow::bail!("secrets must contain at least one entry");
+                }
+                let keys: Vec<String> = secrets.iter().map(|(key, _)| key.clone()).collect();
+
+                let (result, deployed) = self.run(async move {
+                    let result = client.set_secrets(&app_name, &secrets).await?;
+                    let deployed = Self::deploy_secrets(&machines, &app_name, deploy).await?;
+                    Ok((result, deployed))
+                })?;
+                Ok(serde_json::json!({
+                    "set": keys,
+                    "staged": stage,
+                    "deployed": deployed,
+                    "result": result
+                }))
+            }
+            "unset_many" => {
+                let keys = params
+                    .get("keys")
+                    .and_then(|v| v.as_array())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: keys for action=unset_many")
+                    })?
+                    .iter()
+                    .map(|key| {
+                        key.as_str()
+                            .map(String::from)
+                            .ok_or_else(|| anyhow::anyhow!("keys must be an array of strings"))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if keys.is_empty() {
+                    anyhow::bail!("keys must contain at least one entry");
+                }
+
+                let unset = keys.clone();
+                let (result, deployed) = self.run(async move {
+                    let result = client.unset_secrets(&app_name, &unset).await?;
+                    let deployed = Self::deploy_secrets(&machines, &app_name, deploy).await?;
+                    Ok((result, deployed))
+                })?;
+                Ok(serde_json::json!({
+                    "deleted": keys,
+                    "staged": stage,
+                    "deployed": deployed,
+                    "result": result
+                }))
+            }
+            "import" => {
+                let content = Self::get_param_str(&params, "content").ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: content for action=import")
+                })?;
+                let format = Self::get_param_str(&params, "format").unwrap_or("auto");
+                let secrets = secrets::parse(content, secrets::Format::detect(format, content)?)?;
+                let keys: Vec<String> = secrets.iter().map(|(key, _)| key.clone()).collect();
+
+                let (result, deployed) = self.run(async move {
+                    let result = client.set_secrets(&app_name, &secrets).await?;
+                    let deployed = Self::deploy_secrets(&machines, &app_name, deploy).await?;
+                    Ok((result, deployed))
+                })?;
+                Ok(serde_json::json!({
+                    "imported": keys,
+                    "count": keys.len(),
+                    "staged": stage,
+                    "deployed": deployed,
                     "result": result
                 }))
             }
+            "export_names" => {
+                let secrets = self.run(async move { client.list_secrets(&app_name).await })?;
+                let names: Vec<&str> = secrets.iter().map(|s| s.name.as_str()).collect();
+                Ok(serde_json::json!({
+                    "names": names,
+                    "secrets": secrets,
+                    "count": secrets.len(),
+                }))
+            }
+            "sync" => {
+                let target = Self::get_param_str(&params, "target")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: target for action=sync")
+                    })?
+                    .to_string();
+                if target == app_name {
+                    anyhow::bail!("target must be a different app than app");
+                }
+                let values = Self::secret_pairs(&params)?.unwrap_or_default();
+
+                let (source_app, target_app) = (app_name.clone(), target.clone());
+                let list_client = client.clone();
+                let diff = self.run(async move {
+                    let (source, target) = futures::try_join!(
+                        list_client.list_secrets(&source_app),
+                        list_client.list_secrets(&target_app),
+                    )?;
+                    Ok(secrets::diff(&source, &target))
+                })?;
+
+                // Values may only fill in names the source app has
+                let copyable: BTreeSet<&str> = diff
+                    .missing
+                    .iter()
+                    .chain(&diff.differs)
+                    .map(String::as_str)
+                    .collect();
+                if let Some((key, _)) = values.iter().find(|(key, _)| !copyable.contains(key.as_str())) {
+                    anyhow::bail!(
+                        "Secret {} is not missing from or different on {}; only those can be synced",
+                        key,
+                        target
+                    );
+                }
+                let synced: Vec<String> = values.iter().map(|(key, _)| key.clone()).collect();
+                let needs_value: Vec<&String> = diff
+                    .missing
+                    .iter()
+                    .filter(|name| !synced.contains(name))
+                    .collect();
+
+                let (result, deployed) = if values.is_empty() {
+                    (Value::Null, None)
+                } else {
+                    let target = target.clone();
+                    self.run(async move {
+                        let result = client.set_secrets(&target, &values).await?;
+                        let deployed = Self::deploy_secrets(&machines, &target, deploy).await?;
+                        Ok((result, deployed))
+                    })?
+                };
+                Ok(serde_json::json!({
+                    "source": app_name,
+                    "target": target,
+                    "in_sync": diff.is_in_sync() && needs_value.is_empty() && synced.is_empty(),
+                    "diff": diff,
+                    "synced": synced,
+                    "needs_value": needs_value,
+                    "staged": stage,
+                    "deployed": deployed,
+                    "result": result,
+                }))
+            }
+            "deploy" => {
+                let updated = self.run(async move { secrets::deploy(&machines, &app_name).await })?;
+                Ok(serde_json::json!({
+                    "deployed": true,
+                    "machines": updated,
+                    "count": updated.len(),
+                }))
+            }
             _ => anyhow::bail!(
-                "Unknown action: {}. Valid actions are: list, set, delete",
+                "Unknown action: {}. Valid actions are: list, set, delete, set_many, unset_many, import, export_names, sync, deploy",
                 action
             ),
         }
     }
 
-    /// Restart app implementation.
-    fn restart_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+    /// The `secrets` param as `(name, value)` pairs, if present.
+    fn secret_pairs(params: &HashMap<String, Value>) -> Result<Option<Vec<(String, String)>>> {
+        let Some(secrets) = params.get("secrets").filter(|v| !v.is_null()) else {
+            return Ok(None);
+        };
+        let secrets = secrets
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("secrets must be an object of names to values"))?;
+        secrets
+            .iter()
+            .map(|(key, value)| match value.as_str() {
+                Some(value) => Ok((key.clone(), value.to_string())),
+                None => Err(anyhow::anyhow!("Secret {} must be a string", key)),
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+
+    /// Volumes implementation (list/create/extend/delete/snapshots).
+    fn handle_volumes(&self, params: HashMap<String, Value>) -> Result<Value> {
         let app_name = Self::get_param_str(&params, "app")
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
             .to_string();
 
-        let client = self.client.clone();
+        let action = Self::get_param_str(&params, "action").unwrap_or("list");
 
-        let result = self
-            .runtime
-            .block_on(async move { client.restart_app(&app_name).await })?;
+        let machines = self.clients(&params)?.machines.clone();
 
-        Ok(serde_json::json!({
-            "restarted": true,
-            "result": result
-        }))
-    }
-}
+        let volume_id = || {
+            Self::get_param_str(&params, "volume_id")
+                .map(String::from)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Missing required parameter: volume_id for action={}",
+                        action
+                    )
+                })
+        };
+        let size_gb = || {
+            params
+                .get("size_gb")
+                .and_then(|v| v.as_u64())
+                .filter(|s| *s > 0)
+                .map(|s| s as u32)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: size_gb for action={}", action)
+                })
+        };
 
-impl FgpService for FlyService {
-    fn name(&self) -> &str {
-        "fly"
-    }
+        match action {
+            "list" => {
+                let volumes = self.run(async move { machines.list_volumes(&app_name).await })?;
+                Ok(serde_json::json!({
+                    "volumes": volumes,
+                    "count": volumes.len(),
+                }))
+            }
+            "create" => {
+                let name = Self::get_param_str(&params, "name")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: name for action=create")
+                    })?
+                    .to_string();
+                let region = Self::get_param_str(&params, "region")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: region for action=create")
+                    })?
+                    .to_string();
+                let size_gb = size_gb()?;
 
-    fn version(&self) -> &str {
-        env!("CARGO_PKG_VERSION")
-    }
+                let volume = self.run(async move {
+                    machines
+                        .create_volume(&app_name, &name, &region, size_gb)
+                        .await
+                })?;
+                Ok(serde_json::json!({
+                    "created": true,
+                    "volume": volume
+                }))
+            }
+            "extend" => {
+                let volume_id = volume_id()?;
+                let size_gb = size_gb()?;
 
-    fn dispatch(&self, method: &str, params: HashMap<String, Value>) -> Result<Value> {
-        match method {
-            "health" => self.health(),
-            "apps" | "fly.apps" => self.list_apps(params),
-            "status" | "fly.status" => self.app_status(params),
-            "machines" | "fly.machines" => self.list_machines(params),
-            "user" | "fly.user" => self.get_user(),
-            "regions" | "fly.regions" => self.list_regions(),
-            "secrets" | "fly.secrets" => self.handle_secrets(params),
-            "restart" | "fly.restart" => self.restart_app(params),
-            _ => anyhow::bail!("Unknown method: {}", method),
-        }
-    }
+                let result = self.run(async move {
+                    machines.extend_volume(&app_name, &volume_id, size_gb).await
+                })?;
+                Ok(serde_json::json!({
+                    "extended": true,
+                    "result": result
+                }))
+            }
+            "delete" => {
+                let volume_id = volume_id()?;
 
-    fn method_list(&self) -> Vec<MethodInfo> {
-        vec![
-            MethodInfo {
-                name: "fly.apps".into(),
-                description: "List all Fly.io apps".into(),
-                params: vec![ParamInfo {
-                    name: "limit".into(),
-                    param_type: "integer".into(),
-                    required: false,
-                    default: Some(serde_json::json!(25)),
-                }],
-            },
-            MethodInfo {
-                name: "fly.status".into(),
-                description: "Get status for a specific app".into(),
-                params: vec![ParamInfo {
-                    name: "app".into(),
-                    param_type: "string".into(),
-                    required: true,
-                    default: None,
-                }],
-            },
-            MethodInfo {
-                name: "fly.machines".into(),
-                description: "List machines for an app".into(),
-                params: vec![ParamInfo {
-                    name: "app".into(),
-                    param_type: "string".into(),
-                    required: true,
-                    default: None,
-                }],
-            },
-            MethodInfo {
-                name: "fly.user".into(),
-                description: "Get current user info".into(),
-                params: vec![],
-            },
-            MethodInfo {
-                name: "fly.regions".into(),
-                description: "List all Fly.io regions".into(),
-                params: vec![],
-            },
-            MethodInfo {
-                name: "fly.secrets".into(),
-                description: "Manage secrets for an app".into(),
-                params: vec![
-                    ParamInfo {
-                        name: "app".into(),
-                        param_type: "string".into(),
-                        required: true,
-                        default: None,
-                    },
-                    ParamInfo {
-                        name: "action".into(),
-                        param_type: "string".into(),
-                        required: false,
-                        default: Some(serde_json::json!("list")),
-                    },
-                    ParamInfo {
-                        name: "key".into(),
-                        param_type: "string".into(),
-                        required: false,
-                        default: None,
-                    },
-                    ParamInfo {
-                        name: "value".into(),
-                        param_type: "string".into(),
-                        required: false,
-                        default: None,
-                    },
-                ],
-            },
-            MethodInfo {
-                name: "fly.restart".into(),
-                description: "Restart all machines for an app".into(),
-                params: vec![ParamInfo {
-                    name: "app".into(),
-                    param_type: "string".into(),
-                    required: true,
-                    default: None,
-                }],
-            },
-        ]
-    }
+                let volume =
+                    self.run(async move { machines.delete_volume(&app_name, &volume_id).await })?;
+                Ok(serde_json::json!({
+                    "deleted": true,
+                    "volume": volume
+                }))
+            }
+            "snapshots" => {
+                let volume_id = volume_id()?;
 
-    fn on_start(&self) -> Result<()> {
-        tracing::info!("FlyService starting, verifying API connection...");
-        let client = self.client.clone();
-        self.runtime.block_on(async move {
-            match client.ping().await {
-                Ok(true) => {
-                    tracing::info!("Fly.io API connection verified");
-                    Ok(())
-                }
-                Ok(false) => {
-                    tracing::warn!("Fly.io API returned empty viewer ID");
-                    Ok(())
-                }
-                Err(e) => {
-                    tracing::error!("Failed to connect to Fly.io API: {}", e);
-                    Err(e)
-                }
+                let snapshots = self.run(async move {
+                    machines.list_volume_snapshots(&app_name, &volume_id).await
+                })?;
+                Ok(serde_json::json!({
+                    "snapshots": snapshots,
+                    "count": snapshots.len(),
+                }))
             }
-        })
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: list, create, extend, delete, snapshots",
+                action
+            ),
+        }
     }
 
-    fn health_check(&self) -> HashMap<String, HealthStatus> {
-        let mut checks = HashMap::new();
+    /// IP addresses implementation (list/allocate/release).
+    fn handle_ips(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
 
-        let client = self.client.clone();
-        let start = std::time::Instant::now();
-        let result = self.runtime.block_on(async move { client.ping().await });
+        let action = Self::get_param_str(&params, "action").unwrap_or("list");
 
-        let latency = start.elapsed().as_secs_f64() * 1000.0;
+        let client = self.clients(&params)?.client.clone();
 
-        match result {
-            Ok(true) => {
-                checks.insert(
-                    "fly_api".into(),
-                    HealthStatus::healthy_with_latency(latency),
-                );
-            }
-            Ok(false) => {
-                checks.insert("fly_api".into(), HealthStatus::unhealthy("Empty viewer ID"));
-            }
-            Err(e) => {
-                checks.insert("fly_api".into(), HealthStatus::unhealthy(e.to_string()));
+        match action {
+            "list" => {
+                let ips = self.run(async move { client.list_ips(&app_name).await })?;
+                Ok(serde_json::json!({
+                    "ips": ips,
+                    "count": ips.len(),
+                }))
             }
-        }
+            "allocate" => {
+                let ip_type = Self::get_param_str(&params, "type")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: type for action=allocate")
+                    })?
+                    .to_string();
+                if !["v4", "v6", "shared_v4", "private_v6"].contains(&ip_type.as_str()) {
+                    anyhow::bail!(
+                        "Invalid type: {}. Valid types are: v4, v6, shared_v4, private_v6",
+                        ip_type
+                    );
+                }
+                let region = Self::get_param_str(&params, "region").map(String::from);
 
+                let result = self.run(async move {
+                    client
+                        .allocate_ip(&app_name, &ip_type, region.as_deref())
+                        .await
+                })?;
+                Ok(serde_json::json!({
+                    "allocated": true,
+                    "result": result
+                }))
+            }
+            "release" => {
+                let address = Self::get_param_str(&params, "address")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: address for action=release")
+                    })?
+                    .to_string();
+
+                let result =
+                    self.run(async move { client.release_ip(&app_name, &address).await })?;
+                Ok(serde_json::json!({
+                    "released": true,
+                    "result": result
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: list, allocate, release",
+                action
+            ),
+        }
+    }
+
+    /// Certificates implementation (list/add/check/remove).
+    fn handle_certs(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+
+        let action = Self::get_param_str(&params, "action").unwrap_or("list");
+
+        let client = self.clients(&params)?.client.clone();
+
+        if action == "list" {
+            let certs = self.run(async move { client.list_certificates(&app_name).await })?;
+            return Ok(serde_json::json!({
+                "certificates": certs,
+                "count": certs.len(),
+            }));
+        }
+
+        let hostname = Self::get_param_str(&params, "hostname")
+            .ok_or_else(|| {
+                anyhow::anyhow!("Missing required parameter: hostname for action={}", action)
+            })?
+            .to_string();
+
+        match action {
+            "add" => {
+                let cert =
+                    self.run(async move { client.add_certificate(&app_name, &hostname).await })?;
+                Ok(serde_json::json!({
+                    "added": true,
+                    "certificate": cert
+                }))
+            }
+            "check" => {
+                let cert =
+                    self.run(async move { client.check_certificate(&app_name, &hostname).await })?;
+                Ok(serde_json::json!({
+                    "ready": cert.configured && cert.client_status.as_deref() == Some("Ready"),
+                    "certificate": cert
+                }))
+            }
+            "remove" => {
+                let result =
+                    self.run(async move { client.delete_certificate(&app_name, &hostname).await })?;
+                Ok(serde_json::json!({
+                    "removed": true,
+                    "result": result
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: list, add, check, remove",
+                action
+            ),
+        }
+    }
+
+    /// Deploy image implementation.
+    fn deploy_image(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let image = Self::get_param_str(&params, "image")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: image"))?
+            .to_string();
+        let strategy =
+            Strategy::parse(Self::get_param_str(&params, "strategy").unwrap_or("rolling"))?;
+        let number =
+            |name: &str, default: f64| params.get(name).and_then(|v| v.as_f64()).unwrap_or(default);
+        let defaults = CanaryOptions::default();
+        let canary = CanaryOptions {
+            percent: Self::get_param_i32(&params, "canary_percent", defaults.percent as i32)
+                .clamp(1, 100) as u32,
+            bake_secs: Self::get_param_i32(&params, "bake_secs", 0).max(0) as u64,
+            max_error_rate_increase: number(
+                "max_error_rate_increase",
+                defaults.max_error_rate_increase,
+            ),
+            max_latency_ratio: number("max_latency_ratio", defaults.max_latency_ratio),
+            max_error_rate: number("max_error_rate", defaults.max_error_rate),
+            max_p95_ms: number("max_p95_ms", defaults.max_p95_ms),
+            require_bake_data: Self::get_param_bool(
+                &params,
+                "require_bake_data",
+                defaults.require_bake_data,
+            ),
+        };
+        if canary.bake_secs > 0 && strategy != Strategy::Canary {
+            anyhow::bail!("bake_secs only applies to strategy=canary");
+        }
+        let mut options = DeployOptions {
+            health_timeout: Duration::from_secs(
+                Self::get_param_i32(
+                    &params,
+                    "health_timeout_secs",
+                    deploy::HEALTH_TIMEOUT_SECS as i32,
+                )
+                .max(1) as u64,
+            ),
+            canary,
+            metrics: None,
+        };
+        let background = params
+            .get("background")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let machines = clients.machines.clone();
+        let metrics = clients.metrics.clone();
+        let tracker = self.deploys.begin(&app_name, &image, strategy);
+
+        let deploy = {
+            let tracker = tracker.clone();
+            async move {
+                if options.canary.bake_secs > 0 {
+                    let org = match client.get_app_org_slug(&app_name).await {
+                        Ok(org) => org,
+                        Err(e) => {
+                            tracker.fail(&e);
+                            return Err(e);
+                        }
+                    };
+                    options.metrics = Some(BakeMetrics {
+                        client: metrics,
+                        org,
+                    });
+                }
+                deploy::deploy(&machines, &app_name, &image, strategy, &options, &tracker).await
+            }
+        };
+
+        if background {
+            // Progress and the final report are read with fly.deploy.status
+            self.spawn_call("fly.deploy", &params, deploy);
+            return Ok(serde_json::to_value(tracker.snapshot())?);
+        }
+        let report = self.run(deploy)?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Deploy status implementation.
+    fn deploy_status(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let deploys = self.deploys.list(Self::get_param_str(&params, "app"));
+        if let Some(id) = Self::get_param_str(&params, "id") {
+            let status = deploys
+                .into_iter()
+                .find(|status| status.id == id)
+                .ok_or_else(|| anyhow::anyhow!("Deployment not found: {}", id))?;
+            return Ok(serde_json::to_value(status)?);
+        }
+        Ok(serde_json::json!({
+            "deployments": deploys,
+            "count": deploys.len(),
+        }))
+    }
+
+    /// Scale implementation (count/vm).
+    fn handle_scale(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+
+        let action = Self::get_param_str(&params, "action")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: action"))?;
+        let region = Self::get_param_str(&params, "region").map(String::from);
+
+        let machines = self.clients(&params)?.machines.clone();
+
+        let report = match action {
+            "count" => {
+                let count = params
+                    .get("count")
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: count for action=count")
+                    })? as usize;
+
+                self.run(async move {
+                    scale::scale_count(&machines, &app_name, count, region.as_deref()).await
+                })?
+            }
+            "vm" => {
+                let guest = GuestConfig {
+                    cpu_kind: Self::get_param_str(&params, "cpu_kind").map(String::from),
+                    cpus: params
+                        .get("cpus")
+                        .and_then(|v| v.as_u64())
+                        .map(|v| v as u32),
+                    memory_mb: params
+                        .get("memory_mb")
+                        .and_then(|v| v.as_u64())
+                        .map(|v| v as u32),
+                    ..Default::default()
+                };
+                if guest.cpu_kind.is_none() && guest.cpus.is_none() && guest.memory_mb.is_none() {
+                    anyhow::bail!("action=vm requires at least one of: cpu_kind, cpus, memory_mb");
+                }
+
+                self.run(async move {
+                    scale::scale_vm(&machines, &app_name, &guest, region.as_deref()).await
+                })?
+            }
+            _ => anyhow::bail!("Unknown action: {}. Valid actions are: count, vm", action),
+        };
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Restart app implementation.
+    fn restart_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+
+        let strategy = RestartStrategy::parse(
+            Self::get_param_str(&params, "strategy").unwrap_or("immediate"),
+        )?;
+
+        if strategy == RestartStrategy::Rolling {
+            let batch_size = Self::get_param_i32(&params, "batch_size", 1).max(1) as usize;
+            let timeout =
+                Duration::from_secs(Self::get_param_i32(&params, "timeout", 120).max(1) as u64);
+            let machines = self.clients(&params)?.machines.clone();
+
+            let report = self.run(async move {
+                restart::rolling_restart(&machines, &app_name, batch_size, timeout).await
+            })?;
+            return Ok(serde_json::to_value(report)?);
+        }
+
+        let client = self.clients(&params)?.client.clone();
+
+        let result = self.run(async move { client.restart_app(&app_name).await })?;
+
+        Ok(serde_json::json!({
+            "restarted": true,
+            "result": result
+        }))
+    }
+
+    /// Update machine implementation.
+    fn update_machine(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+        let patch = params
+            .get("config")
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: config"))?;
+        let patch: MachineConfigPatch =
+            serde_json::from_value(patch).map_err(|e| anyhow::anyhow!("Invalid config: {}", e))?;
+        let nonce = Self::get_param_str(&params, "nonce").map(String::from);
+
+        let machines = self.clients(&params)?.machines.clone();
+
+        let (previous_version, machine) = self.run(async move {
+            let current = machines.get_machine(&app_name, &machine_id).await?;
+            let mut config = current
+                .config
+                .ok_or_else(|| anyhow::anyhow!("Machine {} has no config", machine_id))?;
+            patch.apply_to(&mut config);
+            if let (Some(_), Some(guest)) = (&patch.guest, &config.guest) {
+                guest.validate(Some(&current.region))?;
+            }
+
+            let updated = machines
+                .update_machine(&app_name, &machine_id, &config, nonce.as_deref())
+                .await?;
+            anyhow::Ok((current.instance_id, updated))
+        })?;
+
+        Ok(serde_json::json!({
+            "updated": true,
+            "previous_version": previous_version,
+            "version": machine.instance_id,
+            "machine": machine
+        }))
+    }
+
+    /// Machine lifecycle implementation (start/stop/kill/restart/cordon/
+    /// uncordon).
+    ///
+    /// Destructive actions pass `nonce` through when the caller holds a lease.
+    fn machine_lifecycle(&self, params: HashMap<String, Value>, action: &str) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+        let nonce = Self::get_param_str(&params, "nonce").map(String::from);
+
+        let machines = self.clients(&params)?.machines.clone();
+        let action_owned = action.to_string();
+        let id = machine_id.clone();
+
+        let result = self.run(async move {
+            match action_owned.as_str() {
+                "start" => machines.start_machine(&app_name, &id).await,
+                "stop" => {
+                    machines
+                        .stop_machine(&app_name, &id, nonce.as_deref())
+                        .await
+                }
+                "kill" => {
+                    machines
+                        .kill_machine(&app_name, &id, nonce.as_deref())
+                        .await
+                }
+                "restart" => {
+                    machines
+                        .restart_machine(&app_name, &id, nonce.as_deref())
+                        .await
+                }
+                "cordon" => {
+                    machines
+                        .cordon_machine(&app_name, &id, nonce.as_deref())
+                        .await
+                }
+                "uncordon" => {
+                    machines
+                        .uncordon_machine(&app_name, &id, nonce.as_deref())
+                        .await
+                }
+                other => anyhow::bail!("Unknown machine action: {}", other),
+            }
+        })?;
+
+        Ok(serde_json::json!({
+            "machine_id": machine_id,
+            "action": action,
+            "result": result
+        }))
+    }
+
+    /// Start or stop every matching machine in an app.
+    fn machines_bulk(&self, params: HashMap<String, Value>, action: BulkAction) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let mut filter = MachineFilter {
+            region: Self::get_param_str(&params, "region").map(String::from),
+            ..Default::default()
+        };
+        if let Some(metadata) = params.get("metadata").and_then(|v| v.as_object()) {
+            for (key, value) in metadata {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("metadata.{} must be a string", key))?;
+                filter.metadata.insert(key.clone(), value.to_string());
+            }
+        }
+        let concurrency = Self::get_param_i32(&params, "concurrency", 4).clamp(1, 16) as usize;
+
+        let machines = self.clients(&params)?.machines.clone();
+        let report = self.run(async move {
+            bulk::run(&machines, &app_name, action, &filter, concurrency).await
+        })?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Create machine implementation.
+    fn create_machine(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let config = params
+            .get("config")
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: config"))?;
+        let config: MachineCreateConfig =
+            serde_json::from_value(config).map_err(|e| anyhow::anyhow!("Invalid config: {}", e))?;
+        config.validate()?;
+
+        let machines = self.clients(&params)?.machines.clone();
+
+        let machine = self.run(async move { machines.create_machine(&app_name, &config).await })?;
+
+        Ok(serde_json::json!({
+            "created": true,
+            "machine": machine
+        }))
+    }
+
+    /// Machine stop reason implementation.
+    fn stop_reason(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+
+        let client = self.clients(&params)?.client.clone();
+
+        let reason = self.run(async move {
+            client
+                .get_machine_stopped_reason(&app_name, &machine_id)
+                .await
+        })?;
+
+        Ok(serde_json::to_value(reason)?)
+    }
+
+    /// Crash-loop monitor implementation.
+    fn monitor_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+
+        let threshold = Self::get_param_i32(&params, "restart_threshold", 3);
+        if threshold < 1 {
+            anyhow::bail!("restart_threshold must be at least 1");
+        }
+        let window_secs = Self::get_param_i32(&params, "window_secs", 300).max(1);
+        let duration = Self::get_param_i32(&params, "duration", 60).max(0);
+        let interval = Self::get_param_i32(&params, "interval_secs", 10).max(1);
+
+        let options = MonitorOptions {
+            app: app_name,
+            restart_threshold: threshold as usize,
+            window: chrono::Duration::seconds(window_secs as i64),
+            duration: std::time::Duration::from_secs(duration as u64),
+            interval: std::time::Duration::from_secs(interval as u64),
+            webhook: Self::get_param_str(&params, "webhook").map(String::from),
+        };
+
+        let client = self.clients(&params)?.client.clone();
+
+        let report = self.run(async move { monitor::monitor_app(&*client, &options).await })?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Declarative apply implementation.
+    fn apply_config(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let config = params
+            .get("config")
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: config"))?;
+        let desired: DesiredConfig =
+            serde_json::from_value(config).map_err(|e| anyhow::anyhow!("Invalid config: {}", e))?;
+        desired
+            .validate()
+            .map_err(|e| anyhow::anyhow!("Invalid config: {}", e))?;
+        let dry_run = Self::get_param_bool(&params, "dry_run", false);
+
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let machines = clients.machines.clone();
+        let deploys = self.deploys.clone();
+
+        self.run(async move {
+            let current = reconcile::fetch_live_state(&*client, &app_name).await?;
+            let plan = reconcile::compute_plan(&current, &desired);
+
+            if dry_run {
+                return Ok(serde_json::json!({
+                    "app": app_name,
+                    "dry_run": true,
+                    "changes": plan.len(),
+                    "plan": plan,
+                }));
+            }
+
+            let applied =
+                reconcile::apply_plan(&machines, &deploys, &app_name, &desired, &plan).await;
+
+            Ok(serde_json::json!({
+                "app": app_name,
+                "dry_run": false,
+                "changes": plan.len(),
+                "plan": plan,
+                "applied": applied,
+            }))
+        })
+    }
+
+    /// App create implementation.
+    fn create_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let name = Self::get_param_str(&params, "name")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: name"))?
+            .to_string();
+        let org = Self::get_param_str(&params, "org")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: org"))?
+            .to_string();
+        let region = Self::get_param_str(&params, "region").map(String::from);
+        let network = Self::get_param_str(&params, "network").map(String::from);
+
+        let client = self.clients(&params)?.client.clone();
+
+        let app = self.run(async move {
+            client
+                .create_app(&name, &org, region.as_deref(), network.as_deref())
+                .await
+        })?;
+
+        Ok(serde_json::to_value(app)?)
+    }
+
+    /// App delete implementation.
+    ///
+    /// `confirm` must repeat the app name to guard against accidental deletes.
+    fn delete_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let confirm = Self::get_param_str(&params, "confirm")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: confirm"))?;
+        if confirm != app_name {
+            anyhow::bail!("confirm must match the app name ({})", app_name);
+        }
+
+        let client = self.clients(&params)?.client.clone();
+        let name = app_name.clone();
+
+        let result = self.run(async move { client.delete_app(&name).await })?;
+
+        Ok(serde_json::json!({
+            "app": app_name,
+            "deleted": true,
+            "result": result
+        }))
+    }
+
+    /// App move implementation.
+    fn move_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let org = Self::get_param_str(&params, "org")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: org"))?
+            .to_string();
+
+        let client = self.clients(&params)?.client.clone();
+
+        let app = self.run(async move { client.move_app(&app_name, &org).await })?;
+
+        Ok(serde_json::to_value(app)?)
+    }
+
+    /// List organizations implementation.
+    fn list_orgs(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let client = self.clients(&params)?.client.clone();
+
+        let orgs = self.run(async move { client.list_organizations().await })?;
+
+        Ok(serde_json::json!({
+            "organizations": orgs,
+            "count": orgs.len(),
+        }))
+    }
+
+    /// Organization detail implementation.
+    fn get_org(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let slug = Self::get_param_str(&params, "org")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: org"))?
+            .to_string();
+
+        let client = self.clients(&params)?.client.clone();
+
+        let org = self.run(async move { client.get_organization(&slug).await })?;
+
+        Ok(serde_json::to_value(org)?)
+    }
+
+    /// Organization billing implementation.
+    fn get_billing(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let slug = Self::get_param_str(&params, "org")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: org"))?
+            .to_string();
+
+        let client = self.clients(&params)?.client.clone();
+
+        let billing = self.run(async move { client.get_billing(&slug).await })?;
+
+        Ok(serde_json::to_value(billing)?)
+    }
+
+    /// Machine metadata implementation (get/set/delete).
+    fn machine_metadata(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+
+        let action = Self::get_param_str(&params, "action").unwrap_or("get");
+
+        let machines = self.clients(&params)?.machines.clone();
+        let id = machine_id.clone();
+
+        match action {
+            "get" => {
+                let metadata =
+                    self.run(async move { machines.get_metadata(&app_name, &id).await })?;
+                Ok(serde_json::json!({
+                    "machine_id": machine_id,
+                    "metadata": metadata,
+                }))
+            }
+            "set" => {
+                let key = Self::get_param_str(&params, "key")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: key for action=set")
+                    })?
+                    .to_string();
+                let value = Self::get_param_str(&params, "value")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: value for action=set")
+                    })?
+                    .to_string();
+
+                let name = key.clone();
+                self.run(
+                    async move { machines.set_metadata(&app_name, &id, &name, &value).await },
+                )?;
+                Ok(serde_json::json!({
+                    "machine_id": machine_id,
+                    "key": key,
+                    "set": true,
+                }))
+            }
+            "delete" => {
+                let key = Self::get_param_str(&params, "key")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: key for action=delete")
+                    })?
+                    .to_string();
+
+                let name = key.clone();
+                self.run(async move { machines.delete_metadata(&app_name, &id, &name).await })?;
+                Ok(serde_json::json!({
+                    "machine_id": machine_id,
+                    "key": key,
+                    "deleted": true,
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: get, set, delete",
+                action
+            ),
+        }
+    }
+
+    /// Machine lease implementation (acquire/release/show).
+    fn machine_lease(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+
+        let action = Self::get_param_str(&params, "action").unwrap_or("show");
+
+        let machines = self.clients(&params)?.machines.clone();
+        let id = machine_id.clone();
+
+        match action {
+            "acquire" => {
+                let ttl = Self::get_param_i32(&params, "ttl", 30).max(1) as u64;
+                let description = Self::get_param_str(&params, "description").map(String::from);
+
+                let lease = self.run(async move {
+                    machines
+                        .acquire_lease(&app_name, &id, ttl, description.as_deref())
+                        .await
+                })?;
+                Ok(serde_json::json!({
+                    "machine_id": machine_id,
+                    "lease": lease,
+                }))
+            }
+            "show" => {
+                let lease = self.run(async move { machines.get_lease(&app_name, &id).await })?;
+                Ok(serde_json::json!({
+                    "machine_id": machine_id,
+                    "lease": lease,
+                }))
+            }
+            "release" => {
+                let nonce = Self::get_param_str(&params, "nonce")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: nonce for action=release")
+                    })?
+                    .to_string();
+
+                self.run(async move { machines.release_lease(&app_name, &id, &nonce).await })?;
+                Ok(serde_json::json!({
+                    "machine_id": machine_id,
+                    "released": true,
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: acquire, release, show",
+                action
+            ),
+        }
+    }
+
+    /// Machine wait implementation.
+    fn machine_wait(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+        let state = Self::get_param_str(&params, "state").unwrap_or("started");
+        if !matches!(state, "started" | "stopped" | "destroyed") {
+            anyhow::bail!(
+                "Invalid state: {}. Valid states are: started, stopped, destroyed",
+                state
+            );
+        }
+        let state = state.to_string();
+        let timeout =
+            Duration::from_secs(Self::get_param_i32(&params, "timeout_secs", 60).max(1) as u64);
+        let poll_interval =
+            Duration::from_secs(Self::get_param_i32(&params, "interval_secs", 2).max(1) as u64);
+
+        let machines = self.clients(&params)?.machines.clone();
+        let id = machine_id.clone();
+        let started = Instant::now();
+
+        let final_state = self.run(async move {
+            machines
+                .wait_until(&app_name, &id, &state, timeout, poll_interval)
+                .await
+        })?;
+
+        Ok(serde_json::json!({
+            "machine_id": machine_id,
+            "state": final_state,
+            "elapsed_ms": started.elapsed().as_millis() as u64,
+        }))
+    }
+
+    /// Health checks implementation.
+    fn list_checks(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id").map(String::from);
+        let failing_only = Self::get_param_bool(&params, "failing_only", false);
+
+        let machines = self.clients(&params)?.machines.clone();
+        let app = app_name.clone();
+
+        let list = self.run(async move { machines.list_machines(&app).await })?;
+
+        let checks: Vec<Value> = list
+            .iter()
+            .filter(|m| match machine_id.as_deref() {
+                Some(id) => m.id == id,
+                None => true,
+            })
+            .flat_map(|m| {
+                m.checks
+                    .iter()
+                    .filter(|c| !failing_only || c.is_failing())
+                    .map(|c| {
+                        serde_json::json!({
+                            "machine_id": m.id,
+                            "region": m.region,
+                            "name": c.name,
+                            "status": c.status,
+                            "output": c.output,
+                            "updated_at": c.updated_at,
+                        })
+                    })
+            })
+            .collect();
+        let failing = checks.iter().filter(|c| c["status"] != "passing").count();
+
+        Ok(serde_json::json!({
+            "app": app_name,
+            "checks": checks,
+            "count": checks.len(),
+            "failing": failing,
+        }))
+    }
+
+    /// App metrics implementation.
+    fn app_metrics(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let org = Self::get_param_str(&params, "org").map(String::from);
+        let metric = Self::get_param_str(&params, "metric").unwrap_or("all");
+        let range_secs = Self::get_param_i32(&params, "range_secs", 3600).max(60) as i64;
+        let step_secs = Self::get_param_i32(&params, "step_secs", 60).max(1) as u64;
+        if range_secs as u64 / step_secs > MAX_METRIC_POINTS {
+            anyhow::bail!(
+                "range_secs / step_secs must not exceed {} points",
+                MAX_METRIC_POINTS
+            );
+        }
+
+        let queries = api::app_queries(&app_name, metric)?;
+        let end = chrono::Utc::now().timestamp();
+        let start = end - range_secs;
+
+        let client = self.clients(&params)?.client.clone();
+        let metrics = self.clients(&params)?.metrics.clone();
+
+        self.run(async move {
+            let org = match org {
+                Some(org) => org,
+                None => client.get_app_org_slug(&app_name).await?,
+            };
+
+            let mut series = serde_json::Map::new();
+            for (name, query) in queries {
+                let result = metrics
+                    .query_range(&org, &query, start, end, step_secs)
+                    .await?;
+                series.insert(name.to_string(), serde_json::to_value(result)?);
+            }
+
+            Ok(serde_json::json!({
+                "app": app_name,
+                "org": org,
+                "start": start,
+                "end": end,
+                "step_secs": step_secs,
+                "series": series,
+            }))
+        })
+    }
+
+    /// Run one call with auditing and secret redaction, keeping the
+    /// classified error for callers that report it structurally.
+    fn dispatch_one(
+        &self,
+        method: &str,
+        params: HashMap<String, Value>,
+    ) -> std::result::Result<Value, FlyError> {
+        // Aliases and bare names run as the canonical method
+        let Some(registered) = self.registry.resolve(method) else {
+            let suggestions = self.registry.suggest(method);
+            return Err(FlyError::Validation {
+                code: "unknown_method",
+                param: None,
+                message: suggest::hint(format!("Unknown method: {}", method), &suggestions),
+                suggestions,
+            });
+        };
+        let method = registered.name;
+        CALL_ORG_ROUTING.with(|r| r.set(registered.routes_by_org()));
+
+        let audited = registered.is_mutating(&params).then(|| params.clone());
+        let secrets = redact::secret_values(registered.secrets(), &params);
+
+        // Secret values must not come back in error strings or logs
+        let result = self.call(method, params).map_err(|e| {
+            let error = FlyError::classify(&e).redact(&secrets);
+            tracing::debug!(code = error.code(), "{} failed: {}", method, error);
+            error
+        });
+
+        let deferred = CALL_AUDIT_DEFERRED.with(|d| d.replace(false));
+        if let Some(params) = audited.filter(|_| !deferred) {
+            let profile = self.profile_name(&params);
+            self.audit.record(
+                method,
+                profile,
+                &params,
+                registered.secrets(),
+                result.as_ref().err(),
+            );
+        }
+
+        result
+    }
+
+    /// Whether a call changes Fly state, as its registration declares.
+    fn is_mutating(&self, method: &str, params: &HashMap<String, Value>) -> bool {
+        self.registry
+            .resolve(method)
+            .is_some_and(|m| m.is_mutating(params))
+    }
+
+    /// Validate params, then serve from the cache or route to a handler.
+    fn call(&self, method: &str, params: HashMap<String, Value>) -> Result<Value> {
+        let registered = self.registry.resolve(method);
+        if let Some(registered) = registered {
+            params::validate(registered, &params)?;
+        }
+        if registered.is_some_and(|m| m.is_mutating(&params)) {
+            let scope = self.clients(&params)?.scope();
+            if !scope.can_mutate() {
+                return Err(FlyError::InsufficientScope(format!(
+                    "{} changes Fly state, but profile {} has a read-only token",
+                    method,
+                    self.profile_name(&params)
+                ))
+                .into());
+            }
+        }
+        Self::set_call_limits(&params)?;
+
+        let Some(ttl) = registered.and_then(|m| m.cache_ttl(&params)) else {
+            let permits = self.permits(method, &params)?;
+            let result = Self::holding(permits, || self.route_with_warnings(method, params));
+            // A failed call may still have changed something
+            if let Some(registered) = registered {
+                self.cache.invalidate(registered.invalidated());
+            }
+            return result;
+        };
+
+        let key = ResponseCache::key(method, &params);
+        if !Self::get_param_bool(&params, "no_cache", false) {
+            if let Some(value) = self.cache.get(&key) {
+                return Ok(value);
+            }
+        }
+
+        let permits = self.permits(method, &params)?;
+        let value = Self::holding(permits, || self.route_with_warnings(method, params))?;
+        self.cache.insert(key, value.clone(), ttl);
+        Ok(value)
+    }
+
+    /// Run `f` holding `permits`, then release them unless a handler took
+    /// them along to background work (see `spawn_call`).
+    fn holding<T>(permits: Option<Permits>, f: impl FnOnce() -> T) -> T {
+        let outer = CALL_PERMITS.with(|p| p.replace(permits));
+        let result = f();
+        CALL_PERMITS.with(|p| p.replace(outer));
+        result
+    }
+
+    /// Finish the current call in the background. `task` keeps the call's
+    /// concurrency permits until it's done, and a mutating call is audited
+    /// with its outcome then rather than when the handler returns. Cached
+    /// responses the call makes stale are dropped again once it's done.
+    fn spawn_call<T>(
+        &self,
+        method: &'static str,
+        params: &HashMap<String, Value>,
+        task: impl Future<Output = Result<T>> + Send + 'static,
+    ) {
+        let permits = CALL_PERMITS.with(|p| p.borrow_mut().take());
+        let stale = self
+            .registry
+            .resolve(method)
+            .map_or(&[][..], |m| m.invalidated());
+        let cache = self.cache.clone();
+        let audited = self
+            .registry
+            .resolve(method)
+            .filter(|m| m.is_mutating(params))
+            .map(|m| {
+                CALL_AUDIT_DEFERRED.with(|d| d.set(true));
+                let profile = self.profile_name(params).to_string();
+                let values = redact::secret_values(m.secrets(), params);
+                let secrets = m.secrets().to_vec();
+                (self.audit.clone(), profile, params.clone(), secrets, values)
+            });
+
+        self.runtime.spawn(async move {
+            let result = task.await;
+            drop(permits);
+            cache.invalidate(stale);
+            if let Some((audit, profile, params, secrets, values)) = audited {
+                let error = result.err().map(|e| FlyError::classify(&e).redact(&values));
+                audit.record(method, &profile, &params, &secrets, error.as_ref());
+            }
+        });
+    }
+
+    /// Wait for concurrency permits for a call, up to its deadline.
+    ///
+    /// `fly.batch` takes none itself since each of its entries does.
+    fn permits(&self, method: &str, params: &HashMap<String, Value>) -> Result<Option<Permits>> {
+        if matches!(method.strip_prefix("fly.").unwrap_or(method), "batch") {
+            return Ok(None);
+        }
+        let class = if self.is_mutating(method, params) {
+            CallClass::Mutate
+        } else {
+            CallClass::Read
+        };
+        let deadline = CALL_DEADLINE.with(Cell::get);
+        Ok(Some(self.limits.acquire(class, deadline)?))
+    }
+
+    /// Route a call and attach any partial-result warnings its queries
+    /// reported as a `warnings` array.
+    fn route_with_warnings(&self, method: &str, params: HashMap<String, Value>) -> Result<Value> {
+        CALL_WARNINGS.with(|w| w.borrow_mut().clear());
+        let mut value = self.route(method, params)?;
+        let warnings = CALL_WARNINGS.with(|w| w.take());
+        Self::push_warnings(&mut value, warnings);
+        Ok(value)
+    }
+
+    /// Append warnings to a response's `warnings` array, skipping repeats.
+    fn push_warnings(value: &mut Value, warnings: Vec<Warning>) {
+        let (false, Value::Object(map)) = (warnings.is_empty(), value) else {
+            return;
+        };
+        let entry = map
+            .entry("warnings")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(list) = entry {
+            for warning in warnings {
+                let warning = serde_json::json!(warning);
+                if !list.contains(&warning) {
+                    list.push(warning);
+                }
+            }
+        }
+    }
+
+    /// Deploy tokens implementation (list/create/revoke).
+    fn handle_tokens(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let action = Self::get_param_str(&params, "action").unwrap_or("list");
+        let app_name = Self::get_param_str(&params, "app").map(String::from);
+        let org_slug = Self::get_param_str(&params, "org").map(String::from);
+
+        let client = self.clients(&params)?.client.clone();
+
+        match action {
+            "list" => {
+                if app_name.is_none() && org_slug.is_none() {
+                    anyhow::bail!("action=list requires app or org");
+                }
+                let tokens = self.run(async move {
+                    client
+                        .list_deploy_tokens(app_name.as_deref(), org_slug.as_deref())
+                        .await
+                })?;
+                Ok(serde_json::json!({
+                    "tokens": tokens,
+                    "count": tokens.len(),
+                }))
+            }
+            "create" => {
+                if app_name.is_none() && org_slug.is_none() {
+                    anyhow::bail!("action=create requires app or org");
+                }
+                let name = Self::get_param_str(&params, "name")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: name for action=create")
+                    })?
+                    .to_string();
+                let expiry = Self::get_param_str(&params, "expiry").map(String::from);
+
+                let (token, secret) = self.run(async move {
+                    client
+                        .create_deploy_token(
+                            app_name.as_deref(),
+                            org_slug.as_deref(),
+                            &name,
+                            expiry.as_deref(),
+                        )
+                        .await
+                })?;
+                Ok(serde_json::json!({
+                    "created": true,
+                    "token": token,
+                    "secret": secret,
+                }))
+            }
+            "revoke" => {
+                let id = Self::get_param_str(&params, "id")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: id for action=revoke")
+                    })?
+                    .to_string();
+
+                let result = self.run(async move { client.revoke_deploy_token(&id).await })?;
+                Ok(serde_json::json!({
+                    "revoked": true,
+                    "result": result
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: list, create, revoke",
+                action
+            ),
+        }
+    }
+
+    /// Exec implementation.
+    fn exec_command(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+
+        let command: Vec<String> = params
+            .get("command")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: command"))?
+            .iter()
+            .map(|arg| {
+                arg.as_str()
+                    .map(String::from)
+                    .ok_or_else(|| anyhow::anyhow!("command arguments must be strings"))
+            })
+            .collect::<Result<_>>()?;
+        if command.is_empty() {
+            anyhow::bail!("command must not be empty");
+        }
+        let timeout = Self::get_param_i32(&params, "timeout", 30).max(1) as u64;
+
+        let machines = self.clients(&params)?.machines.clone();
+
+        let result = self.run(async move {
+            machines
+                .exec(&app_name, &machine_id, &command, timeout)
+                .await
+        })?;
+
+        Ok(serde_json::json!({
+            "success": result.exit_code == Some(0),
+            "exit_code": result.exit_code,
+            "exit_signal": result.exit_signal,
+            "stdout": result.stdout,
+            "stderr": result.stderr,
+        }))
+    }
+
+    /// Postgres cluster management implementation.
+    fn handle_postgres(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let action = Self::get_param_str(&params, "action").unwrap_or("clusters");
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let machines = clients.machines.clone();
+
+        if action == "clusters" {
+            let clusters = self.run(async move { client.list_postgres_clusters().await })?;
+            return Ok(serde_json::json!({
+                "clusters": clusters,
+                "count": clusters.len(),
+            }));
+        }
+
+        let cluster = Self::get_param_str(&params, "cluster")
+            .ok_or_else(|| {
+                anyhow::anyhow!("Missing required parameter: cluster for action={}", action)
+            })?
+            .to_string();
+        let database = Self::get_param_str(&params, "database").map(String::from);
+        let variable = Self::get_param_str(&params, "variable")
+            .unwrap_or(postgres::DEFAULT_VARIABLE)
+            .to_string();
+
+        match action {
+            "databases" => {
+                let databases =
+                    self.run(async move { postgres::list_databases(&machines, &cluster).await })?;
+                Ok(serde_json::json!({
+                    "databases": databases,
+                    "count": databases.len(),
+                }))
+            }
+            "create_database" => {
+                let database = database.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: database for action=create_database")
+                })?;
+                let name = database.clone();
+                self.run(async move {
+                    postgres::create_database(&machines, &cluster, &database).await
+                })?;
+                Ok(serde_json::json!({
+                    "created": true,
+                    "database": name,
+                }))
+            }
+            "users" => {
+                let users =
+                    self.run(async move { postgres::list_users(&machines, &cluster).await })?;
+                Ok(serde_json::json!({
+                    "users": users,
+                    "count": users.len(),
+                }))
+            }
+            "attach" | "detach" => {
+                let app_name = Self::get_param_str(&params, "app")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: app for action={}", action)
+                    })?
+                    .to_string();
+
+                if action == "attach" {
+                    let attachment = self.run(async move {
+                        postgres::attach(
+                            &*client,
+                            &machines,
+                            &cluster,
+                            &app_name,
+                            database.as_deref(),
+                            &variable,
+                        )
+                        .await
+                    })?;
+                    Ok(serde_json::json!({
+                        "attached": true,
+                        "attachment": attachment,
+                    }))
+                } else {
+                    self.run(async move {
+                        postgres::detach(&*client, &machines, &cluster, &app_name, &variable).await
+                    })?;
+                    Ok(serde_json::json!({ "detached": true }))
+                }
+            }
+            "connection_string" => {
+                let url = self.run(async move {
+                    postgres::operator_connection_string(&machines, &cluster, database.as_deref())
+                        .await
+                })?;
+                Ok(serde_json::json!({ "connection_string": url }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: clusters, databases, create_database, users, attach, detach, connection_string",
+                action
+            ),
+        }
+    }
+
+    /// Audit log query implementation.
+    fn audit_log(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let limit = Self::get_param_i32(&params, "limit", 50).max(1) as usize;
+        let method = Self::get_param_str(&params, "method");
+        let app_name = Self::get_param_str(&params, "app");
+
+        let entries = self.audit.recent(limit, method, app_name)?;
+
+        Ok(serde_json::json!({
+            "entries": entries,
+            "count": entries.len(),
+        }))
+    }
+
+    /// Fleet status implementation.
+    fn fleet_status(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let apps: Option<Vec<String>> = params.get("apps").and_then(|v| v.as_array()).map(|apps| {
+            apps.iter()
+                .filter_map(|a| a.as_str())
+                .map(String::from)
+                .collect()
+        });
+        let org = Self::get_param_str(&params, "org").map(String::from);
+        let concurrency = Self::get_param_i32(&params, "concurrency", 8).max(1) as usize;
+
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let machines = clients.machines.clone();
+
+        let status = self.run(async move {
+            let apps = match apps {
+                Some(apps) => apps,
+                None => {
+                    let client = &client;
+                    let all = collect_all(|cursor| async move {
+                        client
+                            .list_apps(Some(PAGE_SIZE), cursor.as_deref(), None)
+                            .await
+                    })
+                    .await?;
+                    api::report_warnings(all.warnings);
+                    all.items
+                        .into_iter()
+                        .filter(|app| match (&org, &app.organization) {
+                            (Some(org), Some(app_org)) => &app_org.slug == org,
+                            (Some(_), None) => false,
+                            (None, _) => true,
+                        })
+                        .map(|app| app.name)
+                        .collect()
+                }
+            };
+
+            Ok(fleet::fleet_status(&machines, apps, concurrency).await)
+        })?;
+
+        Ok(serde_json::to_value(status)?)
+    }
+
+    /// Region placement implementation (set/add/remove).
+    fn set_regions(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let action = Self::get_param_str(&params, "action")
+            .unwrap_or("set")
+            .to_string();
+        let requested: BTreeSet<String> = params
+            .get("regions")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: regions"))?
+            .iter()
+            .filter_map(|r| r.as_str())
+            .map(String::from)
+            .collect();
+        let count = Self::get_param_i32(&params, "count", 1).max(1) as usize;
+
+        let machines = self.clients(&params)?.machines.clone();
+
+        let report = self.run(async move {
+            let target = match action.as_str() {
+                "set" => requested,
+                "add" => {
+                    let mut current = regions::current_regions(&machines, &app_name).await?;
+                    current.extend(requested);
+                    current
+                }
+                "remove" => {
+                    let current = regions::current_regions(&machines, &app_name).await?;
+                    current.difference(&requested).cloned().collect()
+                }
+                _ => anyhow::bail!(
+                    "Unknown action: {}. Valid actions are: set, add, remove",
+                    action
+                ),
+            };
+            regions::set_regions(&machines, &app_name, &target, count).await
+        })?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Background watcher implementation.
+    fn handle_watch(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let action = Self::get_param_str(&params, "action").unwrap_or("events");
+        let app_name = Self::get_param_str(&params, "app").map(String::from);
+
+        match action {
+            "start" => {
+                let app_name = app_name.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: app for action=start")
+                })?;
+                let interval = Self::get_param_i32(&params, "interval_secs", 15).max(5);
+                let threshold = Self::get_param_i32(&params, "restart_threshold", 3);
+                if threshold < 1 {
+                    anyhow::bail!("restart_threshold must be at least 1");
+                }
+                let rule = CrashLoopRule {
+                    restart_threshold: threshold as usize,
+                    window_secs: Self::get_param_i32(&params, "window_secs", 300).max(1) as i64,
+                };
+                let clients = self.clients(&params)?;
+                let info = self.watches.start(
+                    &self.runtime,
+                    clients.client.clone(),
+                    clients.machines.clone(),
+                    app_name,
+                    std::time::Duration::from_secs(interval as u64),
+                    rule,
+                );
+                let saved = SavedWatch {
+                    profile: self.profile_name(&params).to_string(),
+                    interval_secs: info.interval_secs,
+                    crash_loop: rule,
+                };
+                self.save_state("watches", &info.app, Some(&saved));
+                Ok(serde_json::to_value(info)?)
+            }
+            "stop" => {
+                let app_name = app_name.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: app for action=stop")
+                })?;
+                let stopped = self.watches.stop(&app_name);
+                self.save_state::<SavedWatch>("watches", &app_name, None);
+                Ok(serde_json::json!({ "app": app_name, "stopped": stopped }))
+            }
+            "list" => {
+                let watches = self.watches.list();
+                Ok(serde_json::json!({
+                    "watches": watches,
+                    "count": watches.len(),
+                    "machines_by_state": self.watches.machine_states(),
+                }))
+            }
+            "events" => {
+                let since = params.get("since").and_then(|v| v.as_u64()).unwrap_or(0);
+                let limit = Self::get_param_i32(&params, "limit", 100).max(1) as usize;
+                let wait = Self::get_param_i32(&params, "wait_secs", 0).clamp(0, 60);
+                let watches = self.watches.clone();
+
+                let batch = self.run(async move {
+                    Ok(watches
+                        .events(
+                            since,
+                            app_name.as_deref(),
+                            limit,
+                            std::time::Duration::from_secs(wait as u64),
+                        )
+                        .await)
+                })?;
+                Ok(serde_json::to_value(batch)?)
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: start, stop, list, events",
+                action
+            ),
+        }
+    }
+
+    /// Autoscaler control implementation.
+    fn handle_autoscale(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let action = Self::get_param_str(&params, "action").unwrap_or("status");
+        let app_name = Self::get_param_str(&params, "app").map(String::from);
+
+        match action {
+            "enable" => {
+                let app_name = app_name.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: app for action=enable")
+                })?;
+                let target = params
+                    .get("target")
+                    .and_then(|v| v.as_f64())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: target for action=enable")
+                    })?;
+                let rule = AutoscaleRule {
+                    app: app_name,
+                    min: Self::get_param_i32(&params, "min", 1).max(0) as usize,
+                    max: Self::get_param_i32(&params, "max", 3).max(0) as usize,
+                    metric: AutoscaleMetric::parse(
+                        Self::get_param_str(&params, "metric").unwrap_or("cpu"),
+                    )?,
+                    target,
+                    interval_secs: Self::get_param_i32(&params, "interval_secs", 60).max(15) as u64,
+                    cooldown_secs: Self::get_param_i32(&params, "cooldown_secs", 300).max(0) as u64,
+                };
+                rule.validate()?;
+
+                let clients = self.clients(&params)?;
+                let clients = AutoscaleClients {
+                    client: clients.client.clone(),
+                    machines: clients.machines.clone(),
+                    metrics: clients.metrics.clone(),
+                };
+                self.autoscaler.enable(&self.runtime, clients, rule.clone());
+                let saved = SavedAutoscale {
+                    profile: self.profile_name(&params).to_string(),
+                    rule: rule.clone(),
+                };
+                self.save_state("autoscale", &rule.app, Some(&saved));
+                Ok(serde_json::json!({ "enabled": true, "rule": rule }))
+            }
+            "disable" => {
+                let app_name = app_name.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: app for action=disable")
+                })?;
+                let disabled = self.autoscaler.disable(&app_name);
+                self.save_state::<SavedAutoscale>("autoscale", &app_name, None);
+                Ok(serde_json::json!({ "app": app_name, "disabled": disabled }))
+            }
+            "status" => {
+                let rules = self.autoscaler.status(app_name.as_deref());
+                Ok(serde_json::json!({
+                    "rules": rules,
+                    "count": rules.len(),
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: enable, disable, status",
+                action
+            ),
+        }
+    }
+
+    /// Schedule create implementation.
+    fn schedule_create(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let required = |name: &str| {
+            Self::get_param_str(&params, name)
+                .map(String::from)
+                .ok_or_else(|| anyhow::anyhow!("Missing required parameter: {}", name))
+        };
+        let count = match params.get("count") {
+            Some(value) if !value.is_null() => Some(
+                value
+                    .as_u64()
+                    .ok_or_else(|| anyhow::anyhow!("count must be a non-negative integer"))?
+                    as usize,
+            ),
+            _ => None,
+        };
+        let mut metadata = HashMap::new();
+        if let Some(object) = params.get("metadata").and_then(|v| v.as_object()) {
+            for (key, value) in object {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("metadata.{} must be a string", key))?;
+                metadata.insert(key.clone(), value.to_string());
+            }
+        }
+        let schedule = Schedule {
+            name: required("name")?,
+            cron: required("cron")?,
+            app: required("app")?,
+            action: ScheduleAction::parse(&required("action")?)?,
+            count,
+            region: Self::get_param_str(&params, "region").map(String::from),
+            metadata,
+            profile: self.profile_name(&params).to_string(),
+            created_at: Utc::now(),
+        };
+
+        let machines = self.clients(&params)?.machines.clone();
+        let status = self
+            .scheduler
+            .add(&self.runtime, Some(machines), schedule)?;
+        self.scheduler.save()?;
+        Ok(serde_json::json!({ "created": true, "schedule": status }))
+    }
+
+    /// Schedule list implementation.
+    fn schedule_list(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let schedules = self.scheduler.list(Self::get_param_str(&params, "app"));
+        Ok(serde_json::json!({
+            "schedules": schedules,
+            "count": schedules.len(),
+        }))
+    }
+
+    /// Schedule delete implementation.
+    fn schedule_delete(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let name = Self::get_param_str(&params, "name")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: name"))?;
+        let deleted = self.scheduler.delete(name);
+        if deleted {
+            self.scheduler.save()?;
+        }
+        Ok(serde_json::json!({ "name": name, "deleted": deleted }))
+    }
+
+    /// Schedule run-now implementation.
+    fn schedule_run_now(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let name = Self::get_param_str(&params, "name")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: name"))?;
+        let run = self.scheduler.run_now(name)?;
+        let run = self.run(async move { Ok(run.await) })?;
+        Ok(serde_json::json!({ "name": name, "run": run }))
+    }
+
+    /// State export implementation.
+    fn state_export(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let include_logs = params.get("logs").and_then(|v| v.as_bool()).unwrap_or(true);
+        Ok(serde_json::to_value(state::export(
+            &*self.state,
+            include_logs,
+        )?)?)
+    }
+
+    /// State import implementation.
+    fn state_import(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let dump = params
+            .get("state")
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: state"))?;
+        let dump: state::Export =
+            serde_json::from_value(dump).map_err(|e| anyhow::anyhow!("Invalid state: {}", e))?;
+        // Checked and written as a whole; a failure leaves the running tasks alone
+        let report = state::import(&*self.state, &dump)?;
+
+        // Run what was imported in place of what was running
+        self.watches.stop_all();
+        self.autoscaler.disable_all();
+        self.scheduler.stop_all();
+        self.restore_state();
+        Ok(serde_json::json!({ "imported": true, "report": report }))
+    }
+
+    /// Save or remove a background task's settings in the state store. A
+    /// failure is logged; the task is already running or stopped.
+    fn save_state<T: serde::Serialize>(&self, key: &str, app: &str, value: Option<&T>) {
+        if let Err(e) = state::update(&*self.state, key, app, value) {
+            tracing::warn!("Failed to save {} state for {}: {:#}", key, app, e);
+        }
+    }
+
+    /// Restart the watchers, autoscale rules and schedules saved by a
+    /// previous run. Those whose profile is gone are left in the store;
+    /// schedules stay listed as inactive.
+    fn restore_state(&self) {
+        let watches: Result<BTreeMap<String, SavedWatch>> = state::load(&*self.state, "watches");
+        for (app, saved) in watches.unwrap_or_else(|e| {
+            tracing::error!("Failed to load saved watches: {:#}", e);
+            BTreeMap::new()
+        }) {
+            let Some(clients) = self.profiles.get(&saved.profile) else {
+                tracing::warn!(
+                    "Not restoring watch on {}: profile {} is not configured",
+                    app,
+                    saved.profile
+                );
+                continue;
+            };
+            self.watches.start(
+                &self.runtime,
+                clients.client.clone(),
+                clients.machines.clone(),
+                app,
+                Duration::from_secs(saved.interval_secs),
+                saved.crash_loop,
+            );
+        }
+
+        let rules: Result<BTreeMap<String, SavedAutoscale>> =
+            state::load(&*self.state, "autoscale");
+        for (app, saved) in rules.unwrap_or_else(|e| {
+            tracing::error!("Failed to load saved autoscale rules: {:#}", e);
+            BTreeMap::new()
+        }) {
+            let Some(clients) = self.profiles.get(&saved.profile) else {
+                tracing::warn!(
+                    "Not restoring autoscaling of {}: profile {} is not configured",
+                    app,
+                    saved.profile
+                );
+                continue;
+            };
+            if let Err(e) = saved.rule.validate() {
+                tracing::warn!("Not restoring autoscaling of {}: {:#}", app, e);
+                continue;
+            }
+            let clients = AutoscaleClients {
+                client: clients.client.clone(),
+                machines: clients.machines.clone(),
+                metrics: clients.metrics.clone(),
+            };
+            self.autoscaler.enable(&self.runtime, clients, saved.rule);
+        }
+
+        let schedules = match self.scheduler.load() {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                tracing::error!("Failed to load saved schedules: {:#}", e);
+                return;
+            }
+        };
+        for schedule in schedules {
+            let name = schedule.name.clone();
+            let machines = self
+                .profiles
+                .get(&schedule.profile)
+                .map(|clients| clients.machines.clone());
+            if machines.is_none() {
+                tracing::warn!(
+                    "Schedule {} is inactive: profile {} is not configured",
+                    name,
+                    schedule.profile
+                );
+            }
+            if let Err(e) = self.scheduler.add(&self.runtime, machines, schedule) {
+                tracing::warn!("Skipping schedule {}: {:#}", name, e);
+            }
+        }
+    }
+
+    /// HTTP client statistics implementation.
+    fn client_stats(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let format = Self::get_param_str(&params, "format").unwrap_or("json");
+
+        match format {
+            "json" => Ok(serde_json::to_value(api::stats::snapshot())?),
+            "prometheus" => Ok(serde_json::json!({ "text": api::stats::prometheus_text() })),
+            _ => anyhow::bail!(
+                "Unknown format: {}. Valid formats are: json, prometheus",
+                format
+            ),
+        }
+    }
+
+    /// Destroy machine implementation.
+    ///
+    /// `confirm` must repeat the machine ID. Attached volumes are kept unless
+    /// `volumes` is `delete`.
+    fn destroy_machine(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+        let confirm = Self::get_param_str(&params, "confirm")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: confirm"))?;
+        if confirm != machine_id {
+            anyhow::bail!("confirm must match the machine ID ({})", machine_id);
+        }
+        let force = Self::get_param_bool(&params, "force", false);
+        let delete_volumes = match Self::get_param_str(&params, "volumes").unwrap_or("keep") {
+            "keep" => false,
+            "delete" => true,
+            other => anyhow::bail!(
+                "Unknown volumes option: {}. Valid options are: keep, delete",
+                other
+            ),
+        };
+        let nonce = Self::get_param_str(&params, "nonce").map(String::from);
+
+        let machines = self.clients(&params)?.machines.clone();
+        let id = machine_id.clone();
+
+        let volumes = self.run(async move {
+            let machine = machines.get_machine(&app_name, &id).await?;
+            if machine.state == "started" && !force {
+                anyhow::bail!(
+                    "Machine {} is running; stop it first or pass force: true",
+                    id
+                );
+            }
+            let volume_ids: Vec<String> = machine
+                .config
+                .map(|c| c.mounts.into_iter().filter_map(|m| m.volume).collect())
+                .unwrap_or_default();
+
+            machines
+                .destroy_machine(&app_name, &id, force, nonce.as_deref())
+                .await?;
+
+            let mut volumes = Vec::new();
+            for volume_id in volume_ids {
+                let entry = if !delete_volumes {
+                    serde_json::json!({ "id": volume_id, "status": "kept" })
+                } else {
+                    match machines.delete_volume(&app_name, &volume_id).await {
+                        Ok(_) => serde_json::json!({ "id": volume_id, "status": "deleted" }),
+                        Err(e) => serde_json::json!({
+                            "id": volume_id,
+                            "status": "failed",
+                            "error": format!("{:#}", e),
+                        }),
+                    }
+                };
+                volumes.push(entry);
+            }
+            Ok(volumes)
+        })?;
+
+        Ok(serde_json::json!({
+            "destroyed": true,
+            "machine_id": machine_id,
+            "volumes": volumes,
+        }))
+    }
+
+    /// Diagnostics implementation (token, API reachability, clock, orgs).
+    fn doctor(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let clients = self.clients(&params)?;
+        let client = clients.client.clone();
+        let machines = clients.machines.clone();
+
+        let report = self.run(async move { Ok(doctor::run(&*client, &machines).await) })?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Domains implementation (hostname, IPs, certificates, DNS guidance).
+    fn list_domains(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let client = self.clients(&params)?.client.clone();
+
+        let report = self.run(async move {
+            let (status, ips, certs) = futures::try_join!(
+                client.get_app_status(&app_name),
+                client.list_ips(&app_name),
+                client.list_certificates(&app_name),
+            )?;
+            Ok(domains::report(&app_name, status.app.hostname, ips, certs))
+        })?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Start a local TCP forwarder to an app's private service.
+    fn proxy_start(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let port = params
+            .get("port")
+            .and_then(|v| v.as_u64())
+            .filter(|p| (1..=65535).contains(p))
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: port (1-65535)"))?;
+        let machine_id = Self::get_param_str(&params, "machine_id").map(String::from);
+        let local_port = Self::get_param_i32(&params, "local_port", 0).clamp(0, 65535) as u16;
+        let bind = Self::get_param_str(&params, "bind").unwrap_or("127.0.0.1");
+        let bind: std::net::IpAddr = bind
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid bind address: {}", bind))?;
+
+        // A machine's 6PN address, or the app's internal DNS name
+        let target = match &machine_id {
+            Some(machine_id) => {
+                let machines = self.clients(&params)?.machines.clone();
+                let (app, id) = (app_name.clone(), machine_id.clone());
+                let machine = self.run(async move { machines.get_machine(&app, &id).await })?;
+                let ip = machine
+                    .private_ip
+                    .ok_or_else(|| anyhow::anyhow!("Machine {} has no private IP", machine_id))?;
+                format!("[{}]:{}", ip, port)
+            }
+            None => format!("{}.internal:{}", app_name, port),
+        };
+
+        let info = self.proxies.start(
+            &self.runtime,
+            app_name,
+            machine_id,
+            std::net::SocketAddr::new(bind, local_port),
+            target,
+        )?;
+        Ok(serde_json::to_value(info)?)
+    }
+
+    /// Stop a forwarder started by fly.proxy.start.
+    fn proxy_stop(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let id = params
+            .get("id")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: id"))?;
+        let proxy = self
+            .proxies
+            .stop(id)
+            .ok_or_else(|| FlyError::NotFound(format!("No proxy with id {}", id)))?;
+        Ok(serde_json::json!({ "stopped": true, "proxy": proxy }))
+    }
+
+    /// List running forwarders.
+    fn proxy_list(&self, _params: HashMap<String, Value>) -> Result<Value> {
+        let proxies = self.proxies.list();
+        Ok(serde_json::json!({
+            "proxies": proxies,
+            "count": proxies.len(),
+        }))
+    }
+
+    /// WireGuard peers implementation (list/create/remove).
+    fn handle_wireguard(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let action = Self::get_param_str(&params, "action").unwrap_or("list");
+        let org_slug = Self::get_param_str(&params, "org")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: org"))?
+            .to_string();
+        let name = Self::get_param_str(&params, "name").map(String::from);
+
+        let client = self.clients(&params)?.client.clone();
+
+        match action {
+            "list" => {
+                let peers =
+                    self.run(async move { client.list_wireguard_peers(&org_slug).await })?;
+                Ok(serde_json::json!({
+                    "peers": peers,
+                    "count": peers.len(),
+                }))
+            }
+            "create" => {
+                let name = name.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: name for action=create")
+                })?;
+                let region = Self::get_param_str(&params, "region")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Missing required parameter: region for action=create")
+                    })?
+                    .to_string();
+                let network = Self::get_param_str(&params, "network").map(String::from);
+
+                // Generate a keypair unless the caller keeps their own private key
+                let (private_key, pubkey) = match Self::get_param_str(&params, "pubkey") {
+                    Some(pubkey) => (None, pubkey.to_string()),
+                    None => {
+                        let keys = wireguard::generate_keypair()?;
+                        (Some(keys.private_key), keys.public_key)
+                    }
+                };
+
+                let peer = {
+                    let (name, region) = (name.clone(), region.clone());
+                    self.run(async move {
+                        client
+                            .add_wireguard_peer(
+                                &org_slug,
+                                &name,
+                                &region,
+                                &pubkey,
+                                network.as_deref(),
+                            )
+                            .await
+                    })?
+                };
+                let config = wireguard::render_config(private_key.as_deref(), &peer);
+
+                let mut result = serde_json::json!({
+                    "created": true,
+                    "name": name,
+                    "region": region,
+                    "peer": peer,
+                    "config": config,
+                });
+                if let Some(private_key) = private_key {
+                    result["private_key"] = Value::String(private_key);
+                    result["sensitive"] = serde_json::json!(["private_key", "config"]);
+                    result["warning"] = Value::String(
+                        "private_key is shown only once and grants access to the org's private network; store the config securely".into(),
+                    );
+                }
+                Ok(result)
+            }
+            "remove" => {
+                let name = name.ok_or_else(|| {
+                    anyhow::anyhow!("Missing required parameter: name for action=remove")
+                })?;
+                let result = {
+                    let name = name.clone();
+                    self.run(async move { client.remove_wireguard_peer(&org_slug, &name).await })?
+                };
+                Ok(serde_json::json!({
+                    "removed": true,
+                    "name": name,
+                    "result": result,
+                }))
+            }
+            _ => anyhow::bail!(
+                "Unknown action: {}. Valid actions are: list, create, remove",
+                action
+            ),
+        }
+    }
+
+    /// Image show/update implementation.
+    fn handle_image(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let action = Self::get_param_str(&params, "action").unwrap_or("show");
+        if !matches!(action, "show" | "update") {
+            anyhow::bail!(
+                "Unknown action: {}. Valid actions are: show, update",
+                action
+            );
+        }
+        let strategy =
+            Strategy::parse(Self::get_param_str(&params, "strategy").unwrap_or("rolling"))?;
+
+        let clients = self.clients(&params)?;
+        let (client, machines) = (clients.client.clone(), clients.machines.clone());
+
+        let (report, targets) = {
+            let (client, machines, app_name) = (client.clone(), machines.clone(), app_name.clone());
+            self.run(async move {
+                let (targets, latest) = futures::try_join!(
+                    machines.list_machines(&app_name),
+                    client.get_latest_image(&app_name),
+                )?;
+                Ok((image::report(&app_name, &targets, latest), targets))
+            })?
+        };
+
+        if action == "show" {
+            return Ok(serde_json::to_value(report)?);
+        }
+
+        let latest_image = report.latest_image.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "No latest image known for {}; deploy with fly.deploy",
+                app_name
+            )
+        })?;
+        let outdated: Vec<_> = targets
+            .into_iter()
+            .filter(|m| {
+                report
+                    .machines
+                    .iter()
+                    .any(|r| r.machine_id == m.id && r.update_available)
+            })
+            .collect();
+        if outdated.is_empty() {
+            return Ok(serde_json::json!({
+                "app": app_name,
+                "image": latest_image,
+                "status": "up_to_date",
+                "machines": [],
+            }));
+        }
+
+        let tracker = self.deploys.begin(&app_name, &latest_image, strategy);
+        let deployed = self.run(async move {
+            deploy::deploy_machines(
+                &machines,
+                &app_name,
+                &latest_image,
+                strategy,
+                &outdated,
+                &DeployOptions::default(),
+                &tracker,
+            )
+            .await
+        })?;
+        Ok(serde_json::to_value(deployed)?)
+    }
+
+    /// List an app's recent builds.
+    fn list_builds(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let limit = Self::get_param_i32(&params, "limit", 10);
+
+        let client = self.clients(&params)?.client.clone();
+
+        let builds = {
+            let app_name = app_name.clone();
+            self.run(async move { client.list_builds(&app_name, Some(limit)).await })?
+        };
+
+        Ok(serde_json::json!({
+            "app": app_name,
+            "builds": builds,
+            "count": builds.len(),
+        }))
+    }
+
+    /// Remote builder status for an org.
+    fn builder_status(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let org_slug = Self::get_param_str(&params, "org")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: org"))?
+            .to_string();
+
+        let clients = self.clients(&params)?;
+        let (client, machines) = (clients.client.clone(), clients.machines.clone());
+
+        let (builder, builder_machines) = {
+            let org_slug = org_slug.clone();
+            self.run(async move {
+                let builder = client.get_remote_builder(&org_slug).await?;
+                let builder_machines = match &builder.app {
+                    Some(app) => machines.list_machines(app).await?,
+                    None => Vec::new(),
+                };
+                Ok((builder, builder_machines))
+            })?
+        };
+
+        // Builders stop when idle and start on the next remote build
+        let status = if builder.app.is_none() {
+            "none"
+        } else if builder_machines.iter().any(|m| m.state == "started") {
+            "running"
+        } else if builder_machines.is_empty() {
+            "no_machines"
+        } else {
+            "idle"
+        };
+
+        let machines: Vec<Value> = builder_machines
+            .iter()
+            .map(|m| {
+                serde_json::json!({
+                    "id": m.id,
+                    "name": m.name,
+                    "state": m.state,
+                    "region": m.region,
+                    "image": m.config.as_ref().and_then(|c| c.image.clone()),
+                    "updated_at": m.updated_at,
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "org": org_slug,
+            "app": builder.app,
+            "image": builder.image,
+            "status": status,
+            "machines": machines,
+            "count": machines.len(),
+        }))
+    }
+
+    /// `params` with `deadline_ms` cut to what's left until `deadline`.
+    fn batch_entry_params(
+        mut params: HashMap<String, Value>,
+        deadline: Option<Instant>,
+    ) -> std::result::Result<HashMap<String, Value>, FlyError> {
+        let Some(deadline) = deadline else {
+            return Ok(params);
+        };
+        let left = deadline
+            .saturating_duration_since(Instant::now())
+            .as_millis() as i64;
+        if left == 0 {
+            return Err(FlyError::Unavailable(
+                "Batch exceeded its deadline_ms before this call started".into(),
+            ));
+        }
+        let own = params.get("deadline_ms").and_then(|v| v.as_i64());
+        if !matches!(own, Some(own) if own <= left) {
+            params.insert("deadline_ms".into(), serde_json::json!(left));
+        }
+        Ok(params)
+    }
+
+    /// Run several calls concurrently and report each result or error.
+    ///
+    /// Entries go through the same validation, caching, auditing and
+    /// redaction as top-level calls, and inherit the batch's `profile`,
+    /// `account`, `org` and `timeout_ms` unless they set their own. The
+    /// batch's `deadline_ms` covers all of its entries: each gets what's
+    /// left of it when it starts, or its own `deadline_ms` if that's shorter.
+    fn handle_batch(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let calls = params
+            .get("calls")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: calls"))?;
+        if calls.len() > MAX_BATCH_CALLS {
+            anyhow::bail!(
+                "Too many calls in batch: {} (max {})",
+                calls.len(),
+                MAX_BATCH_CALLS
+            );
+        }
+        let concurrency = Self::get_param_i32(&params, "concurrency", 4)
+            .clamp(1, MAX_BATCH_CONCURRENCY as i32) as usize;
+
+        let mut entries = Vec::with_capacity(calls.len());
+        for (index, call) in calls.iter().enumerate() {
+            let method = call
+                .get("method")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow::anyhow!("calls[{}] is missing method", index))?;
+            let nested = self
+                .registry
+                .resolve(method)
+                .is_some_and(|m| m.name == "fly.batch");
+            if nested {
+                anyhow::bail!("calls[{}]: fly.batch cannot be nested", index);
+            }
+            let mut entry_params: HashMap<String, Value> = match call.get("params") {
+                None | Some(Value::Null) => HashMap::new(),
+                Some(Value::Object(map)) => map.clone().into_iter().collect(),
+                Some(_) => anyhow::bail!("calls[{}].params must be an object", index),
+            };
+            for key in ["profile", "account", "org", "timeout_ms"] {
+                if let Some(value) = params.get(key) {
+                    entry_params
+                        .entry(key.to_string())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+            entries.push((method.to_string(), entry_params));
+        }
+
+        // Set from the batch's deadline_ms when it was dispatched
+        let deadline = CALL_DEADLINE.with(Cell::get);
+
+        // Handlers block on the runtime, so each worker is a thread
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<Value>>> = entries.iter().map(|_| Mutex::new(None)).collect();
+        std::thread::scope(|scope| {
+            for _ in 0..concurrency.min(entries.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((method, params)) = entries.get(index) else {
+                        break;
+                    };
+                    let result = Self::batch_entry_params(params.clone(), deadline)
+                        .and_then(|params| self.dispatch_one(method, params));
+                    let result = match result {
+                        Ok(value) => serde_json::json!({
+                            "method": method,
+                            "ok": true,
+                            "result": value,
+                        }),
+                        Err(error) => serde_json::json!({
+                            "method": method,
+                            "ok": false,
+                            "error": error.to_value()["error"],
+                        }),
+                    };
+                    *results[index].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                });
+            }
+        });
+
+        let results: Vec<Value> = results
+            .into_iter()
+            .map(|r| {
+                r.into_inner()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .unwrap_or(Value::Null)
+            })
+            .collect();
+        let succeeded = results.iter().filter(|r| r["ok"] == true).count();
+
+        Ok(serde_json::json!({
+            "results": results,
+            "count": results.len(),
+            "succeeded": succeeded,
+            "failed": results.len() - succeeded,
+        }))
+    }
+
+    /// Reconstruct an app's fly.toml from its live machines.
+    fn export_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let format = Self::get_param_str(&params, "format").unwrap_or("toml");
+
+        let machines = self.clients(&params)?.machines.clone();
+        let export = {
+            let app_name = app_name.clone();
+            self.run(async move {
+                let machines = machines.list_machines(&app_name).await?;
+                Ok(fly_toml::export(&app_name, &machines))
+            })?
+        };
+
+        let mut result = serde_json::json!({
+            "app": app_name,
+            "format": format,
+            "notes": export.notes,
+        });
+        match format {
+            "toml" => result["toml"] = Value::String(export.config.to_toml()?),
+            "json" => result["config"] = serde_json::to_value(&export.config)?,
+            _ => anyhow::bail!("Unknown format: {}. Valid formats are: toml, json", format),
+        }
+        Ok(result)
+    }
+
+    /// Build the machine plan for `fly.app.plan` and `fly.app.apply`.
+    fn app_plan(&self, params: &HashMap<String, Value>) -> Result<AppPlan> {
+        let app_name = Self::get_param_str(params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let (desired, ignored) = match (Self::get_param_str(params, "toml"), params.get("config")) {
+            (Some(text), _) => FlyToml::parse(text)?,
+            (None, Some(config)) => FlyToml::from_json(config.clone())?,
+            (None, None) => anyhow::bail!("Missing required parameter: toml or config"),
+        };
+        if let Some(name) = desired.app.as_deref() {
+            if name != app_name {
+                anyhow::bail!("fly.toml is for app {}, not {}", name, app_name);
+            }
+        }
+        let names = |key: &str| -> Option<BTreeSet<String>> {
+            params.get(key).and_then(|v| v.as_array()).map(|items| {
+                items
+                    .iter()
+                    .filter_map(|r| r.as_str().map(String::from))
+                    .collect()
+            })
+        };
+        let regions = names("regions");
+        if regions.as_ref().is_some_and(|r| r.is_empty()) {
+            anyhow::bail!("regions must list at least one region");
+        }
+        let secrets = names("secrets");
+
+        let clients = self.clients(params)?;
+        let (client, machines) = (clients.client.clone(), clients.machines.clone());
+
+        self.run(async move {
+            let live = machines.list_machines(&app_name).await?;
+            let mut plan = reconcile::plan_app(&app_name, &desired, regions.as_ref(), &live)?;
+            if let Some(secrets) = secrets {
+                let live_secrets = client.list_secrets(&app_name).await?;
+                plan.secrets.set = reconcile::missing_secrets(&secrets, &live_secrets);
+            }
+            if !ignored.is_empty() {
+                plan.notes
+                    .push(format!("Ignored fly.toml sections: {}", ignored.join(", ")));
+            }
+            Ok(plan)
+        })
+    }
+
+    /// Show what `fly.app.apply` would change, without changing anything.
+    fn plan_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let plan = self.app_plan(&params)?;
+        Ok(serde_json::to_value(plan)?)
+    }
+
+    /// Reconcile an app's machines toward a fly.toml.
+    fn apply_app(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let dry_run = Self::get_param_bool(&params, "dry_run", false);
+        let prune = Self::get_param_bool(&params, "prune", false);
+
+        let mut plan = self.app_plan(&params)?;
+        if !plan.secrets.set.is_empty() {
+            plan.notes.push(
+                "Missing secrets are not set by apply; set them with fly.secrets".to_string(),
+            );
+        }
+
+        let mut result = serde_json::json!({
+            "app": plan.app,
+            "dry_run": dry_run,
+            "changes": plan.changes(),
+            "plan": plan,
+        });
+        if !dry_run {
+            let machines = self.clients(&params)?.machines.clone();
+            let applied = self
+                .run(async move { Ok(reconcile::apply_app_plan(&machines, &plan, prune).await) })?;
+            result["status"] = Value::from(if applied.iter().any(|r| r.status == "failed") {
+                "failed"
+            } else if applied.iter().any(|r| r.status == "skipped") {
+                "partial"
+            } else {
+                "complete"
+            });
+            result["applied"] = serde_json::to_value(applied)?;
+        }
+        Ok(result)
+    }
+
+    /// Event filter from the `type`, `window_secs` and `limit` params.
+    fn event_filter(params: &HashMap<String, Value>) -> EventFilter {
+        let kinds = Self::get_param_str(params, "type")
+            .map(|kinds| {
+                kinds
+                    .split(',')
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let since = params
+            .get("window_secs")
+            .and_then(|v| v.as_i64())
+            .filter(|secs| *secs > 0)
+            .map(|secs| chrono::Utc::now() - chrono::Duration::seconds(secs));
+        EventFilter {
+            kinds,
+            since,
+            limit: Self::get_param_i32(params, "limit", 50).clamp(1, 500) as usize,
+        }
+    }
+
+    /// Recent events for one machine.
+    fn machine_events(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let machine_id = Self::get_param_str(&params, "machine_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: machine_id"))?
+            .to_string();
+        let filter = Self::event_filter(&params);
+
+        let machines = self.clients(&params)?.machines.clone();
+        let id = machine_id.clone();
+        let events = self.run(async move {
+            let events = machines.list_machine_events(&app_name, &id).await?;
+            Ok(filter.apply(events))
+        })?;
+
+        Ok(serde_json::json!({
+            "machine_id": machine_id,
+            "events": events,
+            "count": events.len(),
+        }))
+    }
+
+    /// Recent events across an app's machines, with per-machine exit counts.
+    fn app_events(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+        let filter = Self::event_filter(&params);
+
+        let machines = self.clients(&params)?.machines.clone();
+        let report = self.run(async move {
+            let list = machines.list_machines(&app_name).await?;
+            Ok(events::app_events(&machines, &app_name, list, &filter).await)
+        })?;
+
+        Ok(serde_json::to_value(report)?)
+    }
+
+    /// Crash-loop alerts raised by watchers (list/clear).
+    fn handle_alerts(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let action = Self::get_param_str(&params, "action").unwrap_or("list");
+        let app_name = Self::get_param_str(&params, "app");
+
+        match action {
+            "list" => {
+                let alerts = self.watches.alerts(app_name);
+                Ok(serde_json::json!({
+                    "alerts": alerts,
+                    "count": alerts.len(),
+                }))
+            }
+            "clear" => {
+                let cleared = self.watches.clear_alerts(app_name);
+                Ok(serde_json::json!({ "cleared": cleared }))
+            }
+            _ => anyhow::bail!("Unknown action: {}. Valid actions are: list, clear", action),
+        }
+    }
+
+    /// Start shipping an app's logs to a rotating file or syslog.
+    fn logship_start(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?
+            .to_string();
+
+        let sink = match (
+            Self::get_param_str(&params, "path"),
+            Self::get_param_str(&params, "syslog"),
+        ) {
+            (Some(path), None) => Sink::File {
+                path: std::path::PathBuf::from(shellexpand::tilde(path).to_string()),
+                max_bytes: Self::get_param_i32(&params, "max_mb", 10).max(1) as u64 * 1024 * 1024,
+                keep: Self::get_param_i32(&params, "keep", 5).clamp(0, 100) as usize,
+            },
+            (None, Some(addr)) => Sink::Syslog {
+                addr: addr.to_string(),
+            },
+            (Some(_), Some(_)) => anyhow::bail!("Pass either path or syslog, not both"),
+            (None, None) => anyhow::bail!("Missing required parameter: path or syslog"),
+        };
+
+        let source = match Self::get_param_str(&params, "source") {
+            Some(source) => logship::Source::parse(source)?,
+            None => logship::Source::from_env()?,
+        };
+
+        let client = self.clients(&params)?.client.clone();
+        let info = self
+            .shippers
+            .start(&self.runtime, client, app_name, source, sink)?;
+        Ok(serde_json::to_value(info)?)
+    }
+
+    /// Stop a log shipper started by fly.logship.start.
+    fn logship_stop(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: app"))?;
+        let shipper = self.shippers.stop(app_name).ok_or_else(|| {
+            FlyError::NotFound(format!("No log shipper running for {}", app_name))
+        })?;
+        Ok(serde_json::json!({ "stopped": true, "shipper": shipper }))
+    }
+
+    /// Running log shippers, optionally for one app.
+    fn logship_status(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let app_name = Self::get_param_str(&params, "app");
+        let shippers: Vec<_> = self
+            .shippers
+            .list()
+            .into_iter()
+            .filter(|s| app_name.is_none_or(|app| s.app == app))
+            .collect();
+        Ok(serde_json::json!({
+            "shippers": shippers,
+            "count": shippers.len(),
+        }))
+    }
+
+    /// Every method the daemon serves, with its handler and schema.
+    fn registry() -> Registry<Self> {
+        let mut registry = Registry::default();
+        registry
+            .add("health", "Daemon and Fly.io API health", Self::health)
+            .unlisted();
+        registry
+            .add("fly.apps", "List all Fly.io apps", Self::list_apps)
+            .default("limit", "integer", serde_json::json!(25))
+            .optional("cursor", "string")
+            .default("all", "boolean", serde_json::json!(false))
+            .default("no_cache", "boolean", serde_json::json!(false))
+            .optional("org", "string")
+            .optional("name_prefix", "string")
+            .optional("status", "string")
+            .optional("sort_by", "string")
+            .optional("fields", "string")
+            .alias("fly.app.list")
+            .cached(Duration::from_secs(30));
+        registry
+            .add(
+                "fly.status",
+                "Get status for a specific app",
+                Self::app_status,
+            )
+            .required("app", "string")
+            .default("summary", "boolean", serde_json::json!(false))
+            .alias("fly.app.status");
+        registry
+            .add(
+                "fly.releases",
+                "List release history for an app",
+                Self::list_releases,
+            )
+            .required("app", "string")
+            .default("limit", "integer", serde_json::json!(25))
+            .optional("cursor", "string")
+            .default("all", "boolean", serde_json::json!(false))
+            .optional("sort_by", "string")
+            .optional("fields", "string")
+            .alias("fly.app.releases");
+        registry
+            .add(
+                "fly.machines",
+                "List machines for an app",
+                Self::list_machines,
+            )
+            .required("app", "string")
+            .default("detailed", "boolean", serde_json::json!(false))
+            .optional("sort_by", "string")
+            .optional("fields", "string")
+            .alias("fly.machine.list");
+        registry
+            .add(
+                "fly.checks",
+                "List health check status for an app's machines",
+                Self::list_checks,
+            )
+            .required("app", "string")
+            .optional("machine_id", "string")
+            .default("failing_only", "boolean", serde_json::json!(false));
+        registry
+            .add(
+                "fly.fleet.status",
+                "Consolidated health across many apps",
+                Self::fleet_status,
+            )
+            .optional("apps", "array")
+            .optional("org", "string")
+            .default("concurrency", "integer", serde_json::json!(8));
+        registry
+            .add(
+                "fly.logs",
+                "Fetch app logs, optionally following new entries",
+                Self::get_logs,
+            )
+            .required("app", "string")
+            .optional("region", "string")
+            .optional("instance", "string")
+            .optional("cursor", "string")
+            .default("follow", "boolean", serde_json::json!(false))
+            .default("wait_secs", "integer", serde_json::json!(30))
+            .optional("level", "string")
+            .optional("pattern", "string")
+            .optional("start", "string")
+            .optional("end", "string")
+            .default("format", "string", serde_json::json!("json"));
+        registry
+            .add(
+                "fly.metrics",
+                "Query CPU, memory and network metrics for an app",
+                Self::app_metrics,
+            )
+            .required("app", "string")
+            .optional("org", "string")
+            .default("metric", "string", serde_json::json!("all"))
+            .default("range_secs", "integer", serde_json::json!(3600))
+            .default("step_secs", "integer", serde_json::json!(60));
+        registry
+            .add("fly.doctor", "Check token validity and scope, GraphQL and Machines API reachability, clock skew and org access", Self::doctor);
+        registry
+            .add("fly.user", "Get current user info", Self::get_user)
+            .default("no_cache", "boolean", serde_json::json!(false))
+            .cached(Duration::from_secs(300));
+        registry.add(
+            "fly.auth.whoami",
+            "Show the token's type, scope, user and org memberships",
+            Self::whoami,
+        );
+        registry
+            .add(
+                "fly.viewer_id",
+                "Get the authenticated viewer's ID",
+                Self::viewer_id,
+            )
+            .default("no_cache", "boolean", serde_json::json!(false))
+            .cached(Duration::from_secs(300));
+        registry.add(
+            "fly.orgs",
+            "List organizations with type, billing status and member counts",
+            Self::list_orgs,
+        );
+        registry
+            .add(
+                "fly.org",
+                "Get an organization with its apps and members",
+                Self::get_org,
+            )
+            .required("org", "string");
+        registry
+            .add(
+                "fly.billing",
+                "Get an organization's credit balance and current invoice",
+                Self::get_billing,
+            )
+            .required("org", "string");
+        registry
+            .add(
+                "fly.usage",
+                "Estimate an app's monthly cost by resource",
+                Self::app_usage,
+            )
+            .required("app", "string");
+        registry
+            .add("fly.regions", "List all Fly.io regions", Self::list_regions)
+            .optional("app", "string")
+            .default("no_cache", "boolean", serde_json::json!(false))
+            .cached(Duration::from_secs(3600))
+            // An app's placement changes with its machines
+            .cached_for_app(Duration::from_secs(30));
+        registry
+            .add(
+                "fly.platform.sizes",
+                "List VM sizes with CPU/memory options and pricing",
+                Self::platform_sizes,
+            )
+            .optional("cpu_kind", "string")
+            .default("no_cache", "boolean", serde_json::json!(false))
+            .cached(Duration::from_secs(3600));
+        registry
+            .add(
+                "fly.regions.set",
+                "Change which regions an app runs in",
+                Self::set_regions,
+            )
+            .required("app", "string")
+            .required("regions", "array")
+            .default("action", "string", serde_json::json!("set"))
+            .default("count", "integer", serde_json::json!(1))
+            .mutating()
+            .invalidates(&["fly.regions"]);
+        registry
+            .add(
+                "fly.secrets",
+                "Manage secrets for an app",
+                Self::handle_secrets,
+            )
+            .required("app", "string")
+            .default("action", "string", serde_json::json!("list"))
+            .optional("key", "string")
+            .optional("value", "string")
+            .optional("secrets", "object")
+            .optional("keys", "array")
+            .default("stage", "boolean", serde_json::json!(false))
+            .default("deploy", "boolean", serde_json::json!(false))
+            .optional("content", "string")
+            .default("format", "string", serde_json::json!("auto"))
+            .optional("target", "string")
+            .mutating_when(|params| {
+                match params.get("action").and_then(|v| v.as_str()) {
+                    None | Some("list") | Some("export_names") => false,
+                    // Without values, sync only compares the two apps
+                    Some("sync") => params.get("secrets").is_some_and(|v| !v.is_null()),
+                    _ => true,
+                }
+            })
+            .secret("value")
+            .secret_map("secrets")
+            .secret_file("content");
+        registry
+            .add(
+                "fly.volumes",
+                "Manage volumes for an app",
+                Self::handle_volumes,
+            )
+            .required("app", "string")
+            .default("action", "string", serde_json::json!("list"))
+            .optional("volume_id", "string")
+            .optional("name", "string")
+            .optional("region", "string")
+            .optional("size_gb", "integer")
+            .mutating_actions(&["create", "extend", "delete"]);
+        registry
+            .add(
+                "fly.ips",
+                "Manage IP addresses for an app",
+                Self::handle_ips,
+            )
+            .required("app", "string")
+            .default("action", "string", serde_json::json!("list"))
+            .optional("type", "string")
+            .optional("region", "string")
+            .optional("address", "string")
+            .mutating_actions(&["allocate", "release"]);
+        registry
+            .add(
+                "fly.certs",
+                "Manage TLS certificates for an app",
+                Self::handle_certs,
+            )
+            .required("app", "string")
+            .default("action", "string", serde_json::json!("list"))
+            .optional("hostname", "string")
+            .mutating_actions(&["add", "remove"]);
+        registry
+            .add("fly.domains", "Show an app's hostname, IPs, custom domains and the DNS records to create for them", Self::list_domains)
+            .required("app", "string");
+        registry
+            .add(
+                "fly.tokens",
+                "Manage deploy tokens for an app or org",
+                Self::handle_tokens,
+            )
+            .default("action", "string", serde_json::json!("list"))
+            .optional("app", "string")
+            .optional("org", "string")
+            .optional("name", "string")
+            .optional("expiry", "string")
+            .optional("id", "string")
+            .mutating_actions(&["create", "revoke"]);
+        registry
+            .add("fly.wireguard", "Manage WireGuard peers for an org (list, create, remove); create returns a wg-quick config", Self::handle_wireguard)
+            .default("action", "string", serde_json::json!("list"))
+            .required("org", "string")
+            .optional("name", "string")
+            .optional("region", "string")
+            .optional("pubkey", "string")
+            .optional("network", "string")
+            .mutating_actions(&["create", "remove"]);
+        registry
+            .add(
+                "fly.postgres",
+                "Manage Fly Postgres clusters",
+                Self::handle_postgres,
+            )
+            .default("action", "string", serde_json::json!("clusters"))
+            .optional("cluster", "string")
+            .optional("app", "string")
+            .optional("database", "string")
+            .default("variable", "string", serde_json::json!("DATABASE_URL"))
+            .mutating_actions(&["create_database", "attach", "detach"]);
+        registry
+            .add(
+                "fly.deploy",
+                "Roll an app's machines to a new image",
+                Self::deploy_image,
+            )
+            .required("app", "string")
+            .required("image", "string")
+            .default("strategy", "string", serde_json::json!("rolling"))
+            .default("health_timeout_secs", "integer", serde_json::json!(300))
+            .default("canary_percent", "integer", serde_json::json!(10))
+            .default("bake_secs", "integer", serde_json::json!(0))
+            .default("max_error_rate_increase", "number", serde_json::json!(0.01))
+            .default("max_latency_ratio", "number", serde_json::json!(1.5))
+            .default("max_error_rate", "number", serde_json::json!(0.05))
+            .default("max_p95_ms", "number", serde_json::json!(1000))
+            .default("require_bake_data", "boolean", serde_json::json!(true))
+            .default("background", "boolean", serde_json::json!(false))
+            .mutating()
+            .invalidates(&["fly.apps", "fly.regions"]);
+        registry
+            .add(
+                "fly.deploy.status",
+                "Progress of recent deployments",
+                Self::deploy_status,
+            )
+            .optional("id", "string")
+            .optional("app", "string");
+        registry
+            .add(
+                "fly.builds",
+                "List an app's recent builds with status, image, logs URL and duration",
+                Self::list_builds,
+            )
+            .required("app", "string")
+            .default("limit", "integer", serde_json::json!(10));
+        registry
+            .add(
+                "fly.builders",
+                "Show an org's remote builder app and the status of its machines",
+                Self::builder_status,
+            )
+            .required("org", "string");
+        registry
+            .add("fly.image", "Show each machine's image (registry, tag, digest) against the app's latest image, or roll outdated machines to it", Self::handle_image)
+            .required("app", "string")
+            .default("action", "string", serde_json::json!("show"))
+            .default("strategy", "string", serde_json::json!("rolling"))
+            .mutating_actions(&["update"])
+            .invalidates(&["fly.apps", "fly.regions"]);
+        registry
+            .add(
+                "fly.scale",
+                "Scale machine count or VM size for an app",
+                Self::handle_scale,
+            )
+            .required("app", "string")
+            .required("action", "string")
+            .optional("count", "integer")
+            .optional("region", "string")
+            .optional("cpu_kind", "string")
+            .optional("cpus", "integer")
+            .optional("memory_mb", "integer")
+            .mutating()
+            .invalidates(&["fly.regions"]);
+        registry
+            .add(
+                "fly.restart",
+                "Restart all machines for an app",
+                Self::restart_app,
+            )
+            .required("app", "string")
+            .default("strategy", "string", serde_json::json!("immediate"))
+            .default("batch_size", "integer", serde_json::json!(1))
+            .default("timeout", "integer", serde_json::json!(120))
+            .mutating();
+        registry
+            .add(
+                "fly.machine.create",
+                "Create a machine from a config",
+                Self::create_machine,
+            )
+            .required("app", "string")
+            .required("config", "object")
+            .mutating()
+            .invalidates(&["fly.regions"]);
+        registry
+            .add(
+                "fly.machine.update",
+                "Update a machine's config in place",
+                Self::update_machine,
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .required("config", "object")
+            .optional("nonce", "string")
+            .mutating()
+            .invalidates(&["fly.regions"]);
+        registry
+            .add(
+                "fly.machine.destroy",
+                "Destroy a machine (confirm must match the machine ID)",
+                Self::destroy_machine,
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .required("confirm", "string")
+            .default("force", "boolean", serde_json::json!(false))
+            .default("volumes", "string", serde_json::json!("keep"))
+            .optional("nonce", "string")
+            .mutating()
+            .invalidates(&["fly.regions"]);
+        registry
+            .add("fly.machine.start", "Start a stopped machine", |s, p| {
+                s.machine_lifecycle(p, "start")
+            })
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("nonce", "string")
+            .mutating();
+        registry
+            .add("fly.machine.stop", "Gracefully stop a machine", |s, p| {
+                s.machine_lifecycle(p, "stop")
+            })
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("nonce", "string")
+            .mutating();
+        registry
+            .add(
+                "fly.machine.start_all",
+                "Start every stopped machine in an app, optionally by region or metadata",
+                |s, p| s.machines_bulk(p, BulkAction::Start),
+            )
+            .required("app", "string")
+            .optional("region", "string")
+            .optional("metadata", "object")
+            .default("concurrency", "integer", serde_json::json!(4))
+            .mutating();
+        registry
+            .add(
+                "fly.machine.stop_all",
+                "Stop every running machine in an app, optionally by region or metadata",
+                |s, p| s.machines_bulk(p, BulkAction::Stop),
+            )
+            .required("app", "string")
+            .optional("region", "string")
+            .optional("metadata", "object")
+            .default("concurrency", "integer", serde_json::json!(4))
+            .mutating();
+        registry
+            .add("fly.machine.kill", "Kill a machine with SIGKILL", |s, p| {
+                s.machine_lifecycle(p, "kill")
+            })
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("nonce", "string")
+            .mutating();
+        registry
+            .add("fly.machine.restart", "Restart a single machine", |s, p| {
+                s.machine_lifecycle(p, "restart")
+            })
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("nonce", "string")
+            .mutating();
+        registry
+            .add(
+                "fly.machine.cordon",
+                "Stop routing proxy traffic to a machine",
+                |s, p| s.machine_lifecycle(p, "cordon"),
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("nonce", "string")
+            .mutating();
+        registry
+            .add(
+                "fly.machine.uncordon",
+                "Resume routing proxy traffic to a cordoned machine",
+                |s, p| s.machine_lifecycle(p, "uncordon"),
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("nonce", "string")
+            .mutating();
+        registry
+            .add(
+                "fly.machine.events",
+                "List recent events for a machine",
+                Self::machine_events,
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .optional("type", "string")
+            .optional("window_secs", "integer")
+            .default("limit", "integer", serde_json::json!(50));
+        registry
+            .add(
+                "fly.app.events",
+                "List recent machine events across an app",
+                Self::app_events,
+            )
+            .required("app", "string")
+            .optional("type", "string")
+            .optional("window_secs", "integer")
+            .default("limit", "integer", serde_json::json!(50));
+        registry
+            .add(
+                "fly.machine.metadata",
+                "Get, set or delete machine metadata",
+                Self::machine_metadata,
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .default("action", "string", serde_json::json!("get"))
+            .optional("key", "string")
+            .optional("value", "string")
+            .mutating_actions(&["set", "delete"]);
+        registry
+            .add(
+                "fly.machine.lease",
+                "Acquire, release or show a machine lease",
+                Self::machine_lease,
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .default("action", "string", serde_json::json!("show"))
+            .default("ttl", "integer", serde_json::json!(30))
+            .optional("description", "string")
+            .optional("nonce", "string")
+            .mutating_actions(&["acquire", "release"]);
+        registry
+            .add(
+                "fly.machine.wait",
+                "Wait until a machine reaches a state",
+                Self::machine_wait,
+            )
+            .required("app", "string")
+            .required("machine_id", "string")
+            .default("state", "string", serde_json::json!("started"))
+            .default("timeout_secs", "integer", serde_json::json!(60))
+            .default("interval_secs", "integer", serde_json::json!(2));
+        registry
+            .add("fly.exec", "Run a command on a machine", Self::exec_command)
+            .required("app", "string")
+            .required("machine_id", "string")
+            .required("command", "array")
+            .default("timeout", "integer", serde_json::json!(30))
+            .mutating();
+        registry
+            .add(
+                "fly.stop_reason",
+                "Explain why a machine last stopped",
+                Self::stop_reason,
+            )
+            .required("app", "string")
+            .required("machine_id", "string");
+        registry
+            .add(
+                "fly.app.create",
+                "Create an app in an organization",
+                Self::create_app,
+            )
+            .required("name", "string")
+            .required("org", "string")
+            .optional("region", "string")
+            .optional("network", "string")
+            .org_is_target()
+            .mutating()
+            .invalidates(&["fly.apps"]);
+        registry
+            .add(
+                "fly.app.delete",
+                "Delete an app (confirm must match the app name)",
+                Self::delete_app,
+            )
+            .required("app", "string")
+            .required("confirm", "string")
+            .mutating()
+            .invalidates(&["fly.apps", "fly.regions"]);
+        registry
+            .add(
+                "fly.app.move",
+                "Move an app to another organization",
+                Self::move_app,
+            )
+            .required("app", "string")
+            .required("org", "string")
+            .org_is_target()
+            .mutating()
+            .invalidates(&["fly.apps", "fly.regions"]);
+        registry
+            .add("fly.app.plan", "Compute the machines to create, update or destroy, regions to add or remove and secrets to set for a fly.toml, without changing anything", Self::plan_app)
+            .required("app", "string")
+            .optional("toml", "string")
+            .optional("config", "object")
+            .optional("regions", "array")
+            .optional("secrets", "array");
+        registry
+            .add("fly.app.apply", "Reconcile an app's machines (image, services, env, checks, VM size, processes, regions) toward a fly.toml and return the plan and per-machine results", Self::apply_app)
+            .required("app", "string")
+            .optional("toml", "string")
+            .optional("config", "object")
+            .optional("regions", "array")
+            .optional("secrets", "array")
+            .default("dry_run", "boolean", serde_json::json!(false))
+            .default("prune", "boolean", serde_json::json!(false))
+            .mutating_when(|params| !Self::get_param_bool(params, "dry_run", false))
+            .invalidates(&["fly.apps", "fly.regions"]);
+        registry
+            .add("fly.app.export", "Reconstruct an app's fly.toml (services, env, mounts, checks, VM sizes) from its live machine configs", Self::export_app)
+            .required("app", "string")
+            .default("format", "string", serde_json::json!("toml"));
+        registry
+            .add(
+                "fly.app.monitor",
+                "Watch an app's machines for crash loops",
+                Self::monitor_app,
+            )
+            .required("app", "string")
+            .default("restart_threshold", "integer", serde_json::json!(3))
+            .default("window_secs", "integer", serde_json::json!(300))
+            .default("duration", "integer", serde_json::json!(60))
+            .default("interval_secs", "integer", serde_json::json!(10))
+            .optional("webhook", "string");
+        registry
+            .add(
+                "fly.apply",
+                "Reconcile an app toward a desired config",
+                Self::apply_config,
+            )
+            .required("app", "string")
+            .required("config", "object")
+            .default("dry_run", "boolean", serde_json::json!(false))
+            .mutating_when(|params| !Self::get_param_bool(params, "dry_run", false))
+            .secret_map("config.secrets")
+            .invalidates(&["fly.apps", "fly.regions"]);
+        registry
+            .add(
+                "fly.autoscale",
+                "Enable, disable or inspect metric-driven autoscaling",
+                Self::handle_autoscale,
+            )
+            .default("action", "string", serde_json::json!("status"))
+            .optional("app", "string")
+            .default("metric", "string", serde_json::json!("cpu"))
+            .optional("target", "number")
+            .default("min", "integer", serde_json::json!(1))
+            .default("max", "integer", serde_json::json!(3))
+            .default("interval_secs", "integer", serde_json::json!(60))
+            .default("cooldown_secs", "integer", serde_json::json!(300))
+            // Enabled rules scale the app unattended
+            .mutating_actions(&["enable", "disable"]);
+        registry
+            .add(
+                "fly.schedule.create",
+                "Run a machine start, stop or scale on a cron schedule",
+                Self::schedule_create,
+            )
+            .required("name", "string")
+            .required("cron", "string")
+            .required("app", "string")
+            .required("action", "string")
+            .optional("count", "integer")
+            .optional("region", "string")
+            .optional("metadata", "object")
+            .mutating();
+        registry
+            .add(
+                "fly.schedule.list",
+                "List schedules with their next and recent runs",
+                Self::schedule_list,
+            )
+            .optional("app", "string");
+        registry
+            .add(
+                "fly.schedule.delete",
+                "Delete a schedule",
+                Self::schedule_delete,
+            )
+            .required("name", "string")
+            .mutating();
+        registry
+            .add(
+                "fly.schedule.run_now",
+                "Run a schedule once, now",
+                Self::schedule_run_now,
+            )
+            .required("name", "string")
+            .mutating()
+            .invalidates(&["fly.regions"]);
+        registry
+            .add(
+                "fly.state.export",
+                "Export saved daemon state for backup or migration",
+                Self::state_export,
+            )
+            .default("logs", "boolean", serde_json::json!(true));
+        registry
+            .add(
+                "fly.state.import",
+                "Import daemon state from fly.state.export",
+                Self::state_import,
+            )
+            .required("state", "object")
+            // Restarts the imported watchers, autoscale rules and schedules
+            .mutating();
+        registry
+            .add(
+                "fly.watch",
+                "Watch apps in the background and read state change events",
+                Self::handle_watch,
+            )
+            .default("action", "string", serde_json::json!("events"))
+            .optional("app", "string")
+            .default("interval_secs", "integer", serde_json::json!(15))
+            .default("since", "integer", serde_json::json!(0))
+            .default("limit", "integer", serde_json::json!(100))
+            .default("wait_secs", "integer", serde_json::json!(0))
+            .default("restart_threshold", "integer", serde_json::json!(3))
+            .default("window_secs", "integer", serde_json::json!(300));
+        registry
+            .add(
+                "fly.alerts",
+                "List or clear crash-loop alerts raised by watchers",
+                Self::handle_alerts,
+            )
+            .default("action", "string", serde_json::json!("list"))
+            .optional("app", "string");
+        registry
+            .add(
+                "fly.logship.start",
+                "Ship an app's logs to a rotating local file or a syslog server",
+                Self::logship_start,
+            )
+            .required("app", "string")
+            .optional("path", "string")
+            .optional("syslog", "string")
+            .optional("source", "string")
+            .default("max_mb", "integer", serde_json::json!(10))
+            .default("keep", "integer", serde_json::json!(5));
+        registry
+            .add(
+                "fly.logship.stop",
+                "Stop shipping an app's logs",
+                Self::logship_stop,
+            )
+            .required("app", "string");
+        registry
+            .add(
+                "fly.logship.status",
+                "List running log shippers with their counters",
+                Self::logship_status,
+            )
+            .optional("app", "string");
+        registry
+            .add("fly.proxy.start", "Forward a local TCP port to an app's private service (requires private network access)", Self::proxy_start)
+            .required("app", "string")
+            .required("port", "integer")
+            .optional("machine_id", "string")
+            .default("local_port", "integer", serde_json::json!(0))
+            .default("bind", "string", serde_json::json!("127.0.0.1"));
+        registry
+            .add(
+                "fly.proxy.stop",
+                "Stop a local TCP forwarder",
+                Self::proxy_stop,
+            )
+            .required("id", "integer");
+        registry.add(
+            "fly.proxy.list",
+            "List local TCP forwarders with connection counts",
+            Self::proxy_list,
+        );
+        registry
+            .add(
+                "fly.batch",
+                "Run several methods concurrently in one call and return each result or error",
+                Self::handle_batch,
+            )
+            .required("calls", "array")
+            .default("concurrency", "integer", serde_json::json!(4));
+        registry
+            .add(
+                "fly.client_stats",
+                "Request counts, status codes, retries and latencies of the Fly API clients",
+                Self::client_stats,
+            )
+            .default("format", "string", serde_json::json!("json"));
+        registry
+            .add(
+                "fly.audit",
+                "Query recent mutating calls from the audit log",
+                Self::audit_log,
+            )
+            .default("limit", "integer", serde_json::json!(50))
+            .optional("method", "string")
+            .optional("app", "string");
+
+        registry
+    }
+
+    /// Route a method call to its handler.
+    fn route(&self, method: &str, params: HashMap<String, Value>) -> Result<Value> {
+        match self.registry.resolve(method) {
+            Some(method) => (method.handler)(self, params),
+            None => anyhow::bail!("Unknown method: {}", method),
+        }
+    }
+}
+
+impl FgpService for FlyService {
+    fn name(&self) -> &str {
+        "fly"
+    }
+
+    fn version(&self) -> &str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn dispatch(&self, method: &str, params: HashMap<String, Value>) -> Result<Value> {
+        self.dispatch_one(method, params)
+            .map_err(|error| anyhow::anyhow!("{}", error.to_value()))
+    }
+
+    fn method_list(&self) -> Vec<MethodInfo> {
+        self.registry.method_list()
+    }
+
+    fn on_start(&self) -> Result<()> {
+        self.restore_state();
+
+        // Other accounts never hold up startup, even in strict mode
+        for (name, clients) in &self.profiles {
+            if *name != self.default_profile {
+                clients
+                    .connection
+                    .poll_in_background(&self.runtime, clients.client.clone());
+            }
+        }
+
+        let clients = self.default_clients();
+        let client = clients.client.clone();
+        if self.start_mode == StartMode::Lazy {
+            tracing::info!("FlyService starting, connecting to the API in the background...");
+            clients.connection.poll_in_background(&self.runtime, client);
+            return Ok(());
+        }
+
+        tracing::info!("FlyService starting, verifying API connection...");
+        let ping = client.clone();
+        let start = std::time::Instant::now();
+        let result = self.run(async move { ping.ping().await });
+        clients.connection.record(&result, start.elapsed());
+        match result {
+            Ok(true) => tracing::info!("Fly.io API connection verified"),
+            Ok(false) => tracing::warn!("Fly.io API returned empty viewer ID"),
+            Err(e) => {
+                tracing::error!("Failed to connect to Fly.io API: {}", e);
+                return Err(e);
+            }
+        }
+        clients.connection.poll_in_background(&self.runtime, client);
+        Ok(())
+    }
+
+    fn on_stop(&self) -> Result<()> {
+        self.watches.stop_all();
+        self.autoscaler.disable_all();
+        self.scheduler.stop_all();
+        self.proxies.stop_all();
+        self.shippers.stop_all();
+        for clients in self.profiles.values() {
+            clients.connection.stop();
+        }
+        Ok(())
+    }
+
+    fn health_check(&self) -> HashMap<String, HealthStatus> {
+        // Answered from the background pollers' caches, never a live ping
+        let health = |status: ConnectionStatus, lazy: bool| match status.age_secs {
+            None if lazy => HealthStatus::unhealthy("Degraded, still connecting: not tried yet"),
+            None => HealthStatus::unhealthy("Not checked yet"),
+            Some(age) if status.stale => {
+                HealthStatus::unhealthy(format!("Stale, last checked {}s ago", age))
+            }
+            Some(_) if status.healthy => {
+                HealthStatus::healthy_with_latency(status.latency_ms.unwrap_or_default())
+            }
+            Some(_) => {
+                let reason = status.last_error.unwrap_or_else(|| "unknown error".into());
+                if lazy && !status.connected {
+                    HealthStatus::unhealthy(format!("Degraded, still connecting: {}", reason))
+                } else {
+                    HealthStatus::unhealthy(reason)
+                }
+            }
+        };
+
+        let mut checks = HashMap::new();
+        for (name, clients) in &self.profiles {
+            let status = clients.connection.status();
+            if *name == self.default_profile {
+                let lazy = self.start_mode == StartMode::Lazy;
+                checks.insert("fly_api".into(), health(status, lazy));
+            } else {
+                // Other accounts always connect in the background
+                checks.insert(format!("fly_api.{}", name), health(status, true));
+            }
+        }
         checks
     }
 }
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use super::*;
+    use crate::api::MockFlyApi;
+
+    const SECRET: &str = "postgres-hunter2";
+
+    /// Log output captured while a test runs.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn service(api: MockFlyApi) -> FlyService {
+        let tokens = [("default".to_string(), "fo1_test".to_string())].into();
+        FlyService::with_api(Config::from_tokens(tokens).unwrap(), Arc::new(api)).unwrap()
+    }
+
+    #[test]
+    fn secret_values_never_reach_errors_or_logs() {
+        // An API error that echoes the rejected value back
+        let api = MockFlyApi::with_fixtures().fail(
+            "set_secrets",
+            &format!("Invalid value {} for DATABASE_URL", SECRET),
+        );
+        let service = service(api);
+        let params = serde_json::from_value(serde_json::json!({
+            "app": "web",
+            "action": "set_many",
+            "secrets": { "DATABASE_URL": SECRET },
+        }))
+        .unwrap();
+
+        let logs = Captured::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+        let error = tracing::subscriber::with_default(subscriber, || {
+            service.dispatch_one("fly.secrets", params).unwrap_err()
+        });
+
+        let message = error.to_value().to_string();
+        assert!(message.contains("DATABASE_URL"), "{}", message);
+        assert!(message.contains(redact::REDACTED), "{}", message);
+        assert!(!message.contains(SECRET), "{}", message);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("fly.secrets failed"), "{}", logs);
+        assert!(!logs.contains(SECRET), "{}", logs);
+    }
+
+    #[test]
+    fn batch_entries_share_the_batch_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(2_000);
+
+        let inherited = FlyService::batch_entry_params(HashMap::new(), Some(deadline)).unwrap();
+        let left = inherited["deadline_ms"].as_i64().unwrap();
+        assert!((1..=2_000).contains(&left), "{}", left);
+
+        let longer = [("deadline_ms".to_string(), serde_json::json!(60_000))].into();
+        let capped = FlyService::batch_entry_params(longer, Some(deadline)).unwrap();
+        assert!(capped["deadline_ms"].as_i64().unwrap() <= 2_000);
+
+        let shorter = [("deadline_ms".to_string(), serde_json::json!(500))].into();
+        let kept = FlyService::batch_entry_params(shorter, Some(deadline)).unwrap();
+        assert_eq!(kept["deadline_ms"], serde_json::json!(500));
+
+        let passed = Instant::now() - Duration::from_millis(1);
+        let error = FlyService::batch_entry_params(HashMap::new(), Some(passed)).unwrap_err();
+        assert!(matches!(error, FlyError::Unavailable(_)));
+    }
+
+    #[test]
+    fn org_routing_is_deterministic_and_skips_target_orgs() {
+        let tokens = ["default", "work-b", "work-a"]
+            .map(|name| (name.to_string(), format!("fo1_{}", name)))
+            .into();
+        let mut config = Config::from_tokens(tokens).unwrap();
+        for name in ["work-a", "work-b"] {
+            config
+                .profiles
+                .get_mut(name)
+                .unwrap()
+                .orgs
+                .push("acme".into());
+        }
+        let service =
+            FlyService::with_api(config.clone(), Arc::new(MockFlyApi::with_fixtures())).unwrap();
+        let params: HashMap<String, Value> =
+            [("org".to_string(), serde_json::json!("acme"))].into();
+        for _ in 0..10 {
+            assert_eq!(service.profile_name(&params), "work-a");
+        }
+
+        config
+            .profiles
+            .get_mut("default")
+            .unwrap()
+            .orgs
+            .push("acme".into());
+        let service = FlyService::with_api(config, Arc::new(MockFlyApi::with_fixtures())).unwrap();
+        assert_eq!(service.profile_name(&params), "default");
+
+        let moved = service.registry.resolve("fly.app.move").unwrap();
+        assert!(!moved.routes_by_org());
+        assert!(service
+            .registry
+            .resolve("fly.apps")
+            .unwrap()
+            .routes_by_org());
+    }
+
+    #[test]
+    fn mutation_cache_and_secrets_come_from_the_registry() {
+        let registry = FlyService::registry();
+        let method = |name| registry.resolve(name).unwrap();
+        let params =
+            |value: Value| -> HashMap<String, Value> { serde_json::from_value(value).unwrap() };
+
+        assert!(method("fly.scale").is_mutating(&params(serde_json::json!({ "app": "web" }))));
+        assert!(!method("fly.apps").is_mutating(&HashMap::new()));
+        let volumes = method("fly.volumes");
+        assert!(volumes.is_mutating(&params(serde_json::json!({ "action": "delete" }))));
+        assert!(!volumes.is_mutating(&params(serde_json::json!({ "action": "snapshots" }))));
+        assert!(!volumes.is_mutating(&HashMap::new()));
+        let apply = method("fly.apply");
+        assert!(apply.is_mutating(&HashMap::new()));
+        assert!(!apply.is_mutating(&params(serde_json::json!({ "dry_run": true }))));
+        let sync = params(serde_json::json!({ "action": "sync", "target": "web-staging" }));
+        assert!(!method("fly.secrets").is_mutating(&sync));
+
+        let regions = method("fly.regions");
+        assert_eq!(
+            regions.cache_ttl(&HashMap::new()),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            regions.cache_ttl(&params(serde_json::json!({ "app": "web" }))),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(method("fly.machines").cache_ttl(&HashMap::new()), None);
+
+        let set = params(serde_json::json!({
+            "action": "set",
+            "key": "DATABASE_URL",
+            "value": SECRET,
+        }));
+        assert_eq!(
+            redact::secret_values(method("fly.secrets").secrets(), &set),
+            [SECRET]
+        );
+        let config = params(serde_json::json!({
+            "app": "web",
+            "config": { "secrets": { "DATABASE_URL": SECRET } },
+        }));
+        let redacted = redact::redact_params(method("fly.apply").secrets(), &config);
+        assert!(!redacted.to_string().contains(SECRET));
+    }
+
+    #[test]
+    fn mutations_drop_the_cached_responses_they_make_stale() {
+        let api =
+            Arc::new(MockFlyApi::with_fixtures().respond("delete_app", serde_json::json!({})));
+        let tokens = [("default".to_string(), "fo1_test".to_string())].into();
+        let service =
+            FlyService::with_api(Config::from_tokens(tokens).unwrap(), api.clone()).unwrap();
+        let list_calls = || api.calls().iter().filter(|c| *c == "list_apps").count();
+
+        service.dispatch_one("fly.apps", HashMap::new()).unwrap();
+        service.dispatch_one("fly.apps", HashMap::new()).unwrap();
+        assert_eq!(list_calls(), 1);
+
+        let delete = [
+            ("app".to_string(), serde_json::json!("web")),
+            ("confirm".to_string(), serde_json::json!("web")),
+        ]
+        .into();
+        service.dispatch_one("fly.app.delete", delete).unwrap();
+        service.dispatch_one("fly.apps", HashMap::new()).unwrap();
+        assert_eq!(list_calls(), 2);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod dispatch_tests;
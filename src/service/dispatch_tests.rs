@@ -0,0 +1,1463 @@
+//! Every registered method, dispatched end to end against a `FakeFly`.
+//!
+//! Calls go through the registry's param checks, the handler and the real
+//! API clients, so each test covers what a daemon client would see: the
+//! result of a call that works, and the error code of one that doesn't.
+
+use super::*;
+use crate::api::fake_fly::{FakeFly, MACHINE_ID, NEW_MACHINE_ID, STOPPED_MACHINE_ID, VOLUME_ID};
+use crate::api::fixtures;
+use crate::api::fixtures::{APP, ORG};
+use serde_json::json;
+
+/// A service whose default profile talks to `fly`.
+fn service(fly: &FakeFly) -> FlyService {
+    let tokens = [("default".to_string(), "fo1_test".to_string())].into();
+    let mut config = Config::from_tokens(tokens).unwrap();
+    config.client = fly.client_config();
+    FlyService::in_memory(config).unwrap()
+}
+
+/// Dispatch `method`, returning its result or the error object callers get.
+fn call(service: &FlyService, method: &str, params: Value) -> std::result::Result<Value, Value> {
+    let params = serde_json::from_value(params).unwrap();
+    service
+        .dispatch_one(method, params)
+        .map_err(|error| error.to_value()["error"].clone())
+}
+
+/// The error code of a call that must fail.
+fn code(result: std::result::Result<Value, Value>) -> String {
+    let error = result.expect_err("call should fail");
+    error["code"].as_str().unwrap().to_string()
+}
+
+/// The error message of a call that must fail.
+fn message(result: std::result::Result<Value, Value>) -> String {
+    let error = result.expect_err("call should fail");
+    error["message"].as_str().unwrap().to_string()
+}
+
+fn machine_path(segments: &[&str]) -> String {
+    let mut path = format!("/v1/apps/{}/machines", APP);
+    for segment in segments {
+        path.push('/');
+        path.push_str(segment);
+    }
+    path
+}
+
+#[test]
+fn apps() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.apps", json!({ "name_prefix": "we" })).unwrap();
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["apps"][0]["name"], json!(APP));
+
+    let error = call(
+        &service,
+        "fly.apps",
+        json!({ "sort_by": "size", "no_cache": true }),
+    );
+    assert!(message(error).contains("Invalid sort_by: size"));
+}
+
+#[test]
+fn status() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.status", json!({ "app": APP })).unwrap();
+    assert_eq!(result["app"]["name"], json!(APP));
+    assert_eq!(result["machines"].as_array().unwrap().len(), 2);
+
+    fly.graphql_error("app(name:", "Could not find App \"gone\"");
+    assert_eq!(
+        code(call(&service, "fly.status", json!({ "app": "gone" }))),
+        "not_found"
+    );
+}
+
+#[test]
+fn releases() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.releases", json!({ "app": APP })).unwrap();
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["releases"][0]["user"], json!("dev@acme.test"));
+
+    assert_eq!(
+        code(call(&service, "fly.releases", json!({}))),
+        "missing_param"
+    );
+}
+
+#[test]
+fn machines() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.machines",
+        json!({ "app": APP, "detailed": true, "fields": "id,state" }),
+    )
+    .unwrap();
+    assert_eq!(
+        result["machines"],
+        json!([
+            { "id": MACHINE_ID, "state": "started" },
+            { "id": STOPPED_MACHINE_ID, "state": "stopped" }
+        ])
+    );
+
+    let error = call(
+        &service,
+        "fly.machines",
+        json!({ "app": APP, "detailed": "yes" }),
+    );
+    assert_eq!(code(error), "invalid_type");
+}
+
+#[test]
+fn checks() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.checks", json!({ "app": APP })).unwrap();
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["failing"], json!(0));
+
+    fly.respond(
+        "GET",
+        &machine_path(&[]),
+        404,
+        json!({ "error": "app not found" }),
+    );
+    assert_eq!(
+        code(call(&service, "fly.checks", json!({ "app": APP }))),
+        "not_found"
+    );
+}
+
+#[test]
+fn fleet_status() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.fleet.status", json!({})).unwrap();
+    assert_eq!(result["apps"][0]["app"], json!(APP));
+
+    let error = call(&service, "fly.fleet.status", json!({ "apps": APP }));
+    assert_eq!(code(error), "invalid_type");
+}
+
+#[test]
+fn logs() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.logs",
+        json!({ "app": APP, "level": "error" }),
+    )
+    .unwrap();
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["entries"][0]["message"], json!("GET /health 500"));
+    assert_eq!(result["cursor"], json!("tok_2"));
+
+    let error = call(&service, "fly.logs", json!({ "app": APP, "pattern": "(" }));
+    assert!(message(error).contains("Invalid pattern"));
+}
+
+#[test]
+fn metrics() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.metrics",
+        json!({ "app": APP, "metric": "cpu" }),
+    )
+    .unwrap();
+    assert_eq!(result["org"], json!(ORG));
+    assert_eq!(result["series"]["cpu"][0]["region"], json!("iad"));
+
+    let error = call(
+        &service,
+        "fly.metrics",
+        json!({ "app": APP, "range_secs": 86_400, "step_secs": 1 }),
+    );
+    assert!(message(error).contains("must not exceed"));
+}
+
+#[test]
+fn doctor() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.doctor", json!({})).unwrap();
+    assert!(result.is_object());
+
+    let error = call(&service, "fly.doctor", json!({ "profile": "staging" }));
+    assert!(message(error).contains("Unknown profile: staging"));
+}
+
+#[test]
+fn user() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.user", json!({})).unwrap();
+    assert_eq!(result["email"], json!("dev@acme.test"));
+
+    fly.graphql_error("viewer{", "Unauthorized");
+    let error = call(&service, "fly.user", json!({ "no_cache": true }));
+    assert_eq!(code(error), "auth");
+}
+
+#[test]
+fn auth_whoami() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.auth.whoami", json!({})).unwrap();
+    assert_eq!(result["profile"], json!("default"));
+    assert_eq!(result["organizations"][0]["slug"], json!(ORG));
+
+    fly.graphql_error(
+        "organizations(first:",
+        "Not authorized to list organizations",
+    );
+    assert_eq!(code(call(&service, "fly.auth.whoami", json!({}))), "auth");
+}
+
+#[test]
+fn viewer_id() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.viewer_id", json!({})).unwrap();
+    assert_eq!(result["id"], json!("user_dev"));
+
+    fly.graphql_error("viewer{", "Not authorized");
+    let error = call(&service, "fly.viewer_id", json!({ "no_cache": true }));
+    assert_eq!(code(error), "auth");
+}
+
+#[test]
+fn orgs() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.orgs", json!({})).unwrap();
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["organizations"][0]["slug"], json!(ORG));
+
+    fly.graphql_error("organizations(first:", "Internal server error");
+    assert_eq!(code(call(&service, "fly.orgs", json!({}))), "api_error");
+}
+
+#[test]
+fn org() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.org", json!({ "org": ORG })).unwrap();
+    assert_eq!(result["slug"], json!(ORG));
+
+    assert_eq!(code(call(&service, "fly.org", json!({}))), "missing_param");
+}
+
+#[test]
+fn billing() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.billing", json!({ "org": ORG })).unwrap();
+    assert_eq!(result["credit_balance"], json!(500));
+
+    fly.graphql("organization(slug:", json!({ "organization": null }));
+    let error = call(&service, "fly.billing", json!({ "org": "nope" }));
+    assert_eq!(code(error), "not_found");
+}
+
+#[test]
+fn usage() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.usage", json!({ "app": APP })).unwrap();
+    assert_eq!(result["app"], json!(APP));
+
+    fly.respond(
+        "GET",
+        &format!("/v1/apps/{}/volumes", APP),
+        403,
+        json!({ "error": "forbidden" }),
+    );
+    assert_eq!(
+        code(call(&service, "fly.usage", json!({ "app": APP }))),
+        "auth"
+    );
+}
+
+#[test]
+fn regions() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.regions", json!({})).unwrap();
+    assert_eq!(result["count"], json!(3));
+    let placement = call(&service, "fly.regions", json!({ "app": APP })).unwrap();
+    assert!(placement.to_string().contains("cdg"), "{}", placement);
+
+    let error = call(&service, "fly.regions", json!({ "app": 7 }));
+    assert_eq!(code(error), "invalid_type");
+}
+
+#[test]
+fn platform_sizes() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.platform.sizes",
+        json!({ "cpu_kind": "shared" }),
+    )
+    .unwrap();
+    assert_eq!(result["sizes"][0]["name"], json!("shared-cpu-1x"));
+
+    fly.graphql_error("platform{", "Internal server error");
+    let error = call(&service, "fly.platform.sizes", json!({ "no_cache": true }));
+    assert_eq!(code(error), "api_error");
+}
+
+#[test]
+fn regions_set() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "regions": ["iad", "lhr"] });
+    let result = call(&service, "fly.regions.set", params).unwrap();
+    assert_eq!(result["steps"][0]["action"], json!("create"));
+    assert_eq!(result["steps"][0]["machine_id"], json!(NEW_MACHINE_ID));
+    let created = &fly.requests("POST", &machine_path(&[]))[0];
+    let body: Value = serde_json::from_slice(&created.body).unwrap();
+    assert_eq!(body["region"], json!("lhr"));
+    assert_eq!(
+        fly.requests("DELETE", &machine_path(&[STOPPED_MACHINE_ID]))
+            .len(),
+        1
+    );
+
+    let error = call(
+        &service,
+        "fly.regions.set",
+        json!({ "app": APP, "regions": "iad" }),
+    );
+    assert_eq!(code(error), "invalid_type");
+}
+
+#[test]
+fn secrets() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let listed = call(&service, "fly.secrets", json!({ "app": APP })).unwrap();
+    assert_eq!(listed["count"], json!(2));
+    let params = json!({ "app": APP, "action": "set", "key": "API_KEY", "value": "s3cret" });
+    let set = call(&service, "fly.secrets", params).unwrap();
+    assert_eq!(set["set"], json!(true));
+    assert_eq!(set["staged"], json!(true));
+    let request = &fly.graphql_requests("{setSecrets(")[0];
+    assert_eq!(
+        request["variables"]["input"]["secrets"][0]["key"],
+        json!("API_KEY")
+    );
+
+    let params = json!({ "app": APP, "action": "set", "key": "API_KEY" });
+    assert!(message(call(&service, "fly.secrets", params)).contains("value for action=set"));
+    fly.graphql_error("{setSecrets(", "Validation failed: value is too long");
+    let params = json!({ "app": APP, "action": "set", "key": "API_KEY", "value": "x" });
+    assert_eq!(code(call(&service, "fly.secrets", params)), "api_error");
+}
+
+#[test]
+fn volumes() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let listed = call(&service, "fly.volumes", json!({ "app": APP })).unwrap();
+    assert_eq!(listed["volumes"][0]["id"], json!(VOLUME_ID));
+    let params = json!({ "app": APP, "action": "extend", "volume_id": VOLUME_ID, "size_gb": 3 });
+    let extended = call(&service, "fly.volumes", params).unwrap();
+    assert_eq!(extended["extended"], json!(true));
+    let path = format!("/v1/apps/{}/volumes/{}/extend", APP, VOLUME_ID);
+    let body: Value = serde_json::from_slice(&fly.requests("PUT", &path)[0].body).unwrap();
+    assert_eq!(body, json!({ "size_gb": 3 }));
+
+    let params = json!({ "app": APP, "action": "create", "name": "data", "region": "iad" });
+    let error = call(&service, "fly.volumes", params);
+    assert!(message(error).contains("size_gb for action=create"));
+}
+
+#[test]
+fn ips() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let listed = call(&service, "fly.ips", json!({ "app": APP })).unwrap();
+    assert_eq!(listed["ips"][0]["address"], json!("2a09:8280:1::a:1"));
+    let params = json!({ "app": APP, "action": "allocate", "type": "v6" });
+    let allocated = call(&service, "fly.ips", params).unwrap();
+    assert_eq!(allocated["allocated"], json!(true));
+    assert_eq!(fly.graphql_requests("{allocateIpAddress(").len(), 1);
+
+    let params = json!({ "app": APP, "action": "allocate", "type": "v5" });
+    assert!(message(call(&service, "fly.ips", params)).contains("Invalid type: v5"));
+}
+
+#[test]
+fn certs() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "action": "check", "hostname": "www.acme.test" });
+    let checked = call(&service, "fly.certs", params).unwrap();
+    assert_eq!(checked["ready"], json!(true));
+    let params = json!({ "app": APP, "action": "add", "hostname": "www.acme.test" });
+    let added = call(&service, "fly.certs", params).unwrap();
+    assert_eq!(added["certificate"]["hostname"], json!("www.acme.test"));
+
+    let error = call(
+        &service,
+        "fly.certs",
+        json!({ "app": APP, "action": "remove" }),
+    );
+    assert!(message(error).contains("hostname for action=remove"));
+}
+
+#[test]
+fn domains() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.domains", json!({ "app": APP })).unwrap();
+    assert!(result.to_string().contains("www.acme.test"), "{}", result);
+
+    fly.graphql_error("app(name:", "Could not find App \"gone\"");
+    assert_eq!(
+        code(call(&service, "fly.domains", json!({ "app": "gone" }))),
+        "not_found"
+    );
+}
+
+#[test]
+fn tokens() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let listed = call(&service, "fly.tokens", json!({ "app": APP })).unwrap();
+    assert_eq!(listed["tokens"][0]["id"], json!("tok_deploy"));
+    let params = json!({ "action": "create", "app": APP, "name": "ci" });
+    let created = call(&service, "fly.tokens", params).unwrap();
+    assert_eq!(created["secret"], json!("FlyV1 fm2_secret"));
+
+    let error = call(
+        &service,
+        "fly.tokens",
+        json!({ "action": "create", "name": "ci" }),
+    );
+    assert!(message(error).contains("requires app or org"));
+}
+
+#[test]
+fn wireguard() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let listed = call(&service, "fly.wireguard", json!({ "org": ORG })).unwrap();
+    assert_eq!(listed["peers"][0]["name"], json!("laptop"));
+    let pubkey = "Y2ktcHVibGljLWtleQ==";
+    let params =
+        json!({ "action": "create", "org": ORG, "name": "ci", "region": "iad", "pubkey": pubkey });
+    let created = call(&service, "fly.wireguard", params).unwrap();
+    assert!(created["config"].as_str().unwrap().contains("203.0.113.10"));
+    let request = &fly.graphql_requests("{addWireGuardPeer(")[0];
+    assert_eq!(request["variables"]["input"]["pubkey"], json!(pubkey));
+
+    let error = call(
+        &service,
+        "fly.wireguard",
+        json!({ "action": "create", "org": ORG }),
+    );
+    assert!(message(error).contains("name for action=create"));
+}
+
+#[test]
+fn postgres() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.postgres", json!({})).unwrap();
+    assert_eq!(result["count"], json!(0));
+
+    let error = call(&service, "fly.postgres", json!({ "action": "databases" }));
+    assert!(message(error).contains("cluster for action=databases"));
+}
+
+#[test]
+fn deploy() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let image = "registry.fly.io/web:deployment-02";
+    let params = json!({ "app": APP, "image": image, "health_timeout_secs": 5 });
+    let report = call(&service, "fly.deploy", params).unwrap();
+    assert!(report.to_string().contains(MACHINE_ID), "{}", report);
+    let update = &fly.requests("POST", &machine_path(&[MACHINE_ID]))[0];
+    let body: Value = serde_json::from_slice(&update.body).unwrap();
+    assert_eq!(body["config"]["image"], json!(image));
+
+    let params = json!({ "app": APP, "image": image, "bake_secs": 30 });
+    let error = call(&service, "fly.deploy", params);
+    assert!(message(error).contains("bake_secs only applies to strategy=canary"));
+}
+
+#[test]
+fn deploy_status() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "image": "web:v2", "health_timeout_secs": 5 });
+    call(&service, "fly.deploy", params).unwrap();
+    let result = call(&service, "fly.deploy.status", json!({ "app": APP })).unwrap();
+    assert_eq!(result["count"], json!(1));
+
+    let error = call(
+        &service,
+        "fly.deploy.status",
+        json!({ "id": "dep_missing" }),
+    );
+    assert!(message(error).contains("Deployment not found: dep_missing"));
+}
+
+#[test]
+fn builds() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.builds", json!({ "app": APP })).unwrap();
+    assert_eq!(result["builds"][0]["id"], json!("build_7"));
+
+    let error = call(
+        &service,
+        "fly.builds",
+        json!({ "app": APP, "limit": "ten" }),
+    );
+    assert_eq!(code(error), "invalid_type");
+}
+
+#[test]
+fn builders() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let machines = fixtures::machines();
+    fly.respond("GET", "/v1/apps/fly-builder-acme/machines", 200, machines);
+    let result = call(&service, "fly.builders", json!({ "org": ORG })).unwrap();
+    assert_eq!(result["status"], json!("running"));
+
+    assert_eq!(
+        code(call(&service, "fly.builders", json!({}))),
+        "missing_param"
+    );
+}
+
+#[test]
+fn image() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.image", json!({ "app": APP })).unwrap();
+    assert_eq!(result["app"], json!(APP));
+    assert_eq!(result["machines"].as_array().unwrap().len(), 2);
+
+    let error = call(
+        &service,
+        "fly.image",
+        json!({ "app": APP, "action": "pull" }),
+    );
+    assert_eq!(code(error), "invalid_value");
+}
+
+#[test]
+fn scale() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "action": "vm", "memory_mb": 512 });
+    let report = call(&service, "fly.scale", params).unwrap();
+    assert!(report.to_string().contains(MACHINE_ID), "{}", report);
+    let update = &fly.requests("POST", &machine_path(&[MACHINE_ID]))[0];
+    let body: Value = serde_json::from_slice(&update.body).unwrap();
+    assert_eq!(body["config"]["guest"]["memory_mb"], json!(512));
+
+    let error = call(&service, "fly.scale", json!({ "app": APP, "action": "vm" }));
+    assert!(message(error).contains("requires at least one of"));
+}
+
+#[test]
+fn restart() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    call(&service, "fly.restart", json!({ "app": APP })).unwrap();
+    assert_eq!(fly.graphql_requests("{restartApp(").len(), 1);
+
+    let error = call(
+        &service,
+        "fly.restart",
+        json!({ "app": APP, "strategy": "sideways" }),
+    );
+    assert_eq!(code(error), "invalid_value");
+}
+
+#[test]
+fn machine_create() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let config = json!({ "region": "iad", "image": "registry.fly.io/web:deployment-01HQ8Z" });
+    let params = json!({ "app": APP, "config": config });
+    let result = call(&service, "fly.machine.create", params).unwrap();
+    assert_eq!(result["machine"]["id"], json!(NEW_MACHINE_ID));
+    let body: Value =
+        serde_json::from_slice(&fly.requests("POST", &machine_path(&[]))[0].body).unwrap();
+    assert_eq!(body["region"], json!("iad"));
+
+    let params = json!({ "app": APP, "config": { "image": " " } });
+    let error = call(&service, "fly.machine.create", params);
+    assert!(message(error).contains("config.image must not be empty"));
+}
+
+#[test]
+fn machine_update() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({
+        "app": APP,
+        "machine_id": MACHINE_ID,
+        "config": { "env": { "LOG_LEVEL": "debug" } },
+        "nonce": "nonce_7f3a"
+    });
+    let result = call(&service, "fly.machine.update", params).unwrap();
+    assert_eq!(result["updated"], json!(true));
+    let update = &fly.requests("POST", &machine_path(&[MACHINE_ID]))[0];
+    let body: Value = serde_json::from_slice(&update.body).unwrap();
+    assert_eq!(body["config"]["env"]["LOG_LEVEL"], json!("debug"));
+    assert_eq!(
+        body["config"]["image"],
+        json!("registry.fly.io/web:deployment-01HQ8Z")
+    );
+    assert_eq!(update.headers["fly-machine-lease-nonce"], "nonce_7f3a");
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "config": "big" });
+    assert_eq!(
+        code(call(&service, "fly.machine.update", params)),
+        "invalid_type"
+    );
+}
+
+#[test]
+fn machine_destroy() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params =
+        json!({ "app": APP, "machine_id": STOPPED_MACHINE_ID, "confirm": STOPPED_MACHINE_ID });
+    call(&service, "fly.machine.destroy", params).unwrap();
+    let destroyed = &fly.requests("DELETE", &machine_path(&[STOPPED_MACHINE_ID]))[0];
+    assert_eq!(destroyed.url.query(), Some("force=false"));
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "confirm": MACHINE_ID });
+    let error = call(&service, "fly.machine.destroy", params);
+    assert!(message(error).contains("is running; stop it first"));
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "confirm": APP });
+    let error = call(&service, "fly.machine.destroy", params);
+    assert!(message(error).contains("confirm must match the machine ID"));
+}
+
+/// Run a single-machine lifecycle method and check the endpoint it hit.
+fn lifecycle(method: &str, machine_id: &str, endpoint: &str) {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": machine_id });
+    let result = call(&service, method, params).unwrap();
+    assert_eq!(result["machine_id"], json!(machine_id));
+    assert_eq!(
+        fly.requests("POST", &machine_path(&[machine_id, endpoint]))
+            .len(),
+        1
+    );
+
+    assert_eq!(
+        code(call(&service, method, json!({ "app": APP }))),
+        "missing_param"
+    );
+    fly.respond(
+        "POST",
+        &machine_path(&[machine_id, endpoint]),
+        412,
+        json!({ "error": "lease currently held" }),
+    );
+    let params = json!({ "app": APP, "machine_id": machine_id });
+    let error = call(&service, method, params).unwrap_err();
+    assert_eq!(error["code"], json!("api_error"));
+    assert_eq!(error["status"], json!(412));
+}
+
+#[test]
+fn machine_start() {
+    lifecycle("fly.machine.start", STOPPED_MACHINE_ID, "start");
+}
+
+#[test]
+fn machine_stop() {
+    lifecycle("fly.machine.stop", MACHINE_ID, "stop");
+}
+
+#[test]
+fn machine_kill() {
+    lifecycle("fly.machine.kill", MACHINE_ID, "signal");
+}
+
+#[test]
+fn machine_restart() {
+    lifecycle("fly.machine.restart", MACHINE_ID, "restart");
+}
+
+#[test]
+fn machine_cordon() {
+    lifecycle("fly.machine.cordon", MACHINE_ID, "cordon");
+}
+
+#[test]
+fn machine_uncordon() {
+    lifecycle("fly.machine.uncordon", MACHINE_ID, "uncordon");
+}
+
+#[test]
+fn machine_start_all() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.machine.start_all", json!({ "app": APP })).unwrap();
+    assert_eq!(result["succeeded"], json!(1));
+    assert_eq!(result["skipped"], json!(1));
+    assert_eq!(
+        fly.requests("POST", &machine_path(&[STOPPED_MACHINE_ID, "start"]))
+            .len(),
+        1
+    );
+
+    let params = json!({ "app": APP, "metadata": { "fly_process_group": 1 } });
+    let error = call(&service, "fly.machine.start_all", params);
+    assert!(message(error).contains("metadata.fly_process_group must be a string"));
+}
+
+#[test]
+fn machine_stop_all() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "region": "iad" });
+    let result = call(&service, "fly.machine.stop_all", params).unwrap();
+    assert_eq!(result["matched"], json!(1));
+    assert_eq!(
+        fly.requests("POST", &machine_path(&[MACHINE_ID, "stop"]))
+            .len(),
+        1
+    );
+
+    fly.respond(
+        "POST",
+        &machine_path(&[MACHINE_ID, "stop"]),
+        409,
+        json!({ "error": "busy" }),
+    );
+    let result = call(&service, "fly.machine.stop_all", json!({ "app": APP })).unwrap();
+    assert_eq!(result["failed"], json!(1));
+}
+
+#[test]
+fn machine_events() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": STOPPED_MACHINE_ID, "type": "exit" });
+    let result = call(&service, "fly.machine.events", params).unwrap();
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["events"][0]["oom_killed"], json!(true));
+
+    let params = json!({ "app": APP, "machine_id": "ffffffffffffff" });
+    assert_eq!(
+        code(call(&service, "fly.machine.events", params)),
+        "not_found"
+    );
+}
+
+#[test]
+fn app_events() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.app.events",
+        json!({ "app": APP, "type": "exit" }),
+    )
+    .unwrap();
+    assert_eq!(result["count"], json!(2));
+    assert_eq!(result["machines"].as_array().unwrap().len(), 2);
+
+    let path = machine_path(&[STOPPED_MACHINE_ID, "events"]);
+    fly.respond("GET", &path, 404, json!({ "error": "machine not found" }));
+    let result = call(&service, "fly.app.events", json!({ "app": APP })).unwrap();
+    assert!(result["machines"][1]["error"]
+        .as_str()
+        .unwrap()
+        .contains("failed"));
+}
+
+#[test]
+fn machine_metadata() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID });
+    let result = call(&service, "fly.machine.metadata", params).unwrap();
+    assert!(
+        result.to_string().contains("fly_process_group"),
+        "{}",
+        result
+    );
+    let params = json!({
+        "app": APP,
+        "machine_id": MACHINE_ID,
+        "action": "set",
+        "key": "role",
+        "value": "primary"
+    });
+    call(&service, "fly.machine.metadata", params).unwrap();
+    let set = &fly.requests("POST", &machine_path(&[MACHINE_ID, "metadata", "role"]))[0];
+    let body: Value = serde_json::from_slice(&set.body).unwrap();
+    assert_eq!(body, json!({ "value": "primary" }));
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "action": "set", "key": "role" });
+    assert!(message(call(&service, "fly.machine.metadata", params)).contains("value"));
+}
+
+#[test]
+fn machine_lease() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "action": "acquire", "ttl": 60 });
+    let result = call(&service, "fly.machine.lease", params).unwrap();
+    assert_eq!(result["lease"]["nonce"], json!("nonce_7f3a"));
+    let params =
+        json!({ "app": APP, "machine_id": MACHINE_ID, "action": "release", "nonce": "nonce_7f3a" });
+    call(&service, "fly.machine.lease", params).unwrap();
+    let released = &fly.requests("DELETE", &machine_path(&[MACHINE_ID, "lease"]))[0];
+    assert_eq!(released.headers["fly-machine-lease-nonce"], "nonce_7f3a");
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "action": "release" });
+    assert!(
+        message(call(&service, "fly.machine.lease", params)).contains("nonce for action=release")
+    );
+}
+
+#[test]
+fn machine_wait() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "timeout_secs": 5 });
+    let result = call(&service, "fly.machine.wait", params).unwrap();
+    assert_eq!(result["state"], json!("started"));
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "state": "asleep" });
+    assert!(message(call(&service, "fly.machine.wait", params)).contains("Invalid state: asleep"));
+}
+
+#[test]
+fn exec() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "command": ["echo", "ok"] });
+    let result = call(&service, "fly.exec", params).unwrap();
+    assert_eq!(result["success"], json!(true));
+    assert_eq!(result["stdout"], json!("ok\n"));
+
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID, "command": [] });
+    assert!(message(call(&service, "fly.exec", params)).contains("command must not be empty"));
+}
+
+#[test]
+fn stop_reason() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "machine_id": STOPPED_MACHINE_ID });
+    let result = call(&service, "fly.stop_reason", params).unwrap();
+    assert!(!result.to_string().contains("unknown"), "{}", result);
+
+    fly.graphql_error("app(name:", "Could not find Machine \"ffffffffffffff\"");
+    let params = json!({ "app": APP, "machine_id": "ffffffffffffff" });
+    assert_eq!(code(call(&service, "fly.stop_reason", params)), "not_found");
+}
+
+#[test]
+fn app_create() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "name": "web-2", "org": ORG, "region": "cdg" });
+    call(&service, "fly.app.create", params).unwrap();
+    let request = &fly.graphql_requests("{createApp(")[0];
+    assert_eq!(request["variables"]["input"]["name"], json!("web-2"));
+    assert_eq!(
+        request["variables"]["input"]["preferredRegion"],
+        json!("cdg")
+    );
+
+    let error = call(&service, "fly.app.create", json!({ "name": "web-2" }));
+    assert_eq!(code(error), "missing_param");
+}
+
+#[test]
+fn app_delete() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.app.delete",
+        json!({ "app": APP, "confirm": APP }),
+    )
+    .unwrap();
+    assert_eq!(result["deleted"], json!(true));
+    assert_eq!(fly.graphql_requests("{deleteApp(").len(), 1);
+
+    let error = call(
+        &service,
+        "fly.app.delete",
+        json!({ "app": APP, "confirm": "wbe" }),
+    );
+    assert!(message(error).contains("confirm must match the app name (web)"));
+}
+
+#[test]
+fn app_move() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    call(
+        &service,
+        "fly.app.move",
+        json!({ "app": APP, "org": "acme-eu" }),
+    )
+    .unwrap();
+    assert_eq!(fly.graphql_requests("{moveApp(").len(), 1);
+
+    fly.graphql_error("{moveApp(", "Could not find Organization \"nope\"");
+    let error = call(
+        &service,
+        "fly.app.move",
+        json!({ "app": APP, "org": "nope" }),
+    );
+    assert_eq!(code(error), "not_found");
+}
+
+#[test]
+fn app_plan() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let toml = "app = \"web\"\n\n[build]\nimage = \"registry.fly.io/web:deployment-02\"\n";
+    let plan = call(
+        &service,
+        "fly.app.plan",
+        json!({ "app": APP, "toml": toml }),
+    )
+    .unwrap();
+    assert_eq!(plan["summary"]["update"], json!(2));
+    assert_eq!(plan["machines"][1]["fields"], json!(["image"]));
+    assert!(fly
+        .requests("POST", &machine_path(&[MACHINE_ID]))
+        .is_empty());
+
+    let error = call(
+        &service,
+        "fly.app.plan",
+        json!({ "app": "api", "toml": toml }),
+    );
+    assert!(message(error).contains("fly.toml is for app web, not api"));
+}
+
+#[test]
+fn app_apply() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let toml = "app = \"web\"\n\n[build]\nimage = \"registry.fly.io/web:deployment-02\"\n";
+    let result = call(
+        &service,
+        "fly.app.apply",
+        json!({ "app": APP, "toml": toml }),
+    )
+    .unwrap();
+    assert_eq!(result["status"], json!("complete"), "{}", result);
+    let update = &fly.requests("POST", &machine_path(&[MACHINE_ID]))[0];
+    let body: Value = serde_json::from_slice(&update.body).unwrap();
+    assert_eq!(
+        body["config"]["image"],
+        json!("registry.fly.io/web:deployment-02")
+    );
+
+    let params = json!({ "app": APP, "toml": toml, "regions": [] });
+    let error = call(&service, "fly.app.apply", params);
+    assert!(message(error).contains("regions must list at least one region"));
+}
+
+#[test]
+fn app_export() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(&service, "fly.app.export", json!({ "app": APP })).unwrap();
+    let toml = result["toml"].as_str().unwrap();
+    assert!(
+        toml.contains("registry.fly.io/web:deployment-01HQ8Z"),
+        "{}",
+        toml
+    );
+
+    let error = call(
+        &service,
+        "fly.app.export",
+        json!({ "app": APP, "format": "yaml" }),
+    );
+    assert_eq!(code(error), "invalid_value");
+}
+
+#[test]
+fn app_monitor() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let result = call(
+        &service,
+        "fly.app.monitor",
+        json!({ "app": APP, "duration": 0 }),
+    )
+    .unwrap();
+    assert_eq!(result["polls"], json!(1));
+    assert_eq!(result["machines_watched"], json!(2));
+
+    let error = call(
+        &service,
+        "fly.app.monitor",
+        json!({ "app": APP, "restart_threshold": 0 }),
+    );
+    assert!(message(error).contains("restart_threshold must be at least 1"));
+}
+
+#[test]
+fn apply() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let config = json!({ "env": { "LOG_LEVEL": "debug" } });
+    let params = json!({ "app": APP, "config": config, "dry_run": true });
+    let result = call(&service, "fly.apply", params).unwrap();
+    assert_eq!(result["dry_run"], json!(true));
+    assert!(result["changes"].as_u64().unwrap() > 0, "{}", result);
+    assert!(fly
+        .requests("POST", &machine_path(&[MACHINE_ID]))
+        .is_empty());
+
+    let config = json!({ "count": 1, "regions": ["iad", "cdg"] });
+    let error = call(
+        &service,
+        "fly.apply",
+        json!({ "app": APP, "config": config }),
+    );
+    assert!(message(error).contains("count (1) must be at least the number of regions (2)"));
+}
+
+#[test]
+fn autoscale() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "action": "enable", "app": APP, "target": 0.7, "max": 4 });
+    let enabled = call(&service, "fly.autoscale", params).unwrap();
+    assert_eq!(enabled["rule"]["max"], json!(4));
+    let status = call(&service, "fly.autoscale", json!({ "app": APP })).unwrap();
+    assert_eq!(status["count"], json!(1));
+    call(
+        &service,
+        "fly.autoscale",
+        json!({ "action": "disable", "app": APP }),
+    )
+    .unwrap();
+
+    let error = call(
+        &service,
+        "fly.autoscale",
+        json!({ "action": "enable", "app": APP }),
+    );
+    assert!(message(error).contains("target for action=enable"));
+}
+
+/// Create a schedule that stops the fixture app's machines nightly.
+fn nightly_stop(service: &FlyService) -> Value {
+    let params =
+        json!({ "name": "nightly-stop", "cron": "0 2 * * *", "app": APP, "action": "stop" });
+    call(service, "fly.schedule.create", params).unwrap()
+}
+
+#[test]
+fn schedule_create() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let created = nightly_stop(&service);
+    assert_eq!(created["created"], json!(true));
+    assert!(created["schedule"]["next_run"].is_string(), "{}", created);
+
+    let params = json!({ "name": "pause", "cron": "0 2 * * *", "app": APP, "action": "pause" });
+    let error = call(&service, "fly.schedule.create", params);
+    assert_eq!(code(error), "invalid_value");
+}
+
+#[test]
+fn schedule_list() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    nightly_stop(&service);
+    let listed = call(&service, "fly.schedule.list", json!({ "app": APP })).unwrap();
+    assert_eq!(listed["count"], json!(1));
+    let other = call(&service, "fly.schedule.list", json!({ "app": "api" })).unwrap();
+    assert_eq!(other["count"], json!(0));
+
+    assert_eq!(
+        code(call(&service, "fly.schedule.list", json!({ "app": 7 }))),
+        "invalid_type"
+    );
+}
+
+#[test]
+fn schedule_delete() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    nightly_stop(&service);
+    let result = call(
+        &service,
+        "fly.schedule.delete",
+        json!({ "name": "nightly-stop" }),
+    )
+    .unwrap();
+    assert_eq!(result["deleted"], json!(true));
+    let listed = call(&service, "fly.schedule.list", json!({})).unwrap();
+    assert_eq!(listed["count"], json!(0));
+
+    assert_eq!(
+        code(call(&service, "fly.schedule.delete", json!({}))),
+        "missing_param"
+    );
+}
+
+#[test]
+fn schedule_run_now() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    nightly_stop(&service);
+    let result = call(
+        &service,
+        "fly.schedule.run_now",
+        json!({ "name": "nightly-stop" }),
+    )
+    .unwrap();
+    assert_eq!(result["name"], json!("nightly-stop"));
+    assert_eq!(
+        fly.requests("POST", &machine_path(&[MACHINE_ID, "stop"]))
+            .len(),
+        1
+    );
+
+    let error = call(
+        &service,
+        "fly.schedule.run_now",
+        json!({ "name": "weekly" }),
+    );
+    assert!(message(error).contains("weekly"));
+}
+
+#[test]
+fn state_export() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    nightly_stop(&service);
+    let export = call(&service, "fly.state.export", json!({ "logs": false })).unwrap();
+    assert!(
+        export["documents"].to_string().contains("nightly-stop"),
+        "{}",
+        export
+    );
+
+    assert_eq!(
+        code(call(&service, "fly.state.export", json!({ "logs": "no" }))),
+        "invalid_type"
+    );
+}
+
+#[test]
+fn state_import() {
+    let fly = FakeFly::start();
+    let source = service(&fly);
+    nightly_stop(&source);
+    let export = call(&source, "fly.state.export", json!({})).unwrap();
+
+    let service = service(&fly);
+    let result = call(&service, "fly.state.import", json!({ "state": export })).unwrap();
+    assert_eq!(result["imported"], json!(true));
+    let listed = call(&service, "fly.schedule.list", json!({})).unwrap();
+    assert_eq!(listed["schedules"][0]["name"], json!("nightly-stop"));
+
+    let error = call(
+        &service,
+        "fly.state.import",
+        json!({ "state": { "version": "two" } }),
+    );
+    assert!(message(error).contains("Invalid state"));
+}
+
+#[test]
+fn watch() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "action": "start", "app": APP, "interval_secs": 60 });
+    call(&service, "fly.watch", params).unwrap();
+    let listed = call(&service, "fly.watch", json!({ "action": "list" })).unwrap();
+    assert_eq!(listed["count"], json!(1));
+    let stopped = call(
+        &service,
+        "fly.watch",
+        json!({ "action": "stop", "app": APP }),
+    )
+    .unwrap();
+    assert_eq!(stopped["stopped"], json!(true));
+
+    let error = call(&service, "fly.watch", json!({ "action": "start" }));
+    assert!(message(error).contains("app for action=start"));
+}
+
+#[test]
+fn alerts() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let listed = call(&service, "fly.alerts", json!({ "app": APP })).unwrap();
+    assert_eq!(listed["count"], json!(0));
+    let cleared = call(&service, "fly.alerts", json!({ "action": "clear" })).unwrap();
+    assert_eq!(cleared["cleared"], json!(0));
+
+    let error = call(&service, "fly.alerts", json!({ "action": "mute" }));
+    assert_eq!(code(error), "invalid_value");
+}
+
+/// A log file path unique to `test`.
+fn log_path(test: &str) -> String {
+    let path = std::env::temp_dir().join(format!("fgp-fly-{}-{}.log", test, std::process::id()));
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn logship_start() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "path": log_path("logship-start"), "source": "api" });
+    let started = call(&service, "fly.logship.start", params).unwrap();
+    assert_eq!(started["app"], json!(APP));
+    call(&service, "fly.logship.stop", json!({ "app": APP })).unwrap();
+
+    let params = json!({ "app": APP, "path": "/tmp/web.log", "syslog": "127.0.0.1:514" });
+    let error = call(&service, "fly.logship.start", params);
+    assert!(message(error).contains("Pass either path or syslog, not both"));
+}
+
+#[test]
+fn logship_stop() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "path": log_path("logship-stop"), "source": "api" });
+    call(&service, "fly.logship.start", params).unwrap();
+    let stopped = call(&service, "fly.logship.stop", json!({ "app": APP })).unwrap();
+    assert_eq!(stopped["stopped"], json!(true));
+
+    assert_eq!(
+        code(call(&service, "fly.logship.stop", json!({ "app": APP }))),
+        "not_found"
+    );
+}
+
+#[test]
+fn logship_status() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "path": log_path("logship-status"), "source": "api" });
+    call(&service, "fly.logship.start", params).unwrap();
+    let status = call(&service, "fly.logship.status", json!({ "app": APP })).unwrap();
+    assert_eq!(status["count"], json!(1));
+    let other = call(&service, "fly.logship.status", json!({ "app": "api" })).unwrap();
+    assert_eq!(other["count"], json!(0));
+    call(&service, "fly.logship.stop", json!({ "app": APP })).unwrap();
+
+    assert_eq!(
+        code(call(&service, "fly.logship.status", json!({ "app": 7 }))),
+        "invalid_type"
+    );
+}
+
+#[test]
+fn proxy_start() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "port": 5432, "machine_id": MACHINE_ID });
+    let started = call(&service, "fly.proxy.start", params).unwrap();
+    assert_eq!(started["target"], json!("[fdaa:0:1:a7b:1::2]:5432"));
+
+    let params = json!({ "app": APP, "port": 5432, "bind": "localhost:0" });
+    assert!(message(call(&service, "fly.proxy.start", params)).contains("Invalid bind address"));
+}
+
+#[test]
+fn proxy_stop() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "port": 8080, "machine_id": MACHINE_ID });
+    let started = call(&service, "fly.proxy.start", params).unwrap();
+    let stopped = call(&service, "fly.proxy.stop", json!({ "id": started["id"] })).unwrap();
+    assert_eq!(stopped["stopped"], json!(true));
+
+    assert_eq!(
+        code(call(
+            &service,
+            "fly.proxy.stop",
+            json!({ "id": started["id"] })
+        )),
+        "not_found"
+    );
+}
+
+#[test]
+fn proxy_list() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let params = json!({ "app": APP, "port": 8080, "machine_id": MACHINE_ID });
+    call(&service, "fly.proxy.start", params).unwrap();
+    let listed = call(&service, "fly.proxy.list", json!({})).unwrap();
+    assert_eq!(
+        listed["proxies"][0]["target"],
+        json!("[fdaa:0:1:a7b:1::2]:8080")
+    );
+
+    let error = call(&service, "fly.proxy.lst", json!({})).unwrap_err();
+    assert_eq!(error["code"], json!("unknown_method"));
+    assert_eq!(error["suggestions"][0], json!("fly.proxy.list"));
+}
+
+#[test]
+fn batch() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    let calls = json!([
+        { "method": "fly.status", "params": { "app": APP } },
+        { "method": "fly.org", "params": {} }
+    ]);
+    let result = call(&service, "fly.batch", json!({ "calls": calls })).unwrap();
+    assert_eq!(result["succeeded"], json!(1));
+    assert_eq!(
+        result["results"][1]["error"]["code"],
+        json!("missing_param")
+    );
+
+    let calls = json!([{ "method": "fly.batch", "params": { "calls": [] } }]);
+    let error = call(&service, "fly.batch", json!({ "calls": calls }));
+    assert!(message(error).contains("fly.batch cannot be nested"));
+}
+
+#[test]
+fn client_stats() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    call(&service, "fly.status", json!({ "app": APP })).unwrap();
+    let stats = call(&service, "fly.client_stats", json!({})).unwrap();
+    assert!(
+        stats["apis"]["graphql"]["requests"].as_u64().unwrap() > 0,
+        "{}",
+        stats
+    );
+
+    let error = call(&service, "fly.client_stats", json!({ "format": "xml" }));
+    assert_eq!(code(error), "invalid_value");
+}
+
+#[test]
+fn audit() {
+    let fly = FakeFly::start();
+    let service = service(&fly);
+
+    // The log may start with entries imported from the host's legacy file
+    let query = json!({ "app": APP, "limit": 100_000 });
+    let before = call(&service, "fly.audit", query.clone()).unwrap();
+    let params = json!({ "app": APP, "machine_id": MACHINE_ID });
+    call(&service, "fly.machine.restart", params).unwrap();
+    let audit = call(&service, "fly.audit", query).unwrap();
+    assert_eq!(
+        audit["count"].as_u64(),
+        before["count"].as_u64().map(|n| n + 1)
+    );
+    assert_eq!(audit["entries"][0]["method"], json!("fly.machine.restart"));
+
+    assert_eq!(
+        code(call(&service, "fly.audit", json!({ "limit": "all" }))),
+        "invalid_type"
+    );
+}
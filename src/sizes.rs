@@ -0,0 +1,105 @@
+//! VM size catalog for `fly.platform.sizes`.
+//!
+//! Fly's platform query lists the named presets with their base CPU and
+//! memory and list prices. Machines can be given more memory than the
+//! preset's base, within limits per CPU kind, so each preset is expanded
+//! with the memory sizes the Machines API accepts for it.
+
+use serde::Serialize;
+
+use crate::models::VmSize;
+
+/// Memory a shared CPU can be given, per CPU, in MB.
+pub const SHARED_MEMORY_MB: (i64, i64) = (256, 2048);
+const SHARED_MEMORY_STEP_MB: i64 = 256;
+
+/// Memory a performance CPU can be given, per CPU, in MB.
+pub const PERFORMANCE_MEMORY_MB: (i64, i64) = (2048, 8192);
+const PERFORMANCE_MEMORY_STEP_MB: i64 = 1024;
+
+/// Approximate list price of memory beyond a preset's base, USD per GB
+/// per 30 days.
+pub const EXTRA_MEMORY_PRICE_GB_MONTH: f64 = 5.0;
+
+/// One VM preset and the memory sizes it can run with.
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeOption {
+    /// Preset name, as passed in a machine's guest config.
+    pub name: String,
+    /// `shared` or `performance`; absent for GPU and legacy presets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<u32>,
+    pub cpu_cores: f64,
+    /// Memory the preset comes with.
+    pub memory_mb: i64,
+    /// Every memory size the preset accepts, smallest first.
+    pub memory_options_mb: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_month: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_second: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SizeCatalog {
+    pub sizes: Vec<SizeOption>,
+    /// Rough cost of memory beyond a preset's base; prices are hints, not
+    /// a bill.
+    pub extra_memory_price_gb_month: f64,
+}
+
+/// Expand Fly's presets, keeping only `cpu_kind` ones if given.
+pub fn catalog(sizes: Vec<VmSize>, cpu_kind: Option<&str>) -> SizeCatalog {
+    let sizes = sizes
+        .into_iter()
+        .map(expand)
+        .filter(|size| cpu_kind.is_none() || size.cpu_kind == cpu_kind)
+        .collect();
+    SizeCatalog {
+        sizes,
+        extra_memory_price_gb_month: EXTRA_MEMORY_PRICE_GB_MONTH,
+    }
+}
+
+fn expand(size: VmSize) -> SizeOption {
+    let parsed = parse_name(&size.name);
+    let memory_options_mb = match parsed {
+        Some((kind, cpus)) => {
+            let ((min, max), step) = if kind == "shared" {
+                (SHARED_MEMORY_MB, SHARED_MEMORY_STEP_MB)
+            } else {
+                (PERFORMANCE_MEMORY_MB, PERFORMANCE_MEMORY_STEP_MB)
+            };
+            let cpus = i64::from(cpus);
+            (min * cpus..=max * cpus).step_by(step as usize).collect()
+        }
+        None => vec![size.memory_mb],
+    };
+
+    SizeOption {
+        cpu_kind: parsed.map(|(kind, _)| kind),
+        cpus: parsed.map(|(_, cpus)| cpus),
+        cpu_cores: size.cpu_cores,
+        memory_mb: size.memory_mb,
+        memory_options_mb,
+        price_month: size.price_month,
+        price_second: size.price_second,
+        name: size.name,
+    }
+}
+
+/// CPU kind and count from a preset name like `shared-cpu-2x` or
+/// `performance-4x`.
+pub fn parse_name(name: &str) -> Option<(&'static str, u32)> {
+    let (kind, count) = if let Some(count) = name.strip_prefix("shared-cpu-") {
+        ("shared", count)
+    } else if let Some(count) = name.strip_prefix("performance-") {
+        ("performance", count)
+    } else {
+        return None;
+    };
+    let cpus = count.strip_suffix('x')?.parse().ok()?;
+    Some((kind, cpus))
+}
@@ -0,0 +1,493 @@
+//! Daemon-owned state that outlives a restart.
+//!
+//! Schedules, autoscale rules, watcher settings and the audit log live in a
+//! `StateStore`: named JSON documents plus append-only logs. The daemon uses
+//! a SQLite database at `~/.fgp/services/fly/state.db` (override with
+//! `FGP_FLY_STATE`, or set it to `off` to keep state in memory only).
+//! `fly.state.export` and `fly.state.import` move the whole store between
+//! daemons as one JSON document.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::autoscale::AutoscaleRule;
+use crate::schedule::Schedule;
+use crate::watch::CrashLoopRule;
+
+const STATE_PATH: &str = "~/.fgp/services/fly/state.db";
+
+/// Format version written by `export`.
+const EXPORT_VERSION: u64 = 1;
+
+/// Storage for daemon state.
+pub trait StateStore: Send + Sync {
+    /// The document stored under `key`.
+    fn get(&self, key: &str) -> Result<Option<Value>>;
+
+    /// Store `value` under `key`, replacing what was there.
+    fn put(&self, key: &str, value: &Value) -> Result<()>;
+
+    /// Keys of every stored document.
+    fn keys(&self) -> Result<Vec<String>>;
+
+    /// Append `entries` to the log called `log`.
+    fn append(&self, log: &str, entries: &[Value]) -> Result<()>;
+
+    /// Every entry of `log`, oldest first.
+    fn entries(&self, log: &str) -> Result<Vec<Value>>;
+
+    /// Names of every non-empty log.
+    fn logs(&self) -> Result<Vec<String>>;
+
+    /// Store `documents` and append `logs` together: if any write fails,
+    /// none of them is kept.
+    fn import_all(
+        &self,
+        documents: &BTreeMap<String, Value>,
+        logs: &BTreeMap<String, Vec<Value>>,
+    ) -> Result<()>;
+}
+
+/// `FGP_FLY_STATE`, or the default path; `None` when set to `off`.
+pub fn path_from_env() -> Option<PathBuf> {
+    let path = std::env::var("FGP_FLY_STATE").unwrap_or_else(|_| STATE_PATH.into());
+    match path.trim() {
+        "" | "off" => None,
+        path => Some(PathBuf::from(shellexpand::tilde(path).to_string())),
+    }
+}
+
+/// Open the store configured by `FGP_FLY_STATE`.
+///
+/// A database that can't be opened is logged and replaced by an in-memory
+/// store, so the daemon still starts; nothing it does is saved.
+pub fn open_from_env() -> Arc<dyn StateStore> {
+    let Some(path) = path_from_env() else {
+        return Arc::new(MemoryStore::default());
+    };
+    match SqliteStore::open(&path) {
+        Ok(store) => Arc::new(store),
+        Err(e) => {
+            tracing::error!(
+                "Failed to open state store {}, keeping state in memory: {:#}",
+                path.display(),
+                e
+            );
+            Arc::new(MemoryStore::default())
+        }
+    }
+}
+
+/// State kept in a SQLite database.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS documents (
+                 key TEXT PRIMARY KEY,
+                 value TEXT NOT NULL,
+                 updated_at TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS log_entries (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 log TEXT NOT NULL,
+                 entry TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS log_entries_by_log ON log_entries (log, id);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn get(&self, key: &str) -> Result<Option<Value>> {
+        let text: Option<String> = self
+            .conn()
+            .query_row(
+                "SELECT value FROM documents WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        text.map(|text| {
+            serde_json::from_str(&text).with_context(|| format!("Invalid state document {}", key))
+        })
+        .transpose()
+    }
+
+    fn put(&self, key: &str, value: &Value) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO documents (key, value, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (key) DO UPDATE SET value = ?2, updated_at = ?3",
+            params![key, value.to_string(), Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT key FROM documents ORDER BY key")?;
+        let keys = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(keys)
+    }
+
+    fn append(&self, log: &str, entries: &[Value]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare("INSERT INTO log_entries (log, entry) VALUES (?1, ?2)")?;
+            for entry in entries {
+                insert.execute(params![log, entry.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn entries(&self, log: &str) -> Result<Vec<Value>> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare("SELECT entry FROM log_entries WHERE log = ?1 ORDER BY id")?;
+        let rows = statement.query_map(params![log], |row| row.get::<_, String>(0))?;
+        let mut entries = Vec::new();
+        for text in rows {
+            // A damaged entry shouldn't hide the rest of the log
+            match serde_json::from_str(&text?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!("Skipping invalid {} log entry: {}", log, e),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn logs(&self) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT DISTINCT log FROM log_entries ORDER BY log")?;
+        let logs = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(logs)
+    }
+
+    fn import_all(
+        &self,
+        documents: &BTreeMap<String, Value>,
+        logs: &BTreeMap<String, Vec<Value>>,
+    ) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let now = Utc::now().to_rfc3339();
+            let mut put = tx.prepare(
+                "INSERT INTO documents (key, value, updated_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (key) DO UPDATE SET value = ?2, updated_at = ?3",
+            )?;
+            for (key, value) in documents {
+                put.execute(params![key, value.to_string(), now])?;
+            }
+            let mut append = tx.prepare("INSERT INTO log_entries (log, entry) VALUES (?1, ?2)")?;
+            for (log, entries) in logs {
+                for entry in entries {
+                    append.execute(params![log, entry.to_string()])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// State kept in memory, lost when the daemon stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    documents: Mutex<BTreeMap<String, Value>>,
+    logs: Mutex<BTreeMap<String, Vec<Value>>>,
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Value>> {
+        Ok(lock(&self.documents).get(key).cloned())
+    }
+
+    fn put(&self, key: &str, value: &Value) -> Result<()> {
+        lock(&self.documents).insert(key.to_string(), value.clone());
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        Ok(lock(&self.documents).keys().cloned().collect())
+    }
+
+    fn append(&self, log: &str, entries: &[Value]) -> Result<()> {
+        lock(&self.logs)
+            .entry(log.to_string())
+            .or_default()
+            .extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn entries(&self, log: &str) -> Result<Vec<Value>> {
+        Ok(lock(&self.logs).get(log).cloned().unwrap_or_default())
+    }
+
+    fn logs(&self) -> Result<Vec<String>> {
+        Ok(lock(&self.logs).keys().cloned().collect())
+    }
+
+    fn import_all(
+        &self,
+        documents: &BTreeMap<String, Value>,
+        logs: &BTreeMap<String, Vec<Value>>,
+    ) -> Result<()> {
+        let mut stored = lock(&self.documents);
+        let mut stored_logs = lock(&self.logs);
+        stored.extend(documents.clone());
+        for (log, entries) in logs {
+            stored_logs
+                .entry(log.clone())
+                .or_default()
+                .extend_from_slice(entries);
+        }
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Read the document under `key`, or the type's default if there isn't one.
+pub fn load<T: serde::de::DeserializeOwned + Default>(
+    store: &dyn StateStore,
+    key: &str,
+) -> Result<T> {
+    match store.get(key)? {
+        Some(value) => {
+            serde_json::from_value(value).with_context(|| format!("Invalid state document {}", key))
+        }
+        None => Ok(T::default()),
+    }
+}
+
+/// Set or remove `id` in the map stored under `key`.
+pub fn update<T: Serialize>(
+    store: &dyn StateStore,
+    key: &str,
+    id: &str,
+    value: Option<&T>,
+) -> Result<()> {
+    let mut map: BTreeMap<String, Value> = load(store, key)?;
+    match value {
+        Some(value) => map.insert(id.to_string(), serde_json::to_value(value)?),
+        None => map.remove(id),
+    };
+    store.put(key, &serde_json::to_value(map)?)
+}
+
+/// Autoscale rule saved under `autoscale`, by app.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedAutoscale {
+    pub profile: String,
+    pub rule: AutoscaleRule,
+}
+
+/// Watcher settings saved under `watches`, by app.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedWatch {
+    pub profile: String,
+    pub interval_secs: u64,
+    pub crash_loop: CrashLoopRule,
+}
+
+/// Whole-store dump produced by `export` and read by `import`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u64,
+    #[serde(default)]
+    pub exported_at: Option<String>,
+    #[serde(default)]
+    pub documents: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub logs: BTreeMap<String, Vec<Value>>,
+}
+
+/// Dump every document and, if `include_logs`, every log entry.
+pub fn export(store: &dyn StateStore, include_logs: bool) -> Result<Export> {
+    let mut documents = BTreeMap::new();
+    for key in store.keys()? {
+        if let Some(value) = store.get(&key)? {
+            documents.insert(key, value);
+        }
+    }
+    let mut logs = BTreeMap::new();
+    if include_logs {
+        for log in store.logs()? {
+            logs.insert(log.clone(), store.entries(&log)?);
+        }
+    }
+    Ok(Export {
+        version: EXPORT_VERSION,
+        exported_at: Some(Utc::now().to_rfc3339()),
+        documents,
+        logs,
+    })
+}
+
+/// What `import` changed.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    /// Documents written, replacing any existing ones.
+    pub documents: Vec<String>,
+    /// Log entries added, by log.
+    pub log_entries: BTreeMap<String, usize>,
+    /// Log entries skipped as already present, by log.
+    pub duplicates: BTreeMap<String, usize>,
+}
+
+/// Load a dump: documents replace those with the same key, and log entries
+/// not already in the store are appended, so importing twice is harmless.
+///
+/// The schedules, autoscale rules and watches in the dump are checked
+/// first, and everything is written at once, so a bad dump changes nothing.
+pub fn import(store: &dyn StateStore, dump: &Export) -> Result<ImportReport> {
+    if dump.version != EXPORT_VERSION {
+        anyhow::bail!(
+            "Unsupported state export version {} (expected {})",
+            dump.version,
+            EXPORT_VERSION
+        );
+    }
+    for (key, value) in &dump.documents {
+        validate_document(key, value)?;
+    }
+
+    let mut report = ImportReport {
+        documents: dump.documents.keys().cloned().collect(),
+        ..Default::default()
+    };
+    let mut logs = BTreeMap::new();
+    for (log, entries) in &dump.logs {
+        let mut seen: HashSet<String> = store.entries(log)?.iter().map(Value::to_string).collect();
+        let new: Vec<Value> = entries
+            .iter()
+            .filter(|entry| seen.insert(entry.to_string()))
+            .cloned()
+            .collect();
+        report.log_entries.insert(log.clone(), new.len());
+        report
+            .duplicates
+            .insert(log.clone(), entries.len() - new.len());
+        logs.insert(log.clone(), new);
+    }
+    store.import_all(&dump.documents, &logs)?;
+    Ok(report)
+}
+
+/// Check that a document the daemon restores from has the shape it expects.
+fn validate_document(key: &str, value: &Value) -> Result<()> {
+    fn parse<T: serde::de::DeserializeOwned>(key: &str, value: &Value) -> Result<T> {
+        T::deserialize(value).with_context(|| format!("Invalid {} in state", key))
+    }
+    match key {
+        "schedules" => {
+            for schedule in parse::<Vec<Schedule>>(key, value)? {
+                schedule
+                    .validate()
+                    .with_context(|| format!("Invalid schedule {} in state", schedule.name))?;
+            }
+        }
+        "autoscale" => {
+            parse::<BTreeMap<String, SavedAutoscale>>(key, value)?;
+        }
+        "watches" => {
+            parse::<BTreeMap<String, SavedWatch>>(key, value)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dump(documents: Value, logs: Value) -> Export {
+        serde_json::from_value(
+            json!({ "version": EXPORT_VERSION, "documents": documents, "logs": logs }),
+        )
+        .unwrap()
+    }
+
+    fn schedule(cron: &str) -> Value {
+        json!({
+            "name": "nightly",
+            "cron": cron,
+            "app": "web",
+            "action": "stop",
+            "profile": "default",
+            "created_at": "2026-10-01T00:00:00Z"
+        })
+    }
+
+    #[test]
+    fn import_writes_documents_and_skips_duplicate_log_entries() {
+        let path = std::env::temp_dir().join(format!("fgp-fly-state-{}.db", std::process::id()));
+        let store = SqliteStore::open(&path).unwrap();
+        let dump = dump(
+            json!({ "schedules": [schedule("0 20 * * 1-5")] }),
+            json!({ "audit": [{ "method": "fly.scale" }, { "method": "fly.deploy" }] }),
+        );
+
+        let first = import(&store, &dump).unwrap();
+        let second = import(&store, &dump).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(first.documents, ["schedules"]);
+        assert_eq!(first.log_entries["audit"], 2);
+        assert_eq!(second.log_entries["audit"], 0);
+        assert_eq!(second.duplicates["audit"], 2);
+        assert_eq!(store.entries("audit").unwrap().len(), 2);
+        assert!(store.get("schedules").unwrap().is_some());
+    }
+
+    #[test]
+    fn invalid_dumps_change_nothing() {
+        let store = MemoryStore::default();
+        let bad = [
+            json!({ "other": 1, "schedules": [schedule("not cron")] }),
+            json!({ "other": 1, "autoscale": { "web": { "profile": "default" } } }),
+            json!({ "other": 1, "watches": [] }),
+        ];
+        for documents in bad {
+            let dump = dump(documents, json!({ "audit": [{ "method": "fly.scale" }] }));
+            assert!(import(&store, &dump).is_err());
+        }
+
+        assert!(store.keys().unwrap().is_empty());
+        assert!(store.logs().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+//! "Did you mean" suggestions for unknown method and param names.
+//!
+//! Candidates are ranked by Levenshtein distance and kept when they're
+//! within a third of the name's length (at least 2 edits), so short typos
+//! match and unrelated names don't.
+
+/// Most suggestions returned for one name.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Candidates close to `name`, closest first.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    let mut scored: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|candidate| (levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    scored.sort();
+    scored.dedup_by(|a, b| a.1 == b.1);
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// `message` with a "Did you mean ...?" hint appended, if there's one.
+pub fn hint(message: String, suggestions: &[String]) -> String {
+    match suggestions {
+        [] => message,
+        [only] => format!("{}. Did you mean {}?", message, only),
+        [rest @ .., last] => format!("{}. Did you mean {} or {}?", message, rest.join(", "), last),
+    }
+}
+
+/// Edits (insertions, deletions, substitutions) to turn `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
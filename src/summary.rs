@@ -0,0 +1,109 @@
+//! One-glance rollup of an app for `fly.status` with `summary: true`.
+//!
+//! Machine states, regions and checks come from the Machines API, since
+//! GraphQL machine nodes carry no checks; the app status and current release
+//! come from GraphQL. The `health` verdict is the worst of its `reasons`.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::models::{App, Machine};
+
+/// Overall verdict for an app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Healthy,
+    /// Serving, but something needs a look.
+    Degraded,
+    /// Nothing is serving.
+    Down,
+}
+
+/// Version and status of the app's current release.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseSummary {
+    pub version: i32,
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppSummary {
+    pub app: String,
+    /// Fly's app status, e.g. `deployed` or `suspended`.
+    pub status: String,
+    pub health: Health,
+    /// Why `health` isn't `healthy`.
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub machines: usize,
+    pub machines_by_state: BTreeMap<String, usize>,
+    pub regions: BTreeSet<String>,
+    pub release: Option<ReleaseSummary>,
+    /// Checks not passing, across all machines.
+    pub failing_checks: usize,
+}
+
+/// Roll up an app and its machines.
+pub fn summarize(app: &App, machines: &[Machine]) -> AppSummary {
+    let mut machines_by_state: BTreeMap<String, usize> = BTreeMap::new();
+    for machine in machines {
+        *machines_by_state.entry(machine.state.clone()).or_default() += 1;
+    }
+    let count = |state: &str| machines_by_state.get(state).copied().unwrap_or(0);
+    let failing_checks = machines
+        .iter()
+        .flat_map(|m| &m.checks)
+        .filter(|c| c.is_failing())
+        .count();
+    let release = app.current_release.as_ref().map(|r| ReleaseSummary {
+        version: r.version,
+        status: r.status.clone(),
+    });
+
+    let mut verdicts: Vec<(Health, String)> = Vec::new();
+    if app.status == "suspended" {
+        verdicts.push((Health::Down, "app is suspended".into()));
+    }
+    if machines.is_empty() {
+        verdicts.push((Health::Down, "app has no machines".into()));
+    } else if count("started") == 0 {
+        verdicts.push((Health::Down, "no machines are started".into()));
+    }
+    if count("failed") > 0 {
+        verdicts.push((
+            Health::Degraded,
+            format!("{} machines failed", count("failed")),
+        ));
+    }
+    if failing_checks > 0 {
+        verdicts.push((
+            Health::Degraded,
+            format!("{} health checks failing", failing_checks),
+        ));
+    }
+    if let Some(release) = release.as_ref().filter(|r| r.status == "failed") {
+        verdicts.push((
+            Health::Degraded,
+            format!("release v{} failed", release.version),
+        ));
+    }
+
+    AppSummary {
+        app: app.name.clone(),
+        status: app.status.clone(),
+        health: verdicts
+            .iter()
+            .map(|(health, _)| *health)
+            .max()
+            .unwrap_or(Health::Healthy),
+        reasons: verdicts.into_iter().map(|(_, reason)| reason).collect(),
+        hostname: app.hostname.clone(),
+        machines: machines.len(),
+        regions: machines.iter().map(|m| m.region.clone()).collect(),
+        machines_by_state,
+        release,
+        failing_checks,
+    }
+}
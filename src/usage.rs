@@ -0,0 +1,238 @@
+//! Monthly cost estimate for `fly.usage`.
+//!
+//! Built from what an app has provisioned right now, priced with a built-in
+//! table of Fly's list prices: started machines pay for CPU, memory above
+//! their CPUs' included amount, and GPUs; volumes pay per provisioned GB
+//! whether attached or not; dedicated IPv4 addresses pay a flat fee. Free
+//! allowances, discounts and bandwidth aren't modeled, so this is an
+//! estimate, not a bill.
+
+use serde::Serialize;
+
+use crate::models::{IpAddress, Machine, Volume};
+use crate::sizes::{self, EXTRA_MEMORY_PRICE_GB_MONTH, PERFORMANCE_MEMORY_MB, SHARED_MEMORY_MB};
+
+/// Hours in a billing month, as Fly prices them.
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// USD per month for one CPU, including its base memory.
+const SHARED_CPU_MONTH: f64 = 1.94;
+const PERFORMANCE_CPU_MONTH: f64 = 31.0;
+
+/// USD per hour for one GPU.
+const GPU_HOUR: &[(&str, f64)] = &[
+    ("a10", 1.50),
+    ("l40s", 1.25),
+    ("a100-pcie-40gb", 2.50),
+    ("a100-sxm4-80gb", 3.50),
+];
+
+/// USD per provisioned GB of volume per month.
+const VOLUME_GB_MONTH: f64 = 0.15;
+
+/// USD per dedicated IPv4 address per month.
+const DEDICATED_IPV4_MONTH: f64 = 2.0;
+
+/// Cost of one kind of resource.
+#[derive(Debug, Clone, Serialize)]
+pub struct LineItem {
+    /// `cpu`, `memory`, `gpu`, `volumes` or `ipv4`.
+    pub resource: &'static str,
+    pub quantity: f64,
+    /// What `quantity` counts, e.g. `cpus` or `gb`.
+    pub unit: &'static str,
+    pub cost_month: f64,
+}
+
+/// Compute cost of one machine while it runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct MachineCost {
+    pub id: String,
+    pub name: String,
+    pub region: String,
+    pub state: String,
+    pub cpu_kind: &'static str,
+    pub cpus: u32,
+    pub memory_mb: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu_kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<u32>,
+    /// What the machine costs per month if left running.
+    pub cost_month: f64,
+    /// Whether `cost_month` counts toward the app's total.
+    pub billed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AppUsage {
+    pub app: String,
+    pub currency: &'static str,
+    pub total_month: f64,
+    pub breakdown: Vec<LineItem>,
+    pub machines: Vec<MachineCost>,
+}
+
+/// Resources a machine is sized with.
+struct Resources {
+    cpu_kind: &'static str,
+    cpus: u32,
+    memory_mb: u32,
+    gpu: Option<(String, u32)>,
+}
+
+impl Resources {
+    /// From the guest config, falling back to the size preset name, then to
+    /// Fly's default `shared-cpu-1x`.
+    fn of(machine: &Machine) -> Self {
+        let config = machine.config.as_ref();
+        let guest = config.and_then(|c| c.guest.clone()).unwrap_or_default();
+        let preset = config
+            .and_then(|c| c.size.as_deref())
+            .and_then(sizes::parse_name);
+
+        let cpu_kind = match guest.cpu_kind.as_deref() {
+            Some("performance") => "performance",
+            Some(_) => "shared",
+            None => preset.map(|(kind, _)| kind).unwrap_or("shared"),
+        };
+        let cpus = guest
+            .cpus
+            .or(preset.map(|(_, cpus)| cpus))
+            .unwrap_or(1)
+            .max(1);
+        let memory_mb = guest
+            .memory_mb
+            .unwrap_or_else(|| included_memory_mb(cpu_kind, cpus));
+        let gpu = guest.gpu_kind.map(|kind| (kind, guest.gpus.unwrap_or(1)));
+
+        Self {
+            cpu_kind,
+            cpus,
+            memory_mb,
+            gpu,
+        }
+    }
+
+    fn extra_memory_gb(&self) -> f64 {
+        let included = included_memory_mb(self.cpu_kind, self.cpus);
+        f64::from(self.memory_mb.saturating_sub(included)) / 1024.0
+    }
+
+    fn cpu_month(&self) -> f64 {
+        let per_cpu = if self.cpu_kind == "performance" {
+            PERFORMANCE_CPU_MONTH
+        } else {
+            SHARED_CPU_MONTH
+        };
+        per_cpu * f64::from(self.cpus)
+    }
+
+    fn gpu_month(&self) -> f64 {
+        self.gpu.as_ref().map_or(0.0, |(kind, count)| {
+            let hourly = GPU_HOUR
+                .iter()
+                .find(|(k, _)| k == kind)
+                .map_or(0.0, |(_, price)| *price);
+            hourly * HOURS_PER_MONTH * f64::from(*count)
+        })
+    }
+}
+
+/// Memory included with `cpus` CPUs of a kind, in MB.
+fn included_memory_mb(cpu_kind: &str, cpus: u32) -> u32 {
+    let (per_cpu, _) = if cpu_kind == "performance" {
+        PERFORMANCE_MEMORY_MB
+    } else {
+        SHARED_MEMORY_MB
+    };
+    per_cpu as u32 * cpus
+}
+
+/// Estimate an app's monthly cost. Only started machines are billed.
+pub fn estimate(
+    app: &str,
+    machines: &[Machine],
+    volumes: &[Volume],
+    ips: &[IpAddress],
+) -> AppUsage {
+    let mut cpus = 0.0;
+    let mut cpu_cost = 0.0;
+    let mut memory_gb = 0.0;
+    let mut gpus = 0.0;
+    let mut gpu_cost = 0.0;
+    let mut costs = Vec::new();
+
+    for machine in machines {
+        let resources = Resources::of(machine);
+        let extra_memory_gb = resources.extra_memory_gb();
+        let billed = machine.state == "started";
+        if billed {
+            cpus += f64::from(resources.cpus);
+            cpu_cost += resources.cpu_month();
+            memory_gb += extra_memory_gb;
+            gpus += resources.gpu.as_ref().map_or(0.0, |(_, n)| f64::from(*n));
+            gpu_cost += resources.gpu_month();
+        }
+        costs.push(MachineCost {
+            id: machine.id.clone(),
+            name: machine.name.clone(),
+            region: machine.region.clone(),
+            state: machine.state.clone(),
+            cpu_kind: resources.cpu_kind,
+            cpus: resources.cpus,
+            memory_mb: resources.memory_mb,
+            cost_month: round(
+                resources.cpu_month()
+                    + extra_memory_gb * EXTRA_MEMORY_PRICE_GB_MONTH
+                    + resources.gpu_month(),
+            ),
+            gpus: resources.gpu.as_ref().map(|(_, n)| *n),
+            gpu_kind: resources.gpu.map(|(kind, _)| kind),
+            billed,
+        });
+    }
+
+    let volume_gb = f64::from(volumes.iter().map(|v| v.size_gb).sum::<u32>());
+    let dedicated_ipv4 = ips.iter().filter(|ip| ip.ip_type == "v4").count() as f64;
+
+    let breakdown = vec![
+        line("cpu", cpus, "cpus", cpu_cost),
+        line(
+            "memory",
+            memory_gb,
+            "gb",
+            memory_gb * EXTRA_MEMORY_PRICE_GB_MONTH,
+        ),
+        line("gpu", gpus, "gpus", gpu_cost),
+        line("volumes", volume_gb, "gb", volume_gb * VOLUME_GB_MONTH),
+        line(
+            "ipv4",
+            dedicated_ipv4,
+            "addresses",
+            dedicated_ipv4 * DEDICATED_IPV4_MONTH,
+        ),
+    ];
+
+    AppUsage {
+        app: app.to_string(),
+        currency: "USD",
+        total_month: round(breakdown.iter().map(|item| item.cost_month).sum()),
+        breakdown,
+        machines: costs,
+    }
+}
+
+fn line(resource: &'static str, quantity: f64, unit: &'static str, cost: f64) -> LineItem {
+    LineItem {
+        resource,
+        quantity: round(quantity),
+        unit,
+        cost_month: round(cost),
+    }
+}
+
+/// Round to cents.
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
@@ -0,0 +1,481 @@
+//! Background watchers that turn app state changes into events.
+//!
+//! FGP calls are request/response, so watchers run as daemon tasks that poll
+//! an app on an interval and append change events to a shared, bounded log.
+//! Clients read the log with a sequence cursor, optionally long-polling until
+//! something new arrives.
+//!
+//! Each watcher also feeds its machines' events through a crash-loop
+//! detector with the app's own threshold. Restart storms become
+//! `crash_loop` events and are kept as alerts for `fly.alerts`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::api::{FlyApi, MachinesClient};
+use crate::models::Machine;
+use crate::monitor::{CrashLoopAlert, CrashLoopDetector};
+
+/// Events kept in memory before the oldest are dropped.
+const EVENT_CAPACITY: usize = 1000;
+
+/// Crash-loop alerts kept in memory before the oldest are dropped.
+const ALERT_CAPACITY: usize = 200;
+
+/// State change observed by a watcher.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchEvent {
+    /// Increasing sequence number, usable as a cursor.
+    pub seq: u64,
+    pub app: String,
+    /// `machine_created`, `machine_destroyed`, `machine_state`, `machine_crashed`,
+    /// `crash_loop`, `check_failing`, `check_passing`, `release_deployed` or
+    /// `watch_error`.
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// A running watcher, as reported by `fly.watch` `list`.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchInfo {
+    pub app: String,
+    pub interval_secs: u64,
+    pub started_at: DateTime<Utc>,
+    pub crash_loop: CrashLoopRule,
+}
+
+/// When a watched app's machine counts as crash-looping.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CrashLoopRule {
+    /// Alert when a machine restarts more than this many times...
+    pub restart_threshold: usize,
+    /// ...within this many seconds.
+    pub window_secs: i64,
+}
+
+impl Default for CrashLoopRule {
+    fn default() -> Self {
+        Self {
+            restart_threshold: 3,
+            window_secs: 300,
+        }
+    }
+}
+
+/// Events returned for a cursor.
+#[derive(Debug, Serialize)]
+pub struct EventBatch {
+    pub events: Vec<WatchEvent>,
+    pub count: usize,
+    /// Pass as `since` to get only newer events.
+    pub cursor: u64,
+    /// Whether events after `since` were dropped before they were read.
+    pub truncated: bool,
+}
+
+/// What a watcher remembers between polls.
+#[derive(Debug, Default)]
+struct Snapshot {
+    machines: HashMap<String, MachineState>,
+    release: Option<i32>,
+}
+
+#[derive(Debug)]
+struct MachineState {
+    state: String,
+    region: String,
+    failing_checks: bool,
+}
+
+impl Snapshot {
+    fn new(machines: &[Machine], release: Option<i32>) -> Self {
+        Self {
+            machines: machines
+                .iter()
+                .map(|m| {
+                    let state = MachineState {
+                        state: m.state.clone(),
+                        region: m.region.clone(),
+                        failing_checks: m.checks.iter().any(|c| c.is_failing()),
+                    };
+                    (m.id.clone(), state)
+                })
+                .collect(),
+            release,
+        }
+    }
+}
+
+/// Bounded event log shared by all watchers.
+#[derive(Default)]
+struct EventLog {
+    events: VecDeque<WatchEvent>,
+    next_seq: u64,
+}
+
+/// Registry of watchers and their events.
+pub struct WatchHub {
+    watches: Mutex<BTreeMap<String, (WatchInfo, JoinHandle<()>)>>,
+    log: Arc<Mutex<EventLog>>,
+    notify: Arc<Notify>,
+    /// Machine count per state from each app's latest successful poll.
+    states: Arc<Mutex<BTreeMap<String, BTreeMap<String, usize>>>>,
+    alerts: Arc<Mutex<VecDeque<CrashLoopAlert>>>,
+}
+
+impl Default for WatchHub {
+    fn default() -> Self {
+        Self {
+            watches: Mutex::new(BTreeMap::new()),
+            log: Arc::new(Mutex::new(EventLog {
+                events: VecDeque::new(),
+                next_seq: 1,
+            })),
+            notify: Arc::new(Notify::new()),
+            states: Arc::default(),
+            alerts: Arc::default(),
+        }
+    }
+}
+
+impl WatchHub {
+    /// Start watching `app`, replacing any existing watcher for it.
+    pub fn start(
+        &self,
+        runtime: &Handle,
+        client: Arc<dyn FlyApi>,
+        machines: Arc<MachinesClient>,
+        app: String,
+        interval: Duration,
+        rule: CrashLoopRule,
+    ) -> WatchInfo {
+        let info = WatchInfo {
+            app: app.clone(),
+            interval_secs: interval.as_secs(),
+            started_at: Utc::now(),
+            crash_loop: rule,
+        };
+
+        let log = self.log.clone();
+        let notify = self.notify.clone();
+        let states = self.states.clone();
+        let alerts = self.alerts.clone();
+        let task = runtime.spawn(async move {
+            let mut previous: Option<Snapshot> = None;
+            let mut detector = CrashLoopDetector::new(
+                rule.restart_threshold,
+                chrono::Duration::seconds(rule.window_secs),
+            );
+            loop {
+                let events = match poll(&*client, &machines, &app).await {
+                    Ok((next, list)) => {
+                        let mut counts = BTreeMap::new();
+                        for machine in next.machines.values() {
+                            *counts.entry(machine.state.clone()).or_default() += 1;
+                        }
+                        states
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .insert(app.clone(), counts);
+
+                        let mut events = match &previous {
+                            Some(prev) => diff(prev, &next),
+                            None => Vec::new(),
+                        };
+                        previous = Some(next);
+
+                        let fired = check_crash_loops(&*client, &app, &list, &mut detector).await;
+                        if !fired.is_empty() {
+                            events.extend(fired.iter().map(|alert| Change {
+                                kind: "crash_loop",
+                                machine_id: Some(alert.machine_id.clone()),
+                                region: Some(alert.region.clone()),
+                                from: None,
+                                to: Some(format!(
+                                    "{} restarts in {}s",
+                                    alert.restarts, alert.window_secs
+                                )),
+                            }));
+                            let mut alerts = alerts.lock().unwrap_or_else(|e| e.into_inner());
+                            alerts.extend(fired);
+                            while alerts.len() > ALERT_CAPACITY {
+                                alerts.pop_front();
+                            }
+                        }
+                        events
+                    }
+                    Err(e) => {
+                        tracing::warn!("Watch poll failed for {}: {:#}", app, e);
+                        vec![Change {
+                            kind: "watch_error",
+                            machine_id: None,
+                            region: None,
+                            from: None,
+                            to: Some(format!("{:#}", e)),
+                        }]
+                    }
+                };
+                if !events.is_empty() {
+                    push(&log, &app, events);
+                    notify.notify_waiters();
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, old)) = watches.insert(info.app.clone(), (info.clone(), task)) {
+            old.abort();
+        }
+        info
+    }
+
+    /// Stop watching `app`. Returns whether a watcher was running.
+    pub fn stop(&self, app: &str) -> bool {
+        let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+        match watches.remove(app) {
+            Some((_, task)) => {
+                task.abort();
+                self.states
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(app);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop every watcher.
+    pub fn stop_all(&self) {
+        let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, (_, task)) in std::mem::take(&mut *watches) {
+            task.abort();
+        }
+    }
+
+    /// Machine count per state for every watched app that has been polled.
+    pub fn machine_states(&self) -> BTreeMap<String, BTreeMap<String, usize>> {
+        self.states
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Crash-loop alerts raised by watchers, oldest first.
+    pub fn alerts(&self, app: Option<&str>) -> Vec<CrashLoopAlert> {
+        let alerts = self.alerts.lock().unwrap_or_else(|e| e.into_inner());
+        alerts
+            .iter()
+            .filter(|a| app.map(|app| a.app == app).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+
+    /// Drop alerts for `app`, or every alert. Returns how many were dropped.
+    pub fn clear_alerts(&self, app: Option<&str>) -> usize {
+        let mut alerts = self.alerts.lock().unwrap_or_else(|e| e.into_inner());
+        let before = alerts.len();
+        alerts.retain(|a| app.is_some_and(|app| a.app != app));
+        before - alerts.len()
+    }
+
+    /// Running watchers, by app name.
+    pub fn list(&self) -> Vec<WatchInfo> {
+        let watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+        watches.values().map(|(info, _)| info.clone()).collect()
+    }
+
+    /// Events after `since`, waiting up to `wait` for one to arrive.
+    pub async fn events(
+        &self,
+        since: u64,
+        app: Option<&str>,
+        limit: usize,
+        wait: Duration,
+    ) -> EventBatch {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            // Register before reading so an event pushed in between still wakes us
+            let notified = self.notify.notified();
+            let batch = self.read(since, app, limit);
+            if !batch.events.is_empty() || tokio::time::Instant::now() >= deadline {
+                return batch;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return self.read(since, app, limit);
+            }
+        }
+    }
+
+    fn read(&self, since: u64, app: Option<&str>, limit: usize) -> EventBatch {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        let oldest = log.events.front().map(|e| e.seq).unwrap_or(log.next_seq);
+        let events: Vec<WatchEvent> = log
+            .events
+            .iter()
+            .filter(|e| e.seq > since)
+            .filter(|e| app.map(|a| e.app == a).unwrap_or(true))
+            .take(limit)
+            .cloned()
+            .collect();
+        let cursor = match events.last() {
+            Some(last) if events.len() == limit => last.seq,
+            _ => log.next_seq - 1,
+        };
+
+        EventBatch {
+            count: events.len(),
+            events,
+            cursor,
+            truncated: since + 1 < oldest,
+        }
+    }
+}
+
+/// A change before it is stamped into the log.
+struct Change {
+    kind: &'static str,
+    machine_id: Option<String>,
+    region: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+async fn poll(
+    client: &dyn FlyApi,
+    machines: &MachinesClient,
+    app: &str,
+) -> anyhow::Result<(Snapshot, Vec<Machine>)> {
+    let list = machines.list_machines(app).await?;
+    let status = client.get_app_status(app).await?;
+    let release = status.app.current_release.map(|r| r.version);
+    Ok((Snapshot::new(&list, release), list))
+}
+
+/// Feed every live machine's recent events to the detector.
+async fn check_crash_loops(
+    client: &dyn FlyApi,
+    app: &str,
+    machines: &[Machine],
+    detector: &mut CrashLoopDetector,
+) -> Vec<CrashLoopAlert> {
+    let now = Utc::now();
+    let mut alerts = Vec::new();
+    for machine in machines.iter().filter(|m| m.state != "destroyed") {
+        match client.list_machine_events(app, &machine.id).await {
+            Ok(events) => alerts.extend(detector.check_machine(app, machine, &events, now)),
+            Err(e) => tracing::warn!("Failed to fetch events for machine {}: {:#}", machine.id, e),
+        }
+    }
+    alerts
+}
+
+/// Changes between two snapshots of the same app.
+fn diff(prev: &Snapshot, next: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let machine_change = |kind, id: &str, state: &MachineState, from, to| Change {
+        kind,
+        machine_id: Some(id.to_string()),
+        region: Some(state.region.clone()),
+        from,
+        to,
+    };
+
+    for (id, state) in &next.machines {
+        match prev.machines.get(id) {
+            None => changes.push(machine_change(
+                "machine_created",
+                id,
+                state,
+                None,
+                Some(state.state.clone()),
+            )),
+            Some(old) => {
+                if old.state != state.state {
+                    // A machine only lands in `failed` when its process died
+                    let kind = if state.state == "failed" {
+                        "machine_crashed"
+                    } else {
+                        "machine_state"
+                    };
+                    changes.push(machine_change(
+                        kind,
+                        id,
+                        state,
+                        Some(old.state.clone()),
+                        Some(state.state.clone()),
+                    ));
+                }
+                if old.failing_checks != state.failing_checks {
+                    let kind = if state.failing_checks {
+                        "check_failing"
+                    } else {
+                        "check_passing"
+                    };
+                    changes.push(machine_change(kind, id, state, None, None));
+                }
+            }
+        }
+    }
+
+    for (id, state) in &prev.machines {
+        if !next.machines.contains_key(id) {
+            changes.push(machine_change(
+                "machine_destroyed",
+                id,
+                state,
+                Some(state.state.clone()),
+                None,
+            ));
+        }
+    }
+
+    if next.release.is_some() && prev.release != next.release {
+        changes.push(Change {
+            kind: "release_deployed",
+            machine_id: None,
+            region: None,
+            from: prev.release.map(|v| format!("v{}", v)),
+            to: next.release.map(|v| format!("v{}", v)),
+        });
+    }
+
+    changes
+}
+
+fn push(log: &Mutex<EventLog>, app: &str, changes: Vec<Change>) {
+    let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+    let at = Utc::now();
+    for change in changes {
+        let seq = log.next_seq;
+        log.next_seq += 1;
+        log.events.push_back(WatchEvent {
+            seq,
+            app: app.to_string(),
+            kind: change.kind,
+            machine_id: change.machine_id,
+            region: change.region,
+            from: change.from,
+            to: change.to,
+            at,
+        });
+        if log.events.len() > EVENT_CAPACITY {
+            log.events.pop_front();
+        }
+    }
+}
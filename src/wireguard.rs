@@ -0,0 +1,84 @@
+//! WireGuard key generation and peer config rendering.
+//!
+//! Keys are generated with `wg` from wireguard-tools so no key material is
+//! produced by code in this daemon. Callers without `wg` installed can pass
+//! their own public key instead.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::models::WireGuardPeerCreated;
+
+/// UDP port Fly's WireGuard gateways listen on.
+const GATEWAY_PORT: u16 = 51820;
+
+/// Written in place of the private key when the caller supplied the pubkey.
+const PRIVATE_KEY_PLACEHOLDER: &str = "<private key matching the supplied pubkey>";
+
+/// A freshly generated WireGuard keypair (base64).
+pub struct KeyPair {
+    pub private_key: String,
+    pub public_key: String,
+}
+
+/// Generate a keypair with `wg genkey` and `wg pubkey`.
+pub fn generate_keypair() -> Result<KeyPair> {
+    let private_key = run_wg(&["genkey"], None)?;
+    let public_key = run_wg(&["pubkey"], Some(&private_key))?;
+    Ok(KeyPair {
+        private_key,
+        public_key,
+    })
+}
+
+fn run_wg(args: &[&str], input: Option<&str>) -> Result<String> {
+    let mut child = Command::new("wg")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to run `wg` (install wireguard-tools or pass pubkey)")?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        writeln!(stdin, "{}", input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!("`wg {}` exited with {}", args.join(" "), output.status);
+    }
+
+    Ok(String::from_utf8(output.stdout)
+        .context("`wg` printed invalid UTF-8")?
+        .trim()
+        .to_string())
+}
+
+/// wg-quick config for a new peer, in the shape `fly wireguard create` writes.
+///
+/// The org's private network is the /48 around the peer's address, with
+/// internal DNS on `::3` of that prefix.
+pub fn render_config(private_key: Option<&str>, peer: &WireGuardPeerCreated) -> String {
+    let prefix = peer.peerip.split(':').take(3).collect::<Vec<_>>().join(":");
+
+    format!(
+        "[Interface]\n\
+         PrivateKey = {}\n\
+         Address = {}/120\n\
+         DNS = {}::3\n\
+         \n\
+         [Peer]\n\
+         PublicKey = {}\n\
+         AllowedIPs = {}::/48\n\
+         Endpoint = {}:{}\n\
+         PersistentKeepalive = 15\n",
+        private_key.unwrap_or(PRIVATE_KEY_PLACEHOLDER),
+        peer.peerip,
+        prefix,
+        peer.pubkey,
+        prefix,
+        peer.endpointip,
+        GATEWAY_PORT,
+    )
+}
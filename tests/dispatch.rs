@@ -0,0 +1,432 @@
+//! Dispatch tests against a fake Fly API.
+//!
+//! Each test starts a wiremock server standing in for the GraphQL and
+//! Machines APIs, runs the daemon in the foreground pointed at it, and
+//! makes calls over the daemon's socket.
+
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use wiremock::matchers::{body_string_contains, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const TOKEN: &str = "fo1_test_token";
+const APP: &str = "web";
+const MACHINE_ID: &str = "148e21ea7d5e89";
+
+/// Daemon process serving one test, stopped when dropped.
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+}
+
+impl Daemon {
+    async fn start(api: &MockServer) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "fgp-fly-test-{}-{}",
+            std::process::id(),
+            api.address().port()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("daemon.sock");
+
+        let child = Command::new(env!("CARGO_BIN_EXE_fgp-fly"))
+            .args(["start", "--foreground", "--start-mode", "lazy", "--socket"])
+            .arg(&socket)
+            .env("HOME", &dir)
+            .env("FLY_API_TOKEN", TOKEN)
+            .env("FGP_FLY_PROFILES", dir.join("profiles.json"))
+            .env("FGP_FLY_STATE", "off")
+            .env("FGP_FLY_GRAPHQL_URL", format!("{}/graphql", api.uri()))
+            .env("FGP_FLY_API_URL", api.uri())
+            .env("FGP_FLY_MACHINES_URL", format!("{}/v1", api.uri()))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let daemon = Self { child, dir, socket };
+        for _ in 0..100 {
+            if UnixStream::connect(&daemon.socket).await.is_ok() {
+                return daemon;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("daemon did not open {}", daemon.socket.display());
+    }
+
+    /// Call `method`, returning its result or the error message.
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let stream = UnixStream::connect(&self.socket).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let request = json!({ "id": "test", "v": 1, "method": method, "params": params });
+        write
+            .write_all(format!("{}\n", request).as_bytes())
+            .await
+            .unwrap();
+
+        let mut line = String::new();
+        BufReader::new(read).read_line(&mut line).await.unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        if response["ok"] == json!(true) {
+            return Ok(response["result"].clone());
+        }
+        let error = &response["error"];
+        Err(match error["message"].as_str().or(error.as_str()) {
+            Some(message) => message.to_string(),
+            None => error.to_string(),
+        })
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Error code carried in a failed call's message.
+fn error_code(message: &str) -> Value {
+    let error: Value =
+        serde_json::from_str(message).unwrap_or_else(|_| panic!("error is not JSON: {}", message));
+    error["error"]["code"].clone()
+}
+
+fn machine(id: &str, state: &str, region: &str) -> Value {
+    json!({
+        "id": id,
+        "name": format!("{}-{}", APP, region),
+        "state": state,
+        "region": region,
+        "instance_id": "01HQ8Z3K4M5N6P7Q8R9S0T1U2V",
+        "config": {
+            "image": "registry.fly.io/web:deployment-01HQ8Z",
+            "guest": { "cpu_kind": "shared", "cpus": 1, "memory_mb": 256 },
+            "metadata": { "fly_process_group": "app" }
+        },
+        "created_at": "2026-10-01T12:00:00Z"
+    })
+}
+
+#[tokio::test]
+async fn apps_come_from_graphql_with_the_token() {
+    let api = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(header(
+            "authorization",
+            format!("Bearer {}", TOKEN).as_str(),
+        ))
+        .and(body_string_contains("apps("))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {
+                "apps": {
+                    "nodes": [{
+                        "id": "app_web",
+                        "name": APP,
+                        "status": "deployed",
+                        "deployed": true,
+                        "hostname": "web.fly.dev",
+                        "organization": { "id": "org_acme", "name": "Acme Inc", "slug": "acme" }
+                    }],
+                    "pageInfo": { "hasNextPage": false, "endCursor": null }
+                }
+            }
+        })))
+        .expect(1..)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon.call("fly.apps", json!({})).await.unwrap();
+
+    assert_eq!(result["count"], json!(1));
+    assert_eq!(result["apps"][0]["name"], json!(APP));
+}
+
+#[tokio::test]
+async fn detailed_machines_come_from_the_machines_api() {
+    let api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/apps/{}/machines", APP)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            machine(MACHINE_ID, "started", "iad"),
+            machine("3d8d9e1b2c4f57", "stopped", "cdg"),
+        ])))
+        .expect(1)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon
+        .call("fly.machines", json!({ "app": APP, "detailed": true }))
+        .await
+        .unwrap();
+
+    assert_eq!(result["count"], json!(2));
+    let states: Vec<&str> = result["machines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|m| m["state"].as_str())
+        .collect();
+    assert_eq!(states, ["started", "stopped"]);
+}
+
+#[tokio::test]
+async fn machine_start_posts_to_the_machine() {
+    let api = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(format!(
+            "/v1/apps/{}/machines/{}/start",
+            APP, MACHINE_ID
+        )))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "previous_state": "stopped" })),
+        )
+        .expect(1)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon
+        .call(
+            "fly.machine.start",
+            json!({ "app": APP, "machine_id": MACHINE_ID }),
+        )
+        .await;
+
+    assert!(result.is_ok(), "start failed: {:?}", result);
+}
+
+#[tokio::test]
+async fn missing_machine_is_not_found() {
+    let api = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(format!(
+            "/v1/apps/{}/machines/{}/start",
+            APP, MACHINE_ID
+        )))
+        .respond_with(
+            ResponseTemplate::new(404).set_body_json(json!({ "error": "machine not found" })),
+        )
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let error = daemon
+        .call(
+            "fly.machine.start",
+            json!({ "app": APP, "machine_id": MACHINE_ID }),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(error_code(&error), json!("not_found"));
+}
+
+#[tokio::test]
+async fn missing_param_fails_before_calling_fly() {
+    let api = MockServer::start().await;
+    Mock::given(wiremock::matchers::any())
+        .and(path(format!("/v1/apps/{}/machines", APP)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .expect(0)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let error = daemon.call("fly.machines", json!({})).await.unwrap_err();
+
+    assert_eq!(error_code(&error), json!("missing_param"));
+}
+
+#[tokio::test]
+async fn machine_wait_keeps_polling_after_a_wait_timeout() {
+    let api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/v1/apps/{}/machines/{}/wait",
+            APP, MACHINE_ID
+        )))
+        .respond_with(
+            ResponseTemplate::new(408).set_body_json(json!({ "error": "deadline_exceeded" })),
+        )
+        .mount(&api)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/apps/{}/machines/{}", APP, MACHINE_ID)))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(machine(MACHINE_ID, "started", "iad")),
+        )
+        .expect(1)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon
+        .call(
+            "fly.machine.wait",
+            json!({ "app": APP, "machine_id": MACHINE_ID, "timeout_secs": 5 }),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(result["state"], json!("started"));
+}
+
+#[tokio::test]
+async fn machine_wait_stops_on_auth_errors() {
+    let api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/v1/apps/{}/machines/{}/wait",
+            APP, MACHINE_ID
+        )))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({ "error": "unauthorized" })))
+        .expect(1)
+        .mount(&api)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/apps/{}/machines/{}", APP, MACHINE_ID)))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(machine(MACHINE_ID, "started", "iad")),
+        )
+        .expect(0)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let error = daemon
+        .call(
+            "fly.machine.wait",
+            json!({ "app": APP, "machine_id": MACHINE_ID, "timeout_secs": 5 }),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(error_code(&error), json!("auth"));
+}
+
+#[tokio::test]
+async fn machine_wait_treats_a_missing_machine_as_destroyed() {
+    let api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/v1/apps/{}/machines/{}/wait",
+            APP, MACHINE_ID
+        )))
+        .respond_with(
+            ResponseTemplate::new(404).set_body_json(json!({ "error": "machine not found" })),
+        )
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon
+        .call(
+            "fly.machine.wait",
+            json!({ "app": APP, "machine_id": MACHINE_ID, "state": "destroyed", "timeout_secs": 5 }),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(result["state"], json!("destroyed"));
+}
+
+/// Mount a `setSecrets` mutation answering with an empty release.
+async fn mount_set_secrets(api: &MockServer) {
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("setSecrets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": { "setSecrets": { "release": null } }
+        })))
+        .expect(1)
+        .mount(api)
+        .await;
+}
+
+#[tokio::test]
+async fn secrets_set_is_staged_by_default() {
+    let api = MockServer::start().await;
+    mount_set_secrets(&api).await;
+    Mock::given(wiremock::matchers::any())
+        .and(path(format!("/v1/apps/{}/machines", APP)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .expect(0)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon
+        .call(
+            "fly.secrets",
+            json!({ "app": APP, "action": "set", "key": "DATABASE_URL", "value": "postgres://db" }),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(result["staged"], json!(true));
+    assert_eq!(result["deployed"], Value::Null);
+}
+
+#[tokio::test]
+async fn secrets_set_with_deploy_updates_each_machine() {
+    let api = MockServer::start().await;
+    mount_set_secrets(&api).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/apps/{}/machines", APP)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            machine(MACHINE_ID, "started", "iad"),
+            machine("3d8d9e1b2c4f57", "stopped", "cdg"),
+        ])))
+        .expect(1)
+        .mount(&api)
+        .await;
+    for id in [MACHINE_ID, "3d8d9e1b2c4f57"] {
+        Mock::given(method("POST"))
+            .and(path(format!("/v1/apps/{}/machines/{}", APP, id)))
+            .and(body_string_contains(
+                "registry.fly.io/web:deployment-01HQ8Z",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(machine(id, "started", "iad")))
+            .expect(1)
+            .mount(&api)
+            .await;
+    }
+    // Only the machine that was running is waited on
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/v1/apps/{}/machines/{}/wait",
+            APP, MACHINE_ID
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+        .expect(1)
+        .mount(&api)
+        .await;
+
+    let daemon = Daemon::start(&api).await;
+    let result = daemon
+        .call(
+            "fly.secrets",
+            json!({
+                "app": APP,
+                "action": "set",
+                "key": "DATABASE_URL",
+                "value": "postgres://db",
+                "deploy": true
+            }),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(result["staged"], json!(false));
+    assert_eq!(result["deployed"], json!([MACHINE_ID, "3d8d9e1b2c4f57"]));
+}